@@ -0,0 +1,352 @@
+// weframe-bot/src/lib.rs
+//! Automated collaborators, such as an auto-captioner or an assembly bot,
+//! that join a session over a WebSocket and edit alongside people. A bot
+//! implements `Bot` and is driven by `run`, which keeps its view of the
+//! project in sync and sends what it emits no faster than its rate limit.
+mod network;
+mod rate;
+
+pub use rate::RateLimit;
+pub use weframe_shared::{IdGenerator, NetworkConditions};
+
+use futures::{SinkExt, StreamExt};
+use network::{Direction, Link};
+use rate::Bucket;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+use weframe_shared::{
+    EditOperation, OTOperation, ServerMessage, SyncState, VideoProject, PROTOCOL_VERSION,
+};
+
+/// An automated collaborator.
+pub trait Bot: Send {
+    /// Called once the project has been synced and again whenever it
+    /// changes, except for cursor moves. `project` includes the bot's own
+    /// operations the server hasn't confirmed yet.
+    fn on_project_change(&mut self, project: &VideoProject);
+
+    /// Operations to make next, asked for after every `on_project_change`.
+    /// They are applied to the bot's view at once and sent as the rate limit
+    /// allows, so a bot shouldn't emit the same change twice.
+    fn emit_ops(&mut self) -> Vec<EditOperation>;
+
+    /// Called when one of the bot's operations is refused, locally because it
+    /// no longer applies or by the server.
+    fn on_rejected(&mut self, operation: &EditOperation, message: &str) {
+        eprintln!("Operation {} rejected: {}", operation.kind(), message);
+    }
+}
+
+/// Where a bot connects and how it behaves on the wire.
+#[derive(Debug, Clone)]
+pub struct BotConfig {
+    /// Session WebSocket, e.g. `ws://localhost:3030/ws/my-session`.
+    pub url: String,
+    /// JWT for servers that require one. Its claims set the name and role
+    /// others see the bot under; otherwise it gets a guest name.
+    pub auth_token: Option<String>,
+    pub avatar_url: Option<String>,
+    pub rate_limit: RateLimit,
+    /// Most operations sent but not yet confirmed; sending waits beyond it.
+    pub max_in_flight: usize,
+    /// Bad network to put the connection through after the handshake, for
+    /// trying a bot against one. Lost operations are never confirmed, so
+    /// they count against `max_in_flight` for good.
+    pub network: Option<NetworkConditions>,
+    /// Makes the bot's client id; a sequential generator gives the same one
+    /// every run.
+    pub ids: IdGenerator,
+}
+
+impl BotConfig {
+    pub fn new(url: impl Into<String>) -> Self {
+        BotConfig {
+            url: url.into(),
+            auth_token: None,
+            avatar_url: None,
+            rate_limit: RateLimit::default(),
+            max_in_flight: 32,
+            network: None,
+            ids: IdGenerator::Random,
+        }
+    }
+}
+
+/// A bot's connection state: its synced project and the operations it has
+/// queued but not sent.
+struct Runner<B> {
+    bot: B,
+    client_id: String,
+    sync: SyncState,
+    client_version: usize,
+    /// Client versions of pending operations still waiting to be sent,
+    /// oldest first.
+    unsent: VecDeque<usize>,
+    /// Version of the project the server sends after `Welcome`.
+    welcome_version: usize,
+}
+
+impl<B: Bot> Runner<B> {
+    fn new(bot: B, ids: &IdGenerator) -> Self {
+        let client_id = ids.prefixed("bot");
+        let project = VideoProject::new(
+            ids.uuid().to_string(),
+            String::new(),
+            client_id.clone(),
+            "Bot".to_string(),
+        );
+        Runner {
+            bot,
+            client_id,
+            sync: SyncState::new(project),
+            client_version: 0,
+            unsent: VecDeque::new(),
+            welcome_version: 0,
+        }
+    }
+
+    /// Operations sent that the server hasn't confirmed or refused.
+    fn in_flight(&self) -> usize {
+        self.sync
+            .pending
+            .iter()
+            .filter(|op| !self.unsent.contains(&op.client_version))
+            .count()
+    }
+
+    /// Handles a message from the server, returning what to answer it with,
+    /// if anything. An error ends the connection.
+    fn receive(&mut self, text: &str) -> Result<Option<ServerMessage>, String> {
+        match serde_json::from_str::<ServerMessage>(text) {
+            Ok(ServerMessage::ClientOperation(operation)) => {
+                self.sync.confirm(&operation, &self.client_id);
+                if !matches!(
+                    operation.operation,
+                    EditOperation::UpdateCollaboratorCursor { .. }
+                ) {
+                    self.changed();
+                }
+            }
+            Ok(ServerMessage::OperationRejected {
+                client_version,
+                message,
+                ..
+            }) => {
+                if let Some(rejected) = self.sync.reject(client_version) {
+                    self.bot.on_rejected(&rejected.operation, &message);
+                    self.changed();
+                }
+            }
+            Ok(ServerMessage::Welcome { server_version, .. }) => {
+                self.welcome_version = server_version;
+            }
+            Ok(ServerMessage::ProjectUpdate(update)) => {
+                self.sync.load(update, self.welcome_version);
+                self.changed();
+            }
+            Ok(ServerMessage::SyncBegin {
+                server_version,
+                project,
+            }) => {
+                self.sync.server_version = server_version;
+                self.sync.incoming = Some(project);
+            }
+            Ok(ServerMessage::SyncAssets(assets)) => {
+                if let Some(incoming) = self.sync.incoming.as_mut() {
+                    incoming.assets.extend(assets);
+                }
+            }
+            Ok(ServerMessage::SyncClips(clips)) => {
+                if let Some(incoming) = self.sync.incoming.as_mut() {
+                    incoming.clips.extend(clips);
+                }
+            }
+            Ok(ServerMessage::SyncComplete { server_version }) => {
+                if let Some(update) = self.sync.incoming.take() {
+                    self.sync.load(update, server_version);
+                    self.changed();
+                }
+            }
+            Ok(ServerMessage::StateChecksum {
+                server_version,
+                checksum,
+            }) => {
+                if let Some(actual) = self.sync.diverged(server_version, checksum) {
+                    eprintln!(
+                        "Project diverged from the server at version {}, resyncing",
+                        server_version
+                    );
+                    return Ok(Some(ServerMessage::Resync {
+                        server_version,
+                        checksum: actual,
+                    }));
+                }
+            }
+            Ok(ServerMessage::Error {
+                message,
+                code: Some(code),
+                ..
+            }) => {
+                return Err(format!(
+                    "Server refused connection ({:?}): {}",
+                    code, message
+                ))
+            }
+            Ok(ServerMessage::Error { message, .. }) => {
+                eprintln!("Server error: {}", message);
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Ignoring unreadable message: {}", e),
+        }
+        Ok(None)
+    }
+
+    /// Shows the bot the project and queues whatever it emits in response.
+    fn changed(&mut self) {
+        // The bot is told nothing before the initial sync
+        if !self.sync.synced {
+            return;
+        }
+        let mut project = self.sync.rebuild();
+        // Forget queued operations that no longer apply
+        let pending = &self.sync.pending;
+        self.unsent
+            .retain(|version| pending.iter().any(|op| op.client_version == *version));
+        self.bot.on_project_change(&project);
+        for operation in self.bot.emit_ops() {
+            if let Err(message) = project.validate_operation(&operation) {
+                self.bot.on_rejected(&operation, &message);
+                continue;
+            }
+            project.apply_operation(&operation);
+            self.sync.pending.push(OTOperation {
+                client_id: self.client_id.clone(),
+                client_version: self.client_version,
+                server_version: self.sync.server_version,
+                operation,
+                label: None,
+            });
+            self.unsent.push_back(self.client_version);
+            self.client_version += 1;
+        }
+    }
+
+    /// How long until the next queued operation may go out, or `None` if
+    /// nothing can be sent until the server catches up.
+    fn next_send(&self, bucket: &mut Bucket, max_in_flight: usize) -> Option<Duration> {
+        if self.unsent.is_empty() || self.in_flight() >= max_in_flight {
+            return None;
+        }
+        Some(bucket.delay())
+    }
+
+    /// The oldest queued operation, as transformed past everything the
+    /// server confirmed since it was emitted, ready to send.
+    fn take_unsent(&mut self) -> Option<String> {
+        let version = self.unsent.pop_front()?;
+        let server_version = self.sync.server_version;
+        let operation = self
+            .sync
+            .pending
+            .iter_mut()
+            .find(|op| op.client_version == version)?;
+        operation.server_version = server_version;
+        serde_json::to_string(operation).ok()
+    }
+}
+
+/// Handles a message that has come off the wire, returning any answer to
+/// send at once.
+fn arrive<B: Bot>(
+    runner: &mut Runner<B>,
+    link: &mut Link,
+    text: &str,
+) -> Result<Option<String>, String> {
+    let Some(reply) = runner.receive(text)? else {
+        return Ok(None);
+    };
+    let text = serde_json::to_string(&reply).map_err(|e| e.to_string())?;
+    Ok(link.carry(Direction::Outgoing, text))
+}
+
+/// Connects `bot` to the session at `config.url` and runs it until the
+/// connection closes. Fails if the server can't be reached or refuses the
+/// bot.
+pub async fn run<B: Bot>(bot: B, config: BotConfig) -> Result<(), String> {
+    let (socket, _) = tokio_tungstenite::connect_async(config.url.as_str())
+        .await
+        .map_err(|e| format!("Failed to connect to {}: {}", config.url, e))?;
+    let (mut sink, mut stream) = socket.split();
+
+    let mut handshake = Vec::new();
+    if let Some(token) = &config.auth_token {
+        handshake.push(ServerMessage::Authenticate {
+            token: token.clone(),
+        });
+    }
+    handshake.push(ServerMessage::Hello {
+        protocol_version: PROTOCOL_VERSION,
+        features: vec!["chunked_sync".to_string()],
+        guest_token: None,
+        avatar_url: config.avatar_url.clone(),
+        resume_from: None,
+    });
+    for message in handshake {
+        let text = serde_json::to_string(&message).map_err(|e| e.to_string())?;
+        sink.send(Message::Text(text))
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    let mut runner = Runner::new(bot, &config.ids);
+    let mut bucket = Bucket::new(config.rate_limit);
+    let mut link = Link::new(config.network);
+    loop {
+        let next_send = runner.next_send(&mut bucket, config.max_in_flight);
+        let next_arrival = link.next_arrival();
+        tokio::select! {
+            frame = stream.next() => match frame {
+                Some(Ok(Message::Text(text))) => {
+                    let Some(text) = link.carry(Direction::Incoming, text) else {
+                        continue;
+                    };
+                    if let Some(reply) = arrive(&mut runner, &mut link, &text)? {
+                        sink.send(Message::Text(reply))
+                            .await
+                            .map_err(|e| e.to_string())?;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.to_string()),
+            },
+            _ = tokio::time::sleep(next_send.unwrap_or_default()), if next_send.is_some() => {
+                if next_send != Some(Duration::ZERO) {
+                    continue;
+                }
+                if let Some(text) = runner.take_unsent() {
+                    bucket.take();
+                    if let Some(text) = link.carry(Direction::Outgoing, text) {
+                        sink.send(Message::Text(text))
+                            .await
+                            .map_err(|e| e.to_string())?;
+                    }
+                }
+            }
+            _ = tokio::time::sleep_until(next_arrival.unwrap_or_else(tokio::time::Instant::now)), if next_arrival.is_some() => {
+                for (direction, text) in link.take_arrived() {
+                    let text = match direction {
+                        Direction::Outgoing => Some(text),
+                        Direction::Incoming => arrive(&mut runner, &mut link, &text)?,
+                    };
+                    if let Some(text) = text {
+                        sink.send(Message::Text(text))
+                            .await
+                            .map_err(|e| e.to_string())?;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,75 @@
+// weframe-bot/src/network.rs
+use std::mem;
+use tokio::time::Instant;
+use weframe_shared::{NetworkConditions, NetworkSimulator};
+
+/// Which way a message crosses the network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Outgoing,
+    Incoming,
+}
+
+/// Messages held back by a simulated network until they arrive.
+pub(crate) struct Link {
+    /// Simulators for outgoing and incoming messages, if the network is
+    /// simulated at all.
+    simulators: Option<(NetworkSimulator, NetworkSimulator)>,
+    started: Instant,
+    /// Messages on their way, with when they arrive, in the order sent.
+    in_flight: Vec<(Instant, Direction, String)>,
+}
+
+impl Link {
+    pub(crate) fn new(conditions: Option<NetworkConditions>) -> Self {
+        Link {
+            simulators: conditions.map(|conditions| {
+                (
+                    NetworkSimulator::new(conditions),
+                    NetworkSimulator::new(conditions),
+                )
+            }),
+            started: Instant::now(),
+            in_flight: Vec::new(),
+        }
+    }
+
+    /// Puts `text` on the network. Without a simulated network it is handed
+    /// straight back to deliver; otherwise it comes out of `take_arrived`
+    /// later, or never if it is lost.
+    pub(crate) fn carry(&mut self, direction: Direction, text: String) -> Option<String> {
+        let Some((outgoing, incoming)) = self.simulators.as_mut() else {
+            return Some(text);
+        };
+        let simulator = match direction {
+            Direction::Outgoing => outgoing,
+            Direction::Incoming => incoming,
+        };
+        let now = Instant::now().duration_since(self.started);
+        if let Some(arrival) = simulator.arrival(now, rand::random::<f64>) {
+            self.in_flight
+                .push((self.started + arrival, direction, text));
+        }
+        None
+    }
+
+    /// When the next message on its way arrives.
+    pub(crate) fn next_arrival(&self) -> Option<Instant> {
+        self.in_flight.iter().map(|(arrival, _, _)| *arrival).min()
+    }
+
+    /// Messages that have arrived by now, earliest first.
+    pub(crate) fn take_arrived(&mut self) -> Vec<(Direction, String)> {
+        let now = Instant::now();
+        let (mut arrived, waiting): (Vec<_>, Vec<_>) = mem::take(&mut self.in_flight)
+            .into_iter()
+            .partition(|(arrival, _, _)| *arrival <= now);
+        self.in_flight = waiting;
+        // Stable, so messages arriving together keep the order they were sent
+        arrived.sort_by_key(|(arrival, _, _)| *arrival);
+        arrived
+            .into_iter()
+            .map(|(_, direction, text)| (direction, text))
+            .collect()
+    }
+}
@@ -0,0 +1,63 @@
+// weframe-bot/src/rate.rs
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How fast a bot may send operations: a steady `per_second`, with bursts
+/// of up to `burst` after a quiet spell.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            per_second: 10.0,
+            burst: 20,
+        }
+    }
+}
+
+/// Token bucket enforcing a `RateLimit`.
+pub(crate) struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Bucket {
+            limit,
+            tokens: limit.burst as f64,
+            refilled: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let earned = now.duration_since(self.refilled).as_secs_f64() * self.limit.per_second;
+        self.tokens = (self.tokens + earned).min(self.limit.burst.max(1) as f64);
+        self.refilled = now;
+    }
+
+    /// How long until an operation may be sent; zero if one may be now.
+    pub(crate) fn delay(&mut self) -> Duration {
+        self.refill();
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else if self.limit.per_second > 0.0 {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.limit.per_second)
+        } else {
+            // Sending is paused; check back now and then
+            Duration::from_secs(60)
+        }
+    }
+
+    /// Spends a token on an operation being sent.
+    pub(crate) fn take(&mut self) {
+        self.refill();
+        self.tokens -= 1.0;
+    }
+}
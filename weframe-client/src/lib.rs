@@ -1,311 +1,2948 @@
-use js_sys::global;
+use serde::{Deserialize, Serialize};
 use serde_wasm_bindgen::to_value;
-use std::cell::RefCell;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::HashMap;
+use std::io::Read;
 use std::rc::Rc;
+use std::time::Duration;
 use uuid::Uuid;
 use wasm_bindgen::prelude::*;
-use web_sys::{console, MessageEvent, WebSocket};
+use web_sys::{console, BinaryType, EventSource, Headers, MessageEvent, RequestInit, WebSocket};
 use weframe_shared::{
-    CursorPosition, EditOperation, Effect, EffectType, OTOperation, ServerMessage, VideoClip,
-    VideoProject,
+    validate_avatar_url, validate_chat_message, validate_label, validate_view_state, AspectRatio,
+    Capabilities, ClipAudio, ClipKind, ClipText, CursorPosition, CursorVelocity, EditOperation,
+    EditTool, Effect, EffectChain, EffectParameter, EffectType, FrameRate, HdrMetadata,
+    IdGenerator, ImportStatus, JobFailure, JobKind, Marker, MediaReference, MulticamAngle,
+    MulticamGroup, MulticamRef, NetworkConditions, NetworkSimulator, OTOperation, Presentation,
+    Recovery, RejectionCode, Role, SafeAreas, ServerMessage, SourceKind, SpeedKeyframe, SyncState,
+    TrafficStats, Transition, TransitionType, VideoClip, VideoProject, ViewState, PROTOCOL_VERSION,
+    SUPPORTED_FEATURES,
 };
+/// The cursor as last sent: where it was, when it last moved, in
+/// milliseconds since the epoch, and how fast it was moving then.
+struct CursorSample {
+    position: CursorPosition,
+    moved_at: f64,
+    velocity: Option<CursorVelocity>,
+}
+
 #[wasm_bindgen]
 pub struct WeframeClient {
-    ws: WebSocket,
+    connector: Rc<Connector>,
     project: Rc<RefCell<VideoProject>>,
+    sync: Rc<RefCell<SyncState>>,
+    callbacks: Rc<RefCell<Callbacks>>,
+    handshake: Rc<RefCell<Handshake>>,
+    last_cursor: RefCell<Option<CursorSample>>,
     client_id: String,
     client_version: Rc<RefCell<usize>>,
+    traffic: Rc<RefCell<TrafficStats>>,
+    /// Solo another collaborator asked our preview to show, if any.
+    preview_solo: Rc<RefCell<Option<PreviewSolo>>>,
+    /// This user's own track layout, used instead of the project's when the
+    /// project keeps layouts private.
+    local_layout: RefCell<Presentation>,
+    /// View state the server handed back from this user's last visit.
+    view_state: Rc<RefCell<Option<ViewState>>>,
+    history: Rc<RefCell<UndoHistory>>,
+    /// The session's chat as replayed on joining plus what was posted since.
+    chat: Rc<RefCell<Vec<ChatLine>>>,
+    /// Intent label put on every edit until it is changed.
+    label: RefCell<Option<String>>,
+    /// Edits held back since `begin_batch`, to be sent as one.
+    batch: RefCell<Option<Vec<EditOperation>>>,
+    /// Makes ids for the clips, effects, markers and transitions this
+    /// client creates.
+    ids: RefCell<IdGenerator>,
+}
+
+/// Most edits `undo` can step back through.
+const MAX_UNDO: usize = 200;
+
+/// Inverses of this user's own edits, most recent last, each with the
+/// client version of the edit it reverts so that a rejected edit can be
+/// forgotten.
+#[derive(Default)]
+struct UndoHistory {
+    undo: Vec<(usize, EditOperation)>,
+    redo: Vec<(usize, EditOperation)>,
+}
+
+impl UndoHistory {
+    fn record(&mut self, client_version: usize, inverse: EditOperation) {
+        self.redo.clear();
+        self.undo.push((client_version, inverse));
+        if self.undo.len() > MAX_UNDO {
+            self.undo.remove(0);
+        }
+    }
+
+    /// Drops the inverse of an edit the server refused; there is nothing to
+    /// revert.
+    fn forget(&mut self, client_version: usize) {
+        self.undo.retain(|(version, _)| *version != client_version);
+        self.redo.retain(|(version, _)| *version != client_version);
+    }
+}
+
+/// Connection attempts that may fail before a WebSocket has ever opened,
+/// after which the client falls back to server-sent events.
+const WEBSOCKET_ATTEMPTS: u32 = 3;
+
+/// Pause before the first retry of a failed connection, growing with each
+/// further failure up to `MAX_RETRY_MS`.
+const RETRY_MS: i32 = 1000;
+
+const MAX_RETRY_MS: i32 = 30_000;
+
+/// How often an open connection is pinged.
+const HEARTBEAT_MS: i32 = 5000;
+
+/// Heartbeats that may go unanswered before the connection is given up on
+/// and reopened. One unanswered heartbeat marks it degraded.
+const MAX_MISSED_HEARTBEATS: u32 = 3;
+
+/// How long a sent operation may go without the server echoing it back
+/// before it is given up on and the project fetched again.
+const PENDING_TIMEOUT_MS: f64 = 30_000.0;
+
+/// Where the client's connection to the server stands, reported to
+/// `on_connection_state` callbacks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ConnectionState {
+    /// Opening the first connection.
+    Connecting,
+    Open,
+    /// Open, but the server hasn't answered the last heartbeat.
+    Degraded,
+    /// The connection dropped or stopped answering and is being reopened.
+    Reconnecting,
+    /// Closed by `close`; nothing is reopened.
+    Closed,
+}
+
+impl ConnectionState {
+    fn as_str(self) -> &'static str {
+        match self {
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Open => "open",
+            ConnectionState::Degraded => "degraded",
+            ConnectionState::Reconnecting => "reconnecting",
+            ConnectionState::Closed => "closed",
+        }
+    }
+
+    fn is_connected(self) -> bool {
+        matches!(self, ConnectionState::Open | ConnectionState::Degraded)
+    }
+}
+
+/// Payload passed to `on_connection_state` callbacks.
+#[derive(Serialize)]
+struct ConnectionChange {
+    state: ConnectionState,
+    previous: ConnectionState,
+}
+
+/// How messages travel between the client and server. Networks that block
+/// WebSockets get an event stream for receiving and a POST per message for
+/// sending.
+enum Transport {
+    WebSocket(WebSocket),
+    EventSource {
+        source: EventSource,
+        /// Where messages are POSTed, known once the stream's `connection`
+        /// event arrives.
+        post_url: Option<String>,
+        /// Settles once every message POSTed so far has been delivered.
+        tail: js_sys::Promise,
+    },
+}
+
+impl Transport {
+    fn send(&mut self, message: &str) -> Result<(), JsValue> {
+        match self {
+            Transport::WebSocket(ws) => ws.send_with_str(message),
+            Transport::EventSource {
+                post_url: Some(url),
+                tail,
+                ..
+            } => {
+                *tail = post_after(tail, url, message)?;
+                Ok(())
+            }
+            Transport::EventSource { post_url: None, .. } => {
+                Err(JsValue::from_str("Event stream is not connected yet"))
+            }
+        }
+    }
+
+    /// Closes the connection without firing any of its handlers, so that
+    /// nothing reacts to it going away.
+    fn abandon(&self) {
+        match self {
+            Transport::WebSocket(ws) => {
+                ws.set_onopen(None);
+                ws.set_onmessage(None);
+                ws.set_onclose(None);
+                ws.close().ok();
+            }
+            Transport::EventSource { source, .. } => source.close(),
+        }
+    }
+}
+
+type MessageHandler = Box<dyn Fn(&str)>;
+
+/// Everything needed to open, retry or replace the client's connection, and
+/// the state machine tracking it.
+struct Connector {
+    ws_url: String,
+    transport: Rc<RefCell<Transport>>,
+    traffic: Rc<RefCell<TrafficStats>>,
+    callbacks: Rc<RefCell<Callbacks>>,
+    /// Handles the text of every message from the server.
+    on_message: OnceCell<MessageHandler>,
+    /// Read for the version to resume from when reconnecting.
+    sync: Rc<RefCell<SyncState>>,
+    /// Whether this connection has synced, so our operations can go out.
+    /// Until then they are queued.
+    ready: Cell<bool>,
+    /// Client versions of pending operations not sent over this
+    /// connection, oldest first.
+    queued: RefCell<Vec<usize>>,
+    /// When each pending operation was last sent, by client version.
+    sent_at: RefCell<HashMap<usize, f64>>,
+    guest_token: Option<String>,
+    avatar_url: Option<String>,
+    /// Sent in `Authenticate` ahead of each WebSocket's `Hello`, and in the
+    /// event-stream URL.
+    auth_token: Option<String>,
+    state: Cell<ConnectionState>,
+    /// Connection attempts that have failed since one last opened.
+    failed_attempts: Cell<u32>,
+    /// Whether a WebSocket has opened at all. Once one has, a dropped
+    /// connection is retried as a WebSocket rather than falling back.
+    websocket_worked: Cell<bool>,
+    /// Heartbeats sent since anything was last heard from the server.
+    missed_heartbeats: Cell<u32>,
+    heartbeat_timer: Cell<Option<i32>>,
+    /// Bad network the connection is put through, set for testing.
+    network: RefCell<Option<SimulatedNetwork>>,
+}
+
+/// A simulated network for each direction, so each keeps its own order.
+struct SimulatedNetwork {
+    outgoing: NetworkSimulator,
+    incoming: NetworkSimulator,
+}
+
+/// How many milliseconds a message sent now takes to cross `simulator`, or
+/// `None` if it is lost on the way.
+fn simulated_delay(simulator: &mut NetworkSimulator) -> Option<i32> {
+    let now = Duration::from_secs_f64(js_sys::Date::now() / 1000.0);
+    let arrival = simulator.arrival(now, js_sys::Math::random)?;
+    Some(arrival.saturating_sub(now).as_millis() as i32)
+}
+
+/// Runs `f` after `delay` milliseconds.
+fn after(delay: i32, f: impl FnOnce() + 'static) -> Result<(), JsValue> {
+    let callback = Closure::once_into_js(f);
+    let window = web_sys::window().ok_or_else(|| JsValue::from_str("No window to wait on"))?;
+    window
+        .set_timeout_with_callback_and_timeout_and_arguments_0(callback.unchecked_ref(), delay)?;
+    Ok(())
+}
+
+impl Connector {
+    /// Our `Hello`. Event streams can't carry binary frames, so they don't
+    /// offer compression.
+    fn hello(&self, binary: bool) -> String {
+        let hello = ServerMessage::Hello {
+            protocol_version: PROTOCOL_VERSION,
+            features: SUPPORTED_FEATURES
+                .iter()
+                .filter(|f| binary || **f != "zstd")
+                .map(|f| f.to_string())
+                .collect(),
+            guest_token: self.guest_token.clone(),
+            avatar_url: self.avatar_url.clone(),
+            resume_from: self.sync.borrow().resume_from(),
+        };
+        serde_json::to_string(&hello).unwrap()
+    }
+
+    fn set_state(&self, state: ConnectionState) {
+        let previous = self.state.replace(state);
+        if previous != state {
+            emit(
+                &self.callbacks.borrow().connection_state,
+                &ConnectionChange { state, previous },
+            );
+        }
+    }
+
+    /// Notes that a connection has just opened.
+    fn opened(&self) {
+        self.ready.set(false);
+        self.failed_attempts.set(0);
+        self.missed_heartbeats.set(0);
+        self.set_state(ConnectionState::Open);
+    }
+
+    /// Any message from the server answers the heartbeat.
+    fn heard_from_server(&self) {
+        self.missed_heartbeats.set(0);
+        if self.state.get() == ConnectionState::Degraded {
+            self.set_state(ConnectionState::Open);
+        }
+    }
+
+    /// Passes on each message in `txt`, which holds several as a JSON array
+    /// when the server batched them into one frame.
+    fn receive(&self, txt: &str) {
+        self.heard_from_server();
+        let Some(on_message) = self.on_message.get() else {
+            return;
+        };
+        if !txt.starts_with('[') {
+            on_message(txt);
+            return;
+        }
+        match serde_json::from_str::<Vec<serde_json::Value>>(txt) {
+            Ok(messages) => {
+                for message in messages {
+                    on_message(&message.to_string());
+                }
+            }
+            Err(e) => console::error_1(&JsValue::from_str(&format!(
+                "Failed to parse batched messages: {:?}",
+                e
+            ))),
+        }
+    }
+
+    /// Takes a message off the wire, through the simulated network if one
+    /// is set.
+    fn arrive(self: &Rc<Self>, txt: String) {
+        let simulated = self
+            .network
+            .borrow_mut()
+            .as_mut()
+            .map(|network| simulated_delay(&mut network.incoming));
+        match simulated {
+            None => self.receive(&txt),
+            Some(None) => {}
+            Some(Some(delay)) => {
+                let connector = self.clone();
+                if let Err(e) = after(delay, move || connector.receive(&txt)) {
+                    console::error_1(&e);
+                }
+            }
+        }
+    }
+
+    /// Sends `message` over whichever transport is in use, through the
+    /// simulated network if one is set. A message the simulated network
+    /// loses counts as sent.
+    fn send(&self, message: &str) -> Result<(), JsValue> {
+        self.traffic.borrow_mut().record_sent(message.len());
+        let simulated = self
+            .network
+            .borrow_mut()
+            .as_mut()
+            .map(|network| simulated_delay(&mut network.outgoing));
+        match simulated {
+            None => self.transport.borrow_mut().send(message),
+            Some(None) => Ok(()),
+            Some(Some(delay)) => {
+                let transport = self.transport.clone();
+                let message = message.to_string();
+                after(delay, move || {
+                    if let Err(e) = transport.borrow_mut().send(&message) {
+                        console::warn_1(&e);
+                    }
+                })
+            }
+        }
+    }
+
+    /// Sends one of our operations, or queues it for `flush` if the
+    /// connection isn't ready or the send fails.
+    fn send_operation(&self, operation: &OTOperation) {
+        if self.ready.get() && self.state.get().is_connected() {
+            let sent = serde_json::to_string(operation)
+                .map_err(|e| JsValue::from_str(&format!("Failed to serialize operation: {:?}", e)))
+                .and_then(|message| self.send(&message));
+            match sent {
+                Ok(()) => {
+                    self.sent_at
+                        .borrow_mut()
+                        .insert(operation.client_version, js_sys::Date::now());
+                    return;
+                }
+                Err(e) => console::warn_1(&e),
+            }
+        }
+        self.queued.borrow_mut().push(operation.client_version);
+    }
+
+    /// Gives up on operations the server hasn't echoed back within
+    /// `PENDING_TIMEOUT_MS`, e.g. because neither the echo nor a rejection
+    /// got through, and asks for the project again so ours can't stay
+    /// ahead of the server's.
+    fn expire_pending(&self) {
+        let now = js_sys::Date::now();
+        let mut sync = self.sync.borrow_mut();
+        let mut sent_at = self.sent_at.borrow_mut();
+        sent_at.retain(|version, _| sync.pending.iter().any(|op| op.client_version == *version));
+        let expired: Vec<usize> = sent_at
+            .iter()
+            .filter(|(_, sent)| now - **sent >= PENDING_TIMEOUT_MS)
+            .map(|(version, _)| *version)
+            .collect();
+        if expired.is_empty() {
+            return;
+        }
+        for version in &expired {
+            sent_at.remove(version);
+            sync.reject(*version);
+        }
+        console::warn_1(&JsValue::from_str(&format!(
+            "Server never confirmed {} operations, resyncing",
+            expired.len()
+        )));
+        // The project sent back is shown with what is still pending on top
+        let resync = ServerMessage::Resync {
+            server_version: sync.server_version,
+            checksum: sync.confirmed.checksum(),
+        };
+        drop(sync);
+        if let Err(e) = self.send(&serde_json::to_string(&resync).unwrap()) {
+            console::error_1(&e);
+        }
+    }
+
+    /// Sends the operations queued while we couldn't, rebased onto the
+    /// server's version now that this connection has synced, and lets new
+    /// ones go straight out. Queued operations that no longer apply were
+    /// already dropped from `sync`.
+    fn flush(&self, sync: &mut SyncState) {
+        self.ready.set(true);
+        let server_version = sync.server_version;
+        let queued = std::mem::take(&mut *self.queued.borrow_mut());
+        for version in queued {
+            let Some(operation) = sync
+                .pending
+                .iter_mut()
+                .find(|op| op.client_version == version)
+            else {
+                continue;
+            };
+            operation.server_version = server_version;
+            self.send_operation(operation);
+        }
+    }
+
+    /// Pings the server every `HEARTBEAT_MS` while connected.
+    fn start_heartbeat(self: &Rc<Self>) -> Result<(), JsValue> {
+        let connector = self.clone();
+        let tick = Closure::wrap(Box::new(move || connector.heartbeat()) as Box<dyn FnMut()>);
+        let window = web_sys::window().ok_or_else(|| JsValue::from_str("No window to wait on"))?;
+        let timer = window.set_interval_with_callback_and_timeout_and_arguments_0(
+            tick.as_ref().unchecked_ref(),
+            HEARTBEAT_MS,
+        )?;
+        tick.forget();
+        self.heartbeat_timer.set(Some(timer));
+        Ok(())
+    }
+
+    /// Marks the connection degraded once a heartbeat goes unanswered, and
+    /// reopens it after `MAX_MISSED_HEARTBEATS`.
+    fn heartbeat(self: &Rc<Self>) {
+        if !self.state.get().is_connected() {
+            return;
+        }
+        let missed = self.missed_heartbeats.get();
+        if missed >= MAX_MISSED_HEARTBEATS {
+            console::warn_1(&JsValue::from_str(
+                "Server stopped answering heartbeats, reconnecting",
+            ));
+            self.set_state(ConnectionState::Reconnecting);
+            if let Err(e) = self.reopen() {
+                console::error_1(&e);
+            }
+            return;
+        }
+        if missed > 0 {
+            self.set_state(ConnectionState::Degraded);
+        }
+        self.missed_heartbeats.set(missed + 1);
+        let ping = serde_json::to_string(&ServerMessage::Ping(js_sys::Date::now() as u64)).unwrap();
+        if let Err(e) = self.send(&ping) {
+            console::error_1(&e);
+        }
+        self.expire_pending();
+    }
+
+    /// Drops the current connection and opens another of the same kind.
+    fn reopen(self: &Rc<Self>) -> Result<(), JsValue> {
+        let websocket = {
+            let transport = self.transport.borrow();
+            transport.abandon();
+            matches!(*transport, Transport::WebSocket(_))
+        };
+        if websocket {
+            self.open_websocket()
+        } else {
+            self.open_event_source()
+        }
+    }
+
+    /// Stops heartbeats and closes the connection for good.
+    fn close(&self) {
+        if let (Some(timer), Some(window)) = (self.heartbeat_timer.take(), web_sys::window()) {
+            window.clear_interval_with_handle(timer);
+        }
+        self.transport.borrow().abandon();
+        self.set_state(ConnectionState::Closed);
+    }
+
+    fn open_websocket(self: &Rc<Self>) -> Result<(), JsValue> {
+        let ws = WebSocket::new(&self.ws_url)?;
+        *self.transport.borrow_mut() = Transport::WebSocket(ws.clone());
+        self.watch_websocket(&ws);
+        Ok(())
+    }
+
+    /// Handles `ws`'s events. If it closes it is retried, unless it never
+    /// opened and no WebSocket has worked after `WEBSOCKET_ATTEMPTS`, in
+    /// which case it is replaced by an event stream.
+    fn watch_websocket(self: &Rc<Self>, ws: &WebSocket) {
+        ws.set_binary_type(BinaryType::Arraybuffer);
+        let opened = Rc::new(Cell::new(false));
+
+        let connector = self.clone();
+        let socket = ws.clone();
+        let socket_opened = opened.clone();
+        let onopen_callback = Closure::wrap(Box::new(move || {
+            socket_opened.set(true);
+            connector.websocket_worked.set(true);
+            let authenticate = connector.auth_token.as_ref().map(|token| {
+                serde_json::to_string(&ServerMessage::Authenticate {
+                    token: token.clone(),
+                })
+                .unwrap()
+            });
+            for message in authenticate.iter().chain([&connector.hello(true)]) {
+                connector.traffic.borrow_mut().record_sent(message.len());
+                if let Err(e) = socket.send_with_str(message) {
+                    console::error_1(&e);
+                }
+            }
+            connector.opened();
+        }) as Box<dyn FnMut()>);
+        ws.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
+        onopen_callback.forget();
+
+        let connector = self.clone();
+        let onmessage_callback = Closure::wrap(Box::new(move |e: MessageEvent| {
+            connector
+                .traffic
+                .borrow_mut()
+                .record_received(message_size(&e.data()));
+            match message_text(e.data()) {
+                Ok(txt) => connector.arrive(txt),
+                Err(err) => console::error_1(&JsValue::from_str(&err)),
+            }
+        }) as Box<dyn FnMut(_)>);
+        ws.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
+        onmessage_callback.forget();
+
+        let connector = self.clone();
+        let onclose_callback = Closure::wrap(Box::new(move || {
+            if connector.state.get() == ConnectionState::Closed {
+                return;
+            }
+            if connector.state.get() != ConnectionState::Connecting {
+                connector.set_state(ConnectionState::Reconnecting);
+            }
+            let failed = if opened.get() {
+                0
+            } else {
+                connector.failed_attempts.get() + 1
+            };
+            connector.failed_attempts.set(failed);
+            let result = if failed >= WEBSOCKET_ATTEMPTS && !connector.websocket_worked.get() {
+                console::warn_1(&JsValue::from_str(
+                    "WebSocket unavailable, falling back to server-sent events",
+                ));
+                connector.open_event_source()
+            } else {
+                connector.retry(failed)
+            };
+            if let Err(e) = result {
+                console::error_1(&e);
+            }
+        }) as Box<dyn FnMut()>);
+        ws.set_onclose(Some(onclose_callback.as_ref().unchecked_ref()));
+        onclose_callback.forget();
+    }
+
+    /// Reopens the connection after a pause that grows with each of
+    /// `failed` consecutive failures.
+    fn retry(self: &Rc<Self>, failed: u32) -> Result<(), JsValue> {
+        let connector = self.clone();
+        let retry = Closure::once_into_js(move || {
+            if connector.state.get() == ConnectionState::Closed {
+                return;
+            }
+            if let Err(e) = connector.reopen() {
+                console::error_1(&e);
+            }
+        });
+        let delay = RETRY_MS
+            .saturating_mul(1 << failed.min(5))
+            .min(MAX_RETRY_MS);
+        let window = web_sys::window().ok_or_else(|| JsValue::from_str("No window to wait on"))?;
+        window
+            .set_timeout_with_callback_and_timeout_and_arguments_0(retry.unchecked_ref(), delay)?;
+        Ok(())
+    }
+
+    /// Switches to an event stream for receiving and POSTs for sending. The
+    /// browser reconnects the stream by itself; each reconnect brings a new
+    /// `connection` event and a new handshake.
+    fn open_event_source(self: &Rc<Self>) -> Result<(), JsValue> {
+        let (base, session_id) = split_ws_url(&self.ws_url)?;
+        let stream_url = format!("{}/sse/{}", base, session_id);
+        let source = match &self.auth_token {
+            Some(token) => EventSource::new(&format!(
+                "{}?token={}",
+                stream_url,
+                js_sys::encode_uri_component(token)
+            ))?,
+            None => EventSource::new(&stream_url)?,
+        };
+
+        let connector = self.clone();
+        let onconnection_callback = Closure::wrap(Box::new(move |e: MessageEvent| {
+            let Some(token) = e.data().as_string() else {
+                return;
+            };
+            if let Transport::EventSource { post_url, tail, .. } =
+                &mut *connector.transport.borrow_mut()
+            {
+                *post_url = Some(format!("{}/{}", stream_url, token));
+                *tail = js_sys::Promise::resolve(&JsValue::UNDEFINED);
+            }
+            if let Err(e) = connector.send(&connector.hello(false)) {
+                console::error_1(&e);
+            }
+            connector.opened();
+        }) as Box<dyn FnMut(_)>);
+        source.add_event_listener_with_callback(
+            "connection",
+            onconnection_callback.as_ref().unchecked_ref(),
+        )?;
+        onconnection_callback.forget();
+
+        let connector = self.clone();
+        let onmessage_callback = Closure::wrap(Box::new(move |e: MessageEvent| {
+            let Some(txt) = e.data().as_string() else {
+                return;
+            };
+            connector.traffic.borrow_mut().record_received(txt.len());
+            connector.arrive(txt);
+        }) as Box<dyn FnMut(_)>);
+        source.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
+        onmessage_callback.forget();
+
+        // The browser retries a dropped stream on its own, but gives up for
+        // good on some failures, e.g. an error status.
+        let connector = self.clone();
+        let stream = source.clone();
+        let onerror_callback = Closure::wrap(Box::new(move || {
+            if connector.state.get() == ConnectionState::Closed {
+                return;
+            }
+            if connector.state.get() != ConnectionState::Connecting {
+                connector.set_state(ConnectionState::Reconnecting);
+            }
+            if stream.ready_state() == EventSource::CLOSED {
+                let failed = connector.failed_attempts.get() + 1;
+                connector.failed_attempts.set(failed);
+                if let Err(e) = connector.retry(failed) {
+                    console::error_1(&e);
+                }
+            }
+        }) as Box<dyn FnMut()>);
+        source.set_onerror(Some(onerror_callback.as_ref().unchecked_ref()));
+        onerror_callback.forget();
+
+        *self.transport.borrow_mut() = Transport::EventSource {
+            source,
+            post_url: None,
+            tail: js_sys::Promise::resolve(&JsValue::UNDEFINED),
+        };
+        Ok(())
+    }
+}
+
+/// What the server agreed to in reply to our `Hello`.
+#[derive(Default)]
+struct Handshake {
+    protocol_version: Option<u32>,
+    features: Vec<String>,
+    server_client_id: Option<String>,
+    /// Name the server gave this connection.
+    name: Option<String>,
+    capabilities: Capabilities,
+    /// Flags enabled in the session, kept up to date after the handshake.
+    session_flags: Vec<String>,
+    role: Role,
+    /// Version of the project the server is about to send.
+    server_version: usize,
+    /// Why the session is frozen for maintenance, while it is.
+    frozen: Option<String>,
+}
+
+/// JS callbacks registered by the UI.
+#[derive(Default)]
+struct Callbacks {
+    cursor_update: Option<js_sys::Function>,
+    preview_solo: Option<js_sys::Function>,
+    view_state: Option<js_sys::Function>,
+    connection_state: Option<js_sys::Function>,
+    session_flags: Option<js_sys::Function>,
+    chat: Option<js_sys::Function>,
+    divergence: Option<js_sys::Function>,
+    operation: Option<js_sys::Function>,
+    collaborator_joined: Option<js_sys::Function>,
+    collaborator_left: Option<js_sys::Function>,
+    project_changed: Option<js_sys::Function>,
+    import_progress: Option<js_sys::Function>,
+    job_failed: Option<js_sys::Function>,
+    operation_rejected: Option<js_sys::Function>,
+    frozen: Option<js_sys::Function>,
+}
+
+/// Payload passed to `on_collaborator_joined` callbacks.
+#[derive(Serialize)]
+struct CollaboratorJoined {
+    client_id: String,
+    name: String,
+    avatar_url: Option<String>,
+    role: Role,
+}
+
+/// Payload passed to `on_collaborator_left` callbacks.
+#[derive(Serialize)]
+struct CollaboratorLeft {
+    client_id: String,
+}
+
+/// Payload passed to `on_import_progress` callbacks.
+#[derive(Serialize)]
+struct ImportUpdate {
+    import_id: String,
+    url: String,
+    status: ImportStatus,
+}
+
+/// Payload passed to `on_job_failed` callbacks.
+#[derive(Serialize)]
+struct JobFailed {
+    job_id: String,
+    kind: JobKind,
+    reason: JobFailure,
+    message: String,
+}
+
+/// Payload passed to `on_frozen` callbacks; `reason` is `None` when the
+/// session thaws.
+#[derive(Serialize)]
+struct FreezeNotice {
+    reason: Option<String>,
+}
+
+/// Payload passed to `on_operation_rejected` callbacks.
+#[derive(Serialize)]
+struct OperationRejection {
+    client_version: usize,
+    code: RejectionCode,
+    message: String,
+    operation: EditOperation,
+    recovery: Recovery,
+}
+
+/// Payload passed to `on_divergence` callbacks when our project stopped
+/// matching the server's and is being resynced. Checksums are hex, as
+/// JavaScript numbers can't hold them.
+#[derive(Serialize)]
+struct Divergence {
+    server_version: usize,
+    expected: String,
+    actual: String,
+}
+
+/// A chat message, as passed to `on_chat` callbacks.
+#[derive(Clone, Serialize)]
+struct ChatLine {
+    client_id: String,
+    name: String,
+    message: String,
+    /// Milliseconds since the Unix epoch.
+    sent_at: u64,
+}
+
+/// Payload passed to `on_preview_solo` callbacks; `clip_ids` is empty when
+/// the solo ends.
+#[derive(Clone, Serialize)]
+struct PreviewSolo {
+    collaborator_id: String,
+    clip_ids: Vec<String>,
+}
+
+/// Payload passed to `on_cursor_update` callbacks.
+#[derive(Serialize)]
+struct CursorUpdate {
+    collaborator_id: String,
+    name: String,
+    avatar_url: Option<String>,
+    color: String,
+    track: usize,
+    time: f64,
+    velocity: Option<CursorVelocity>,
+    selected_clip: Option<String>,
+    hovered_clip: Option<String>,
+    tool: Option<EditTool>,
+}
+
+/// Entry returned by `get_subtitle_tracks`.
+#[derive(Serialize)]
+struct SubtitleTrackSummary<'a> {
+    id: &'a str,
+    name: &'a str,
+    language: Option<&'a str>,
+    asset_id: Option<&'a str>,
+}
+
+/// Entry returned by `get_effect_types`: the name `apply_effect` and
+/// `apply_transform` take, the range of the first parameter, which is what
+/// `apply_effect` sets, and each parameter's default. `parameter_specs`
+/// lists every parameter the type accepts with its range.
+#[derive(Serialize)]
+struct EffectTypeInfo {
+    name: &'static str,
+    transform: bool,
+    min: f64,
+    max: f64,
+    parameters: HashMap<String, f64>,
+    parameter_specs: &'static [EffectParameter],
+}
+
+/// Object `apply_transform` takes: the transform's `type` and any of its
+/// named parameters.
+#[derive(Deserialize)]
+struct TransformParams {
+    #[serde(rename = "type")]
+    transform_type: String,
+    #[serde(flatten)]
+    parameters: HashMap<String, f64>,
+}
+
+/// Summary of how far the local project is ahead of the server, for
+/// "syncing…" indicators.
+#[derive(Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+enum SyncStatus {
+    Synced,
+    Pending { pending: usize },
+    Reconnecting { pending: usize },
 }
 
 #[wasm_bindgen]
 impl WeframeClient {
+    /// Connects to `ws_url`. `avatar_url`, if given, is shown to other
+    /// collaborators next to this client's cursor and messages.
+    /// `auth_token` is the JWT to present to servers that require one; its
+    /// claims then decide the name and avatar others see.
     #[wasm_bindgen(constructor)]
-    pub fn new(ws_url: &str, client_id: &str, client_name: &str) -> Result<WeframeClient, JsValue> {
+    pub fn new(
+        ws_url: &str,
+        client_id: &str,
+        client_name: &str,
+        avatar_url: Option<String>,
+        auth_token: Option<String>,
+    ) -> Result<WeframeClient, JsValue> {
         console::log_1(&JsValue::from_str("Creating new WeframeClient"));
-        let ws = WebSocket::new(ws_url)?;
-        let project = Rc::new(RefCell::new(VideoProject::new(
+        if let Some(url) = &avatar_url {
+            validate_avatar_url(url).map_err(|e| JsValue::from_str(&e))?;
+        }
+        let mut project = VideoProject::new(
             uuid::Uuid::new_v4().to_string(),
             "New Project".to_string(),
             client_id.to_string(),
             client_name.to_string(),
-        )));
+        );
+        project.collaborators[0].avatar_url = avatar_url.clone();
+        let ws = WebSocket::new(ws_url)?;
+        let traffic = Rc::new(RefCell::new(TrafficStats::default()));
+        let callbacks = Rc::new(RefCell::new(Callbacks::default()));
+        let sync = Rc::new(RefCell::new(SyncState::new(project.clone())));
+        let connector = Rc::new(Connector {
+            ws_url: ws_url.to_string(),
+            transport: Rc::new(RefCell::new(Transport::WebSocket(ws.clone()))),
+            traffic: traffic.clone(),
+            callbacks: callbacks.clone(),
+            on_message: OnceCell::new(),
+            sync: sync.clone(),
+            ready: Cell::new(false),
+            queued: RefCell::new(Vec::new()),
+            sent_at: RefCell::new(HashMap::new()),
+            guest_token: guest_token(),
+            avatar_url,
+            auth_token,
+            state: Cell::new(ConnectionState::Connecting),
+            failed_attempts: Cell::new(0),
+            websocket_worked: Cell::new(false),
+            missed_heartbeats: Cell::new(0),
+            heartbeat_timer: Cell::new(None),
+            network: RefCell::new(None),
+        });
 
         let client = WeframeClient {
-            ws,
-            project,
+            connector,
+            sync,
+            project: Rc::new(RefCell::new(project)),
+            callbacks,
+            handshake: Rc::new(RefCell::new(Handshake::default())),
+            last_cursor: RefCell::new(None),
             client_id: client_id.to_string(),
             client_version: Rc::new(RefCell::new(0)),
+            traffic,
+            preview_solo: Rc::new(RefCell::new(None)),
+            local_layout: RefCell::new(Presentation::default()),
+            view_state: Rc::new(RefCell::new(None)),
+            history: Rc::new(RefCell::new(UndoHistory::default())),
+            chat: Rc::new(RefCell::new(Vec::new())),
+            label: RefCell::new(None),
+            batch: RefCell::new(None),
+            ids: RefCell::new(IdGenerator::Random),
+        };
+
+        client
+            .connector
+            .on_message
+            .set(client.message_handler())
+            .ok();
+        client.connector.watch_websocket(&ws);
+        client.connector.start_heartbeat()?;
+        Ok(client)
+    }
+
+    /// Handles the text of a message from the server, whichever transport
+    /// it came over.
+    fn message_handler(&self) -> MessageHandler {
+        let handshake = self.handshake.clone();
+        let project = self.project.clone();
+        let sync = self.sync.clone();
+        let callbacks = self.callbacks.clone();
+        let client_id = self.client_id.clone();
+        let preview_solo = self.preview_solo.clone();
+        let view_state = self.view_state.clone();
+        let history = self.history.clone();
+        let chat = self.chat.clone();
+        // Weak, as the connector owns this handler
+        let connector = Rc::downgrade(&self.connector);
+        Box::new(move |txt_string: &str| {
+            match serde_json::from_str::<ServerMessage>(txt_string) {
+                Ok(ServerMessage::ClientOperation(operation)) => {
+                    console::log_1(&JsValue::from_str(&format!(
+                        "Received operation: {:?}",
+                        operation
+                    )));
+                    {
+                        let mut sync = sync.borrow_mut();
+                        sync.confirm(&operation, &client_id);
+                        *project.borrow_mut() = sync.rebuild();
+                    }
+                    emit(&callbacks.borrow().operation, &operation);
+
+                    if let EditOperation::UpdateCollaboratorCursor {
+                        collaborator_id,
+                        new_position,
+                        velocity,
+                    } = &operation.operation
+                    {
+                        if *collaborator_id != client_id {
+                            let project = project.borrow();
+                            let collaborator = project
+                                .collaborators
+                                .iter()
+                                .find(|c| c.id == *collaborator_id);
+                            let update = CursorUpdate {
+                                collaborator_id: collaborator_id.clone(),
+                                name: collaborator
+                                    .map_or_else(|| collaborator_id.clone(), |c| c.name.clone()),
+                                avatar_url: collaborator.and_then(|c| c.avatar_url.clone()),
+                                color: collaborator
+                                    .map_or_else(|| String::from("gray"), |c| c.color()),
+                                track: new_position.track,
+                                time: new_position.time.as_secs_f64(),
+                                velocity: *velocity,
+                                selected_clip: new_position.selected_clip.clone(),
+                                hovered_clip: new_position.hovered_clip.clone(),
+                                tool: new_position.tool,
+                            };
+                            emit(&callbacks.borrow().cursor_update, &update);
+                        }
+                    } else {
+                        emit_project(&callbacks, &project);
+                    }
+                }
+                Ok(ServerMessage::OperationRejected {
+                    client_version,
+                    message,
+                    code,
+                }) => {
+                    history.borrow_mut().forget(client_version);
+                    let mut sync = sync.borrow_mut();
+                    if let Some(rejected) = sync.reject(client_version) {
+                        *project.borrow_mut() = sync.rebuild();
+                        drop(sync);
+                        emit_project(&callbacks, &project);
+                        if callbacks.borrow().operation_rejected.is_some() {
+                            let rejection = OperationRejection {
+                                client_version,
+                                code,
+                                message,
+                                operation: rejected.operation,
+                                recovery: code.recovery(),
+                            };
+                            emit(&callbacks.borrow().operation_rejected, &rejection);
+                        } else {
+                            console::warn_1(&JsValue::from_str(&format!(
+                                "Server rejected operation {:?}: {}",
+                                rejected.operation, message
+                            )));
+                        }
+                    }
+                }
+                Ok(ServerMessage::Welcome {
+                    protocol_version,
+                    features,
+                    client_id,
+                    capabilities,
+                    name,
+                    session_flags,
+                    role,
+                    server_version,
+                }) => {
+                    console::log_1(&JsValue::from_str(&format!(
+                        "Connected with protocol {} and features {:?}",
+                        protocol_version, features
+                    )));
+                    let mut handshake = handshake.borrow_mut();
+                    handshake.protocol_version = Some(protocol_version);
+                    handshake.features = features;
+                    handshake.server_client_id = Some(client_id);
+                    handshake.name = Some(name);
+                    handshake.capabilities = capabilities;
+                    handshake.role = role;
+                    handshake.server_version = server_version;
+                    // Still frozen, the server says so right after this
+                    if handshake.frozen.take().is_some() {
+                        emit(&callbacks.borrow().frozen, &FreezeNotice { reason: None });
+                    }
+                    // The server replays the chat after welcoming us
+                    chat.borrow_mut().clear();
+                    // Whatever was in flight on an earlier connection may
+                    // never have arrived; it goes out again once we're synced
+                    if let Some(connector) = connector.upgrade() {
+                        *connector.queued.borrow_mut() = sync
+                            .borrow()
+                            .pending
+                            .iter()
+                            .map(|op| op.client_version)
+                            .collect();
+                    }
+                    emit(&callbacks.borrow().session_flags, &session_flags);
+                    handshake.session_flags = session_flags;
+                }
+                Ok(ServerMessage::SessionFlags(flags)) => {
+                    emit(&callbacks.borrow().session_flags, &flags);
+                    handshake.borrow_mut().session_flags = flags;
+                }
+                Ok(ServerMessage::ProjectUpdate(update)) => {
+                    let mut sync = sync.borrow_mut();
+                    sync.load(update, handshake.borrow().server_version);
+                    *project.borrow_mut() = sync.rebuild();
+                    if let Some(connector) = connector.upgrade() {
+                        connector.flush(&mut sync);
+                    }
+                    drop(sync);
+                    emit_project(&callbacks, &project);
+                }
+                // The server replayed what we missed while disconnected;
+                // whatever is still pending never reached it, so send it
+                // again from where we are now
+                Ok(ServerMessage::Resumed { server_version }) => {
+                    let mut sync = sync.borrow_mut();
+                    sync.server_version = server_version;
+                    if let Some(connector) = connector.upgrade() {
+                        connector.flush(&mut sync);
+                    }
+                }
+                Ok(ServerMessage::StateChecksum {
+                    server_version,
+                    checksum,
+                }) => {
+                    let Some(actual) = sync.borrow().diverged(server_version, checksum) else {
+                        return;
+                    };
+                    console::warn_1(&JsValue::from_str(&format!(
+                        "Project diverged from the server at version {}, resyncing",
+                        server_version
+                    )));
+                    let resync = ServerMessage::Resync {
+                        server_version,
+                        checksum: actual,
+                    };
+                    if let Some(connector) = connector.upgrade() {
+                        if let Err(e) = connector.send(&serde_json::to_string(&resync).unwrap()) {
+                            console::error_1(&e);
+                        }
+                    }
+                    emit(
+                        &callbacks.borrow().divergence,
+                        &Divergence {
+                            server_version,
+                            expected: format!("{:016x}", checksum),
+                            actual: format!("{:016x}", actual),
+                        },
+                    );
+                }
+                Ok(ServerMessage::SyncBegin {
+                    server_version,
+                    project: skeleton,
+                }) => {
+                    let mut sync = sync.borrow_mut();
+                    sync.server_version = server_version;
+                    sync.incoming = Some(skeleton);
+                }
+                Ok(ServerMessage::SyncAssets(assets)) => {
+                    if let Some(incoming) = sync.borrow_mut().incoming.as_mut() {
+                        incoming.assets.extend(assets);
+                    }
+                }
+                Ok(ServerMessage::SyncClips(clips)) => {
+                    if let Some(incoming) = sync.borrow_mut().incoming.as_mut() {
+                        incoming.clips.extend(clips);
+                    }
+                }
+                Ok(ServerMessage::SyncComplete { server_version }) => {
+                    let mut sync = sync.borrow_mut();
+                    if let Some(update) = sync.incoming.take() {
+                        sync.load(update, server_version);
+                        *project.borrow_mut() = sync.rebuild();
+                        if let Some(connector) = connector.upgrade() {
+                            connector.flush(&mut sync);
+                        }
+                        drop(sync);
+                        emit_project(&callbacks, &project);
+                    }
+                }
+                Ok(ServerMessage::PreviewSolo {
+                    client_id: collaborator_id,
+                    clip_ids,
+                }) => {
+                    let solo = PreviewSolo {
+                        collaborator_id,
+                        clip_ids,
+                    };
+                    *preview_solo.borrow_mut() = (!solo.clip_ids.is_empty()).then(|| solo.clone());
+                    emit(&callbacks.borrow().preview_solo, &solo);
+                }
+                Ok(ServerMessage::Frozen { reason }) => {
+                    handshake.borrow_mut().frozen = reason.clone();
+                    emit(&callbacks.borrow().frozen, &FreezeNotice { reason });
+                }
+                Ok(ServerMessage::ViewState(state)) => {
+                    emit(&callbacks.borrow().view_state, &state);
+                    *view_state.borrow_mut() = Some(state);
+                }
+                Ok(ServerMessage::ImportProgress {
+                    import_id,
+                    url,
+                    status,
+                }) => {
+                    let update = ImportUpdate {
+                        import_id,
+                        url,
+                        status,
+                    };
+                    emit(&callbacks.borrow().import_progress, &update);
+                }
+                Ok(ServerMessage::JobFailed {
+                    job_id,
+                    kind,
+                    reason,
+                    message,
+                }) => {
+                    let failure = JobFailed {
+                        job_id,
+                        kind,
+                        reason,
+                        message,
+                    };
+                    emit(&callbacks.borrow().job_failed, &failure);
+                }
+                // The server refused our token; reconnecting won't help
+                Ok(ServerMessage::Error {
+                    message,
+                    code: Some(code),
+                    ..
+                }) => {
+                    console::error_1(&JsValue::from_str(&format!(
+                        "Server refused connection ({:?}): {}",
+                        code, message
+                    )));
+                    if let Some(connector) = connector.upgrade() {
+                        connector.close();
+                    }
+                }
+                Ok(ServerMessage::ChatMessage {
+                    client_id,
+                    message,
+                    name,
+                    sent_at,
+                }) => {
+                    let mut chat = chat.borrow_mut();
+                    chat.push(ChatLine {
+                        client_id,
+                        name,
+                        message,
+                        sent_at,
+                    });
+                    emit(&callbacks.borrow().chat, &*chat);
+                }
+                Ok(ServerMessage::Error { message, .. }) => {
+                    console::warn_1(&JsValue::from_str(&format!("Server error: {}", message)));
+                }
+                Ok(ServerMessage::NewClient {
+                    client_id,
+                    name,
+                    avatar_url,
+                    role,
+                }) => {
+                    let mut handshake = handshake.borrow_mut();
+                    if handshake.server_client_id.as_deref() == Some(client_id.as_str()) {
+                        handshake.role = role;
+                        return;
+                    }
+                    drop(handshake);
+                    emit(
+                        &callbacks.borrow().collaborator_joined,
+                        &CollaboratorJoined {
+                            client_id,
+                            name,
+                            avatar_url,
+                            role,
+                        },
+                    );
+                }
+                Ok(ServerMessage::ClientDisconnected(collaborator_id)) => {
+                    emit(
+                        &callbacks.borrow().collaborator_left,
+                        &CollaboratorLeft {
+                            client_id: collaborator_id.clone(),
+                        },
+                    );
+                    // A solo ends with the connection that started it
+                    let mut solo = preview_solo.borrow_mut();
+                    if solo
+                        .as_ref()
+                        .is_some_and(|s| s.collaborator_id == collaborator_id)
+                    {
+                        *solo = None;
+                        emit(
+                            &callbacks.borrow().preview_solo,
+                            &PreviewSolo {
+                                collaborator_id,
+                                clip_ids: Vec::new(),
+                            },
+                        );
+                    }
+                }
+                // Heartbeat replies only show the connection is alive, which
+                // the connector noted on receipt
+                Ok(ServerMessage::Pong(_)) => {}
+                Ok(other_message) => {
+                    console::log_1(&JsValue::from_str(&format!(
+                        "Received other message: {:?}",
+                        other_message
+                    )));
+                }
+                Err(e) => {
+                    console::error_1(&JsValue::from_str(&format!(
+                        "Failed to parse message: {:?}",
+                        e
+                    )));
+                }
+            }
+        })
+    }
+
+    fn validate(&self, operation: &EditOperation) -> Result<(), JsValue> {
+        self.project
+            .borrow()
+            .validate_operation(operation)
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    fn send_freeze(&self, reason: Option<String>) -> Result<(), JsValue> {
+        let message = serde_json::to_string(&ServerMessage::Frozen { reason })
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize freeze: {:?}", e)))?;
+        self.connector.send(&message)
+    }
+
+    /// Validates `operation`, sends it to the server and applies it
+    /// optimistically. It stays pending until the server echoes it back.
+    /// Edits that can be reverted are added to the undo history.
+    fn submit(&self, operation: EditOperation) -> Result<(), JsValue> {
+        if let Some(batch) = self.batch.borrow_mut().as_mut() {
+            let mut preview = self.project.borrow().clone();
+            for batched in batch.iter() {
+                preview.apply_operation(batched);
+            }
+            preview
+                .validate_operation(&operation)
+                .map_err(|e| JsValue::from_str(&e))?;
+            batch.push(operation);
+            return Ok(());
+        }
+        self.validate(&operation)?;
+        let inverse = operation.invert(&self.project.borrow());
+        let client_version = self.send_edit(operation)?;
+        if let Some(inverse) = inverse {
+            self.history.borrow_mut().record(client_version, inverse);
+        }
+        Ok(())
+    }
+
+    /// Sends an already validated operation and applies it optimistically,
+    /// returning its client version. While offline it is queued instead and
+    /// sent once the client reconnects.
+    fn send_edit(&self, operation: EditOperation) -> Result<usize, JsValue> {
+        // Cursor moves are neither part of an editing phase nor a change
+        // `on_project_changed` callbacks hear about
+        let cursor = matches!(operation, EditOperation::UpdateCollaboratorCursor { .. });
+        let label = if cursor {
+            None
+        } else {
+            self.label.borrow().clone()
         };
+        let operation = OTOperation {
+            client_id: self.client_id.clone(),
+            client_version: *self.client_version.borrow(),
+            server_version: self.sync.borrow().server_version,
+            operation,
+            label,
+        };
+
+        *self.client_version.borrow_mut() += 1;
+        self.connector.send_operation(&operation);
+
+        self.project
+            .borrow_mut()
+            .apply_operation(&operation.operation);
+        let client_version = operation.client_version;
+        self.sync.borrow_mut().pending.push(operation);
+        if !cursor {
+            emit_project(&self.callbacks, &self.project);
+        }
+        Ok(client_version)
+    }
+
+    /// Makes the ids of clips, effects, markers and transitions this client
+    /// creates from now on count up from `seed` rather than be random, so a
+    /// test or replay gets the same ids every run. Give each client in a
+    /// session its own seed.
+    #[wasm_bindgen]
+    pub fn use_sequential_ids(&self, seed: u32) {
+        *self.ids.borrow_mut() = IdGenerator::sequential(seed as u64);
+    }
+
+    /// Holds back the edits that follow until `commit_batch`, which sends
+    /// them as one operation that everyone sees applied, and that `undo`
+    /// reverts, all at once. Each edit is still checked when it is made,
+    /// against the project as the batch so far leaves it, but shows up in
+    /// the project only once the batch is committed.
+    #[wasm_bindgen]
+    pub fn begin_batch(&self) -> Result<(), JsValue> {
+        let mut batch = self.batch.borrow_mut();
+        if batch.is_some() {
+            return Err(JsValue::from_str("A batch is already open"));
+        }
+        *batch = Some(Vec::new());
+        Ok(())
+    }
+
+    /// Sends the edits made since `begin_batch` as one operation. Returns
+    /// whether there was anything to send.
+    #[wasm_bindgen]
+    pub fn commit_batch(&self) -> Result<bool, JsValue> {
+        let operations = self
+            .batch
+            .borrow_mut()
+            .take()
+            .ok_or_else(|| JsValue::from_str("No batch is open"))?;
+        if operations.is_empty() {
+            return Ok(false);
+        }
+        self.submit(EditOperation::Batch(operations))?;
+        Ok(true)
+    }
+
+    /// Drops the edits made since `begin_batch` without sending them.
+    #[wasm_bindgen]
+    pub fn cancel_batch(&self) {
+        self.batch.borrow_mut().take();
+    }
+
+    /// Reverts this user's most recent edit, as a new edit everyone sees.
+    /// Others' edits are left alone; an undo that no longer applies, e.g.
+    /// because someone else deleted the clip, is skipped in favour of the
+    /// one before it. Returns whether anything was undone.
+    #[wasm_bindgen]
+    pub fn undo(&self) -> Result<bool, JsValue> {
+        self.step_history(false)
+    }
+
+    /// Reapplies the edit last reverted by `undo`. Any new edit clears what
+    /// there is to redo. Returns whether anything was redone.
+    #[wasm_bindgen]
+    pub fn redo(&self) -> Result<bool, JsValue> {
+        self.step_history(true)
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn can_undo(&self) -> bool {
+        !self.history.borrow().undo.is_empty()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn can_redo(&self) -> bool {
+        !self.history.borrow().redo.is_empty()
+    }
+
+    /// Submits the top of the undo (or redo) stack, moving its own inverse
+    /// onto the other stack.
+    fn step_history(&self, redo: bool) -> Result<bool, JsValue> {
+        loop {
+            let entry = {
+                let mut history = self.history.borrow_mut();
+                if redo {
+                    history.redo.pop()
+                } else {
+                    history.undo.pop()
+                }
+            };
+            let Some((_, operation)) = entry else {
+                return Ok(false);
+            };
+            if self.validate(&operation).is_err() {
+                continue;
+            }
+            let inverse = operation.invert(&self.project.borrow());
+            let client_version = self.send_edit(operation)?;
+            if let Some(inverse) = inverse {
+                let mut history = self.history.borrow_mut();
+                let other = if redo {
+                    &mut history.undo
+                } else {
+                    &mut history.redo
+                };
+                other.push((client_version, inverse));
+            }
+            return Ok(true);
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn get_project(&self) -> Result<JsValue, JsValue> {
+        let project = self.project.borrow();
+        to_value(&*project).map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
+    }
+
+    /// The project flattened for simple exporters such as EDL writers.
+    #[wasm_bindgen]
+    pub fn get_flat_project(&self) -> Result<JsValue, JsValue> {
+        let project = self.project.borrow().flatten();
+        to_value(&project).map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
+    }
+
+    /// Registers `callback` to receive `{ collaborator_id, name, avatar_url,
+    /// color, track, time }` whenever another collaborator moves their cursor.
+    #[wasm_bindgen]
+    pub fn on_cursor_update(&self, callback: js_sys::Function) {
+        self.callbacks.borrow_mut().cursor_update = Some(callback);
+    }
+
+    /// Registers `callback` to receive `{ collaborator_id, clip_ids }` when
+    /// another collaborator solos clips in everyone's preview. `clip_ids` is
+    /// empty when the solo ends.
+    #[wasm_bindgen]
+    pub fn on_preview_solo(&self, callback: js_sys::Function) {
+        self.callbacks.borrow_mut().preview_solo = Some(callback);
+    }
+
+    /// Asks other collaborators' previews to show only `clip_ids`, without
+    /// changing the project. An empty list ends the solo.
+    #[wasm_bindgen]
+    pub fn solo_preview(&self, clip_ids: Vec<String>) -> Result<(), JsValue> {
+        let message = ServerMessage::PreviewSolo {
+            client_id: self.client_id.clone(),
+            clip_ids,
+        };
+        let message = serde_json::to_string(&message)
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize solo: {:?}", e)))?;
+        self.connector.send(&message)
+    }
+
+    /// The solo currently asked of this preview as `{ collaborator_id,
+    /// clip_ids }`, or `null`.
+    #[wasm_bindgen]
+    pub fn get_preview_solo(&self) -> Result<JsValue, JsValue> {
+        to_value(&*self.preview_solo.borrow())
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
+    }
+
+    /// Registers `callback` to receive this user's saved `{ zoom,
+    /// scroll_time, scroll_track, local_markers }` when the server restores
+    /// it after connecting.
+    #[wasm_bindgen]
+    pub fn on_view_state(&self, callback: js_sys::Function) {
+        self.callbacks.borrow_mut().view_state = Some(callback);
+    }
+
+    /// Stores this user's private view state on the server, to be restored
+    /// next time they join the session. Nobody else sees it.
+    #[wasm_bindgen]
+    pub fn save_view_state(&self, state: JsValue) -> Result<(), JsValue> {
+        let state: ViewState = serde_wasm_bindgen::from_value(state)
+            .map_err(|e| JsValue::from_str(&format!("Invalid view state: {}", e)))?;
+        validate_view_state(&state).map_err(|e| JsValue::from_str(&e))?;
+        let message = serde_json::to_string(&ServerMessage::SaveViewState(state.clone()))
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize view state: {:?}", e)))?;
+        self.connector.send(&message)?;
+        *self.view_state.borrow_mut() = Some(state);
+        Ok(())
+    }
+
+    /// This user's view state as last saved or restored, or `null`.
+    #[wasm_bindgen]
+    pub fn get_view_state(&self) -> Result<JsValue, JsValue> {
+        to_value(&*self.view_state.borrow())
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
+    }
+
+    /// Protocol version negotiated with the server, or `undefined` before the
+    /// handshake completes.
+    #[wasm_bindgen(getter)]
+    pub fn protocol_version(&self) -> Option<u32> {
+        self.handshake.borrow().protocol_version
+    }
+
+    /// Name the server knows this client by, e.g. a generated guest name
+    /// like "Amber Otter". `undefined` before the handshake completes.
+    #[wasm_bindgen(getter)]
+    pub fn name(&self) -> Option<String> {
+        self.handshake.borrow().name.clone()
+    }
+
+    /// Optional protocol features both sides support.
+    #[wasm_bindgen(getter)]
+    pub fn features(&self) -> Vec<String> {
+        self.handshake.borrow().features.clone()
+    }
+
+    /// Optional services the server offers, e.g. `{ asset_uploads: true, ... }`.
+    /// All false until the handshake completes.
+    #[wasm_bindgen(getter)]
+    pub fn capabilities(&self) -> Result<JsValue, JsValue> {
+        to_value(&self.handshake.borrow().capabilities)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Integrity issues in the project as this client sees it, e.g. clips
+    /// ending before they start or transitions longer than their clip, as
+    /// `[{ kind, ... }]`. Empty when the project is sound.
+    #[wasm_bindgen]
+    pub fn validate_project(&self) -> Result<JsValue, JsValue> {
+        to_value(&self.project.borrow().validate())
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
+    }
+
+    /// Labels this user's edits from now on with what they are doing, e.g.
+    /// "rough cut pass", so the session's history can be browsed by editing
+    /// phase. `None` stops labeling them.
+    #[wasm_bindgen]
+    pub fn set_intent_label(&self, label: Option<String>) -> Result<(), JsValue> {
+        if let Some(label) = &label {
+            validate_label(label).map_err(|e| JsValue::from_str(&e))?;
+        }
+        *self.label.borrow_mut() = label;
+        Ok(())
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn intent_label(&self) -> Option<String> {
+        self.label.borrow().clone()
+    }
+
+    /// Posts `message` to the session's chat. It shows up through
+    /// `on_chat` once the server has relayed it.
+    #[wasm_bindgen]
+    pub fn send_chat(&self, message: &str) -> Result<(), JsValue> {
+        validate_chat_message(message).map_err(|e| JsValue::from_str(&e))?;
+        let handshake = self.handshake.borrow();
+        if handshake.protocol_version.is_some()
+            && !handshake.session_flags.iter().any(|f| f == "chat")
+        {
+            return Err(JsValue::from_str("Chat is not enabled in this session"));
+        }
+        let message = serde_json::to_string(&ServerMessage::ChatMessage {
+            client_id: self.client_id.clone(),
+            message: message.to_string(),
+            name: String::new(),
+            sent_at: 0,
+        })
+        .map_err(|e| JsValue::from_str(&format!("Failed to serialize chat message: {:?}", e)))?;
+        self.connector.send(&message)
+    }
+
+    /// Registers `callback` to receive the whole chat, as an array of
+    /// `{ client_id, name, message, sent_at }` oldest first, whenever a
+    /// message arrives, including history replayed on joining.
+    #[wasm_bindgen]
+    pub fn on_chat(&self, callback: js_sys::Function) {
+        self.callbacks.borrow_mut().chat = Some(callback);
+    }
+
+    #[wasm_bindgen]
+    pub fn get_chat_history(&self) -> Result<JsValue, JsValue> {
+        to_value(&*self.chat.borrow())
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
+    }
+
+    /// What this client may do: `"viewer"`, `"editor"` or `"owner"`.
+    /// `"editor"` until the handshake completes.
+    #[wasm_bindgen(getter)]
+    pub fn role(&self) -> String {
+        serde_json::to_value(self.handshake.borrow().role)
+            .ok()
+            .and_then(|role| role.as_str().map(String::from))
+            .unwrap_or_default()
+    }
+
+    /// Flags enabled in the session, e.g. `["chat", "magnetic_timeline"]`.
+    /// Empty until the handshake completes.
+    #[wasm_bindgen(getter)]
+    pub fn session_flags(&self) -> Vec<String> {
+        self.handshake.borrow().session_flags.clone()
+    }
+
+    #[wasm_bindgen]
+    pub fn has_session_flag(&self, flag: &str) -> bool {
+        self.handshake
+            .borrow()
+            .session_flags
+            .iter()
+            .any(|f| f == flag)
+    }
+
+    /// Registers `callback` to receive every operation the server commits,
+    /// this client's own included, as `{ client_id, client_version,
+    /// server_version, operation, label }`.
+    #[wasm_bindgen]
+    pub fn on_operation(&self, callback: js_sys::Function) {
+        self.callbacks.borrow_mut().operation = Some(callback);
+    }
+
+    /// Registers `callback` to receive `{ client_id, name, avatar_url, role }`
+    /// when another collaborator joins, and again when their name, avatar
+    /// or role changes.
+    #[wasm_bindgen]
+    pub fn on_collaborator_joined(&self, callback: js_sys::Function) {
+        self.callbacks.borrow_mut().collaborator_joined = Some(callback);
+    }
+
+    /// Registers `callback` to receive `{ client_id }` when a collaborator
+    /// disconnects.
+    #[wasm_bindgen]
+    pub fn on_collaborator_left(&self, callback: js_sys::Function) {
+        self.callbacks.borrow_mut().collaborator_left = Some(callback);
+    }
+
+    /// Registers `callback` to receive the whole project, as `get_project`
+    /// returns it, whenever it changes: through this user's edits, others'
+    /// or a sync. Cursor moves go to `on_cursor_update` instead.
+    #[wasm_bindgen]
+    pub fn on_project_changed(&self, callback: js_sys::Function) {
+        self.callbacks.borrow_mut().project_changed = Some(callback);
+    }
+
+    /// Registers `callback` to receive `{ server_version, expected, actual }`
+    /// whenever the project is found to have diverged from the server's and
+    /// a resync is requested, for telemetry.
+    #[wasm_bindgen]
+    pub fn on_divergence(&self, callback: js_sys::Function) {
+        self.callbacks.borrow_mut().divergence = Some(callback);
+    }
+
+    /// Registers `callback` to follow bulk asset imports into this session:
+    /// it gets `{import_id, url, status}` as each URL downloads, is added as
+    /// an asset or fails.
+    #[wasm_bindgen]
+    pub fn on_import_progress(&self, callback: js_sys::Function) {
+        self.callbacks.borrow_mut().import_progress = Some(callback);
+    }
+
+    /// Registers `callback` to hear when a render, import or transcription
+    /// running on the server for this session fails. It gets
+    /// `{job_id, kind, reason, message}`, where `reason` is `bad_codec`,
+    /// `missing_file`, `out_of_disk`, `unreachable` or `other`.
+    #[wasm_bindgen]
+    pub fn on_job_failed(&self, callback: js_sys::Function) {
+        self.callbacks.borrow_mut().job_failed = Some(callback);
+    }
+
+    /// Registers `callback` to receive `{ reason }` when the session is
+    /// frozen for maintenance, to show as a banner; edits are refused until
+    /// it gets `{ reason: null }`, after which the server resyncs the
+    /// project.
+    #[wasm_bindgen]
+    pub fn on_frozen(&self, callback: js_sys::Function) {
+        self.callbacks.borrow_mut().frozen = Some(callback);
+    }
+
+    /// Why the session is frozen for maintenance, or `None` if it isn't.
+    #[wasm_bindgen]
+    pub fn get_frozen(&self) -> Option<String> {
+        self.handshake.borrow().frozen.clone()
+    }
+
+    /// Freezes the session for maintenance, refusing everyone's edits until
+    /// `unfreeze_session`. Only the session's owner may.
+    #[wasm_bindgen]
+    pub fn freeze_session(&self, reason: &str) -> Result<(), JsValue> {
+        self.send_freeze(Some(reason.to_string()))
+    }
+
+    #[wasm_bindgen]
+    pub fn unfreeze_session(&self) -> Result<(), JsValue> {
+        self.send_freeze(None)
+    }
+
+    /// Registers `callback` to hear when the server rejects one of our
+    /// operations, after it has been rolled back locally. It gets
+    /// `{client_version, code, message, operation, recovery}`, where `code`
+    /// is `forbidden`, `invalid`, `conflict`, `over_limit` or `frozen` and
+    /// `recovery` suggests what to do: `request_access`, `revise`, `retry`,
+    /// `free_space` or `wait`. Without one, rejections are logged to the
+    /// console.
+    #[wasm_bindgen]
+    pub fn on_operation_rejected(&self, callback: js_sys::Function) {
+        self.callbacks.borrow_mut().operation_rejected = Some(callback);
+    }
+
+    /// Registers `callback` to receive the session's flags when joining and
+    /// whenever they change.
+    #[wasm_bindgen]
+    pub fn on_session_flags(&self, callback: js_sys::Function) {
+        self.callbacks.borrow_mut().session_flags = Some(callback);
+    }
+
+    /// Messages and bytes this client has sent and received over its
+    /// WebSocket, for diagnosing slow connections.
+    #[wasm_bindgen]
+    pub fn get_connection_stats(&self) -> Result<JsValue, JsValue> {
+        to_value(&*self.traffic.borrow())
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
+    }
+
+    /// `"websocket"`, or `"event_source"` once the client has fallen back to
+    /// server-sent events because WebSockets kept failing.
+    #[wasm_bindgen(getter)]
+    pub fn transport(&self) -> String {
+        match &*self.connector.transport.borrow() {
+            Transport::WebSocket(_) => "websocket".to_string(),
+            Transport::EventSource { .. } => "event_source".to_string(),
+        }
+    }
+
+    /// Puts the connection through a simulated bad network, for trying the
+    /// UI against one: `{ latency_ms, jitter_ms, drop_rate, reorder_rate }`,
+    /// each optional, applied to messages both ways. Lost messages stay
+    /// lost, as on a connection that silently drops them. `null` goes back
+    /// to the real network.
+    #[wasm_bindgen]
+    pub fn set_network_conditions(&self, conditions: JsValue) -> Result<(), JsValue> {
+        let network = if conditions.is_null() || conditions.is_undefined() {
+            None
+        } else {
+            let conditions: NetworkConditions = serde_wasm_bindgen::from_value(conditions)
+                .map_err(|e| JsValue::from_str(&format!("Invalid network conditions: {}", e)))?;
+            conditions.validate().map_err(|e| JsValue::from_str(&e))?;
+            Some(SimulatedNetwork {
+                outgoing: NetworkSimulator::new(conditions),
+                incoming: NetworkSimulator::new(conditions),
+            })
+        };
+        *self.connector.network.borrow_mut() = network;
+        Ok(())
+    }
+
+    /// The simulated network conditions in effect, or `null`.
+    #[wasm_bindgen]
+    pub fn get_network_conditions(&self) -> Result<JsValue, JsValue> {
+        let conditions = self
+            .connector
+            .network
+            .borrow()
+            .as_ref()
+            .map(|network| network.outgoing.conditions());
+        to_value(&conditions)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
+    }
+
+    /// `"connecting"`, `"open"`, `"degraded"` while heartbeats go
+    /// unanswered, `"reconnecting"` or `"closed"`.
+    #[wasm_bindgen(getter)]
+    pub fn connection_state(&self) -> String {
+        self.connector.state.get().as_str().to_string()
+    }
+
+    /// Registers `callback` to receive `{ state, previous }` whenever the
+    /// connection state changes.
+    #[wasm_bindgen]
+    pub fn on_connection_state(&self, callback: js_sys::Function) {
+        self.callbacks.borrow_mut().connection_state = Some(callback);
+    }
+
+    /// Disconnects from the server for good; the client stops reconnecting.
+    #[wasm_bindgen]
+    pub fn close(&self) {
+        self.connector.close();
+    }
+
+    #[wasm_bindgen]
+    pub fn get_pending_ops(&self) -> Result<JsValue, JsValue> {
+        let sync = self.sync.borrow();
+        to_value(&sync.pending)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
+    }
+
+    /// Edits made while offline, or before the connection synced, that
+    /// haven't been sent yet, e.g. for an "N unsynced changes" indicator.
+    #[wasm_bindgen(getter)]
+    pub fn queued_ops(&self) -> usize {
+        self.connector.queued.borrow().len()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn sync_state(&self) -> Result<JsValue, JsValue> {
+        let pending = self.sync.borrow().pending.len();
+        let status = if !self.connector.state.get().is_connected() {
+            SyncStatus::Reconnecting { pending }
+        } else if pending > 0 {
+            SyncStatus::Pending { pending }
+        } else {
+            SyncStatus::Synced
+        };
+        to_value(&status).map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
+    }
+
+    #[wasm_bindgen]
+    pub fn update_cursor_position(&self, track: usize, time: f64) -> Result<(), JsValue> {
+        let previous = self.last_cursor_position();
+        let new_position = CursorPosition {
+            track,
+            time: seconds_to_duration("time", time)?,
+            ..previous
+        };
+        self.send_cursor(new_position, true)
+    }
+
+    /// Shares what this user is doing along with where they are: the
+    /// selected and hovered clip ids and the active tool name.
+    #[wasm_bindgen]
+    pub fn set_cursor_context(
+        &self,
+        selected_clip: Option<String>,
+        hovered_clip: Option<String>,
+        tool: Option<String>,
+    ) -> Result<(), JsValue> {
+        let tool = match tool.as_deref() {
+            None => None,
+            Some("select") => Some(EditTool::Select),
+            Some("trim") => Some(EditTool::Trim),
+            Some("razor") => Some(EditTool::Razor),
+            Some("slip") => Some(EditTool::Slip),
+            Some("hand") => Some(EditTool::Hand),
+            Some(_) => return Err(JsValue::from_str("Unsupported tool")),
+        };
+        let previous = self.last_cursor_position();
+        self.send_cursor(
+            CursorPosition {
+                selected_clip,
+                hovered_clip,
+                tool,
+                ..previous
+            },
+            false,
+        )
+    }
+
+    fn last_cursor_position(&self) -> CursorPosition {
+        self.last_cursor
+            .borrow()
+            .as_ref()
+            .map(|sample| sample.position.clone())
+            .unwrap_or_default()
+    }
+
+    /// Sends the cursor at `new_position`. Unless it `moved`, the velocity
+    /// measured at the last move is sent again, so a context change doesn't
+    /// stop the cursor for others extrapolating its motion.
+    fn send_cursor(&self, new_position: CursorPosition, moved: bool) -> Result<(), JsValue> {
+        let now = js_sys::Date::now();
+        let last = self.last_cursor.borrow_mut().take();
+        let (moved_at, velocity) = match last {
+            // A cursor that stopped a while ago isn't moving any more, by
+            // the same second `cursor_velocity` allows between samples
+            Some(last) if !moved => (
+                last.moved_at,
+                last.velocity.filter(|_| now - last.moved_at <= 1000.0),
+            ),
+            Some(last) => (
+                now,
+                cursor_velocity(
+                    &last.position,
+                    &new_position,
+                    (now - last.moved_at) / 1000.0,
+                ),
+            ),
+            None => (now, None),
+        };
+        *self.last_cursor.borrow_mut() = Some(CursorSample {
+            position: new_position.clone(),
+            moved_at,
+            velocity,
+        });
+
+        self.submit(EditOperation::UpdateCollaboratorCursor {
+            collaborator_id: self.client_id.clone(),
+            new_position,
+            velocity,
+        })
+        .map_err(|e| {
+            JsValue::from_str(&format!(
+                "Failed to send update_cursor_position operation: {:?}",
+                e
+            ))
+        })
+    }
+
+    #[wasm_bindgen]
+    pub fn move_clip(
+        &self,
+        clip_id: &str,
+        new_start_time: f64,
+        new_track: usize,
+    ) -> Result<(), JsValue> {
+        console::log_1(&JsValue::from_str(&format!(
+            "Moving clip {} to start time {} and track {}",
+            clip_id, new_start_time, new_track
+        )));
+        self.submit(EditOperation::MoveClip {
+            id: clip_id.to_string(),
+            new_start_time: seconds_to_duration("new_start_time", new_start_time)?,
+            new_track,
+        })
+    }
+
+    /// Moves a clip to the trash, from where `restore_clip` brings it back.
+    /// Fails without sending anything if no clip has that id.
+    #[wasm_bindgen]
+    pub fn remove_clip(&self, clip_id: &str) -> Result<(), JsValue> {
+        self.submit(EditOperation::RemoveClip(clip_id.to_string()))
+    }
+
+    /// Sets a clip's speed curve: `speeds[i]` applies `offsets[i]` seconds
+    /// into the clip, easing linearly between keyframes. Empty arrays return
+    /// the clip to normal speed.
+    #[wasm_bindgen]
+    pub fn set_clip_speed(
+        &self,
+        clip_id: &str,
+        offsets: Vec<f64>,
+        speeds: Vec<f64>,
+    ) -> Result<(), JsValue> {
+        if offsets.len() != speeds.len() {
+            return Err(JsValue::from_str("Every speed keyframe needs an offset"));
+        }
+        let keyframes = offsets
+            .into_iter()
+            .zip(speeds)
+            .map(|(offset, speed)| {
+                Ok(SpeedKeyframe {
+                    offset: seconds_to_duration("offset", offset)?,
+                    speed,
+                })
+            })
+            .collect::<Result<_, JsValue>>()?;
+        self.submit(EditOperation::SetClipSpeed {
+            clip_id: clip_id.to_string(),
+            keyframes,
+        })
+    }
+
+    /// Sets a clip's audio gain in dB and its fade in and out in seconds.
+    #[wasm_bindgen]
+    pub fn set_clip_audio(
+        &self,
+        clip_id: &str,
+        gain_db: f64,
+        fade_in: f64,
+        fade_out: f64,
+    ) -> Result<(), JsValue> {
+        self.submit(EditOperation::SetClipAudio {
+            clip_id: clip_id.to_string(),
+            audio: ClipAudio {
+                gain_db,
+                fade_in: seconds_to_duration("fade_in", fade_in)?,
+                fade_out: seconds_to_duration("fade_out", fade_out)?,
+            },
+        })
+    }
+
+    /// Hides a clip from preview and export without removing it, or brings
+    /// it back.
+    #[wasm_bindgen]
+    pub fn set_clip_enabled(&self, clip_id: &str, enabled: bool) -> Result<(), JsValue> {
+        self.submit(EditOperation::SetClipEnabled {
+            clip_id: clip_id.to_string(),
+            enabled,
+        })
+    }
+
+    /// Leaves a track out of the audio mix, or puts it back.
+    #[wasm_bindgen]
+    pub fn set_track_muted(&self, track: usize, muted: bool) -> Result<(), JsValue> {
+        self.submit(EditOperation::SetTrackMuted { track, muted })
+    }
+
+    /// Raises or lowers everything on a track by `gain_db`, on top of each
+    /// clip's own gain.
+    #[wasm_bindgen]
+    pub fn set_track_gain(&self, track: usize, gain_db: f64) -> Result<(), JsValue> {
+        self.submit(EditOperation::SetTrackGain { track, gain_db })
+    }
+
+    /// Seconds into its media that a clip shows at timeline `time`, for
+    /// seeking previews of remapped clips.
+    #[wasm_bindgen]
+    pub fn source_time_at(&self, clip_id: &str, time: f64) -> Result<f64, JsValue> {
+        let time = seconds_to_duration("time", time)?;
+        self.project
+            .borrow()
+            .clips
+            .iter()
+            .find(|c| c.id == clip_id)
+            .map(|c| c.source_time_at(time).as_secs_f64())
+            .ok_or_else(|| JsValue::from_str("Clip not found"))
+    }
+
+    #[wasm_bindgen]
+    pub fn resize_clip(&self, clip_id: &str, new_end_time: f64) -> Result<(), JsValue> {
+        let new_end_time = seconds_to_duration("new_end_time", new_end_time)?;
+        let new_start_time = self
+            .project
+            .borrow()
+            .clips
+            .iter()
+            .find(|c| c.id == clip_id)
+            .map(|c| c.start_time)
+            .ok_or_else(|| JsValue::from_str("Clip not found"))?;
+
+        self.submit(EditOperation::TrimClip {
+            id: clip_id.to_string(),
+            new_start_time,
+            new_end_time,
+        })
+    }
+
+    /// Cuts a clip in two at `time` seconds on the timeline and returns the
+    /// id of the clip playing the part after the cut.
+    #[wasm_bindgen]
+    pub fn split_clip(&self, clip_id: &str, time: f64) -> Result<String, JsValue> {
+        let new_clip_id = self.ids.borrow().prefixed("clip");
+        self.submit(EditOperation::SplitClip {
+            id: clip_id.to_string(),
+            split_time: seconds_to_duration("time", time)?,
+            new_clip_id: new_clip_id.clone(),
+        })?;
+        Ok(new_clip_id)
+    }
+
+    /// Swaps a clip's media for another project asset, e.g. to replace
+    /// placeholder footage with the final cut.
+    #[wasm_bindgen]
+    pub fn replace_clip_source(&self, clip_id: &str, new_asset_id: &str) -> Result<(), JsValue> {
+        self.submit(EditOperation::ReplaceClipSource {
+            clip_id: clip_id.to_string(),
+            new_asset_id: new_asset_id.to_string(),
+        })
+    }
+
+    /// Points every clip playing `old` at another asset. `old` is matched
+    /// as an asset id first, then as a source URI.
+    #[wasm_bindgen]
+    pub fn relink_asset(&self, old: &str, new_asset_id: &str) -> Result<(), JsValue> {
+        let from = if self.project.borrow().assets.iter().any(|a| a.id == old) {
+            MediaReference::AssetId(old.to_string())
+        } else {
+            MediaReference::Uri(old.to_string())
+        };
+        self.submit(EditOperation::RelinkAsset {
+            from,
+            new_asset_id: new_asset_id.to_string(),
+        })
+    }
+
+    #[wasm_bindgen]
+    pub fn add_clip(
+        &self,
+        start_time: f64,
+        end_time: f64,
+        track: usize,
+        source_file: &str,
+    ) -> Result<(), JsValue> {
+        let clip_id = self.ids.borrow().prefixed("clip");
+        self.submit(EditOperation::AddClip(VideoClip {
+            id: clip_id,
+            source_file: source_file.to_string(),
+            start_time: seconds_to_duration("start_time", start_time)?,
+            end_time: seconds_to_duration("end_time", end_time)?,
+            track,
+            ..Default::default()
+        }))
+    }
+
+    /// Adds a still image shown for `duration` seconds, or for 5 seconds
+    /// when `duration` is left out or 0. Returns the new clip's id.
+    #[wasm_bindgen]
+    pub fn add_image_clip(
+        &self,
+        start_time: f64,
+        duration: Option<f64>,
+        track: usize,
+        source_file: &str,
+    ) -> Result<String, JsValue> {
+        let clip_id = self.ids.borrow().prefixed("clip");
+        let start_time = seconds_to_duration("start_time", start_time)?;
+        let duration = seconds_to_duration("duration", duration.unwrap_or(0.0))?;
+        self.submit(EditOperation::AddClip(VideoClip {
+            id: clip_id.clone(),
+            source_file: source_file.to_string(),
+            start_time,
+            end_time: start_time + duration,
+            track,
+            source_kind: SourceKind::Image,
+            ..Default::default()
+        }))?;
+        Ok(clip_id)
+    }
+
+    /// Adds a clip that is only heard, e.g. music or a voice-over. Audio clips
+    /// can't share a track with video clips.
+    #[wasm_bindgen]
+    pub fn add_audio_clip(
+        &self,
+        start_time: f64,
+        end_time: f64,
+        track: usize,
+        source_file: &str,
+    ) -> Result<(), JsValue> {
+        let clip_id = self.ids.borrow().prefixed("clip");
+        self.submit(EditOperation::AddClip(VideoClip {
+            id: clip_id,
+            source_file: source_file.to_string(),
+            start_time: seconds_to_duration("start_time", start_time)?,
+            end_time: seconds_to_duration("end_time", end_time)?,
+            track,
+            kind: ClipKind::Audio,
+            ..Default::default()
+        }))
+    }
+
+    /// Adds a title or other text laid over the picture. `text` is
+    /// `{ content, font, size, color, x, y }`: `size` is the line height as
+    /// a fraction of the frame's height, `color` is `#rrggbb` or
+    /// `#rrggbbaa` and `x`, `y` place the middle of the text as fractions of
+    /// the frame. Text clips can't share a track with other kinds. Returns
+    /// the new clip's id.
+    #[wasm_bindgen]
+    pub fn add_text_clip(
+        &self,
+        start_time: f64,
+        end_time: f64,
+        track: usize,
+        text: JsValue,
+    ) -> Result<String, JsValue> {
+        let clip_id = self.ids.borrow().prefixed("clip");
+        self.submit(EditOperation::AddTextClip(VideoClip {
+            id: clip_id.clone(),
+            start_time: seconds_to_duration("start_time", start_time)?,
+            end_time: seconds_to_duration("end_time", end_time)?,
+            track,
+            kind: ClipKind::Text,
+            text: Some(parse_text(text)?),
+            ..Default::default()
+        }))?;
+        Ok(clip_id)
+    }
+
+    /// Changes what a text clip shows or how; `text` is as for
+    /// `add_text_clip`.
+    #[wasm_bindgen]
+    pub fn update_text_clip(&self, clip_id: &str, text: JsValue) -> Result<(), JsValue> {
+        self.submit(EditOperation::UpdateTextClip {
+            clip_id: clip_id.to_string(),
+            text: parse_text(text)?,
+        })
+    }
+
+    /// Groups synced recordings into a multicam group. `offsets[i]` is the
+    /// time in seconds on the shared clock where `asset_ids[i]` starts.
+    #[wasm_bindgen]
+    pub fn add_multicam_group(
+        &self,
+        name: &str,
+        asset_ids: Vec<String>,
+        offsets: Vec<f64>,
+    ) -> Result<(), JsValue> {
+        if asset_ids.len() != offsets.len() {
+            return Err(JsValue::from_str("Every angle needs an offset"));
+        }
+        let angles = asset_ids
+            .into_iter()
+            .zip(offsets)
+            .map(|(asset_id, offset)| {
+                Ok(MulticamAngle {
+                    asset_id,
+                    offset: seconds_to_duration("offset", offset)?,
+                })
+            })
+            .collect::<Result<_, JsValue>>()?;
+        self.submit(EditOperation::AddMulticamGroup(MulticamGroup {
+            id: self.ids.borrow().prefixed("multicam"),
+            name: name.to_string(),
+            angles,
+            active_angle: 0,
+        }))
+    }
+
+    /// Adds a clip playing a multicam group's active angle, in sync with
+    /// the group's clock from `group_time` seconds.
+    #[wasm_bindgen]
+    pub fn add_multicam_clip(
+        &self,
+        group_id: &str,
+        start_time: f64,
+        end_time: f64,
+        track: usize,
+        group_time: f64,
+    ) -> Result<(), JsValue> {
+        let project = self.project.borrow();
+        let group = project
+            .multicam_groups
+            .iter()
+            .find(|g| g.id == group_id)
+            .ok_or_else(|| JsValue::from_str("Multicam group not found"))?;
+        let angle = group
+            .angles
+            .get(group.active_angle)
+            .ok_or_else(|| JsValue::from_str("Multicam group has no active angle"))?;
+        let asset = project
+            .assets
+            .iter()
+            .find(|a| a.id == angle.asset_id)
+            .ok_or_else(|| JsValue::from_str("Asset not found"))?;
+        let clip = VideoClip {
+            id: self.ids.borrow().prefixed("clip"),
+            source_file: asset.uri.clone(),
+            asset_id: Some(asset.id.clone()),
+            start_time: seconds_to_duration("start_time", start_time)?,
+            end_time: seconds_to_duration("end_time", end_time)?,
+            source_start: seconds_to_duration("group_time", group_time)?
+                .saturating_sub(angle.offset),
+            track,
+            multicam: Some(MulticamRef {
+                group_id: group_id.to_string(),
+                angle: group.active_angle,
+            }),
+            ..Default::default()
+        };
+        drop(project);
+        self.submit(EditOperation::AddClip(clip))
+    }
+
+    /// Cuts a multicam group's clip to `angle` at `at_time` seconds.
+    #[wasm_bindgen]
+    pub fn switch_angle(&self, group_id: &str, angle: usize, at_time: f64) -> Result<(), JsValue> {
+        self.submit(EditOperation::SwitchAngle {
+            group_id: group_id.to_string(),
+            angle,
+            at_time: seconds_to_duration("at_time", at_time)?,
+        })
+    }
+
+    /// Adds an effect of one of the types `get_effect_types` lists to a
+    /// clip, with `value` as its first parameter or, if left out, the type's
+    /// default.
+    #[wasm_bindgen]
+    pub fn apply_effect(
+        &self,
+        clip_id: &str,
+        effect_type: &str,
+        value: Option<f64>,
+    ) -> Result<(), JsValue> {
+        console::log_1(&JsValue::from_str(&format!(
+            "Applying effect: {} with value {:?} to clip {}",
+            effect_type, value, clip_id
+        )));
+
+        self.submit(EditOperation::AddEffect {
+            clip_id: clip_id.to_string(),
+            effect: Effect::new(&self.ids.borrow(), parse_effect_type(effect_type)?, value),
+        })
+        .map_err(|e| JsValue::from_str(&format!("Failed to send apply_effect operation: {:?}", e)))
+    }
 
-        client.setup_ws_handlers();
-        Ok(client)
+    /// Crops, scales, rotates or moves a clip's picture, given e.g.
+    /// `{ type: "crop", x: 0.1, y: 0, width: 0.8, height: 1 }` or
+    /// `{ type: "rotate", angle: 90 }`. Parameters left out keep their
+    /// defaults; `get_effect_types` lists each transform's.
+    #[wasm_bindgen]
+    pub fn apply_transform(&self, clip_id: &str, params: JsValue) -> Result<(), JsValue> {
+        let params: TransformParams = serde_wasm_bindgen::from_value(params)
+            .map_err(|e| JsValue::from_str(&format!("Invalid transform: {}", e)))?;
+        let effect_type = parse_effect_type(&params.transform_type)?;
+        if !effect_type.is_transform() {
+            return Err(JsValue::from_str(&format!(
+                "{} is not a transform; use apply_effect",
+                params.transform_type
+            )));
+        }
+        self.submit(EditOperation::AddEffect {
+            clip_id: clip_id.to_string(),
+            effect: Effect::with_parameters(&self.ids.borrow(), effect_type, params.parameters),
+        })
     }
 
-    fn setup_ws_handlers(&self) {
-        let project = self.project.clone();
-        let client_version = self.client_version.clone();
-        let onmessage_callback = Closure::wrap(Box::new(move |e: MessageEvent| {
-            if let Ok(txt) = e.data().dyn_into::<js_sys::JsString>() {
-                let txt_string = txt.as_string().unwrap();
-                match serde_json::from_str::<ServerMessage>(&txt_string) {
-                    Ok(ServerMessage::ClientOperation(operation)) => {
-                        console::log_1(&JsValue::from_str(&format!(
-                            "Received operation: {:?}",
-                            operation
-                        )));
-                        let mut project = project.borrow_mut();
-                        project.apply_operation(&operation.operation);
-                        *client_version.borrow_mut() = operation.server_version;
-
-                        // Use js_sys::global() to access the global object
-                        let global = global();
-                        if let Some(post_message) =
-                            js_sys::Reflect::get(&global, &JsValue::from_str("postMessage")).ok()
-                        {
-                            if let Some(post_message_func) =
-                                post_message.dyn_ref::<js_sys::Function>()
-                            {
-                                let _ = post_message_func.call2(
-                                    &global,
-                                    &JsValue::from_str(&txt_string),
-                                    &JsValue::from_str("*"),
-                                );
-                            }
-                        }
-                    }
-                    Ok(other_message) => {
-                        console::log_1(&JsValue::from_str(&format!(
-                            "Received other message: {:?}",
-                            other_message
-                        )));
-                    }
-                    Err(e) => {
-                        console::error_1(&JsValue::from_str(&format!(
-                            "Failed to parse message: {:?}",
-                            e
-                        )));
-                    }
-                }
-            }
-        }) as Box<dyn FnMut(_)>);
-        self.ws
-            .set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
-        onmessage_callback.forget();
+    #[wasm_bindgen]
+    pub fn remove_effect(&self, clip_id: &str, effect_id: &str) -> Result<(), JsValue> {
+        self.submit(EditOperation::RemoveEffect {
+            clip_id: clip_id.to_string(),
+            effect_id: effect_id.to_string(),
+        })
+        .map_err(|e| JsValue::from_str(&format!("Failed to send remove_effect operation: {:?}", e)))
     }
 
-    fn send_operation(&self, operation: &OTOperation) -> Result<(), JsValue> {
-        let message = serde_json::to_string(&operation)
-            .map_err(|e| JsValue::from_str(&format!("Failed to serialize operation: {:?}", e)))?;
-        self.ws.send_with_str(&message)
+    /// Every effect type, for building effect pickers.
+    #[wasm_bindgen]
+    pub fn get_effect_types(&self) -> Result<JsValue, JsValue> {
+        let types: Vec<EffectTypeInfo> = EffectType::ALL
+            .iter()
+            .map(|effect_type| {
+                let specs = effect_type.parameters();
+                let (min, max) = specs.first().map_or((0.0, 0.0), |p| (p.min, p.max));
+                EffectTypeInfo {
+                    name: effect_type.name(),
+                    transform: effect_type.is_transform(),
+                    min,
+                    max,
+                    parameters: effect_type.default_parameters(),
+                    parameter_specs: specs,
+                }
+            })
+            .collect();
+        to_value(&types).map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
     }
 
+    /// A clip's own effects, in the order they apply, e.g. to list the ids
+    /// `remove_effect` takes. Track effects are left out; see
+    /// `get_effective_effects`.
     #[wasm_bindgen]
-    pub fn get_project(&self) -> Result<JsValue, JsValue> {
+    pub fn get_effects(&self, clip_id: &str) -> Result<JsValue, JsValue> {
         let project = self.project.borrow();
-        to_value(&*project).map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
+        let clip = project
+            .clips
+            .iter()
+            .find(|c| c.id == clip_id)
+            .ok_or_else(|| JsValue::from_str("Clip not found"))?;
+        to_value(&clip.effects)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
     }
 
+    /// Gives a clip a `fade`, `wipe` or `dissolve` transition lasting
+    /// `duration_secs`, replacing any it had.
     #[wasm_bindgen]
-    pub fn update_cursor_position(&self, track: usize, time: f64) -> Result<(), JsValue> {
-        let new_position = CursorPosition {
-            track,
-            time: std::time::Duration::from_secs_f64(time),
-        };
+    pub fn set_transition(
+        &self,
+        clip_id: &str,
+        transition_type: &str,
+        duration_secs: f64,
+    ) -> Result<(), JsValue> {
+        self.submit(EditOperation::AddTransition {
+            clip_id: clip_id.to_string(),
+            transition: Transition {
+                id: self.ids.borrow().prefixed("transition"),
+                transition_type: parse_transition_type(transition_type)?,
+                duration: seconds_to_duration("duration_secs", duration_secs)?,
+            },
+        })
+    }
 
-        let mut project = self.project.borrow_mut();
-        if let Some(collaborator) = project
-            .collaborators
-            .iter_mut()
-            .find(|c| c.id == self.client_id)
-        {
-            collaborator.cursor_position = new_position.clone();
-        }
+    #[wasm_bindgen]
+    pub fn clear_transition(&self, clip_id: &str) -> Result<(), JsValue> {
+        self.submit(EditOperation::RemoveTransition {
+            clip_id: clip_id.to_string(),
+        })
+    }
 
-        let operation = OTOperation {
-            client_id: self.client_id.clone(),
-            client_version: *self.client_version.borrow(),
-            server_version: 0,
-            operation: EditOperation::UpdateCollaboratorCursor {
-                collaborator_id: self.client_id.clone(),
-                new_position,
-            },
-        };
+    /// Applies an effect to every clip on a track, replacing any track
+    /// effect of the same type.
+    #[wasm_bindgen]
+    pub fn add_track_effect(
+        &self,
+        track: usize,
+        effect_type: &str,
+        value: Option<f64>,
+    ) -> Result<(), JsValue> {
+        self.submit(EditOperation::AddTrackEffect {
+            track,
+            effect: Effect::new(&self.ids.borrow(), parse_effect_type(effect_type)?, value),
+        })
+    }
 
-        *self.client_version.borrow_mut() += 1;
-        self.send_operation(&operation).map_err(|e| {
-            JsValue::from_str(&format!(
-                "Failed to send update_cursor_position operation: {:?}",
-                e
-            ))
+    #[wasm_bindgen]
+    pub fn remove_track_effect(&self, track: usize, effect_id: &str) -> Result<(), JsValue> {
+        self.submit(EditOperation::RemoveTrackEffect {
+            track,
+            effect_id: effect_id.to_string(),
         })
     }
 
+    /// Changes the value of an effect on a track.
     #[wasm_bindgen]
-    pub fn move_clip(
+    pub fn update_track_effect(
         &self,
-        clip_id: &str,
-        new_start_time: f64,
-        new_track: usize,
+        track: usize,
+        effect_id: &str,
+        value: f64,
     ) -> Result<(), JsValue> {
-        console::log_1(&JsValue::from_str(&format!(
-            "Moving clip {} to start time {} and track {}",
-            clip_id, new_start_time, new_track
-        )));
-        let mut project = self.project.borrow_mut();
-        let clip_index = project
+        let mut effect = self
+            .project
+            .borrow()
+            .track_effects
+            .get(&track)
+            .and_then(|effects| effects.iter().find(|e| e.id == effect_id))
+            .cloned()
+            .ok_or_else(|| JsValue::from_str("Effect not found"))?;
+        effect.parameters.insert("value".to_string(), value);
+        self.submit(EditOperation::UpdateTrackEffect { track, effect })
+    }
+
+    /// Effects the preview should apply to a clip: its own, then its
+    /// track's.
+    #[wasm_bindgen]
+    pub fn get_effective_effects(&self, clip_id: &str) -> Result<JsValue, JsValue> {
+        let project = self.project.borrow();
+        let clip = project
             .clips
             .iter()
-            .position(|c| c.id == clip_id)
+            .find(|c| c.id == clip_id)
             .ok_or_else(|| JsValue::from_str("Clip not found"))?;
-        let mut clip = project.clips.remove(clip_index);
-        clip.start_time = std::time::Duration::from_secs_f64(new_start_time);
-        clip.track = new_track;
-        project.clips.push(clip);
+        to_value(&project.effective_effects(clip))
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
+    }
 
-        let operation = OTOperation {
-            client_id: self.client_id.clone(),
-            client_version: *self.client_version.borrow(),
-            server_version: 0,
-            operation: EditOperation::MoveClip {
-                id: clip_id.to_string(),
-                new_start_time: std::time::Duration::from_secs_f64(new_start_time),
-                new_track,
-            },
-        };
+    /// A clip's effects as a JSON snippet that `apply_effect_chain` can
+    /// paste onto other clips, in this project or another.
+    #[wasm_bindgen]
+    pub fn export_effect_chain(&self, clip_id: &str) -> Result<String, JsValue> {
+        let chain = self
+            .project
+            .borrow()
+            .effect_chain(clip_id)
+            .map_err(|e| JsValue::from_str(&e))?;
+        serde_json::to_string(&chain)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
+    }
 
-        *self.client_version.borrow_mut() += 1;
-        self.send_operation(&operation)
+    /// Replaces the effects of each clip with an exported chain. Nothing is
+    /// sent unless the chain fits every clip.
+    #[wasm_bindgen]
+    pub fn apply_effect_chain(&self, chain: &str, clip_ids: Vec<String>) -> Result<(), JsValue> {
+        let chain = EffectChain::from_json(chain).map_err(|e| JsValue::from_str(&e))?;
+        let operations: Vec<_> = clip_ids
+            .into_iter()
+            .map(|clip_id| EditOperation::SetClipEffects {
+                clip_id,
+                effects: chain.to_effects(&self.ids.borrow()),
+            })
+            .collect();
+        for operation in &operations {
+            self.validate(operation)?;
+        }
+        for operation in operations {
+            self.submit(operation)?;
+        }
+        Ok(())
     }
 
     #[wasm_bindgen]
-    pub fn resize_clip(&self, clip_id: &str, new_end_time: f64) -> Result<(), JsValue> {
-        let mut project = self.project.borrow_mut();
+    pub fn rename_project(&self, new_name: &str) -> Result<(), JsValue> {
+        self.submit(EditOperation::RenameProject(new_name.to_string()))
+    }
 
-        let clip = project
-            .clips
-            .iter_mut()
-            .find(|c| c.id == clip_id)
-            .ok_or_else(|| JsValue::from_str("Clip not found"))?;
+    #[wasm_bindgen]
+    pub fn set_frame_rate(&self, numerator: u32, denominator: u32) -> Result<(), JsValue> {
+        self.submit(EditOperation::SetFrameRate(FrameRate {
+            numerator,
+            denominator,
+        }))
+    }
 
-        let new_end_time = std::time::Duration::from_secs_f64(new_end_time);
-        clip.end_time = new_end_time;
+    /// Turns on server-side rounding of all edit times to frame boundaries.
+    #[wasm_bindgen]
+    pub fn set_snap_to_frames(&self, enabled: bool) -> Result<(), JsValue> {
+        self.submit(EditOperation::SetSnapToFrames(enabled))
+    }
 
-        let operation = OTOperation {
-            client_id: self.client_id.clone(),
-            client_version: *self.client_version.borrow(),
-            server_version: 0,
-            operation: EditOperation::TrimClip {
-                id: clip_id.to_string(),
-                new_start_time: clip.start_time,
-                new_end_time,
-            },
-        };
+    /// Turns ripple editing on or off: adding, moving and trimming clips then
+    /// shifts the clips after them on the same track.
+    #[wasm_bindgen]
+    pub fn set_ripple_edits(&self, enabled: bool) -> Result<(), JsValue> {
+        self.submit(EditOperation::SetRippleEdits(enabled))
+    }
 
-        *self.client_version.borrow_mut() += 1;
-        self.send_operation(&operation)
+    /// Turns the magnetic timeline on or off: track 0 then never has gaps,
+    /// and clips on other tracks follow the track 0 clip they start over.
+    #[wasm_bindgen]
+    pub fn set_magnetic_timeline(&self, enabled: bool) -> Result<(), JsValue> {
+        self.submit(EditOperation::SetMagneticTimeline(enabled))
+    }
+
+    /// Changes the track layout: shared with everyone, or kept locally if
+    /// the project makes layouts private.
+    fn edit_layout(&self, operation: EditOperation) -> Result<(), JsValue> {
+        if !self.project.borrow().settings.private_track_layout {
+            return self.submit(operation);
+        }
+        Presentation::validate(&operation).map_err(|e| JsValue::from_str(&e))?;
+        self.local_layout.borrow_mut().apply(&operation);
+        Ok(())
     }
 
     #[wasm_bindgen]
-    pub fn add_clip(
-        &self,
-        start_time: f64,
-        end_time: f64,
-        track: usize,
-        source_file: &str,
-    ) -> Result<(), JsValue> {
-        let clip_id = format!("clip-{}", Uuid::new_v4().to_string());
-        let new_clip = VideoClip {
-            id: clip_id.clone(),
-            source_file: source_file.to_string(),
-            start_time: std::time::Duration::from_secs_f64(start_time),
-            end_time: std::time::Duration::from_secs_f64(end_time),
-            track,
-            effects: Vec::new(),
-            transition: None,
+    pub fn set_track_height(&self, track: usize, height: u32) -> Result<(), JsValue> {
+        self.edit_layout(EditOperation::SetTrackHeight { track, height })
+    }
+
+    /// Sets a track's label color as `#rrggbb`, or clears it.
+    #[wasm_bindgen]
+    pub fn set_track_color(&self, track: usize, color: Option<String>) -> Result<(), JsValue> {
+        self.edit_layout(EditOperation::SetTrackColor { track, color })
+    }
+
+    #[wasm_bindgen]
+    pub fn set_track_collapsed(&self, track: usize, collapsed: bool) -> Result<(), JsValue> {
+        self.edit_layout(EditOperation::SetTrackCollapsed { track, collapsed })
+    }
+
+    /// Makes track layouts per user, or shares one layout with everyone.
+    /// Going private starts this user's layout from the shared one.
+    #[wasm_bindgen]
+    pub fn set_private_track_layout(&self, private: bool) -> Result<(), JsValue> {
+        if private {
+            *self.local_layout.borrow_mut() = self.project.borrow().presentation.clone();
+        }
+        self.submit(EditOperation::SetPrivateTrackLayout(private))
+    }
+
+    /// Header of `track` as `{ height, color, collapsed }`, from this user's
+    /// layout or the shared one as the project's policy says.
+    #[wasm_bindgen]
+    pub fn get_track_header(&self, track: usize) -> Result<JsValue, JsValue> {
+        let project = self.project.borrow();
+        let header = if project.settings.private_track_layout {
+            self.local_layout.borrow().track(track)
+        } else {
+            project.presentation.track(track)
         };
+        to_value(&header).map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
+    }
 
-        let operation = OTOperation {
-            client_id: self.client_id.clone(),
-            client_version: *self.client_version.borrow(),
-            server_version: 0,
-            operation: EditOperation::AddClip(new_clip.clone()),
+    /// Clips removed from the timeline that can still be restored.
+    /// Sets the color space the project works and renders in, e.g.
+    /// `"rec709"` or `"display_p3"`.
+    #[wasm_bindgen]
+    pub fn set_working_color_space(&self, color_space: &str) -> Result<(), JsValue> {
+        let color_space = color_space
+            .parse()
+            .map_err(|e: String| JsValue::from_str(&e))?;
+        self.submit(EditOperation::SetWorkingColorSpace(color_space))
+    }
+
+    /// Tags an asset with the color space of its media; an empty string
+    /// clears the tag.
+    #[wasm_bindgen]
+    pub fn set_asset_color_space(&self, asset_id: &str, color_space: &str) -> Result<(), JsValue> {
+        let color_space = match color_space {
+            "" => None,
+            name => Some(name.parse().map_err(|e: String| JsValue::from_str(&e))?),
         };
+        self.submit(EditOperation::SetAssetColorSpace {
+            asset_id: asset_id.to_string(),
+            color_space,
+        })
+    }
 
-        *self.client_version.borrow_mut() += 1;
-        self.send_operation(&operation)?;
+    /// Records an asset's HDR metadata, given as `{ transfer: "pq" | "hlg",
+    /// mastering_display?, max_cll?, max_fall? }`, or `null` for SDR media.
+    #[wasm_bindgen]
+    pub fn set_asset_hdr(&self, asset_id: &str, hdr: JsValue) -> Result<(), JsValue> {
+        self.submit(EditOperation::SetAssetHdr {
+            asset_id: asset_id.to_string(),
+            hdr: parse_hdr(hdr)?,
+        })
+    }
 
-        let mut project = self.project.borrow_mut();
-        project.clips.push(new_clip);
+    /// Sets the HDR signalling for rendered output, or `null` to render SDR.
+    #[wasm_bindgen]
+    pub fn set_hdr_output(&self, hdr: JsValue) -> Result<(), JsValue> {
+        self.submit(EditOperation::SetHdrOutput(parse_hdr(hdr)?))
+    }
 
-        Ok(())
+    /// Sets the delivery shape: `"landscape"`, `"vertical"`, `"square"` or
+    /// `"<width>:<height>"`.
+    #[wasm_bindgen]
+    pub fn set_aspect_ratio(&self, aspect_ratio: &str) -> Result<(), JsValue> {
+        let aspect_ratio = aspect_ratio
+            .parse()
+            .map_err(|e: String| JsValue::from_str(&e))?;
+        self.submit(EditOperation::SetAspectRatio(aspect_ratio))
     }
 
+    /// Sets title- and action-safe regions as fractions of the frame.
     #[wasm_bindgen]
-    pub fn apply_effect(
-        &self,
-        clip_id: &str,
-        effect_type: &str,
-        value: f64,
-    ) -> Result<(), JsValue> {
-        console::log_1(&JsValue::from_str(&format!(
-            "Applying effect: {} with value {} to clip {}",
-            effect_type, value, clip_id
-        )));
+    pub fn set_safe_areas(&self, title: f64, action: f64) -> Result<(), JsValue> {
+        self.submit(EditOperation::SetSafeAreas(SafeAreas { title, action }))
+    }
 
-        let effect_type = match effect_type {
-            "brightness" => EffectType::Brightness,
-            "contrast" => EffectType::Contrast,
-            "saturation" => EffectType::Saturation,
-            "hue" => EffectType::Hue,
-            "grayscale" => EffectType::Grayscale,
-            _ => return Err(JsValue::from_str("Unsupported effect type")),
-        };
+    /// Crop that reframes the project's frame for a `target` deliverable,
+    /// as `{ x, y, width, height }` fractions of the frame. `focus` places
+    /// the crop along the cropped axis, 0.5 being centred.
+    #[wasm_bindgen]
+    pub fn reframe_preset(&self, target: &str, focus: f64) -> Result<JsValue, JsValue> {
+        let target: AspectRatio = target.parse().map_err(|e: String| JsValue::from_str(&e))?;
+        if target.width == 0 || target.height == 0 {
+            return Err(JsValue::from_str("Aspect ratio must be positive"));
+        }
+        let reframe = self
+            .project
+            .borrow()
+            .settings
+            .aspect_ratio
+            .reframe(target, focus);
+        to_value(&reframe).map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
+    }
 
-        let effect = Effect::new(effect_type, value);
+    /// Subtitle tracks as `{ id, name, language, asset_id }`, without cues.
+    #[wasm_bindgen]
+    pub fn get_subtitle_tracks(&self) -> Result<JsValue, JsValue> {
+        let project = self.project.borrow();
+        let tracks: Vec<_> = project
+            .subtitle_tracks
+            .iter()
+            .map(|t| SubtitleTrackSummary {
+                id: &t.id,
+                name: &t.name,
+                language: t.language.as_deref(),
+                asset_id: t.asset_id.as_deref(),
+            })
+            .collect();
+        to_value(&tracks).map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
+    }
 
-        let mut project = self.project.borrow_mut();
+    /// Cues of a subtitle track placed on the timeline, with word timings.
+    #[wasm_bindgen]
+    pub fn get_subtitle_cues(&self, track_id: &str) -> Result<JsValue, JsValue> {
+        to_value(&self.project.borrow().timeline_cues(track_id))
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
+    }
 
-        let clip = project
-            .clips
-            .iter_mut()
-            .find(|c| c.id == clip_id)
-            .ok_or_else(|| JsValue::from_str("Clip not found"))?;
+    #[wasm_bindgen]
+    pub fn remove_subtitle_track(&self, track_id: &str) -> Result<(), JsValue> {
+        self.submit(EditOperation::RemoveSubtitleTrack(track_id.to_string()))
+    }
 
-        // Remove existing effect of the same type
-        clip.effects.retain(|e| e.effect_type != effect.effect_type);
-        clip.effects.push(effect.clone());
+    /// Drops a marker at `time` seconds and returns its id.
+    #[wasm_bindgen]
+    pub fn add_marker(&self, time: f64, label: &str) -> Result<String, JsValue> {
+        let id = self.ids.borrow().prefixed("marker");
+        self.submit(EditOperation::AddMarkers(vec![Marker {
+            id: id.clone(),
+            time: seconds_to_duration("time", time)?,
+            label: label.to_string(),
+            color: None,
+        }]))?;
+        Ok(id)
+    }
 
-        let operation = OTOperation {
-            client_id: self.client_id.clone(),
-            client_version: *self.client_version.borrow(),
-            server_version: 0,
-            operation: EditOperation::AddEffect {
-                clip_id: clip_id.to_string(),
-                effect,
-            },
-        };
+    #[wasm_bindgen]
+    pub fn remove_markers(&self, marker_ids: Vec<String>) -> Result<(), JsValue> {
+        self.submit(EditOperation::RemoveMarkers(marker_ids))
+    }
 
-        *self.client_version.borrow_mut() += 1;
-        self.send_operation(&operation).map_err(|e| {
-            JsValue::from_str(&format!("Failed to send apply_effect operation: {:?}", e))
+    #[wasm_bindgen]
+    pub fn get_markers(&self) -> Result<JsValue, JsValue> {
+        to_value(&self.project.borrow().markers)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
+    }
+
+    /// Renders the frame at `time` seconds on the server, at full size with
+    /// all effects applied. Resolves to the `fetch` response, whose body is
+    /// the PNG.
+    #[wasm_bindgen]
+    pub fn export_frame(&self, time: f64) -> Result<js_sys::Promise, JsValue> {
+        #[derive(Serialize)]
+        struct FrameRequest {
+            time: Duration,
+        }
+        let body = serde_json::to_string(&FrameRequest {
+            time: seconds_to_duration("time", time)?,
+        })
+        .map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))?;
+        post_json(&self.session_url("export-frame")?, &body)
+    }
+
+    /// HTTP URL of `path` under this client's session on the server it is
+    /// connected to, e.g. `wss://host/ws/abc` gives
+    /// `https://host/sessions/abc/<path>`, with `?token=` when the client
+    /// has one, since the server checks it on every session route.
+    fn session_url(&self, path: &str) -> Result<String, JsValue> {
+        let (base, session_id) = split_ws_url(&self.connector.ws_url)?;
+        let url = format!("{}/sessions/{}/{}", base, session_id, path);
+        Ok(match &self.connector.auth_token {
+            Some(token) => format!("{}?token={}", url, js_sys::encode_uri_component(token)),
+            None => url,
         })
     }
 
     #[wasm_bindgen]
-    pub fn rename_project(&self, new_name: &str) -> Result<(), JsValue> {
-        let operation = OTOperation {
-            client_id: self.client_id.clone(),
-            client_version: *self.client_version.borrow(),
-            server_version: 0,
-            operation: EditOperation::RenameProject(new_name.to_string()),
-        };
+    pub fn get_trash(&self) -> Result<JsValue, JsValue> {
+        to_value(&self.project.borrow().trash)
+            .map_err(|e| JsValue::from_str(&format!("Serialization error: {:?}", e)))
+    }
 
-        *self.client_version.borrow_mut() += 1;
-        self.send_operation(&operation)?;
+    #[wasm_bindgen]
+    pub fn restore_clip(&self, clip_id: &str) -> Result<(), JsValue> {
+        self.submit(EditOperation::RestoreClip(clip_id.to_string()))
+    }
 
-        let mut project = self.project.borrow_mut();
-        project.name = new_name.to_string();
+    /// Permanently deletes everything in the trash.
+    #[wasm_bindgen]
+    pub fn empty_trash(&self) -> Result<(), JsValue> {
+        self.submit(EditOperation::EmptyTrash { clip_ids: None })
+    }
+}
 
-        Ok(())
+fn parse_text(text: JsValue) -> Result<ClipText, JsValue> {
+    serde_wasm_bindgen::from_value(text)
+        .map_err(|e| JsValue::from_str(&format!("Invalid text: {}", e)))
+}
+
+fn parse_hdr(hdr: JsValue) -> Result<Option<HdrMetadata>, JsValue> {
+    serde_wasm_bindgen::from_value(hdr)
+        .map_err(|e| JsValue::from_str(&format!("Invalid HDR metadata: {}", e)))
+}
+
+/// Passes the project to `on_project_changed` callbacks. It is serialized
+/// before the callback runs, so the callback may edit it in turn.
+fn emit_project(callbacks: &RefCell<Callbacks>, project: &RefCell<VideoProject>) {
+    let Some(callback) = callbacks.borrow().project_changed.clone() else {
+        return;
+    };
+    let payload = to_value(&*project.borrow());
+    match payload {
+        Ok(value) => {
+            if let Err(e) = callback.call1(&JsValue::NULL, &value) {
+                console::error_1(&e);
+            }
+        }
+        Err(e) => console::error_1(&JsValue::from_str(&format!(
+            "Failed to serialize callback payload: {:?}",
+            e
+        ))),
+    }
+}
+
+fn emit<T: Serialize>(callback: &Option<js_sys::Function>, payload: &T) {
+    let Some(callback) = callback else {
+        return;
+    };
+    match to_value(payload) {
+        Ok(value) => {
+            if let Err(e) = callback.call1(&JsValue::NULL, &value) {
+                console::error_1(&e);
+            }
+        }
+        Err(e) => console::error_1(&JsValue::from_str(&format!(
+            "Failed to serialize callback payload: {:?}",
+            e
+        ))),
+    }
+}
+
+/// Velocity between two consecutive cursor samples. Samples too close together
+/// or too far apart say nothing useful about current motion.
+fn cursor_velocity(
+    from: &CursorPosition,
+    to: &CursorPosition,
+    elapsed_secs: f64,
+) -> Option<CursorVelocity> {
+    if !(0.001..=1.0).contains(&elapsed_secs) {
+        return None;
+    }
+    Some(CursorVelocity {
+        time: (to.time.as_secs_f64() - from.time.as_secs_f64()) / elapsed_secs,
+        track: (to.track as f64 - from.track as f64) / elapsed_secs,
+    })
+}
+
+fn parse_transition_type(transition_type: &str) -> Result<TransitionType, JsValue> {
+    match transition_type {
+        "fade" => Ok(TransitionType::Fade),
+        "wipe" => Ok(TransitionType::Wipe),
+        "dissolve" => Ok(TransitionType::Dissolve),
+        _ => Err(JsValue::from_str("Unsupported transition type")),
+    }
+}
+
+fn parse_effect_type(effect_type: &str) -> Result<EffectType, JsValue> {
+    EffectType::from_name(effect_type).ok_or_else(|| JsValue::from_str("Unsupported effect type"))
+}
+
+fn seconds_to_duration(name: &str, secs: f64) -> Result<Duration, JsValue> {
+    if !secs.is_finite() || secs < 0.0 {
+        return Err(JsValue::from_str(&format!(
+            "{} must be a non-negative number of seconds, got {}",
+            name, secs
+        )));
+    }
+    Ok(Duration::from_secs_f64(secs))
+}
+
+/// HTTP base URL and session id of a session's WebSocket URL, e.g.
+/// `wss://host/ws/abc` gives `("https://host", "abc")`.
+fn split_ws_url(ws_url: &str) -> Result<(String, &str), JsValue> {
+    let (base, session_id) = ws_url
+        .rsplit_once("/ws/")
+        .ok_or_else(|| JsValue::from_str("Unexpected WebSocket URL"))?;
+    // ws:// becomes http:// and wss:// https://
+    Ok((base.replacen("ws", "http", 1), session_id))
+}
+
+/// POSTs `body` as JSON, resolving to the `fetch` response.
+fn post_json(url: &str, body: &str) -> Result<js_sys::Promise, JsValue> {
+    let headers = Headers::new()?;
+    headers.set("Content-Type", "application/json")?;
+    let init = RequestInit::new();
+    init.set_method("POST");
+    init.set_headers(&headers);
+    init.set_body(&JsValue::from_str(body));
+    let window = web_sys::window().ok_or_else(|| JsValue::from_str("No window to fetch from"))?;
+    Ok(window.fetch_with_str_and_init(url, &init))
+}
+
+/// POSTs `body` once `tail` settles, so messages reach the server in the
+/// order they were sent. A failed POST doesn't hold up the ones after it.
+fn post_after(tail: &js_sys::Promise, url: &str, body: &str) -> Result<js_sys::Promise, JsValue> {
+    let url = url.to_string();
+    let body = body.to_string();
+    let post = Closure::once_into_js(move |_: JsValue| -> JsValue {
+        match post_json(&url, &body) {
+            Ok(response) => response.into(),
+            Err(e) => {
+                console::error_1(&e);
+                JsValue::UNDEFINED
+            }
+        }
+    });
+    let then: js_sys::Function =
+        js_sys::Reflect::get(tail, &JsValue::from_str("then"))?.dyn_into()?;
+    then.call2(tail, &post, &post)?.dyn_into()
+}
+
+/// Key the browser's guest token is kept under in local storage.
+const GUEST_TOKEN_KEY: &str = "weframe-guest-token";
+
+/// This browser's guest token, created on first use. `None` where local
+/// storage isn't available, in which case the server picks a name at random.
+fn guest_token() -> Option<String> {
+    let storage = web_sys::window()?.local_storage().ok()??;
+    if let Ok(Some(token)) = storage.get_item(GUEST_TOKEN_KEY) {
+        return Some(token);
+    }
+    let token = Uuid::new_v4().to_string();
+    storage.set_item(GUEST_TOKEN_KEY, &token).ok()?;
+    Some(token)
+}
+
+/// Size on the wire of a WebSocket message's data.
+fn message_size(data: &JsValue) -> usize {
+    match data.as_string() {
+        Some(txt) => txt.len(),
+        None => data
+            .dyn_ref::<js_sys::ArrayBuffer>()
+            .map_or(0, |buffer| buffer.byte_length() as usize),
+    }
+}
+
+/// Text of a WebSocket message. Binary frames carry zstd-compressed JSON.
+fn message_text(data: JsValue) -> Result<String, String> {
+    if let Some(txt) = data.as_string() {
+        return Ok(txt);
     }
+    let buffer = data
+        .dyn_into::<js_sys::ArrayBuffer>()
+        .map_err(|_| "Unexpected message type".to_string())?;
+    let compressed = js_sys::Uint8Array::new(&buffer).to_vec();
+    let mut decoder = ruzstd::StreamingDecoder::new(compressed.as_slice())
+        .map_err(|e| format!("Failed to decompress message: {}", e))?;
+    let mut txt = String::new();
+    decoder
+        .read_to_string(&mut txt)
+        .map_err(|e| format!("Failed to decompress message: {}", e))?;
+    Ok(txt)
 }
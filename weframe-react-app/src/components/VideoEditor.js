@@ -18,6 +18,7 @@ const VideoEditor = () => {
     const [resizingClip, setResizingClip] = useState(null);
     const [currentTime, setCurrentTime] = useState(0);
     const [isPlaying, setIsPlaying] = useState(false);
+    const [soloClipIds, setSoloClipIds] = useState([]);
     const wsRef = useRef(null);
     const timelineRef = useRef(null);
     const playIntervalRef = useRef(null);
@@ -68,6 +69,7 @@ const VideoEditor = () => {
                 ws.onopen = () => {
                     console.log('WebSocket connected successfully');
                     const newClient = new WeframeClient('ws://localhost:3030/ws/default-session', 'user1', 'User 1');
+                    newClient.on_preview_solo((solo) => setSoloClipIds(solo.clip_ids));
                     setClient(newClient);
                     updateProject(newClient);
                 };
@@ -257,21 +259,7 @@ const VideoEditor = () => {
 
     useEffect(() => {
         if (client) {
-            const handleServerMessage = (event) => {
-                if (event.origin === window.location.origin) {
-                    try {
-                        const message = JSON.parse(event.data);
-                        if (message.ClientOperation) {
-                            console.log('Received operation from server:', message.ClientOperation);
-                            updateProject(client);
-                        }
-                    } catch (error) {
-                        console.error('Error parsing message:', error);
-                    }
-                }
-            };
-
-            window.addEventListener('message', handleServerMessage);
+            client.on_project_changed(() => updateProject(client));
 
             // Set up a timer to periodically check for updates
             const intervalId = setInterval(() => {
@@ -279,7 +267,6 @@ const VideoEditor = () => {
             }, 1000); // Check every second
 
             return () => {
-                window.removeEventListener('message', handleServerMessage);
                 clearInterval(intervalId);
             };
         }
@@ -326,6 +313,12 @@ const VideoEditor = () => {
                                 <button onClick={() => applyEffect(clip.id, 'hue', -30)}>-Hue</button>
                                 <button onClick={() => applyEffect(clip.id, 'grayscale', 1)}>Grayscale</button>
                                 <button onClick={() => applyEffect(clip.id, 'grayscale', 0)}>Color</button>
+                                <button onClick={() => applyEffect(clip.id, 'blur')}>Blur</button>
+                                <button onClick={() => applyEffect(clip.id, 'sharpen')}>Sharpen</button>
+                                <button onClick={() => applyEffect(clip.id, 'vignette')}>Vignette</button>
+                                <button onClick={() => applyEffect(clip.id, 'sepia')}>Sepia</button>
+                                <button onClick={() => applyEffect(clip.id, 'invert')}>Invert</button>
+                                <button onClick={() => applyEffect(clip.id, 'noise')}>Noise</button>
                             </div>
                         ))}
                     </div>
@@ -353,6 +346,8 @@ const VideoEditor = () => {
             <VideoPreview
                 currentTime={currentTime}
                 clips={project.clips}
+                soloClipIds={soloClipIds}
+                effectsFor={client ? (clip) => client.get_effective_effects(clip.id) : null}
                 onTimeUpdate={handleTimeUpdate}
                 isPlaying={isPlaying}
             />
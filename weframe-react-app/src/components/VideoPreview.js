@@ -1,6 +1,6 @@
 import React, { useRef, useEffect, useState, useCallback } from 'react';
 
-const VideoPreview = ({ currentTime, clips, onTimeUpdate, isPlaying }) => {
+const VideoPreview = ({ currentTime, clips, soloClipIds, effectsFor, onTimeUpdate, isPlaying }) => {
     const videoRef = useRef(null);
     const [activeClip, setActiveClip] = useState(null);
     const [error, setError] = useState(null);
@@ -8,8 +8,12 @@ const VideoPreview = ({ currentTime, clips, onTimeUpdate, isPlaying }) => {
     const [isVideoReady, setIsVideoReady] = useState(false);
 
     const findActiveClip = useCallback((time) => {
-        return clips.find(c => time >= c.start_time.secs && time < c.end_time.secs);
-    }, [clips]);
+        // While a collaborator has clips soloed, show only those
+        const visible = soloClipIds && soloClipIds.length > 0
+            ? clips.filter(c => soloClipIds.includes(c.id))
+            : clips;
+        return visible.find(c => c.enabled !== false && time >= c.start_time.secs && time < c.end_time.secs);
+    }, [clips, soloClipIds]);
 
     const loadVideo = useCallback(async (clip) => {
         if (!videoRef.current) return;
@@ -21,7 +25,7 @@ const VideoPreview = ({ currentTime, clips, onTimeUpdate, isPlaying }) => {
         try {
             videoRef.current.src = clip.source_file;
             await videoRef.current.load();
-            videoRef.current.currentTime = currentTime - clip.start_time.secs;
+            videoRef.current.currentTime = currentTime - clip.start_time.secs + (clip.source_start?.secs || 0);
             setIsVideoReady(true);
             console.log('Video loaded successfully');
             if (isPlaying) {
@@ -48,7 +52,7 @@ const VideoPreview = ({ currentTime, clips, onTimeUpdate, isPlaying }) => {
 
     const handleTimeUpdate = () => {
         if (videoRef.current && activeClip) {
-            const newTime = activeClip.start_time.secs + videoRef.current.currentTime;
+            const newTime = activeClip.start_time.secs + videoRef.current.currentTime - (activeClip.source_start?.secs || 0);
             onTimeUpdate(newTime);
         }
     };
@@ -56,9 +60,15 @@ const VideoPreview = ({ currentTime, clips, onTimeUpdate, isPlaying }) => {
     const applyEffects = useCallback(() => {
         if (videoRef.current && activeClip) {
             console.log('Applying effects to clip:', activeClip.id);
-            console.log('Effects:', activeClip.effects);
+            // Clip effects followed by those on the clip's track
+            const effects = effectsFor ? effectsFor(activeClip) : activeClip.effects;
+            console.log('Effects:', effects);
             let filterString = '';
-            activeClip.effects.forEach(effect => {
+            let transformString = '';
+            let clipPath = '';
+            let offsetX = 0;
+            let offsetY = 0;
+            effects.forEach(effect => {
                 const value = effect.parameters.value;
                 console.log(`Applying effect: ${effect.effect_type}, value: ${value}`);
                 switch (effect.effect_type) {
@@ -77,14 +87,51 @@ const VideoPreview = ({ currentTime, clips, onTimeUpdate, isPlaying }) => {
                     case 'Grayscale':
                         filterString += `grayscale(${value || 1}) `;
                         break;
+                    case 'Blur':
+                        filterString += `blur(${value || 0}px) `;
+                        break;
+                    case 'Sepia':
+                        filterString += `sepia(${value || 1}) `;
+                        break;
+                    case 'Invert':
+                        filterString += `invert(${value || 1}) `;
+                        break;
+                    case 'Crop': {
+                        const { x = 0, y = 0, width = 1, height = 1 } = effect.parameters;
+                        clipPath = `inset(${y * 100}% ${(1 - x - width) * 100}% ${(1 - y - height) * 100}% ${x * 100}%)`;
+                        break;
+                    }
+                    case 'Scale':
+                        transformString += `scale(${effect.parameters.x ?? 1}, ${effect.parameters.y ?? 1}) `;
+                        break;
+                    case 'Rotate':
+                        transformString += `rotate(${effect.parameters.angle || 0}deg) `;
+                        break;
+                    case 'Position':
+                        offsetX += effect.parameters.x || 0;
+                        offsetY += effect.parameters.y || 0;
+                        break;
+                    // Sharpen, Vignette and Noise have no CSS filter; only
+                    // renders show them
+                    default:
+                        break;
                 }
             });
             console.log('Applying filter:', filterString);
             videoRef.current.style.filter = filterString;
+            // Positions are fractions of the frame, which the video's box
+            // is, as in renders. Moving first keeps the clip's own scale
+            // and rotation from changing how far it goes.
+            const { clientWidth, clientHeight } = videoRef.current;
+            const translate = offsetX || offsetY
+                ? `translate(${offsetX * clientWidth}px, ${offsetY * clientHeight}px) `
+                : '';
+            videoRef.current.style.transform = translate + transformString;
+            videoRef.current.style.clipPath = clipPath;
         } else {
             console.log('No active clip or video element');
         }
-    }, [activeClip]);
+    }, [activeClip, effectsFor]);
 
     useEffect(() => {
         const newActiveClip = findActiveClip(currentTime);
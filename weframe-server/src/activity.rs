@@ -0,0 +1,149 @@
+// weframe-server/src/activity.rs
+use crate::{SessionManager, VideoSession};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tokio::sync::RwLock;
+use warp::Filter;
+use weframe_shared::{JobFailure, JobKind, ServerMessage};
+
+/// Most entries a session's activity feed keeps.
+const ACTIVITY_FEED_LEN: usize = 200;
+
+/// Something that happened in a session besides edits and chat.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ActivityEvent {
+    JobFailed {
+        job_id: String,
+        kind: JobKind,
+        reason: JobFailure,
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ActivityEntry {
+    /// Milliseconds since the Unix epoch.
+    pub at: u64,
+    #[serde(flatten)]
+    pub event: ActivityEvent,
+}
+
+/// Makes sense of a job's error message, which is usually the last line
+/// ffmpeg logged or an I/O error.
+pub(crate) fn failure_reason(message: &str) -> JobFailure {
+    let message = message.to_ascii_lowercase();
+    let mentions = |patterns: &[&str]| patterns.iter().any(|p| message.contains(p));
+    if mentions(&["no space left", "disk quota", "storage full"]) {
+        JobFailure::OutOfDisk
+    } else if mentions(&["could not run"]) {
+        // ffmpeg itself is missing or broken; nothing users can fix
+        JobFailure::Other
+    } else if mentions(&[
+        "invalid data found",
+        "decoder",
+        "encoder",
+        "codec",
+        "unknown format",
+        "not playable",
+    ]) {
+        JobFailure::BadCodec
+    } else if mentions(&["no such file", "not found", "does not exist"]) {
+        JobFailure::MissingFile
+    } else if mentions(&["could not fetch", "unreachable", "answered", "redirect"]) {
+        JobFailure::Unreachable
+    } else {
+        JobFailure::Other
+    }
+}
+
+impl VideoSession {
+    /// Tells everyone in the session a background job failed and why, and
+    /// adds it to the activity feed.
+    pub(crate) fn job_failed(&mut self, job_id: &str, kind: JobKind, message: &str) {
+        let reason = failure_reason(message);
+        eprintln!(
+            "{:?} job {} of {} failed ({:?}): {}",
+            kind,
+            job_id,
+            self.metadata.name(),
+            reason,
+            message
+        );
+        self.broadcast_message(&ServerMessage::JobFailed {
+            job_id: job_id.to_string(),
+            kind,
+            reason,
+            message: message.to_string(),
+        });
+        self.record_activity(ActivityEvent::JobFailed {
+            job_id: job_id.to_string(),
+            kind,
+            reason,
+            message: message.to_string(),
+        });
+    }
+
+    fn record_activity(&mut self, event: ActivityEvent) {
+        if self.activity_feed.len() == ACTIVITY_FEED_LEN {
+            self.activity_feed.pop_front();
+        }
+        self.activity_feed.push_back(ActivityEntry {
+            at: self
+                .config
+                .clock
+                .now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            event,
+        });
+    }
+
+    /// The session's activity feed, oldest first.
+    pub fn activity_feed(&self) -> impl Iterator<Item = &ActivityEntry> {
+        self.activity_feed.iter()
+    }
+}
+
+/// Reports a failed job to its session, if the session is still live;
+/// otherwise the failure is only logged.
+pub(crate) async fn report_job_failure(
+    manager: &RwLock<SessionManager>,
+    session_id: &str,
+    job_id: &str,
+    kind: JobKind,
+    message: &str,
+) {
+    let session = manager.read().await.get_session(session_id);
+    match session {
+        Some(session) => session.write().await.job_failed(job_id, kind, message),
+        None => eprintln!(
+            "{:?} job {} of unloaded session {} failed: {}",
+            kind, job_id, session_id, message
+        ),
+    }
+}
+
+/// `GET /sessions/:id/activity` lists what happened in the session besides
+/// edits and chat, such as failed background jobs, oldest first.
+pub fn activity_route(
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("sessions" / String / "activity"))
+        .and_then(move |session_id: String| {
+            let manager = manager.clone();
+            async move {
+                let session = manager
+                    .read()
+                    .await
+                    .get_session(&session_id)
+                    .ok_or_else(warp::reject::not_found)?;
+                let session = session.read().await;
+                let entries: Vec<&ActivityEntry> = session.activity_feed().collect();
+                Ok::<_, warp::Rejection>(warp::reply::json(&entries))
+            }
+        })
+}
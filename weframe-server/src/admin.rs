@@ -0,0 +1,64 @@
+// weframe-server/src/admin.rs
+use crate::replies::error_reply;
+use std::sync::Arc;
+use warp::filters::path::Peek;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection};
+
+/// An `/admin` request that didn't carry the admin token.
+#[derive(Debug)]
+struct AdminUnauthorized;
+
+impl warp::reject::Reject for AdminUnauthorized {}
+
+/// Compares without stopping at the first differing byte, so response
+/// times don't give away how much of a guessed token was right.
+fn tokens_match(presented: &[u8], expected: &[u8]) -> bool {
+    presented.len() == expected.len()
+        && presented
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Passes `/admin` requests carrying `Authorization: Bearer <token>`. Other
+/// `/admin` requests are rejected as unauthorized, and requests elsewhere
+/// as not found, so the admin routes behind it never see them.
+pub fn admin_guard(token: Arc<str>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::path::peek()
+        .and(warp::header::optional::<String>("authorization"))
+        .and_then(move |path: Peek, authorization: Option<String>| {
+            let token = token.clone();
+            async move {
+                if path.segments().next() != Some("admin") {
+                    return Err(warp::reject::not_found());
+                }
+                let presented = authorization
+                    .as_deref()
+                    .and_then(|value| value.strip_prefix("Bearer "));
+                match presented {
+                    Some(presented) if tokens_match(presented.as_bytes(), token.as_bytes()) => {
+                        Ok(())
+                    }
+                    _ => Err(warp::reject::custom(AdminUnauthorized)),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Answers requests `admin_guard` turned away with 401, and passes other
+/// rejections on.
+pub async fn reject_unauthorized(
+    rejection: Rejection,
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, Rejection> {
+    if rejection.find::<AdminUnauthorized>().is_some() {
+        Ok(error_reply(
+            "Admin routes need the admin token".to_string(),
+            StatusCode::UNAUTHORIZED,
+        ))
+    } else {
+        Err(rejection)
+    }
+}
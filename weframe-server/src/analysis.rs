@@ -0,0 +1,166 @@
+// weframe-server/src/analysis.rs
+use crate::media::MediaStore;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use weframe_shared::{VideoClip, VideoProject};
+
+/// Where a clip's media is stored, if it is a file this server holds.
+pub(crate) fn clip_media_path(
+    project: &VideoProject,
+    store: &MediaStore,
+    clip: &VideoClip,
+) -> Option<PathBuf> {
+    let asset = project
+        .assets
+        .iter()
+        .find(|a| clip.asset_id.as_deref() == Some(a.id.as_str()) || a.uri == clip.source_file)?;
+    store.path(asset.storage_key()?)
+}
+
+/// Where an asset's media is stored, if it is a file this server holds.
+pub(crate) fn asset_media_path(
+    project: &VideoProject,
+    store: &MediaStore,
+    asset_id: &str,
+) -> Result<PathBuf, String> {
+    let asset = project
+        .assets
+        .iter()
+        .find(|a| a.id == asset_id)
+        .ok_or_else(|| format!("Asset {} not found", asset_id))?;
+    asset
+        .storage_key()
+        .and_then(|key| store.path(key))
+        .ok_or_else(|| format!("Asset {} is not stored on this server", asset_id))
+}
+
+/// Loudness envelope of a WAV file: RMS of all channels over each
+/// `1 / rate` second window. Only 16-bit PCM and 32-bit float data are
+/// understood; anything else needs decoding to WAV first.
+pub(crate) fn read_envelope(path: &Path, rate: u32) -> Result<Vec<f64>, String> {
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("Media is not a WAV file".to_string());
+    }
+    let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+    let u32_at =
+        |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+
+    let mut format = None;
+    let mut data = None;
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let size = u32_at(offset + 4) as usize;
+        let body = offset + 8;
+        let end = body.saturating_add(size).min(bytes.len());
+        if id == b"fmt " && end - body >= 16 {
+            // Extensible format keeps the real format code in its sub-format
+            let mut code = u16_at(body);
+            if code == 0xFFFE && end - body >= 26 {
+                code = u16_at(body + 24);
+            }
+            format = Some((code, u16_at(body + 2), u32_at(body + 4), u16_at(body + 14)));
+        } else if id == b"data" {
+            data = Some(&bytes[body..end]);
+        }
+        offset = body.saturating_add(size + size % 2);
+    }
+    let (code, channels, sample_rate, bits) = format.ok_or("WAV file has no format chunk")?;
+    let data = data.ok_or("WAV file has no data chunk")?;
+    if channels == 0 || sample_rate == 0 {
+        return Err("WAV file has no audio".to_string());
+    }
+
+    let samples: Vec<f64> = match (code, bits) {
+        (1, 16) => data
+            .chunks_exact(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]) as f64 / 32768.0)
+            .collect(),
+        (3, 32) => data
+            .chunks_exact(4)
+            .map(|s| f32::from_le_bytes([s[0], s[1], s[2], s[3]]) as f64)
+            .collect(),
+        _ => {
+            return Err(format!(
+                "Unsupported WAV encoding (format {}, {} bits)",
+                code, bits
+            ))
+        }
+    };
+    let window = ((sample_rate / rate).max(1) * channels as u32) as usize;
+    Ok(samples
+        .chunks(window)
+        .map(|w| (w.iter().map(|s| s * s).sum::<f64>() / w.len() as f64).sqrt())
+        .collect())
+}
+
+/// Runs ffmpeg over `input` with `filter_args` and discards the output,
+/// returning what it logged: analysis filters report their findings there.
+pub(crate) async fn ffmpeg_log(
+    ffmpeg: &Path,
+    input: &Path,
+    filter_args: &[&str],
+) -> Result<String, String> {
+    let output = tokio::process::Command::new(ffmpeg)
+        .args(["-hide_banner", "-nostats", "-i"])
+        .arg(input)
+        .args(filter_args)
+        .args(["-f", "null", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| format!("Could not run {}: {}", ffmpeg.display(), e))?;
+    let log = String::from_utf8_lossy(&output.stderr).into_owned();
+    if !output.status.success() {
+        let reason = log.lines().last().unwrap_or("no output");
+        return Err(format!("ffmpeg failed: {}", reason));
+    }
+    Ok(log)
+}
+
+/// Every number following `key` in an ffmpeg log, e.g. `pts_time:` values.
+pub(crate) fn log_values(log: &str, key: &str) -> Vec<f64> {
+    log.match_indices(key)
+        .filter_map(|(i, _)| {
+            let rest = log[i + key.len()..].trim_start();
+            let end = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+                .unwrap_or(rest.len());
+            rest[..end].parse().ok()
+        })
+        .collect()
+}
+
+/// Decodes the audio of `input` to a mono 16-bit PCM WAV at `sample_rate`,
+/// the form the in-process analyses and speech recognizers take.
+pub(crate) async fn extract_audio(
+    ffmpeg: &Path,
+    input: &Path,
+    output: &Path,
+    sample_rate: u32,
+) -> Result<(), String> {
+    let rate = sample_rate.to_string();
+    let result = tokio::process::Command::new(ffmpeg)
+        .args(["-hide_banner", "-nostats", "-y", "-i"])
+        .arg(input)
+        .args(["-vn", "-ac", "1", "-ar", &rate, "-c:a", "pcm_s16le"])
+        .arg(output)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| format!("Could not run {}: {}", ffmpeg.display(), e))?;
+    if !result.status.success() {
+        let log = String::from_utf8_lossy(&result.stderr);
+        let reason = log.lines().last().unwrap_or("no output");
+        return Err(format!("ffmpeg failed: {}", reason));
+    }
+    Ok(())
+}
+
+/// A fresh path in the temp directory for intermediate files.
+pub(crate) fn scratch_path(extension: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("weframe-{}.{}", uuid::Uuid::new_v4(), extension))
+}
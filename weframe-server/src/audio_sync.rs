@@ -0,0 +1,256 @@
+// weframe-server/src/audio_sync.rs
+use crate::analysis::{clip_media_path, read_envelope};
+use crate::jobs::{self, JobClass};
+use crate::media::MediaStore;
+use crate::replies::{error_reply, MAX_JSON_BODY_BYTES};
+use crate::{auth, freeze, roles, SessionManager};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use warp::http::StatusCode;
+use warp::Filter;
+use weframe_shared::{EditOperation, Role, SyncPoint};
+
+/// Loudness samples per second of audio. Sync is found to this resolution.
+const ENVELOPE_RATE: u32 = 100;
+const DEFAULT_MAX_OFFSET: Duration = Duration::from_secs(30);
+const MAX_OFFSET: Duration = Duration::from_secs(120);
+/// Offsets where the recordings overlap by less than this fraction of the
+/// shorter one are ignored; a tiny overlap correlates well by chance.
+const MIN_OVERLAP: f64 = 0.25;
+
+#[derive(Debug, Deserialize)]
+pub struct AudioSyncRequest {
+    pub reference_clip_id: String,
+    pub clip_ids: Vec<String>,
+    /// Furthest the recordings may be apart, in seconds.
+    #[serde(default)]
+    pub max_offset_secs: Option<f64>,
+    /// Also move the clips into sync, as one `AlignToReference` operation.
+    #[serde(default)]
+    pub apply: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClipSyncResult {
+    pub clip_id: String,
+    /// Seconds the reference recording runs ahead of this clip's: the clip's
+    /// media at `t` matches the reference's media at `t + offset_secs`.
+    pub offset_secs: Option<f64>,
+    /// Correlation at the chosen offset, from -1 to 1.
+    pub confidence: Option<f64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AudioSyncReport {
+    pub results: Vec<ClipSyncResult>,
+    /// Set when the clips were aligned.
+    pub server_version: Option<usize>,
+}
+
+fn normalize(envelope: &mut [f64]) {
+    let n = envelope.len().max(1) as f64;
+    let mean = envelope.iter().sum::<f64>() / n;
+    let deviation = (envelope.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n).sqrt();
+    for value in envelope.iter_mut() {
+        *value = if deviation > 0.0 {
+            (*value - mean) / deviation
+        } else {
+            0.0
+        };
+    }
+}
+
+/// Lag, in envelope samples, at which `other` best matches `reference`
+/// (`other[i]` lines up with `reference[i + lag]`), and the correlation there.
+fn best_lag(reference: &[f64], other: &[f64], max_lag: i64) -> Option<(i64, f64)> {
+    let min_overlap = ((reference.len().min(other.len()) as f64 * MIN_OVERLAP) as usize).max(1);
+    let mut best: Option<(i64, f64)> = None;
+    for lag in -max_lag..=max_lag {
+        let start = (-lag).max(0) as usize;
+        let end = (reference.len() as i64 - lag).min(other.len() as i64);
+        if end <= start as i64 || ((end as usize) - start) < min_overlap {
+            continue;
+        }
+        let end = end as usize;
+        let sum: f64 = (start..end)
+            .map(|i| other[i] * reference[(i as i64 + lag) as usize])
+            .sum();
+        let score = sum / (end - start) as f64;
+        if best.is_none_or(|(_, best_score)| score > best_score) {
+            best = Some((lag, score));
+        }
+    }
+    best
+}
+
+/// Cross-correlates each clip's audio against the reference clip's.
+fn analyze(
+    reference: &Path,
+    clips: Vec<(String, Result<PathBuf, String>)>,
+    max_offset: Duration,
+) -> Result<Vec<ClipSyncResult>, String> {
+    let mut reference = read_envelope(reference, ENVELOPE_RATE)?;
+    normalize(&mut reference);
+    let max_lag = (max_offset.as_secs_f64() * ENVELOPE_RATE as f64) as i64;
+    Ok(clips
+        .into_iter()
+        .map(|(clip_id, path)| {
+            let result = path
+                .and_then(|path| read_envelope(&path, ENVELOPE_RATE))
+                .and_then(|mut envelope| {
+                    normalize(&mut envelope);
+                    best_lag(&reference, &envelope, max_lag)
+                        .ok_or_else(|| "Recordings do not overlap".to_string())
+                });
+            match result {
+                Ok((lag, score)) => ClipSyncResult {
+                    clip_id,
+                    offset_secs: Some(lag as f64 / ENVELOPE_RATE as f64),
+                    confidence: Some(score.clamp(-1.0, 1.0)),
+                    error: None,
+                },
+                Err(error) => ClipSyncResult {
+                    clip_id,
+                    offset_secs: None,
+                    confidence: None,
+                    error: Some(error),
+                },
+            }
+        })
+        .collect())
+}
+
+fn sync_point(result: &ClipSyncResult) -> Option<SyncPoint> {
+    let offset = result.offset_secs?;
+    let lag = Duration::from_secs_f64(offset.abs());
+    let (reference_source_time, source_time) = if offset >= 0.0 {
+        (lag, Duration::ZERO)
+    } else {
+        (Duration::ZERO, lag)
+    };
+    Some(SyncPoint {
+        clip_id: result.clip_id.clone(),
+        reference_source_time,
+        source_time,
+    })
+}
+
+/// `POST /sessions/:id/audio-sync` finds how far each of `clip_ids` is out
+/// of sync with the reference clip by cross-correlating their audio, and with
+/// `apply` lines them up. Works on media held in the server's media store.
+pub fn audio_sync_route(
+    manager: Arc<RwLock<SessionManager>>,
+    store: Option<MediaStore>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("sessions" / String / "audio-sync"))
+        .and(warp::body::content_length_limit(MAX_JSON_BODY_BYTES))
+        .and(warp::body::json())
+        .and(auth::request_role(manager.clone()))
+        .and_then(
+            move |session_id: String, request: AudioSyncRequest, role: Role| {
+                let manager = manager.clone();
+                let store = store.clone();
+                async move {
+                    let session = manager
+                        .read()
+                        .await
+                        .get_session(&session_id)
+                        .ok_or_else(warp::reject::not_found)?;
+                    let Some(store) = store else {
+                        return Ok(error_reply(
+                            "Audio sync needs a media store".to_string(),
+                            StatusCode::SERVICE_UNAVAILABLE,
+                        ));
+                    };
+                    let _permit =
+                        match jobs::acquire(&manager, JobClass::Analysis, &session_id).await {
+                            Ok(permit) => permit,
+                            Err(message) => {
+                                return Ok(error_reply(message, StatusCode::SERVICE_UNAVAILABLE))
+                            }
+                        };
+
+                    let (reference, clips) = {
+                        let session = session.read().await;
+                        let project = session.project();
+                        let find = |id: &str| project.clips.iter().find(|c| c.id == id);
+                        let Some(reference) = find(&request.reference_clip_id) else {
+                            return Ok(error_reply(
+                                format!("Clip {} not found", request.reference_clip_id),
+                                StatusCode::UNPROCESSABLE_ENTITY,
+                            ));
+                        };
+                        let Some(reference) = clip_media_path(project, &store, reference) else {
+                            return Ok(error_reply(
+                                "Reference clip media is not stored on this server".to_string(),
+                                StatusCode::UNPROCESSABLE_ENTITY,
+                            ));
+                        };
+                        let clips: Vec<_> = request
+                            .clip_ids
+                            .iter()
+                            .map(|id| {
+                                let path = find(id)
+                                    .ok_or_else(|| format!("Clip {} not found", id))
+                                    .and_then(|c| {
+                                        clip_media_path(project, &store, c).ok_or_else(|| {
+                                            "Clip media is not stored on this server".to_string()
+                                        })
+                                    });
+                                (id.clone(), path)
+                            })
+                            .collect();
+                        (reference, clips)
+                    };
+
+                    let max_offset = request
+                        .max_offset_secs
+                        .filter(|secs| secs.is_finite() && *secs > 0.0)
+                        .map_or(DEFAULT_MAX_OFFSET, Duration::from_secs_f64)
+                        .min(MAX_OFFSET);
+                    let analysis =
+                        tokio::task::spawn_blocking(move || analyze(&reference, clips, max_offset))
+                            .await
+                            .unwrap_or_else(|e| Err(e.to_string()));
+                    let results = match analysis {
+                        Ok(results) => results,
+                        Err(message) => {
+                            return Ok(error_reply(message, StatusCode::UNPROCESSABLE_ENTITY))
+                        }
+                    };
+
+                    let mut server_version = None;
+                    let sync_points: Vec<_> = results.iter().filter_map(sync_point).collect();
+                    if request.apply && !sync_points.is_empty() {
+                        let operation = EditOperation::AlignToReference {
+                            reference_clip_id: request.reference_clip_id,
+                            sync_points,
+                        };
+                        if let Err(message) = roles::permits(role, &operation) {
+                            return Ok(roles::forbidden(message));
+                        }
+                        let mut session = session.write().await;
+                        if let Err(message) = session.check_frozen(&operation) {
+                            return Ok(freeze::frozen(message));
+                        }
+                        if let Err(message) = session.apply_server_operation(operation) {
+                            return Ok(error_reply(message, StatusCode::CONFLICT));
+                        }
+                        server_version = Some(session.server_version);
+                    }
+                    Ok::<_, warp::Rejection>(warp::reply::with_status(
+                        warp::reply::json(&AudioSyncReport {
+                            results,
+                            server_version,
+                        }),
+                        StatusCode::OK,
+                    ))
+                }
+            },
+        )
+}
@@ -0,0 +1,365 @@
+// weframe-server/src/auth.rs
+use crate::workspaces::check_member;
+use crate::{SessionManager, VideoSession};
+use futures::stream::SplitStream;
+use futures::StreamExt;
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use warp::filters::path::{FullPath, Peek};
+use warp::http::StatusCode;
+use warp::ws::WebSocket;
+use warp::{Filter, Rejection};
+use weframe_shared::{validate_avatar_url, ErrorCode, Role, ServerMessage};
+
+/// How long a WebSocket client that didn't put its token in the URL has to
+/// send `Authenticate`.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `?token=` on the WebSocket, event-stream and `/sessions/:id` URLs.
+#[derive(Deserialize)]
+pub struct TokenQuery {
+    pub token: Option<String>,
+}
+
+/// Claims read from a client's token. `exp` is required and checked.
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    avatar_url: Option<String>,
+    /// Session the token is limited to; any session when absent.
+    #[serde(default)]
+    session: Option<String>,
+    /// Role the client gets instead of the one it would be given on joining.
+    #[serde(default)]
+    role: Option<Role>,
+    /// Workspace the user works in. Sessions belong to the workspace of
+    /// whoever opened them first.
+    #[serde(default)]
+    workspace: Option<String>,
+}
+
+/// Who a verified token says the client is.
+pub struct Identity {
+    pub user_id: String,
+    pub name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub role: Option<Role>,
+    pub workspace: Option<String>,
+}
+
+/// Verifies HS256 tokens signed with the server's secret.
+pub struct Authenticator {
+    key: DecodingKey,
+    validation: Validation,
+}
+
+impl Authenticator {
+    pub fn new(secret: &[u8]) -> Self {
+        Authenticator {
+            key: DecodingKey::from_secret(secret),
+            validation: Validation::new(Algorithm::HS256),
+        }
+    }
+
+    /// The identity in `token` if it is valid for `session_id`.
+    pub fn verify(&self, token: Option<&str>, session_id: &str) -> Result<Identity, AuthError> {
+        let token = token.ok_or_else(AuthError::missing)?;
+        let claims = jsonwebtoken::decode::<Claims>(token, &self.key, &self.validation)
+            .map_err(|e| match e.kind() {
+                ErrorKind::ExpiredSignature => {
+                    AuthError::new(ErrorCode::TokenExpired, "Token has expired")
+                }
+                _ => AuthError::new(ErrorCode::Unauthorized, format!("Invalid token: {}", e)),
+            })?
+            .claims;
+        if claims.session.as_deref().is_some_and(|s| s != session_id) {
+            return Err(AuthError::new(
+                ErrorCode::Forbidden,
+                "Token is not valid for this session",
+            ));
+        }
+        Ok(Identity {
+            user_id: claims.sub,
+            name: claims.name,
+            avatar_url: claims.avatar_url,
+            role: claims.role,
+            workspace: claims.workspace,
+        })
+    }
+}
+
+/// Why a client was refused, sent to it as a `ServerMessage::Error` before
+/// its connection is closed.
+pub struct AuthError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl AuthError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        AuthError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// A token was needed and none was given.
+    fn missing() -> Self {
+        AuthError::new(
+            ErrorCode::Unauthorized,
+            "A token is required to join this session",
+        )
+    }
+
+    pub fn into_message(self) -> ServerMessage {
+        ServerMessage::Error {
+            client_id: String::new(),
+            message: self.message,
+            code: Some(self.code),
+        }
+    }
+}
+
+/// Waits for the `Authenticate` a client sends first when its token isn't in
+/// the URL. Anything else, or nothing within `AUTH_TIMEOUT`, gives `None`.
+/// The size of the message read comes back too, for traffic stats.
+pub(crate) async fn read_token(receiver: &mut SplitStream<WebSocket>) -> (usize, Option<String>) {
+    let Ok(Some(Ok(first))) = tokio::time::timeout(AUTH_TIMEOUT, receiver.next()).await else {
+        return (0, None);
+    };
+    let token = match first.to_str().map(serde_json::from_str::<ServerMessage>) {
+        Ok(Ok(ServerMessage::Authenticate { token })) => Some(token),
+        _ => None,
+    };
+    (first.as_bytes().len(), token)
+}
+
+impl VideoSession {
+    /// Makes a client who `identity` says: per-user state is keyed by the
+    /// token's subject, and the name, avatar and any role come from its
+    /// claims rather than the client's `Hello`.
+    pub(crate) fn sign_in(&mut self, client_id: &str, identity: Identity) {
+        if let Some(client) = self.clients.get_mut(client_id) {
+            client.user_key = Some(format!("user:{}", identity.user_id));
+            client.signed_in = true;
+        }
+        let avatar_url = identity
+            .avatar_url
+            .filter(|url| match validate_avatar_url(url) {
+                Ok(()) => true,
+                Err(message) => {
+                    println!("Ignoring avatar claim for {}: {}", client_id, message);
+                    false
+                }
+            });
+        self.update_identity(client_id, identity.name, avatar_url);
+        self.claim_ownership(client_id);
+        if let Some(role) = identity.role {
+            self.set_role(client_id, role);
+        }
+    }
+}
+
+/// A `/sessions/:id` request refused by `session_guard`.
+#[derive(Debug)]
+struct SessionDenied {
+    status: StatusCode,
+    message: ServerMessage,
+}
+
+impl warp::reject::Reject for SessionDenied {}
+
+impl From<AuthError> for SessionDenied {
+    fn from(error: AuthError) -> Self {
+        let status = match error.code {
+            ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            _ => StatusCode::UNAUTHORIZED,
+        };
+        SessionDenied {
+            status,
+            message: error.into_message(),
+        }
+    }
+}
+
+/// `?token=` on any request; a query string that doesn't parse carries no
+/// token.
+fn token_query() -> impl Filter<Extract = (TokenQuery,), Error = std::convert::Infallible> + Clone {
+    warp::query::<TokenQuery>()
+        .or(warp::any().map(|| TokenQuery { token: None }))
+        .unify()
+}
+
+/// Who `token` says is calling a route of `session_id`: `None` when the
+/// server requires no tokens.
+fn caller(
+    manager: &SessionManager,
+    session_id: &str,
+    token: Option<&str>,
+) -> Result<Option<Identity>, Rejection> {
+    match manager.authenticator() {
+        Some(authenticator) => authenticator
+            .verify(token, session_id)
+            .map(Some)
+            .map_err(|e| warp::reject::custom(SessionDenied::from(e))),
+        None => Ok(None),
+    }
+}
+
+/// Checks every `/sessions/:id/...` request before it is routed: when the
+/// server requires tokens, `?token=` must hold one valid for the session,
+/// and a session belonging to a workspace lets in members of it only.
+/// Requests for other paths pass through.
+pub fn session_guard(
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::path::peek()
+        .and(token_query())
+        .and_then(move |path: Peek, query: TokenQuery| {
+            let manager = manager.clone();
+            async move {
+                let mut segments = path.segments();
+                let (Some("sessions"), Some(session_id)) = (segments.next(), segments.next())
+                else {
+                    return Ok(());
+                };
+                let manager = manager.read().await;
+                let workspace = caller(&manager, session_id, query.token.as_deref())?
+                    .and_then(|identity| identity.workspace);
+                check_member(&manager, session_id, workspace.as_deref())
+                    .await
+                    .map_err(|e| warp::reject::custom(SessionDenied::from(e)))
+            }
+        })
+        .untuple_one()
+}
+
+/// Role the caller of a `/sessions/:id/...` route edits with: the one its
+/// token claims, else the editor's. Checked against each edit the route
+/// makes on the caller's behalf.
+pub fn request_role(
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl Filter<Extract = (Role,), Error = Rejection> + Clone {
+    warp::path::full()
+        .and(token_query())
+        .and_then(move |path: FullPath, query: TokenQuery| {
+            let manager = manager.clone();
+            async move {
+                let session_id = path.as_str().split('/').nth(2).unwrap_or_default();
+                let manager = manager.read().await;
+                Ok::<_, Rejection>(
+                    caller(&manager, session_id, query.token.as_deref())?
+                        .and_then(|identity| identity.role)
+                        .unwrap_or_default(),
+                )
+            }
+        })
+}
+
+/// Answers requests `session_guard` refused with the error a WebSocket
+/// client would get, and passes other rejections on.
+pub async fn reject_denied(
+    rejection: Rejection,
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, Rejection> {
+    match rejection.find::<SessionDenied>() {
+        Some(denied) => Ok(warp::reply::with_status(
+            warp::reply::json(&denied.message),
+            denied.status,
+        )),
+        None => Err(rejection),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{EncodingKey, Header};
+
+    fn sign(secret: &[u8], claims: serde_json::Value) -> String {
+        jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(secret),
+        )
+        .unwrap()
+    }
+
+    fn refusal(result: Result<Identity, AuthError>) -> ErrorCode {
+        match result {
+            Ok(_) => panic!("Token was accepted"),
+            Err(e) => e.code,
+        }
+    }
+
+    #[test]
+    fn tokens_are_checked_and_mapped_to_identities() {
+        let authenticator = Authenticator::new(b"secret");
+        let token = sign(
+            b"secret",
+            serde_json::json!({
+                "sub": "user-1",
+                "exp": 4_000_000_000u64,
+                "name": "Ada",
+                "avatar_url": "https://example.com/ada.png",
+                "session": "s",
+                "role": "viewer",
+                "workspace": "studio",
+            }),
+        );
+        let Ok(identity) = authenticator.verify(Some(&token), "s") else {
+            panic!("Valid token was refused");
+        };
+        assert_eq!(identity.user_id, "user-1");
+        assert_eq!(identity.name.as_deref(), Some("Ada"));
+        assert_eq!(
+            identity.avatar_url.as_deref(),
+            Some("https://example.com/ada.png")
+        );
+        assert_eq!(identity.role, Some(Role::Viewer));
+        assert_eq!(identity.workspace.as_deref(), Some("studio"));
+
+        // Limited to session s
+        let other_session = authenticator.verify(Some(&token), "t");
+        assert_eq!(refusal(other_session), ErrorCode::Forbidden);
+
+        let forged = sign(
+            b"other",
+            serde_json::json!({ "sub": "user-1", "exp": 4_000_000_000u64 }),
+        );
+        let forged = authenticator.verify(Some(&forged), "s");
+        assert_eq!(refusal(forged), ErrorCode::Unauthorized);
+
+        let expired = sign(
+            b"secret",
+            serde_json::json!({ "sub": "user-1", "exp": 1_000 }),
+        );
+        let expired = authenticator.verify(Some(&expired), "s");
+        assert_eq!(refusal(expired), ErrorCode::TokenExpired);
+
+        let missing = authenticator.verify(None, "s");
+        assert_eq!(refusal(missing), ErrorCode::Unauthorized);
+    }
+
+    #[test]
+    fn tokens_without_a_session_claim_are_valid_for_any_session() {
+        let authenticator = Authenticator::new(b"secret");
+        let token = sign(
+            b"secret",
+            serde_json::json!({ "sub": "user-1", "exp": 4_000_000_000u64 }),
+        );
+        let Ok(identity) = authenticator.verify(Some(&token), "any") else {
+            panic!("Valid token was refused");
+        };
+        assert_eq!(identity.user_id, "user-1");
+        assert!(identity.name.is_none());
+        assert!(identity.role.is_none());
+        assert!(identity.workspace.is_none());
+    }
+}
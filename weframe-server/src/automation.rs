@@ -0,0 +1,345 @@
+// weframe-server/src/automation.rs
+use crate::replies::MAX_JSON_BODY_BYTES;
+use crate::{get_or_create_session, memory, roles, SessionManager, VideoSession};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use warp::http::StatusCode;
+use warp::Filter;
+use weframe_shared::{
+    ClipKind, EditOperation, IdGenerator, OTOperation, RejectionCode, Role, SourceKind, Transition,
+    TransitionType, VideoClip, VideoProject,
+};
+
+/// A program of edit steps run server-side as one atomic batch: either every
+/// resulting operation applies, or none do.
+#[derive(Debug, Deserialize)]
+pub struct Script {
+    pub steps: Vec<ScriptStep>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptStep {
+    /// A single edit, exactly as a client would send it.
+    Operation(Box<EditOperation>),
+    /// Lays clips end to end on one track, after whatever is already there
+    /// unless `start` is given. With `crossfade`, each clip overlaps the one
+    /// before it by that much and dissolves in.
+    AppendClips {
+        track: usize,
+        #[serde(default)]
+        start: Option<Duration>,
+        #[serde(default)]
+        crossfade: Option<Duration>,
+        clips: Vec<ClipSpec>,
+    },
+    /// Cuts a clip at each of the given timeline times. The first piece
+    /// keeps the clip's id; the rest are new clips.
+    SplitClip { clip_id: String, at: Vec<Duration> },
+    /// Cuts the given timeline ranges out of a clip, leaving gaps; magnetic
+    /// or later ripple edits close them.
+    RemoveRanges {
+        clip_id: String,
+        ranges: Vec<TimeRange>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TimeRange {
+    pub start: Duration,
+    pub end: Duration,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClipSpec {
+    pub source_file: String,
+    #[serde(default)]
+    pub asset_id: Option<String>,
+    #[serde(default)]
+    pub kind: ClipKind,
+    #[serde(default)]
+    pub source_kind: SourceKind,
+    #[serde(default)]
+    pub source_start: Duration,
+    pub duration: Duration,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScriptReport {
+    pub operations: usize,
+    pub server_version: usize,
+    /// Ids of clips the script created, in order.
+    pub clip_ids: Vec<String>,
+}
+
+/// Why a script was refused, and which step caused it.
+#[derive(Debug, Serialize)]
+pub struct ScriptError {
+    pub step: Option<usize>,
+    pub message: String,
+    pub code: RejectionCode,
+}
+
+impl ScriptError {
+    fn new(step: Option<usize>, code: RejectionCode, message: String) -> Self {
+        ScriptError {
+            step,
+            message,
+            code,
+        }
+    }
+
+    /// Status an HTTP route answers the refusal with.
+    pub fn status(&self) -> StatusCode {
+        match self.code {
+            RejectionCode::Forbidden => StatusCode::FORBIDDEN,
+            RejectionCode::Frozen => StatusCode::CONFLICT,
+            _ => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+impl ScriptStep {
+    fn expand(
+        &self,
+        project: &VideoProject,
+        ids: &IdGenerator,
+    ) -> Result<Vec<EditOperation>, String> {
+        match self {
+            ScriptStep::Operation(operation) => Ok(vec![(**operation).clone()]),
+            ScriptStep::AppendClips {
+                track,
+                start,
+                crossfade,
+                clips,
+            } => {
+                let mut cursor = start.unwrap_or_else(|| {
+                    project
+                        .clips
+                        .iter()
+                        .filter(|c| c.track == *track)
+                        .map(|c| c.end_time)
+                        .max()
+                        .unwrap_or_default()
+                });
+                let mut operations = Vec::new();
+                for (i, spec) in clips.iter().enumerate() {
+                    let fade = crossfade.filter(|_| i > 0);
+                    if let Some(fade) = fade {
+                        cursor = cursor.saturating_sub(fade);
+                    }
+                    let clip = VideoClip {
+                        id: ids.uuid().to_string(),
+                        source_file: spec.source_file.clone(),
+                        asset_id: spec.asset_id.clone(),
+                        kind: spec.kind,
+                        source_kind: spec.source_kind,
+                        start_time: cursor,
+                        end_time: cursor + spec.duration,
+                        source_start: spec.source_start,
+                        track: *track,
+                        transition: fade.map(|duration| Transition {
+                            id: ids.uuid().to_string(),
+                            transition_type: TransitionType::Dissolve,
+                            duration,
+                        }),
+                        ..Default::default()
+                    };
+                    cursor = clip.end_time;
+                    operations.push(EditOperation::AddClip(clip));
+                }
+                Ok(operations)
+            }
+            ScriptStep::SplitClip { clip_id, at } => {
+                let clip = find_clip(project, clip_id)?;
+                let mut cuts: Vec<Duration> = at
+                    .iter()
+                    .copied()
+                    .filter(|t| *t > clip.start_time && *t < clip.end_time)
+                    .collect();
+                cuts.sort();
+                cuts.dedup();
+                let starts = std::iter::once(clip.start_time).chain(cuts.iter().copied());
+                let ends = cuts.iter().copied().chain([clip.end_time]);
+                Ok(keep_pieces(clip, starts.zip(ends).collect(), ids))
+            }
+            ScriptStep::RemoveRanges { clip_id, ranges } => {
+                let clip = find_clip(project, clip_id)?;
+                let mut ranges: Vec<TimeRange> = ranges
+                    .iter()
+                    .map(|r| TimeRange {
+                        start: r.start.max(clip.start_time),
+                        end: r.end.min(clip.end_time),
+                    })
+                    .filter(|r| r.start < r.end)
+                    .collect();
+                ranges.sort_by_key(|r| r.start);
+
+                let mut kept = Vec::new();
+                let mut cursor = clip.start_time;
+                for range in &ranges {
+                    if range.start > cursor {
+                        kept.push((cursor, range.start));
+                    }
+                    cursor = cursor.max(range.end);
+                }
+                if cursor < clip.end_time {
+                    kept.push((cursor, clip.end_time));
+                }
+                if kept.is_empty() {
+                    return Ok(vec![EditOperation::RemoveClip(clip.id.clone())]);
+                }
+                Ok(keep_pieces(clip, kept, ids))
+            }
+        }
+    }
+}
+
+fn find_clip<'a>(project: &'a VideoProject, clip_id: &str) -> Result<&'a VideoClip, String> {
+    project
+        .clips
+        .iter()
+        .find(|c| c.id == clip_id)
+        .ok_or_else(|| format!("Clip {} not found", clip_id))
+}
+
+/// Operations that leave only the given timeline ranges of `clip`, in
+/// order and non-empty: the clip itself is trimmed to the first, and each
+/// later range becomes a new clip playing the same media.
+fn keep_pieces(
+    clip: &VideoClip,
+    pieces: Vec<(Duration, Duration)>,
+    ids: &IdGenerator,
+) -> Vec<EditOperation> {
+    if pieces == [(clip.start_time, clip.end_time)] {
+        return Vec::new();
+    }
+    let mut operations = Vec::new();
+    for (i, (start, end)) in pieces.into_iter().enumerate() {
+        if i == 0 {
+            operations.push(EditOperation::TrimClip {
+                id: clip.id.clone(),
+                new_start_time: start,
+                new_end_time: end,
+            });
+            continue;
+        }
+        operations.push(EditOperation::AddClip(VideoClip {
+            id: ids.uuid().to_string(),
+            transition: None,
+            thumbnail_url: None,
+            filmstrip_url: None,
+            ..clip.slice(start, end)
+        }));
+    }
+    operations
+}
+
+impl VideoSession {
+    /// Expands and validates every step against a scratch copy of the
+    /// project, then commits the resulting operations back to back. Nothing
+    /// is applied if any step fails, makes an edit `role` doesn't allow, or
+    /// makes one while the session is frozen.
+    pub fn run_script(&mut self, role: Role, script: &Script) -> Result<ScriptReport, ScriptError> {
+        self.expand_and_commit(role, script, true)
+    }
+
+    /// Runs an admin's script with an owner's rights. Unlike callers'
+    /// scripts it still runs while the session is frozen, so maintenance
+    /// can be scripted.
+    pub fn run_admin_script(&mut self, script: &Script) -> Result<ScriptReport, ScriptError> {
+        self.expand_and_commit(Role::Owner, script, false)
+    }
+
+    fn expand_and_commit(
+        &mut self,
+        role: Role,
+        script: &Script,
+        check_frozen: bool,
+    ) -> Result<ScriptReport, ScriptError> {
+        let limit = self.timeline_limit();
+        let mut preview = self.project.clone();
+        let mut operations = Vec::new();
+        for (step_index, step) in script.steps.iter().enumerate() {
+            let at = Some(step_index);
+            let invalid = |message| ScriptError::new(at, RejectionCode::Invalid, message);
+            let expanded = step.expand(&preview, &self.config.ids).map_err(invalid)?;
+            for mut operation in expanded {
+                roles::permits(role, &operation)
+                    .map_err(|message| ScriptError::new(at, RejectionCode::Forbidden, message))?;
+                if check_frozen {
+                    self.check_frozen(&operation)
+                        .map_err(|message| ScriptError::new(at, RejectionCode::Frozen, message))?;
+                }
+                self.check_flags(&operation).map_err(invalid)?;
+                if self.metadata.has_flag("frame_quantization") {
+                    preview.quantize_operation(&mut operation);
+                }
+                preview.clamp_operation(&mut operation, limit);
+                preview.validate_operation(&operation).map_err(invalid)?;
+                preview.apply_operation(&operation);
+                operations.push(operation);
+            }
+        }
+
+        let needed = operations.iter().map(memory::approx_size).sum();
+        let adds = operations.iter().any(memory::adds_content);
+        self.reserve_bytes(needed, adds)
+            .map_err(|message| ScriptError::new(None, RejectionCode::OverLimit, message))?;
+
+        let clip_ids = operations
+            .iter()
+            .filter_map(|op| match op {
+                EditOperation::AddClip(clip) | EditOperation::AddTextClip(clip) => {
+                    Some(clip.id.clone())
+                }
+                _ => None,
+            })
+            .collect();
+        let count = operations.len();
+        for operation in operations {
+            let operation = OTOperation {
+                client_id: "automation".to_string(),
+                client_version: self.server_version,
+                server_version: self.server_version,
+                operation,
+                label: None,
+            };
+            self.commit_operation(operation);
+        }
+        Ok(ScriptReport {
+            operations: count,
+            server_version: self.server_version,
+            clip_ids,
+        })
+    }
+}
+
+/// `POST /admin/sessions/:id/automation` runs a script against a live
+/// session.
+pub fn automation_route(
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("admin" / "sessions" / String / "automation"))
+        .and(warp::body::content_length_limit(MAX_JSON_BODY_BYTES))
+        .and(warp::body::json())
+        .and_then(move |session_id: String, script: Script| {
+            let manager = manager.clone();
+            async move {
+                let session = get_or_create_session(&manager, &session_id).await;
+                let result = session.write().await.run_admin_script(&script);
+                Ok::<_, warp::Rejection>(match result {
+                    Ok(report) => {
+                        warp::reply::with_status(warp::reply::json(&report), StatusCode::OK)
+                    }
+                    Err(error) => {
+                        let status = error.status();
+                        warp::reply::with_status(warp::reply::json(&error), status)
+                    }
+                })
+            }
+        })
+}
@@ -0,0 +1,351 @@
+// weframe-server/src/beats.rs
+use crate::analysis::{asset_media_path, extract_audio, read_envelope, scratch_path};
+use crate::jobs::{self, JobClass};
+use crate::media::MediaStore;
+use crate::replies::error_reply;
+use crate::{auth, freeze, roles, SessionManager};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use warp::http::StatusCode;
+use warp::Filter;
+use weframe_shared::{EditOperation, Marker, Role};
+
+/// Audio is decoded at this rate for analysis; beats need no more.
+const ANALYSIS_SAMPLE_RATE: u32 = 22_050;
+/// Onset strength samples per second, i.e. beats are placed to 5 ms.
+const ONSET_RATE: u32 = 200;
+const DEFAULT_MIN_BPM: f64 = 60.0;
+const DEFAULT_MAX_BPM: f64 = 180.0;
+/// Tempo the estimate leans towards when several fit equally well, so a
+/// 120 BPM track isn't reported as 60 or 240.
+const PREFERRED_BPM: f64 = 120.0;
+/// How strongly beat tracking keeps to the estimated tempo over following
+/// individual onsets.
+const TEMPO_TIGHTNESS: f64 = 100.0;
+const MARKER_COLOR: &str = "#f5a623";
+
+#[derive(Deserialize)]
+struct BeatQuery {
+    min_bpm: Option<f64>,
+    max_bpm: Option<f64>,
+}
+
+impl BeatQuery {
+    fn bpm_range(&self) -> (f64, f64) {
+        let pick = |value: Option<f64>, default: f64| {
+            value
+                .filter(|bpm| bpm.is_finite())
+                .unwrap_or(default)
+                .clamp(20.0, 400.0)
+        };
+        let min = pick(self.min_bpm, DEFAULT_MIN_BPM);
+        let max = pick(self.max_bpm, DEFAULT_MAX_BPM);
+        (min.min(max), min.max(max))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BeatReport {
+    pub asset_id: String,
+    pub tempo_bpm: f64,
+    /// Beat times in the asset's media.
+    pub beats: Vec<Duration>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BeatMarkerReport {
+    pub clip_id: String,
+    pub tempo_bpm: f64,
+    pub marker_ids: Vec<String>,
+    pub server_version: usize,
+}
+
+/// How sharply loudness rises in each frame: the positive part of the change
+/// in log energy, which peaks on drum hits and note attacks.
+fn onset_strength(envelope: &[f64]) -> Vec<f64> {
+    let log: Vec<f64> = envelope.iter().map(|v| (v + 1e-4).ln()).collect();
+    let mut onsets: Vec<f64> = std::iter::once(0.0)
+        .chain(log.windows(2).map(|w| (w[1] - w[0]).max(0.0)))
+        .collect();
+    let n = onsets.len().max(1) as f64;
+    let mean = onsets.iter().sum::<f64>() / n;
+    let deviation = (onsets.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n).sqrt();
+    if deviation > 0.0 {
+        for value in &mut onsets {
+            *value /= deviation;
+        }
+    }
+    onsets
+}
+
+/// Beat period in onset frames: the lag at which the onset signal best
+/// repeats, weighted towards `PREFERRED_BPM`.
+fn estimate_period(onsets: &[f64], (min_bpm, max_bpm): (f64, f64)) -> Option<usize> {
+    let rate = ONSET_RATE as f64;
+    let min_lag = (60.0 * rate / max_bpm).floor().max(1.0) as usize;
+    let max_lag = ((60.0 * rate / min_bpm).ceil() as usize).min(onsets.len() / 2);
+    (min_lag..=max_lag)
+        .map(|lag| {
+            let correlation = onsets[lag..]
+                .iter()
+                .zip(onsets)
+                .map(|(a, b)| a * b)
+                .sum::<f64>()
+                / (onsets.len() - lag) as f64;
+            let bpm = 60.0 * rate / lag as f64;
+            let weight = (-0.5 * (bpm / PREFERRED_BPM).log2().powi(2)).exp();
+            (lag, correlation * weight)
+        })
+        .filter(|(_, score)| *score > 0.0)
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(lag, _)| lag)
+}
+
+/// Dynamic-programming beat tracker (Ellis, 2007): picks the onset frames
+/// that score highest while staying close to one beat `period` apart.
+fn track_beats(onsets: &[f64], period: usize) -> Vec<usize> {
+    let n = onsets.len();
+    let mut score = vec![0.0; n];
+    let mut previous: Vec<Option<usize>> = vec![None; n];
+    for i in 0..n {
+        let earliest = i.saturating_sub(2 * period);
+        let latest = i.saturating_sub(period / 2);
+        let best = (earliest..latest)
+            .map(|j| {
+                let gap = ((i - j) as f64 / period as f64).ln();
+                (j, score[j] - TEMPO_TIGHTNESS * gap * gap)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        score[i] = onsets[i];
+        // A chain only starts here if no earlier beat leads up to it well
+        if let Some((j, value)) = best.filter(|(_, value)| *value > 0.0) {
+            score[i] += value;
+            previous[i] = Some(j);
+        }
+    }
+
+    // Finish on the best-scoring frame within the last beat
+    let tail = n.saturating_sub(period);
+    let Some(mut beat) = (tail..n).max_by(|&a, &b| score[a].total_cmp(&score[b])) else {
+        return Vec::new();
+    };
+    let mut beats = vec![beat];
+    while let Some(earlier) = previous[beat] {
+        beats.push(earlier);
+        beat = earlier;
+    }
+    beats.reverse();
+    beats
+}
+
+/// Tempo in BPM and beat times of a WAV file.
+fn analyze(wav: &Path, bpm_range: (f64, f64)) -> Result<(f64, Vec<Duration>), String> {
+    let onsets = onset_strength(&read_envelope(wav, ONSET_RATE)?);
+    let period = estimate_period(&onsets, bpm_range)
+        .ok_or("No steady beat found in the audio".to_string())?;
+    let beats = track_beats(&onsets, period)
+        .into_iter()
+        .map(|frame| Duration::from_secs_f64(frame as f64 / ONSET_RATE as f64))
+        .collect();
+    Ok((60.0 * ONSET_RATE as f64 / period as f64, beats))
+}
+
+/// Decodes a file's audio and finds its tempo and beats.
+pub async fn detect_beats(
+    ffmpeg: &Path,
+    media: &Path,
+    bpm_range: (f64, f64),
+) -> Result<(f64, Vec<Duration>), String> {
+    let wav = scratch_path("wav");
+    let decoded = extract_audio(ffmpeg, media, &wav, ANALYSIS_SAMPLE_RATE).await;
+    let result = match decoded {
+        Ok(()) => {
+            let path = wav.clone();
+            tokio::task::spawn_blocking(move || analyze(&path, bpm_range))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+        }
+        Err(message) => Err(message),
+    };
+    let _ = tokio::fs::remove_file(&wav).await;
+    result
+}
+
+/// `GET /sessions/:id/assets/:asset_id/beats?min_bpm=&max_bpm=` reports
+/// the tempo and beat times of a stored asset, and
+/// `POST /sessions/:id/clips/:clip_id/beat-markers` with the same query
+/// adds a timeline marker on every beat the clip plays.
+pub fn beat_routes(
+    manager: Arc<RwLock<SessionManager>>,
+    store: Option<MediaStore>,
+    ffmpeg: PathBuf,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let report_manager = manager.clone();
+    let report_store = store.clone();
+    let report_ffmpeg = ffmpeg.clone();
+    let report = warp::get()
+        .and(warp::path!(
+            "sessions" / String / "assets" / String / "beats"
+        ))
+        .and(warp::query::<BeatQuery>())
+        .and_then(
+            move |session_id: String, asset_id: String, query: BeatQuery| {
+                let manager = report_manager.clone();
+                let store = report_store.clone();
+                let ffmpeg = report_ffmpeg.clone();
+                async move {
+                    let session = manager
+                        .read()
+                        .await
+                        .get_session(&session_id)
+                        .ok_or_else(warp::reject::not_found)?;
+                    let Some(store) = store else {
+                        return Ok(error_reply(
+                            "Beat detection needs a media store".to_string(),
+                            StatusCode::SERVICE_UNAVAILABLE,
+                        ));
+                    };
+                    let _permit =
+                        match jobs::acquire(&manager, JobClass::Analysis, &session_id).await {
+                            Ok(permit) => permit,
+                            Err(message) => {
+                                return Ok(error_reply(message, StatusCode::SERVICE_UNAVAILABLE))
+                            }
+                        };
+                    let media = {
+                        let session = session.read().await;
+                        asset_media_path(session.project(), &store, &asset_id)
+                    };
+                    let result = match media {
+                        Ok(media) => detect_beats(&ffmpeg, &media, query.bpm_range()).await,
+                        Err(message) => Err(message),
+                    };
+                    Ok::<_, warp::Rejection>(match result {
+                        Ok((tempo_bpm, beats)) => warp::reply::with_status(
+                            warp::reply::json(&BeatReport {
+                                asset_id,
+                                tempo_bpm,
+                                beats,
+                            }),
+                            StatusCode::OK,
+                        ),
+                        Err(message) => error_reply(message, StatusCode::UNPROCESSABLE_ENTITY),
+                    })
+                }
+            },
+        );
+
+    let mark = warp::post()
+        .and(warp::path!(
+            "sessions" / String / "clips" / String / "beat-markers"
+        ))
+        .and(warp::query::<BeatQuery>())
+        .and(auth::request_role(manager.clone()))
+        .and_then(
+            move |session_id: String, clip_id: String, query: BeatQuery, role: Role| {
+                let manager = manager.clone();
+                let store = store.clone();
+                let ffmpeg = ffmpeg.clone();
+                async move {
+                    let session = manager
+                        .read()
+                        .await
+                        .get_session(&session_id)
+                        .ok_or_else(warp::reject::not_found)?;
+                    let Some(store) = store else {
+                        return Ok(error_reply(
+                            "Beat detection needs a media store".to_string(),
+                            StatusCode::SERVICE_UNAVAILABLE,
+                        ));
+                    };
+                    let _permit =
+                        match jobs::acquire(&manager, JobClass::Analysis, &session_id).await {
+                            Ok(permit) => permit,
+                            Err(message) => {
+                                return Ok(error_reply(message, StatusCode::SERVICE_UNAVAILABLE))
+                            }
+                        };
+                    let media = {
+                        let session = session.read().await;
+                        let project = session.project();
+                        project
+                            .clips
+                            .iter()
+                            .find(|c| c.id == clip_id)
+                            .ok_or_else(|| format!("Clip {} not found", clip_id))
+                            .and_then(|clip| {
+                                let asset_id = clip.asset_id.as_deref().ok_or_else(|| {
+                                    format!("Clip {} does not play a project asset", clip_id)
+                                })?;
+                                asset_media_path(project, &store, asset_id)
+                            })
+                    };
+                    let detected = match media {
+                        Ok(media) => detect_beats(&ffmpeg, &media, query.bpm_range()).await,
+                        Err(message) => Err(message),
+                    };
+                    let (tempo_bpm, beats) = match detected {
+                        Ok(detected) => detected,
+                        Err(message) => {
+                            return Ok(error_reply(message, StatusCode::UNPROCESSABLE_ENTITY))
+                        }
+                    };
+
+                    // Place markers against where the clip is now, in case it
+                    // moved during analysis
+                    let mut session = session.write().await;
+                    let Some(clip) = session.project().clips.iter().find(|c| c.id == clip_id)
+                    else {
+                        return Ok(error_reply(
+                            format!("Clip {} not found", clip_id),
+                            StatusCode::CONFLICT,
+                        ));
+                    };
+                    let (source_start, source_end) = clip.source_range();
+                    let markers: Vec<Marker> = beats
+                        .into_iter()
+                        .filter(|beat| source_start <= *beat && *beat < source_end)
+                        .filter_map(|beat| clip.timeline_time_at(beat))
+                        .enumerate()
+                        .map(|(i, time)| Marker {
+                            id: session.config.ids.uuid().to_string(),
+                            time,
+                            label: format!("Beat {}", i + 1),
+                            color: Some(MARKER_COLOR.to_string()),
+                        })
+                        .collect();
+                    let marker_ids = markers.iter().map(|m| m.id.clone()).collect();
+                    if !markers.is_empty() {
+                        let mut operation = EditOperation::AddMarkers(markers);
+                        if session.metadata().has_flag("frame_quantization") {
+                            session.project().quantize_operation(&mut operation);
+                        }
+                        if let Err(message) = roles::permits(role, &operation) {
+                            return Ok(roles::forbidden(message));
+                        }
+                        if let Err(message) = session.check_frozen(&operation) {
+                            return Ok(freeze::frozen(message));
+                        }
+                        let added = session.apply_server_operation(operation);
+                        if let Err(message) = added {
+                            return Ok(error_reply(message, StatusCode::CONFLICT));
+                        }
+                    }
+                    Ok::<_, warp::Rejection>(warp::reply::with_status(
+                        warp::reply::json(&BeatMarkerReport {
+                            clip_id,
+                            tempo_bpm,
+                            marker_ids,
+                            server_version: session.server_version,
+                        }),
+                        StatusCode::OK,
+                    ))
+                }
+            },
+        );
+
+    report.or(mark)
+}
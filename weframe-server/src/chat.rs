@@ -0,0 +1,77 @@
+// weframe-server/src/chat.rs
+use crate::VideoSession;
+use std::time::UNIX_EPOCH;
+use weframe_shared::{validate_chat_message, ServerMessage};
+
+/// Most chat messages a session keeps for replaying to clients who join.
+const CHAT_HISTORY_LEN: usize = 200;
+
+/// A message posted to a session's chat.
+#[derive(Debug, Clone)]
+pub struct ChatEntry {
+    pub client_id: String,
+    pub name: String,
+    pub message: String,
+    /// Milliseconds since the Unix epoch.
+    pub sent_at: u64,
+}
+
+impl ChatEntry {
+    fn to_message(&self) -> ServerMessage {
+        ServerMessage::ChatMessage {
+            client_id: self.client_id.clone(),
+            message: self.message.clone(),
+            name: self.name.clone(),
+            sent_at: self.sent_at,
+        }
+    }
+}
+
+impl VideoSession {
+    /// Posts a client's message to the session's chat and sends it to
+    /// everyone, the author included. Every role may chat, in sessions with
+    /// the `chat` flag.
+    pub(crate) fn post_chat(&mut self, client_id: &str, message: String) -> Result<(), String> {
+        if !self.metadata.has_flag("chat") {
+            return Err("Chat is not enabled in this session".to_string());
+        }
+        validate_chat_message(&message)?;
+        let name = self
+            .project
+            .collaborators
+            .iter()
+            .find(|c| c.id == client_id)
+            .map_or_else(|| client_id.to_string(), |c| c.name.clone());
+        let entry = ChatEntry {
+            client_id: client_id.to_string(),
+            name,
+            message,
+            sent_at: self
+                .config
+                .clock
+                .now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+        };
+        if self.chat_history.len() == CHAT_HISTORY_LEN {
+            self.chat_history.pop_front();
+        }
+        self.broadcast_message(&entry.to_message());
+        self.chat_history.push_back(entry);
+        self.last_activity = self.config.clock.now();
+        Ok(())
+    }
+
+    /// Messages in the session's chat, oldest first.
+    pub fn chat_history(&self) -> impl Iterator<Item = &ChatEntry> {
+        self.chat_history.iter()
+    }
+
+    /// Sends a client who just joined the chat so far.
+    pub(crate) fn replay_chat(&self, client_id: &str) {
+        for entry in &self.chat_history {
+            self.send_to(client_id, &entry.to_message());
+        }
+    }
+}
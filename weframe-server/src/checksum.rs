@@ -0,0 +1,64 @@
+// weframe-server/src/checksum.rs
+use crate::{SessionManager, VideoSession};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use weframe_shared::ServerMessage;
+
+impl VideoSession {
+    /// Sends every client the project's checksum, unless nothing changed
+    /// since it was last sent or nobody is here to compare it.
+    pub(crate) fn broadcast_checksum(&mut self) {
+        if self.clients.is_empty() || self.checksummed_version == Some(self.server_version) {
+            return;
+        }
+        self.broadcast_message(&ServerMessage::StateChecksum {
+            server_version: self.server_version,
+            checksum: self.project.checksum(),
+        });
+        self.checksummed_version = Some(self.server_version);
+    }
+
+    /// Sends a client whose project diverged the whole project again, as a
+    /// chunked sync since a bare `ProjectUpdate` doesn't say which version
+    /// it holds. Clients that didn't negotiate `chunked_sync` are refused
+    /// and have to reconnect instead.
+    pub(crate) fn resync(&self, client_id: &str, server_version: usize, checksum: u64) {
+        eprintln!(
+            "Client {} diverged from project {}: checksum {:016x} at version {}, expected {:016x} at {}",
+            client_id,
+            self.project.id,
+            checksum,
+            server_version,
+            self.project.checksum(),
+            self.server_version
+        );
+        self.metrics.record_divergence();
+        if !self.takes_chunked_sync(client_id) {
+            self.send_to(
+                client_id,
+                &ServerMessage::Error {
+                    client_id: client_id.to_string(),
+                    message: "Resyncing needs the chunked_sync feature; reconnect instead"
+                        .to_string(),
+                    code: None,
+                },
+            );
+            return;
+        }
+        self.send_chunked_sync(client_id);
+    }
+}
+
+/// Sends the clients of every session that changed their project's
+/// checksum.
+pub async fn broadcast_checksums(manager: &RwLock<SessionManager>) {
+    let sessions: Vec<Arc<RwLock<VideoSession>>> = manager
+        .read()
+        .await
+        .sessions()
+        .map(|(_, session)| session.clone())
+        .collect();
+    for session in sessions {
+        session.write().await.broadcast_checksum();
+    }
+}
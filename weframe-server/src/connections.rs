@@ -0,0 +1,70 @@
+// weframe-server/src/connections.rs
+use crate::{SessionManager, VideoSession};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tokio::sync::RwLock;
+use warp::Filter;
+use weframe_shared::TrafficStats;
+
+/// One client connected to a session, as reported to operators.
+#[derive(Debug, Serialize)]
+pub struct ConnectionInfo {
+    pub client_id: String,
+    pub name: Option<String>,
+    pub protocol_version: u32,
+    pub features: Vec<String>,
+    /// Seconds since the Unix epoch.
+    pub connected_at: u64,
+    pub traffic: TrafficStats,
+}
+
+impl VideoSession {
+    /// Everyone connected to the session, longest-connected first.
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        let mut connections: Vec<ConnectionInfo> = self
+            .clients
+            .iter()
+            .map(|(client_id, client)| ConnectionInfo {
+                client_id: client_id.clone(),
+                name: self
+                    .project
+                    .collaborators
+                    .iter()
+                    .find(|c| c.id == *client_id)
+                    .map(|c| c.name.clone()),
+                protocol_version: client.protocol_version,
+                features: client.features.clone(),
+                connected_at: client
+                    .connected_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+                traffic: *client.traffic.lock().unwrap(),
+            })
+            .collect();
+        connections.sort_by_key(|c| c.connected_at);
+        connections
+    }
+}
+
+/// `GET /admin/sessions/:id/connections` lists a session's clients with
+/// the traffic each has sent and received, for diagnosing slow sessions.
+pub fn connections_route(
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("admin" / "sessions" / String / "connections"))
+        .and_then(move |session_id: String| {
+            let manager = manager.clone();
+            async move {
+                let session = manager
+                    .read()
+                    .await
+                    .get_session(&session_id)
+                    .ok_or_else(warp::reject::not_found)?;
+                let connections = session.read().await.connections();
+                Ok::<_, warp::Rejection>(warp::reply::json(&connections))
+            }
+        })
+}
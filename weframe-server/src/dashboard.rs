@@ -0,0 +1,216 @@
+// weframe-server/src/dashboard.rs
+use crate::{SessionManager, VideoSession};
+use futures::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, RwLock};
+use warp::Filter;
+use weframe_shared::{EditOperation, OTOperation};
+
+/// How often summaries are published. Dashboards don't need more, and a
+/// fixed beat keeps the cost independent of how busy sessions are.
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Window `ops_per_minute` is counted over.
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Recent edits to a session, for its summary.
+#[derive(Default)]
+pub(crate) struct EditActivity {
+    recent: VecDeque<SystemTime>,
+    last: Option<LastEdit>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LastEdit {
+    /// Operation kind, e.g. `MoveClip`.
+    pub kind: String,
+    pub client_id: String,
+    pub name: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub at: u64,
+}
+
+/// What a producer dashboard shows for one session.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionSummary {
+    pub session_id: String,
+    pub name: String,
+    pub active_users: usize,
+    pub ops_per_minute: usize,
+    pub server_version: usize,
+    pub last_edit: Option<LastEdit>,
+}
+
+impl VideoSession {
+    /// Counts an applied operation towards the session's activity. Cursor
+    /// moves aren't edits and are left out.
+    pub(crate) fn record_edit(&mut self, operation: &OTOperation) {
+        if matches!(
+            operation.operation,
+            EditOperation::UpdateCollaboratorCursor { .. }
+        ) {
+            return;
+        }
+        let now = self.config.clock.now();
+        let activity = &mut self.activity;
+        activity.recent.push_back(now);
+        while activity
+            .recent
+            .front()
+            .is_some_and(|t| now.duration_since(*t).unwrap_or_default() > RATE_WINDOW)
+        {
+            activity.recent.pop_front();
+        }
+        activity.last = Some(LastEdit {
+            kind: operation.operation.kind().to_string(),
+            client_id: operation.client_id.clone(),
+            name: self
+                .project
+                .collaborators
+                .iter()
+                .find(|c| c.id == operation.client_id)
+                .map(|c| c.name.clone()),
+            at: now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        });
+    }
+
+    pub fn summary(&self, session_id: &str) -> SessionSummary {
+        let now = self.config.clock.now();
+        SessionSummary {
+            session_id: session_id.to_string(),
+            name: self.metadata.name().to_string(),
+            active_users: self.clients.len(),
+            ops_per_minute: self
+                .activity
+                .recent
+                .iter()
+                .filter(|t| now.duration_since(**t).unwrap_or_default() <= RATE_WINDOW)
+                .count(),
+            server_version: self.server_version,
+            last_edit: self.activity.last.clone(),
+        }
+    }
+}
+
+/// Summaries of every live session, by session id.
+pub async fn summaries(manager: &RwLock<SessionManager>) -> Vec<SessionSummary> {
+    let sessions: Vec<_> = manager
+        .read()
+        .await
+        .sessions()
+        .map(|(id, session)| (id.clone(), session.clone()))
+        .collect();
+    let mut summaries = Vec::with_capacity(sessions.len());
+    for (id, session) in sessions {
+        summaries.push(session.read().await.summary(&id));
+    }
+    summaries.sort_by(|a, b| a.session_id.cmp(&b.session_id));
+    summaries
+}
+
+/// Publishes session summaries every `SUMMARY_INTERVAL` on the returned
+/// topic, whether or not anyone is listening.
+pub fn start(manager: Arc<RwLock<SessionManager>>) -> broadcast::Sender<Arc<Vec<SessionSummary>>> {
+    let (sender, _) = broadcast::channel(4);
+    let topic = sender.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(SUMMARY_INTERVAL).await;
+            if topic.receiver_count() > 0 {
+                topic.send(Arc::new(summaries(&manager).await)).ok();
+            }
+        }
+    });
+    sender
+}
+
+#[derive(Deserialize)]
+struct DashboardQuery {
+    /// Comma-separated session ids to watch; all sessions if absent.
+    sessions: Option<String>,
+}
+
+impl DashboardQuery {
+    fn filter(&self, summaries: &[SessionSummary]) -> Vec<SessionSummary> {
+        let wanted: Option<Vec<&str>> = self.sessions.as_deref().map(|s| s.split(',').collect());
+        summaries
+            .iter()
+            .filter(|s| {
+                wanted
+                    .as_ref()
+                    .is_none_or(|ids| ids.contains(&s.session_id.as_str()))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+fn summary_event(summaries: &[SessionSummary]) -> Result<warp::sse::Event, Infallible> {
+    Ok(warp::sse::Event::default()
+        .event("summary")
+        .json_data(summaries)
+        .unwrap_or_else(|_| warp::sse::Event::default().comment("serialization failed")))
+}
+
+/// The current summaries, then every published batch after that. Batches
+/// missed by a slow reader are skipped; the next one supersedes them.
+fn summary_stream(
+    first: Vec<SessionSummary>,
+    topic: broadcast::Receiver<Arc<Vec<SessionSummary>>>,
+    query: DashboardQuery,
+) -> impl Stream<Item = Result<warp::sse::Event, Infallible>> {
+    let first = summary_event(&query.filter(&first));
+    let updates = stream::unfold((topic, query), |(mut topic, query)| async move {
+        loop {
+            match topic.recv().await {
+                Ok(summaries) => {
+                    let event = summary_event(&query.filter(&summaries));
+                    return Some((event, (topic, query)));
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    stream::once(async move { first }).chain(updates)
+}
+
+/// `GET /admin/dashboard?sessions=a,b` returns session summaries once, and
+/// `GET /admin/dashboard/events?sessions=a,b` streams them as server-sent
+/// `summary` events, so dashboards can watch many sessions over a single
+/// connection.
+pub fn dashboard_routes(
+    manager: Arc<RwLock<SessionManager>>,
+    topic: broadcast::Sender<Arc<Vec<SessionSummary>>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let snapshot_manager = manager.clone();
+    let snapshot = warp::get()
+        .and(warp::path!("admin" / "dashboard"))
+        .and(warp::query::<DashboardQuery>())
+        .and_then(move |query: DashboardQuery| {
+            let manager = snapshot_manager.clone();
+            async move {
+                let summaries = summaries(&manager).await;
+                Ok::<_, warp::Rejection>(warp::reply::json(&query.filter(&summaries)))
+            }
+        });
+
+    let events = warp::get()
+        .and(warp::path!("admin" / "dashboard" / "events"))
+        .and(warp::query::<DashboardQuery>())
+        .and_then(move |query: DashboardQuery| {
+            let manager = manager.clone();
+            let topic = topic.subscribe();
+            async move {
+                let first = summaries(&manager).await;
+                let stream = summary_stream(first, topic, query);
+                Ok::<_, warp::Rejection>(warp::sse::reply(warp::sse::keep_alive().stream(stream)))
+            }
+        });
+
+    snapshot.or(events)
+}
@@ -0,0 +1,445 @@
+// weframe-server/src/database.rs
+use crate::clock::Clock;
+use crate::hibernation::Snapshot;
+use crate::store::{ProjectStore, RecordedOperation, StoreFuture};
+use sqlx::any::AnyPoolOptions;
+use sqlx::AnyPool;
+use std::io;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tokio::sync::OnceCell;
+use weframe_shared::OTOperation;
+
+/// Tables shared by SQLite and Postgres. `operations` and `snapshots` are
+/// only ever appended to while a session exists, so its history can be
+/// replayed or audited.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sessions (
+    id TEXT PRIMARY KEY,
+    server_version BIGINT NOT NULL,
+    saved_at BIGINT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS projects (
+    session_id TEXT PRIMARY KEY,
+    project_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    project TEXT NOT NULL,
+    view_states TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS collaborators (
+    session_id TEXT NOT NULL,
+    collaborator_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    avatar_url TEXT,
+    PRIMARY KEY (session_id, collaborator_id)
+);
+CREATE TABLE IF NOT EXISTS operations (
+    session_id TEXT NOT NULL,
+    server_version BIGINT NOT NULL,
+    client_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    operation TEXT NOT NULL,
+    recorded_at BIGINT NOT NULL,
+    PRIMARY KEY (session_id, server_version)
+);
+CREATE TABLE IF NOT EXISTS operation_labels (
+    session_id TEXT NOT NULL,
+    server_version BIGINT NOT NULL,
+    label TEXT NOT NULL,
+    PRIMARY KEY (session_id, server_version)
+);
+CREATE TABLE IF NOT EXISTS snapshots (
+    session_id TEXT NOT NULL,
+    server_version BIGINT NOT NULL,
+    project TEXT NOT NULL,
+    label TEXT,
+    saved_at BIGINT NOT NULL,
+    PRIMARY KEY (session_id, server_version)
+);
+CREATE TABLE IF NOT EXISTS session_flags (
+    session_id TEXT PRIMARY KEY,
+    flags TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS session_workspaces (
+    session_id TEXT PRIMARY KEY,
+    workspace TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS session_owners (
+    session_id TEXT PRIMARY KEY,
+    owner TEXT NOT NULL
+);
+";
+
+fn db_error(e: sqlx::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+/// A session's latest snapshot: its version, project, view states, flags,
+/// intent label, workspace and owner.
+type SnapshotRow = (
+    i64,
+    String,
+    String,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
+/// Sessions, their projects and collaborators, and the log of operations
+/// applied to them, in a SQLite or Postgres database.
+pub struct DatabaseStore {
+    pool: AnyPool,
+    /// Set once the tables have been created.
+    schema: OnceCell<()>,
+    /// Stamps snapshots and operations with when they were recorded.
+    clock: Arc<dyn Clock>,
+}
+
+impl DatabaseStore {
+    /// Sets up a pool for `url`, e.g. `sqlite://weframe.db?mode=rwc` or
+    /// `postgres://user@host/weframe`. Nothing connects until the store is
+    /// first used.
+    pub fn connect(url: &str, clock: Arc<dyn Clock>) -> Result<Self, String> {
+        sqlx::any::install_default_drivers();
+        let pool = AnyPoolOptions::new()
+            .connect_lazy(url)
+            .map_err(|e| e.to_string())?;
+        Ok(DatabaseStore {
+            pool,
+            schema: OnceCell::new(),
+            clock,
+        })
+    }
+
+    fn unix_now(&self) -> i64 {
+        self.clock
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64
+    }
+
+    async fn pool(&self) -> io::Result<&AnyPool> {
+        self.schema
+            .get_or_try_init(|| async {
+                sqlx::raw_sql(SCHEMA).execute(&self.pool).await.map(|_| ())
+            })
+            .await
+            .map_err(db_error)?;
+        Ok(&self.pool)
+    }
+
+    async fn load_snapshot(&self, session_id: &str) -> io::Result<Option<Snapshot>> {
+        let row: Option<SnapshotRow> = sqlx::query_as(
+            "SELECT s.server_version, p.project, p.view_states, f.flags, h.label, w.workspace,
+                    o.owner
+             FROM sessions s JOIN projects p ON p.session_id = s.id
+             LEFT JOIN session_flags f ON f.session_id = s.id
+             LEFT JOIN session_workspaces w ON w.session_id = s.id
+             LEFT JOIN session_owners o ON o.session_id = s.id
+             LEFT JOIN snapshots h
+                 ON h.session_id = s.id AND h.server_version = s.server_version
+             WHERE s.id = $1",
+        )
+        .bind(session_id)
+        .fetch_optional(self.pool().await?)
+        .await
+        .map_err(db_error)?;
+        let Some((server_version, project, view_states, flags, label, workspace, owner)) = row
+        else {
+            return Ok(None);
+        };
+        let project = weframe_shared::migrate_project(serde_json::from_str(&project)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Some(Snapshot {
+            project,
+            server_version: server_version as usize,
+            view_states: serde_json::from_str(&view_states)?,
+            flags: flags
+                .map(|flags| serde_json::from_str(&flags))
+                .transpose()?,
+            label,
+            workspace,
+            owner,
+        }))
+    }
+
+    async fn save_snapshot(&self, session_id: &str, snapshot: &Snapshot) -> io::Result<()> {
+        let project = serde_json::to_string(&snapshot.project)?;
+        let view_states = serde_json::to_string(&snapshot.view_states)?;
+        let mut tx = self.pool().await?.begin().await.map_err(db_error)?;
+        sqlx::query(
+            "INSERT INTO sessions (id, server_version, saved_at) VALUES ($1, $2, $3)
+             ON CONFLICT (id) DO UPDATE
+             SET server_version = excluded.server_version, saved_at = excluded.saved_at",
+        )
+        .bind(session_id)
+        .bind(snapshot.server_version as i64)
+        .bind(self.unix_now())
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+        sqlx::query(
+            "INSERT INTO projects (session_id, project_id, name, project, view_states)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (session_id) DO UPDATE
+             SET project_id = excluded.project_id, name = excluded.name,
+                 project = excluded.project, view_states = excluded.view_states",
+        )
+        .bind(session_id)
+        .bind(&snapshot.project.id)
+        .bind(&snapshot.project.name)
+        .bind(&project)
+        .bind(view_states)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+        sqlx::query(
+            "INSERT INTO snapshots (session_id, server_version, project, label, saved_at)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (session_id, server_version) DO NOTHING",
+        )
+        .bind(session_id)
+        .bind(snapshot.server_version as i64)
+        .bind(project)
+        .bind(snapshot.label.clone())
+        .bind(self.unix_now())
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+        if let Some(flags) = &snapshot.flags {
+            sqlx::query(
+                "INSERT INTO session_flags (session_id, flags) VALUES ($1, $2)
+                 ON CONFLICT (session_id) DO UPDATE SET flags = excluded.flags",
+            )
+            .bind(session_id)
+            .bind(serde_json::to_string(flags)?)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        }
+        if let Some(workspace) = &snapshot.workspace {
+            sqlx::query(
+                "INSERT INTO session_workspaces (session_id, workspace) VALUES ($1, $2)
+                 ON CONFLICT (session_id) DO UPDATE SET workspace = excluded.workspace",
+            )
+            .bind(session_id)
+            .bind(workspace)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        }
+        if let Some(owner) = &snapshot.owner {
+            sqlx::query(
+                "INSERT INTO session_owners (session_id, owner) VALUES ($1, $2)
+                 ON CONFLICT (session_id) DO UPDATE SET owner = excluded.owner",
+            )
+            .bind(session_id)
+            .bind(owner)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        }
+        sqlx::query("DELETE FROM collaborators WHERE session_id = $1")
+            .bind(session_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        for collaborator in &snapshot.project.collaborators {
+            sqlx::query(
+                "INSERT INTO collaborators (session_id, collaborator_id, name, avatar_url)
+                 VALUES ($1, $2, $3, $4)",
+            )
+            .bind(session_id)
+            .bind(&collaborator.id)
+            .bind(&collaborator.name)
+            .bind(collaborator.avatar_url.clone())
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        }
+        tx.commit().await.map_err(db_error)
+    }
+
+    async fn remove_session(&self, session_id: &str) -> io::Result<()> {
+        let mut tx = self.pool().await?.begin().await.map_err(db_error)?;
+        for table in [
+            "operations",
+            "operation_labels",
+            "snapshots",
+            "collaborators",
+            "session_flags",
+            "session_workspaces",
+            "session_owners",
+            "projects",
+        ] {
+            sqlx::query(&format!("DELETE FROM {} WHERE session_id = $1", table))
+                .bind(session_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+        }
+        sqlx::query("DELETE FROM sessions WHERE id = $1")
+            .bind(session_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)
+    }
+
+    async fn select_session_ids(&self) -> io::Result<Vec<String>> {
+        let rows: Vec<(String,)> = sqlx::query_as("SELECT id FROM sessions")
+            .fetch_all(self.pool().await?)
+            .await
+            .map_err(db_error)?;
+        Ok(rows.into_iter().map(|(id,)| id).collect())
+    }
+
+    async fn insert_operations(
+        &self,
+        session_id: &str,
+        first_version: usize,
+        operations: &[OTOperation],
+    ) -> io::Result<()> {
+        let recorded_at = self.unix_now();
+        let mut tx = self.pool().await?.begin().await.map_err(db_error)?;
+        for (offset, operation) in operations.iter().enumerate() {
+            sqlx::query(
+                "INSERT INTO operations
+                 (session_id, server_version, client_id, kind, operation, recorded_at)
+                 VALUES ($1, $2, $3, $4, $5, $6)
+                 ON CONFLICT (session_id, server_version) DO NOTHING",
+            )
+            .bind(session_id)
+            .bind((first_version + offset) as i64)
+            .bind(&operation.client_id)
+            .bind(operation.operation.kind())
+            .bind(serde_json::to_string(&operation.operation)?)
+            .bind(recorded_at)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+            if let Some(label) = &operation.label {
+                sqlx::query(
+                    "INSERT INTO operation_labels (session_id, server_version, label)
+                     VALUES ($1, $2, $3)
+                     ON CONFLICT (session_id, server_version) DO NOTHING",
+                )
+                .bind(session_id)
+                .bind((first_version + offset) as i64)
+                .bind(label)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+            }
+        }
+        tx.commit().await.map_err(db_error)
+    }
+
+    async fn load_snapshot_at(
+        &self,
+        session_id: &str,
+        server_version: usize,
+    ) -> io::Result<Option<Snapshot>> {
+        let row: Option<(i64, String, Option<String>)> = sqlx::query_as(
+            "SELECT server_version, project, label FROM snapshots
+             WHERE session_id = $1 AND server_version <= $2
+             ORDER BY server_version DESC LIMIT 1",
+        )
+        .bind(session_id)
+        .bind(server_version as i64)
+        .fetch_optional(self.pool().await?)
+        .await
+        .map_err(db_error)?;
+        let Some((server_version, project, label)) = row else {
+            return Ok(None);
+        };
+        let project = weframe_shared::migrate_project(serde_json::from_str(&project)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Some(Snapshot {
+            project,
+            server_version: server_version as usize,
+            view_states: Default::default(),
+            flags: None,
+            label,
+            workspace: None,
+            owner: None,
+        }))
+    }
+
+    async fn select_operations(
+        &self,
+        session_id: &str,
+        from: usize,
+        to: usize,
+    ) -> io::Result<Vec<RecordedOperation>> {
+        let rows: Vec<(i64, String, Option<String>)> = sqlx::query_as(
+            "SELECT o.server_version, o.operation, l.label FROM operations o
+             LEFT JOIN operation_labels l
+                 ON l.session_id = o.session_id AND l.server_version = o.server_version
+             WHERE o.session_id = $1 AND o.server_version >= $2 AND o.server_version < $3
+             ORDER BY o.server_version",
+        )
+        .bind(session_id)
+        .bind(from as i64)
+        .bind(to as i64)
+        .fetch_all(self.pool().await?)
+        .await
+        .map_err(db_error)?;
+        rows.into_iter()
+            .map(|(server_version, operation, label)| {
+                Ok(RecordedOperation {
+                    server_version: server_version as usize,
+                    operation: serde_json::from_str(&operation)?,
+                    label,
+                })
+            })
+            .collect()
+    }
+}
+
+impl ProjectStore for DatabaseStore {
+    fn load<'a>(&'a self, session_id: &'a str) -> StoreFuture<'a, Option<Snapshot>> {
+        Box::pin(self.load_snapshot(session_id))
+    }
+
+    fn save<'a>(&'a self, session_id: &'a str, snapshot: &'a Snapshot) -> StoreFuture<'a, ()> {
+        Box::pin(self.save_snapshot(session_id, snapshot))
+    }
+
+    /// Deletes the session along with its operation log.
+    fn remove<'a>(&'a self, session_id: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(self.remove_session(session_id))
+    }
+
+    fn session_ids(&self) -> StoreFuture<'_, Vec<String>> {
+        Box::pin(self.select_session_ids())
+    }
+
+    fn append_operations<'a>(
+        &'a self,
+        session_id: &'a str,
+        first_version: usize,
+        operations: &'a [OTOperation],
+    ) -> StoreFuture<'a, ()> {
+        Box::pin(self.insert_operations(session_id, first_version, operations))
+    }
+
+    fn snapshot_at<'a>(
+        &'a self,
+        session_id: &'a str,
+        server_version: usize,
+    ) -> StoreFuture<'a, Option<Snapshot>> {
+        Box::pin(self.load_snapshot_at(session_id, server_version))
+    }
+
+    fn operations<'a>(
+        &'a self,
+        session_id: &'a str,
+        from: usize,
+        to: usize,
+    ) -> StoreFuture<'a, Vec<RecordedOperation>> {
+        Box::pin(self.select_operations(session_id, from, to))
+    }
+}
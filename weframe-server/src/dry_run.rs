@@ -0,0 +1,94 @@
+// weframe-server/src/dry_run.rs
+use crate::replies::MAX_JSON_BODY_BYTES;
+use crate::{auth, roles, SessionManager, VideoSession};
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use warp::Filter;
+use weframe_shared::{Adjustment, EditOperation, OTOperation, RejectionCode, Role};
+
+/// What the server would do with an operation if it were sent now.
+#[derive(Debug, Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum DryRunOutcome {
+    /// It would be applied as `operation`, after snapping to frames,
+    /// clamping and transforming, with these follow-on adjustments.
+    Accepted {
+        operation: Box<OTOperation>,
+        adjustments: Vec<Adjustment>,
+    },
+    Rejected {
+        code: RejectionCode,
+        message: String,
+    },
+}
+
+impl VideoSession {
+    /// Runs `operation` through the same checks as an edit by a client with
+    /// `role` and applies it to a copy of the project, leaving the session
+    /// untouched.
+    pub fn dry_run(&self, role: Role, operation: EditOperation) -> DryRunOutcome {
+        if let Err(message) = roles::permits(role, &operation) {
+            return DryRunOutcome::Rejected {
+                code: RejectionCode::Forbidden,
+                message,
+            };
+        }
+        if let Err(message) = self.check_frozen(&operation) {
+            return DryRunOutcome::Rejected {
+                code: RejectionCode::Frozen,
+                message,
+            };
+        }
+        let mut client_op = OTOperation {
+            client_id: "dry-run".to_string(),
+            client_version: 0,
+            server_version: self.server_version,
+            operation,
+            label: None,
+        };
+        if let Err((code, message)) = self
+            .prepare_operation("dry-run", &mut client_op)
+            .map_err(|message| (RejectionCode::Invalid, message))
+            .and_then(|()| {
+                self.check_memory(&client_op)
+                    .map_err(|message| (RejectionCode::OverLimit, message))
+            })
+        {
+            return DryRunOutcome::Rejected { code, message };
+        }
+        client_op.server_version = self.server_version;
+        let adjustments = self.project.clone().apply_operation(&client_op.operation);
+        DryRunOutcome::Accepted {
+            operation: Box::new(client_op),
+            adjustments,
+        }
+    }
+}
+
+/// `POST /sessions/:id/dry-run` with an `EditOperation` reports whether the
+/// session would accept it and in what form, without applying it, so UIs
+/// can e.g. refuse a drop before sending it.
+pub fn dry_run_route(
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("sessions" / String / "dry-run"))
+        .and(warp::body::content_length_limit(MAX_JSON_BODY_BYTES))
+        .and(warp::body::json())
+        .and(auth::request_role(manager.clone()))
+        .and_then(
+            move |session_id: String, operation: EditOperation, role: Role| {
+                let manager = manager.clone();
+                async move {
+                    let session = manager
+                        .read()
+                        .await
+                        .get_session(&session_id)
+                        .ok_or_else(warp::reject::not_found)?;
+                    let outcome = session.read().await.dry_run(role, operation);
+                    Ok::<_, warp::Rejection>(warp::reply::json(&outcome))
+                }
+            },
+        )
+}
@@ -0,0 +1,93 @@
+// weframe-server/src/effect_chain.rs
+use crate::automation::{Script, ScriptStep};
+use crate::replies::{error_reply, MAX_JSON_BODY_BYTES};
+use crate::{auth, SessionManager};
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use warp::http::StatusCode;
+use warp::Filter;
+use weframe_shared::{EditOperation, EffectChain, Role};
+
+#[derive(Debug, Deserialize)]
+pub struct ApplyChainRequest {
+    pub chain: EffectChain,
+    /// Clips whose effects the chain replaces.
+    pub clip_ids: Vec<String>,
+}
+
+/// `GET /sessions/:id/clips/:clip_id/effect-chain` exports a clip's effects
+/// as a portable snippet, and `POST /sessions/:id/effect-chain` with
+/// `{"chain": ..., "clip_ids": [...]}` pastes one onto clips, replacing
+/// their effects, all or nothing.
+pub fn effect_chain_routes(
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let export_manager = manager.clone();
+    let export = warp::get()
+        .and(warp::path!(
+            "sessions" / String / "clips" / String / "effect-chain"
+        ))
+        .and_then(move |session_id: String, clip_id: String| {
+            let manager = export_manager.clone();
+            async move {
+                let Some(session) = manager.read().await.get_session(&session_id) else {
+                    return Err(warp::reject::not_found());
+                };
+                let chain = session.read().await.project().effect_chain(&clip_id);
+                Ok(match chain {
+                    Ok(chain) => {
+                        warp::reply::with_status(warp::reply::json(&chain), StatusCode::OK)
+                    }
+                    Err(message) => error_reply(message, StatusCode::UNPROCESSABLE_ENTITY),
+                })
+            }
+        });
+
+    let apply = warp::post()
+        .and(warp::path!("sessions" / String / "effect-chain"))
+        .and(warp::body::content_length_limit(MAX_JSON_BODY_BYTES))
+        .and(warp::body::json())
+        .and(auth::request_role(manager.clone()))
+        .and_then(
+            move |session_id: String, request: ApplyChainRequest, role: Role| {
+                let manager = manager.clone();
+                async move {
+                    let (session, ids) = {
+                        let manager = manager.read().await;
+                        (manager.get_session(&session_id), manager.config.ids.clone())
+                    };
+                    let Some(session) = session else {
+                        return Err(warp::reject::not_found());
+                    };
+                    if let Err(message) = request.chain.check_version() {
+                        return Ok(error_reply(message, StatusCode::UNPROCESSABLE_ENTITY));
+                    }
+                    let script = Script {
+                        steps: request
+                            .clip_ids
+                            .into_iter()
+                            .map(|clip_id| {
+                                ScriptStep::Operation(Box::new(EditOperation::SetClipEffects {
+                                    clip_id,
+                                    effects: request.chain.to_effects(&ids),
+                                }))
+                            })
+                            .collect(),
+                    };
+                    let result = session.write().await.run_script(role, &script);
+                    Ok(match result {
+                        Ok(report) => {
+                            warp::reply::with_status(warp::reply::json(&report), StatusCode::OK)
+                        }
+                        Err(error) => {
+                            let status = error.status();
+                            warp::reply::with_status(warp::reply::json(&error), status)
+                        }
+                    })
+                }
+            },
+        );
+
+    export.or(apply)
+}
@@ -0,0 +1,103 @@
+// weframe-server/src/flags.rs
+use crate::replies::{error_reply, MAX_JSON_BODY_BYTES};
+use crate::{SessionManager, VideoSession};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use warp::http::StatusCode;
+use warp::Filter;
+use weframe_shared::{EditOperation, ServerMessage, SESSION_FLAGS};
+
+/// The flags named in `names`, or an error naming one this build doesn't
+/// know.
+pub fn parse_flags<S: AsRef<str>>(names: &[S]) -> Result<BTreeSet<String>, String> {
+    names
+        .iter()
+        .map(|name| {
+            let name = name.as_ref().trim();
+            if SESSION_FLAGS.contains(&name) {
+                Ok(name.to_string())
+            } else {
+                Err(format!("Unknown session flag: {}", name))
+            }
+        })
+        .collect()
+}
+
+impl VideoSession {
+    /// Replaces the session's flags and tells every client. Flags aren't
+    /// versioned, so the next snapshot is forced to include them.
+    pub fn set_flags(&mut self, flags: BTreeSet<String>) {
+        if flags == self.metadata.flags {
+            return;
+        }
+        self.metadata.flags = flags;
+        self.persisted_version = None;
+        self.broadcast_message(&ServerMessage::SessionFlags(
+            self.metadata.flags.iter().cloned().collect(),
+        ));
+    }
+
+    /// Refuses operations that turn on a behavior the session's flags keep
+    /// off.
+    pub(crate) fn check_flags(&self, op: &EditOperation) -> Result<(), String> {
+        let needed = match op {
+            EditOperation::Batch(operations) => {
+                return operations.iter().try_for_each(|op| self.check_flags(op))
+            }
+            EditOperation::SetMagneticTimeline(true) => "magnetic_timeline",
+            EditOperation::SetSnapToFrames(true) => "frame_quantization",
+            _ => return Ok(()),
+        };
+        if self.metadata.has_flag(needed) {
+            Ok(())
+        } else {
+            Err(format!("{} is not enabled in this session", needed))
+        }
+    }
+}
+
+/// `GET /sessions/:id/flags` lists the flags enabled in a session.
+pub fn flag_route(
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("sessions" / String / "flags"))
+        .and_then(move |session_id: String| {
+            let manager = manager.clone();
+            async move {
+                let Some(session) = manager.read().await.get_session(&session_id) else {
+                    return Err(warp::reject::not_found());
+                };
+                let session = session.read().await;
+                Ok::<_, warp::Rejection>(warp::reply::json(session.metadata().flags()))
+            }
+        })
+}
+
+/// `PUT /admin/sessions/:id/flags` with a list of flag names replaces the
+/// flags enabled in a session.
+pub fn set_flags_route(
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl Filter<Extract = (Box<dyn warp::Reply>,), Error = warp::Rejection> + Clone {
+    warp::put()
+        .and(warp::path!("admin" / "sessions" / String / "flags"))
+        .and(warp::body::content_length_limit(MAX_JSON_BODY_BYTES))
+        .and(warp::body::json())
+        .and_then(move |session_id: String, names: Vec<String>| {
+            let manager = manager.clone();
+            async move {
+                let Some(session) = manager.read().await.get_session(&session_id) else {
+                    return Err(warp::reject::not_found());
+                };
+                let reply = match parse_flags(&names) {
+                    Ok(flags) => {
+                        session.write().await.set_flags(flags);
+                        Box::new(StatusCode::NO_CONTENT) as Box<dyn warp::Reply>
+                    }
+                    Err(message) => Box::new(error_reply(message, StatusCode::BAD_REQUEST)),
+                };
+                Ok::<_, warp::Rejection>(reply)
+            }
+        })
+}
@@ -0,0 +1,225 @@
+// weframe-server/src/freeze.rs
+use crate::replies::{error_reply, MAX_JSON_BODY_BYTES};
+use crate::{SessionManager, VideoSession};
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use warp::http::StatusCode;
+use warp::Filter;
+use weframe_shared::{EditOperation, Role, ServerMessage};
+
+/// Longest reason a session can be frozen with, in bytes.
+const MAX_FREEZE_REASON: usize = 500;
+
+/// Reason given when a freeze doesn't say why.
+const DEFAULT_FREEZE_REASON: &str = "Maintenance in progress";
+
+impl VideoSession {
+    /// Why the session is frozen, if it is.
+    pub fn frozen(&self) -> Option<&str> {
+        self.frozen.as_deref()
+    }
+
+    /// Refuses clients' edits until `thaw`, e.g. while a migration, large
+    /// import or restore runs, and tells every client why. Operations the
+    /// server makes itself still apply. A session unloaded while frozen
+    /// comes back thawed.
+    pub fn freeze(&mut self, reason: &str) -> Result<(), String> {
+        let reason = match reason.trim() {
+            "" => DEFAULT_FREEZE_REASON,
+            reason => reason,
+        };
+        if reason.len() > MAX_FREEZE_REASON {
+            return Err(format!(
+                "Freeze reason is {} bytes, over the {} byte limit",
+                reason.len(),
+                MAX_FREEZE_REASON
+            ));
+        }
+        println!("Froze session {}: {}", self.metadata.name, reason);
+        self.frozen = Some(reason.to_string());
+        self.broadcast_message(&ServerMessage::Frozen {
+            reason: self.frozen.clone(),
+        });
+        Ok(())
+    }
+
+    /// Lets clients edit again, and sends each of them the project as it
+    /// now is, since whatever ran meanwhile may have changed it wholesale.
+    pub fn thaw(&mut self) {
+        if self.frozen.take().is_none() {
+            return;
+        }
+        println!("Thawed session {}", self.metadata.name);
+        self.broadcast_message(&ServerMessage::Frozen { reason: None });
+        for client_id in self.clients.keys() {
+            self.send_full_project(client_id);
+        }
+    }
+
+    /// Freezes or thaws the session at a client's request; only its owner
+    /// may.
+    pub(crate) fn request_freeze(
+        &mut self,
+        client_id: &str,
+        reason: Option<String>,
+    ) -> Result<(), String> {
+        if self.role_of(client_id) < Role::Owner {
+            return Err("Only the session's owner can freeze it".to_string());
+        }
+        match reason {
+            Some(reason) => self.freeze(&reason),
+            None => {
+                self.thaw();
+                Ok(())
+            }
+        }
+    }
+
+    /// Refuses edits while the session is frozen, whether a client sends
+    /// them or an HTTP route makes them for its caller. Cursor moves and
+    /// other operations a viewer may make aren't edits and still go through.
+    pub(crate) fn check_frozen(&self, op: &EditOperation) -> Result<(), String> {
+        match &self.frozen {
+            Some(reason) if Role::required_for(op) > Role::Viewer => {
+                Err(format!("Session is frozen for maintenance: {}", reason))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Tells a joining client the session is frozen, if it is.
+    pub(crate) fn announce_freeze(&self, client_id: &str) {
+        if self.frozen.is_some() {
+            self.send_to(
+                client_id,
+                &ServerMessage::Frozen {
+                    reason: self.frozen.clone(),
+                },
+            );
+        }
+    }
+}
+
+/// Answers an HTTP request for an edit a frozen session refuses: the 409
+/// of `RejectionCode::Frozen`, saying why it is frozen.
+pub(crate) fn frozen(message: String) -> warp::reply::WithStatus<warp::reply::Json> {
+    error_reply(message, StatusCode::CONFLICT)
+}
+
+#[derive(Deserialize)]
+struct FreezeRequest {
+    #[serde(default)]
+    reason: String,
+}
+
+/// `PUT /admin/sessions/:id/freeze` with `{"reason": ...}` freezes a
+/// session for maintenance, and `DELETE /admin/sessions/:id/freeze` thaws
+/// it.
+pub fn freeze_routes(
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl Filter<Extract = (Box<dyn warp::Reply>,), Error = warp::Rejection> + Clone {
+    let freeze_manager = manager.clone();
+    let freeze = warp::put()
+        .and(warp::path!("admin" / "sessions" / String / "freeze"))
+        .and(warp::body::content_length_limit(MAX_JSON_BODY_BYTES))
+        .and(warp::body::json())
+        .and_then(move |session_id: String, request: FreezeRequest| {
+            let manager = freeze_manager.clone();
+            async move {
+                let Some(session) = manager.read().await.get_session(&session_id) else {
+                    return Err(warp::reject::not_found());
+                };
+                let reply = match session.write().await.freeze(&request.reason) {
+                    Ok(()) => Box::new(StatusCode::NO_CONTENT) as Box<dyn warp::Reply>,
+                    Err(message) => Box::new(error_reply(message, StatusCode::BAD_REQUEST)),
+                };
+                Ok::<_, warp::Rejection>(reply)
+            }
+        });
+
+    let thaw = warp::delete()
+        .and(warp::path!("admin" / "sessions" / String / "freeze"))
+        .and_then(move |session_id: String| {
+            let manager = manager.clone();
+            async move {
+                let Some(session) = manager.read().await.get_session(&session_id) else {
+                    return Err(warp::reject::not_found());
+                };
+                session.write().await.thaw();
+                Ok::<_, warp::Rejection>(Box::new(StatusCode::NO_CONTENT) as Box<dyn warp::Reply>)
+            }
+        });
+
+    freeze.or(thaw).unify()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_or_create_session, outbox, relink};
+    use weframe_shared::{OTOperation, RejectionCode};
+
+    #[tokio::test]
+    async fn frozen_sessions_refuse_edits_from_clients_and_http_callers() {
+        let manager = Arc::new(RwLock::new(SessionManager::new()));
+        let session = get_or_create_session(&manager, "s").await;
+        let (sender, mut outbox) = outbox::outbox();
+        {
+            let mut session = session.write().await;
+            session.add_client("me".to_string(), sender, Arc::default());
+            session.clients.get_mut("me").unwrap().protocol_version = 2;
+            session.freeze("Restoring a backup").unwrap();
+            session.handle_client_operation(
+                "me",
+                OTOperation {
+                    client_id: "me".to_string(),
+                    client_version: 0,
+                    server_version: 0,
+                    operation: EditOperation::SetSnapToFrames(true),
+                    label: None,
+                },
+            );
+            assert_eq!(session.server_version, 0);
+        }
+        loop {
+            let message = outbox.recv().await.unwrap();
+            let message: ServerMessage = serde_json::from_str(message.to_str().unwrap()).unwrap();
+            if let ServerMessage::OperationRejected { code, message, .. } = message {
+                assert_eq!(code, RejectionCode::Frozen);
+                assert!(message.contains("Restoring a backup"));
+                break;
+            }
+        }
+
+        let routes = relink::relink_route(manager);
+        let relink = || {
+            warp::test::request()
+                .method("POST")
+                .path("/sessions/s/relink")
+                .json(&serde_json::json!({ "from": { "asset_id": "a" }, "new_asset_id": "b" }))
+        };
+        let refused = relink().reply(&routes).await;
+        assert_eq!(refused.status(), StatusCode::CONFLICT);
+        let body: serde_json::Value = serde_json::from_slice(refused.body()).unwrap();
+        assert!(body["error"]
+            .as_str()
+            .unwrap()
+            .contains("Restoring a backup"));
+
+        session.write().await.thaw();
+        // A protocol 2 client that didn't negotiate chunked_sync is resynced
+        // with a single ProjectUpdate
+        loop {
+            let message = outbox.recv().await.unwrap();
+            match serde_json::from_str(message.to_str().unwrap()).unwrap() {
+                ServerMessage::ProjectUpdate(_) => break,
+                ServerMessage::SyncBegin { .. } => panic!("Sent a chunked sync unasked"),
+                _ => {}
+            }
+        }
+        // Thawed, it gets as far as finding no clip to relink
+        let allowed = relink().reply(&routes).await;
+        assert_eq!(allowed.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
@@ -0,0 +1,76 @@
+// weframe-server/src/guests.rs
+use crate::VideoSession;
+use weframe_shared::IdGenerator;
+
+const ADJECTIVES: &[&str] = &[
+    "Amber", "Brave", "Bright", "Calm", "Clever", "Cosmic", "Crimson", "Curious", "Dapper",
+    "Eager", "Gentle", "Golden", "Happy", "Jolly", "Keen", "Lively", "Lucky", "Mellow", "Misty",
+    "Nimble", "Polar", "Quiet", "Rapid", "Rosy", "Silver", "Sleepy", "Sunny", "Swift", "Teal",
+    "Velvet", "Witty", "Zesty",
+];
+
+const ANIMALS: &[&str] = &[
+    "Badger", "Beaver", "Bison", "Crane", "Dolphin", "Falcon", "Ferret", "Fox", "Gecko", "Heron",
+    "Ibis", "Koala", "Lemur", "Lynx", "Marmot", "Moose", "Narwhal", "Ocelot", "Otter", "Owl",
+    "Panda", "Puffin", "Quokka", "Raven", "Salmon", "Seal", "Sparrow", "Tapir", "Tiger", "Walrus",
+    "Wombat", "Yak",
+];
+
+/// Longest guest token a client may present.
+const MAX_TOKEN_LEN: usize = 128;
+
+/// FNV-1a, so a token maps to the same name across server builds.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Adjective-animal name picked by `seed`.
+fn guest_name(seed: u64) -> String {
+    let adjective = ADJECTIVES[(seed % ADJECTIVES.len() as u64) as usize];
+    let animal = ANIMALS[(seed / ADJECTIVES.len() as u64 % ANIMALS.len() as u64) as usize];
+    format!("{} {}", adjective, animal)
+}
+
+/// Tokens that are empty, too long or not printable ASCII are ignored.
+fn valid_token(token: &str) -> bool {
+    !token.is_empty() && token.len() <= MAX_TOKEN_LEN && token.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// Name for a guest presenting `token`, the same every time the token is
+/// seen.
+pub fn name_for_token(token: &str) -> Option<String> {
+    valid_token(token).then(|| guest_name(fnv1a(token.as_bytes())))
+}
+
+/// Key for state kept per user, derived from their token so the token
+/// itself is never stored.
+pub(crate) fn user_key(token: &str) -> Option<String> {
+    valid_token(token).then(|| format!("{:016x}", fnv1a(token.as_bytes())))
+}
+
+/// A fresh guest name for a client that hasn't presented a token.
+pub fn random_name(ids: &IdGenerator) -> String {
+    guest_name(ids.number())
+}
+
+impl VideoSession {
+    /// `name`, or `name 2`, `name 3`... if another collaborator in the
+    /// session already goes by it.
+    pub(crate) fn unique_name(&self, client_id: &str, name: String) -> String {
+        let taken = |candidate: &str| {
+            self.project
+                .collaborators
+                .iter()
+                .any(|c| c.id != client_id && c.name == candidate)
+        };
+        if !taken(&name) {
+            return name;
+        }
+        (2..)
+            .map(|n| format!("{} {}", name, n))
+            .find(|candidate| !taken(candidate))
+            .unwrap()
+    }
+}
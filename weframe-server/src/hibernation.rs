@@ -0,0 +1,300 @@
+// weframe-server/src/hibernation.rs
+use crate::clock::Clock;
+use crate::{get_or_create_session, memory, SessionManager, VideoSession};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tokio::sync::RwLock;
+use warp::Filter;
+use weframe_shared::{VideoProject, ViewState};
+
+/// What survives hibernation. The op log and connected clients do not;
+/// `server_version` does, so history cursors stay meaningful, and so do
+/// users' private view states.
+#[derive(Serialize, Deserialize)]
+struct HibernatedSession {
+    #[serde(default)]
+    session_id: String,
+    /// Unix seconds when the snapshot was written.
+    #[serde(default)]
+    saved_at: u64,
+    server_version: usize,
+    /// Kept as raw JSON so older snapshots go through schema migration.
+    project: Value,
+    #[serde(default)]
+    view_states: HashMap<String, ViewState>,
+    /// Missing from snapshots written before sessions had flags.
+    #[serde(default)]
+    flags: Option<BTreeSet<String>>,
+    #[serde(default)]
+    label: Option<String>,
+    #[serde(default)]
+    workspace: Option<String>,
+    #[serde(default)]
+    owner: Option<String>,
+}
+
+/// A session read back from disk.
+pub struct Snapshot {
+    pub project: VideoProject,
+    pub server_version: usize,
+    pub view_states: HashMap<String, ViewState>,
+    /// `None` leaves the session with the configured default flags.
+    pub flags: Option<BTreeSet<String>>,
+    /// Intent label of the latest labeled operation before the snapshot.
+    pub label: Option<String>,
+    /// Workspace the session belongs to, if any.
+    pub workspace: Option<String>,
+    /// User key of the session's owner, once one has been recorded.
+    pub owner: Option<String>,
+}
+
+/// Summary of a stored snapshot, for listings.
+#[derive(Debug, Serialize)]
+pub struct SnapshotInfo {
+    pub session_id: String,
+    pub saved_at: u64,
+    pub server_version: usize,
+}
+
+/// Sessions written to disk, one JSON file per session. Used both for
+/// hibernation and for the recycle bin.
+#[derive(Clone)]
+pub struct HibernationStore {
+    root: PathBuf,
+    /// Stamps snapshots with when they were written.
+    clock: Arc<dyn Clock>,
+}
+
+impl HibernationStore {
+    pub fn new(root: PathBuf, clock: Arc<dyn Clock>) -> Self {
+        HibernationStore { root, clock }
+    }
+
+    /// Session ids come from URLs, so anything beyond a conservative set of
+    /// characters is hex-encoded to keep file names safe.
+    fn path(&self, session_id: &str) -> PathBuf {
+        let safe = !session_id.is_empty()
+            && session_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        let name = if safe {
+            session_id.to_string()
+        } else {
+            format!("x-{}", crate::media::hex(session_id.as_bytes()))
+        };
+        self.root.join(format!("{}.json", name))
+    }
+
+    pub async fn save(&self, session_id: &str, session: &VideoSession) -> io::Result<()> {
+        self.write(session_id, &session.snapshot()).await
+    }
+
+    pub async fn write(&self, session_id: &str, snapshot: &Snapshot) -> io::Result<()> {
+        let snapshot = HibernatedSession {
+            session_id: session_id.to_string(),
+            saved_at: self
+                .clock
+                .now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            server_version: snapshot.server_version,
+            project: serde_json::to_value(&snapshot.project)?,
+            view_states: snapshot.view_states.clone(),
+            flags: snapshot.flags.clone(),
+            label: snapshot.label.clone(),
+            workspace: snapshot.workspace.clone(),
+            owner: snapshot.owner.clone(),
+        };
+        tokio::fs::create_dir_all(&self.root).await?;
+        let path = self.path(session_id);
+        let temp_path = path.with_extension("json.tmp");
+        tokio::fs::write(&temp_path, serde_json::to_vec(&snapshot)?).await?;
+        tokio::fs::rename(&temp_path, &path).await
+    }
+
+    /// Reads a stored session, leaving it in place. `Ok(None)` if there is
+    /// none.
+    pub async fn load(&self, session_id: &str) -> io::Result<Option<Snapshot>> {
+        let bytes = match tokio::fs::read(self.path(session_id)).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let snapshot: HibernatedSession = serde_json::from_slice(&bytes)?;
+        let project = weframe_shared::migrate_project(snapshot.project)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Some(Snapshot {
+            project,
+            server_version: snapshot.server_version,
+            view_states: snapshot.view_states,
+            flags: snapshot.flags,
+            label: snapshot.label,
+            workspace: snapshot.workspace,
+            owner: snapshot.owner,
+        }))
+    }
+
+    /// Reads and removes a hibernated session. `Ok(None)` if there is none.
+    pub async fn take(&self, session_id: &str) -> io::Result<Option<Snapshot>> {
+        let snapshot = self.load(session_id).await?;
+        if snapshot.is_some() {
+            tokio::fs::remove_file(self.path(session_id)).await?;
+        }
+        Ok(snapshot)
+    }
+
+    pub async fn contains(&self, session_id: &str) -> bool {
+        tokio::fs::try_exists(self.path(session_id))
+            .await
+            .unwrap_or(false)
+    }
+
+    pub async fn remove(&self, session_id: &str) -> io::Result<()> {
+        match tokio::fs::remove_file(self.path(session_id)).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Every stored snapshot, skipping files that can't be read.
+    pub async fn list(&self) -> io::Result<Vec<SnapshotInfo>> {
+        let mut entries = match tokio::fs::read_dir(&self.root).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut snapshots = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.path().extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let Ok(bytes) = tokio::fs::read(entry.path()).await else {
+                continue;
+            };
+            if let Ok(snapshot) = serde_json::from_slice::<HibernatedSession>(&bytes) {
+                snapshots.push(SnapshotInfo {
+                    session_id: snapshot.session_id,
+                    saved_at: snapshot.saved_at,
+                    server_version: snapshot.server_version,
+                });
+            }
+        }
+        snapshots.sort_by_key(|s| s.saved_at);
+        Ok(snapshots)
+    }
+}
+
+impl VideoSession {
+    /// What `restore` needs to bring the session back.
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            project: self.project.clone(),
+            server_version: self.server_version,
+            view_states: self.view_states.clone(),
+            flags: Some(self.metadata.flags.clone()),
+            label: self.label.clone(),
+            workspace: self.metadata.workspace.clone(),
+            owner: self.metadata.owner.clone(),
+        }
+    }
+
+    /// Replaces a freshly created session's state with a rehydrated one.
+    pub(crate) fn restore(&mut self, snapshot: Snapshot) {
+        self.memory.project = memory::approx_size(&snapshot.project);
+        self.project = snapshot.project;
+        self.server_version = snapshot.server_version;
+        self.op_log_start = snapshot.server_version;
+        self.view_states = snapshot.view_states;
+        if let Some(flags) = snapshot.flags {
+            self.metadata.flags = flags;
+        }
+        self.label = snapshot.label;
+        if snapshot.workspace.is_some() {
+            self.metadata.workspace = snapshot.workspace;
+        }
+        if snapshot.owner.is_some() {
+            self.metadata.owner = snapshot.owner;
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PrewarmReport {
+    session_id: String,
+    /// Whether the session was already in memory before this request.
+    already_loaded: bool,
+    server_version: usize,
+    clips: usize,
+}
+
+/// `POST /sessions/:id/prewarm` loads a session ahead of a scheduled
+/// collaboration so the first joiner doesn't wait for rehydration, and
+/// resets its idle clock so it isn't hibernated again before then.
+pub fn prewarm_route(
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("sessions" / String / "prewarm"))
+        .and_then(move |session_id: String| {
+            let manager = manager.clone();
+            async move {
+                let already_loaded = manager.read().await.get_session(&session_id).is_some();
+                let session = get_or_create_session(&manager, &session_id).await;
+
+                let mut session = session.write().await;
+                session.last_activity = session.config.clock.now();
+                Ok::<_, warp::Rejection>(warp::reply::json(&PrewarmReport {
+                    session_id,
+                    already_loaded,
+                    server_version: session.server_version,
+                    clips: session.project.clips.len(),
+                }))
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::{cleanup_inactive_sessions, ServerConfig};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn idle_sessions_hibernate_and_rehydrate() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_000_000));
+        let dir = std::env::temp_dir().join(format!("weframe-test-{}", uuid::Uuid::new_v4()));
+        let config = ServerConfig {
+            clock: Arc::new(clock.clone()),
+            hibernate_dir: Some(dir.clone()),
+            session_idle_timeout: Duration::from_secs(600),
+            ..ServerConfig::default()
+        };
+        let manager = RwLock::new(SessionManager::with_config(Arc::new(config)));
+        let session = get_or_create_session(&manager, "idle").await;
+        session.write().await.project.name = "Pilot".to_string();
+        drop(session);
+
+        clock.advance(Duration::from_secs(599));
+        cleanup_inactive_sessions(&manager).await;
+        assert!(manager.read().await.get_session("idle").is_some());
+
+        clock.advance(Duration::from_secs(1));
+        cleanup_inactive_sessions(&manager).await;
+        assert!(manager.read().await.get_session("idle").is_none());
+        let store = manager.read().await.hibernation.clone().unwrap();
+        assert!(store.contains("idle").await);
+
+        let session = get_or_create_session(&manager, "idle").await;
+        assert_eq!(session.read().await.project.name, "Pilot");
+        // Kept until the session is persisted or hibernated again
+        assert!(store.contains("idle").await);
+        tokio::fs::remove_dir_all(&dir).await.ok();
+    }
+}
@@ -0,0 +1,229 @@
+// weframe-server/src/history.rs
+use crate::media_access::MediaUrls;
+use crate::replies::error_reply;
+use crate::store::RecordedOperation;
+use crate::{media, SessionManager, VideoSession};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use warp::http::StatusCode;
+use warp::Filter;
+use weframe_shared::{OTOperation, VideoProject};
+
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// An applied operation and the server version it was applied at.
+#[derive(Debug, Clone, Serialize)]
+pub struct LoggedOperation {
+    pub server_version: usize,
+    pub operation: OTOperation,
+}
+
+/// One page of the op log. `next` is the `from` cursor for the following
+/// page, or `None` once the page reaches the current server version.
+/// `oldest` is the earliest version still retained; sessions under memory
+/// pressure drop history from the front.
+#[derive(Debug, Serialize)]
+pub struct HistoryPage {
+    pub operations: Vec<LoggedOperation>,
+    pub next: Option<usize>,
+    pub oldest: usize,
+    pub server_version: usize,
+}
+
+impl VideoSession {
+    /// Up to `limit` logged operations starting at server version `from`,
+    /// oldest first.
+    pub fn history(&self, from: usize, limit: usize) -> HistoryPage {
+        let from = from.max(self.op_log_start);
+        let operations: Vec<_> = self
+            .op_log
+            .iter()
+            .skip(from - self.op_log_start)
+            .take(limit)
+            .enumerate()
+            .map(|(i, operation)| LoggedOperation {
+                server_version: from + i,
+                operation: operation.clone(),
+            })
+            .collect();
+        let end = from + operations.len();
+        HistoryPage {
+            next: (end < self.server_version).then_some(end),
+            operations,
+            oldest: self.op_log_start,
+            server_version: self.server_version,
+        }
+    }
+}
+
+/// A session's project as it was at `server_version`, and the intent label
+/// of the latest labeled operation before then: the editing phase it was
+/// in.
+#[derive(Debug, Serialize)]
+pub struct ProjectState {
+    pub server_version: usize,
+    pub label: Option<String>,
+    pub project: VideoProject,
+}
+
+/// Reconstructs a session's project as of server version `version` by
+/// replaying the operations after the newest stored snapshot before it,
+/// from the project store's log and then the live session's. Fails with
+/// `NotFound` when the session never got that far or the history leading
+/// there wasn't kept, e.g. without a project store or after operations
+/// were shed before being persisted.
+pub async fn project_at(
+    manager: &RwLock<SessionManager>,
+    session_id: &str,
+    version: usize,
+) -> io::Result<ProjectState> {
+    let unavailable = |message: String| io::Error::new(io::ErrorKind::NotFound, message);
+    let (session, store) = {
+        let manager = manager.read().await;
+        (manager.get_session(session_id), manager.projects.clone())
+    };
+    let mut recent = Vec::new();
+    if let Some(session) = session {
+        let session = session.read().await;
+        if version > session.server_version {
+            return Err(unavailable(format!(
+                "Session is only at version {}",
+                session.server_version
+            )));
+        }
+        if version == session.server_version {
+            return Ok(ProjectState {
+                server_version: version,
+                label: session.label.clone(),
+                project: session.project.clone(),
+            });
+        }
+        recent = (session.op_log_start..version)
+            .zip(&session.op_log)
+            .map(|(server_version, operation)| RecordedOperation {
+                server_version,
+                operation: operation.operation.clone(),
+                label: operation.label.clone(),
+            })
+            .collect();
+    }
+    let store = store
+        .ok_or_else(|| unavailable("History is only kept with a project store".to_string()))?;
+    let snapshot = store
+        .snapshot_at(session_id, version)
+        .await?
+        .ok_or_else(|| unavailable(format!("No snapshot at or before version {}", version)))?;
+    let logged = store
+        .operations(session_id, snapshot.server_version, version)
+        .await?;
+    let mut project = snapshot.project;
+    let mut label = snapshot.label;
+    let mut next = snapshot.server_version;
+    // The two logs overlap where operations were persisted but are still
+    // in memory
+    for recorded in logged.into_iter().chain(recent) {
+        if recorded.server_version < next {
+            continue;
+        }
+        if recorded.server_version > next {
+            break;
+        }
+        project.apply_operation(&recorded.operation);
+        if recorded.label.is_some() {
+            label = recorded.label;
+        }
+        next += 1;
+    }
+    if next != version {
+        return Err(unavailable(format!(
+            "Operation at version {} is no longer recorded",
+            next
+        )));
+    }
+    Ok(ProjectState {
+        server_version: version,
+        label,
+        project,
+    })
+}
+
+#[derive(Deserialize)]
+struct StateQuery {
+    version: usize,
+}
+
+/// `GET /sessions/:id/state?version=N` returns the session's project as it
+/// was at server version `N`, for scrubbing through its history or finding
+/// when something broke.
+pub fn state_route(
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("sessions" / String / "state"))
+        .and(warp::query::<StateQuery>())
+        .and_then(move |session_id: String, query: StateQuery| {
+            let manager = manager.clone();
+            async move {
+                let reply = match project_at(&manager, &session_id, query.version).await {
+                    Ok(mut state) => {
+                        let urls = MediaUrls::new(&manager.read().await.config, &session_id);
+                        media::add_project_public_urls(&mut state.project, &urls);
+                        warp::reply::with_status(warp::reply::json(&state), StatusCode::OK)
+                    }
+                    Err(e) => {
+                        let status = if e.kind() == io::ErrorKind::NotFound {
+                            StatusCode::NOT_FOUND
+                        } else {
+                            eprintln!("Failed to read history of session {}: {}", session_id, e);
+                            StatusCode::INTERNAL_SERVER_ERROR
+                        };
+                        error_reply(e.to_string(), status)
+                    }
+                };
+                Ok::<_, warp::Rejection>(reply)
+            }
+        })
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    #[serde(default)]
+    from: usize,
+    limit: Option<usize>,
+    label: Option<String>,
+}
+
+/// `GET /sessions/:id/ops?from=&limit=` pages through a session's applied
+/// operations in server-version order. With `label`, a page keeps only the
+/// operations made under that intent label, so it may hold fewer than
+/// `limit`.
+pub fn history_route(
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("sessions" / String / "ops"))
+        .and(warp::query::<HistoryQuery>())
+        .and_then(move |session_id: String, query: HistoryQuery| {
+            let manager = manager.clone();
+            async move {
+                let session = manager
+                    .read()
+                    .await
+                    .get_session(&session_id)
+                    .ok_or_else(warp::reject::not_found)?;
+                let limit = query
+                    .limit
+                    .unwrap_or(DEFAULT_PAGE_SIZE)
+                    .clamp(1, MAX_PAGE_SIZE);
+                let mut page = session.read().await.history(query.from, limit);
+                if let Some(label) = &query.label {
+                    page.operations
+                        .retain(|logged| logged.operation.label.as_ref() == Some(label));
+                }
+                Ok::<_, warp::Rejection>(warp::reply::json(&page))
+            }
+        })
+}
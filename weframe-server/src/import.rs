@@ -0,0 +1,464 @@
+// weframe-server/src/import.rs
+use crate::analysis::ffmpeg_log;
+use crate::jobs::{JobClass, JobPool};
+use crate::media::{check_adds_assets, dedup_namespace, upload_extension, DedupScope, MediaStore};
+use crate::replies::{error_reply, MAX_JSON_BODY_BYTES};
+use crate::workspaces::check_storage;
+use crate::{auth, freeze, roles, SessionManager, VideoSession};
+use futures::future::BoxFuture;
+use futures::StreamExt;
+use serde::Deserialize;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::RwLock;
+use warp::http::header::{CONTENT_LENGTH, LOCATION};
+use warp::http::{StatusCode, Uri};
+use warp::hyper::client::connect::dns::Name;
+use warp::hyper::client::HttpConnector;
+use warp::hyper::service::Service;
+use warp::hyper::{Body, Client, Response};
+use warp::Filter;
+use weframe_shared::{
+    Asset, EditOperation, IdGenerator, ImportStatus, JobKind, Role, ServerMessage,
+};
+
+/// Most URLs one import may list.
+const MAX_IMPORT_URLS: usize = 50;
+const MAX_REDIRECTS: usize = 5;
+/// Downloads report their progress each time this many more bytes arrive.
+const PROGRESS_STEP: u64 = 4 * 1024 * 1024;
+
+#[derive(Deserialize)]
+struct ImportRequest {
+    urls: Vec<String>,
+}
+
+/// Whether `ip` is on the server's own host or network, or otherwise not a
+/// public address an import should reach.
+fn is_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // This network, 0.0.0.0/8
+                || a == 0
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (b & 0xc0) == 64)
+                // Benchmarking, 198.18.0.0/15
+                || (a == 198 && (b & 0xfe) == 18)
+        }
+        IpAddr::V6(ip) => match embedded_ipv4(ip) {
+            Some(ip) => is_local(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    // Unique local, fc00::/7, and link-local, fe80::/10
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80
+            }
+        },
+    }
+}
+
+/// The IPv4 address an IPv6 one stands for: IPv4-mapped `::ffff:a.b.c.d`,
+/// IPv4-compatible `::a.b.c.d` and NAT64 `64:ff9b::a.b.c.d` addresses.
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let [.., a, b, c, d] = ip.octets();
+    match ip.segments() {
+        [0x64, 0xff9b, 0, 0, 0, 0, _, _] => Some(Ipv4Addr::new(a, b, c, d)),
+        _ => ip.to_ipv4(),
+    }
+}
+
+/// Parses a URL to import from. Plain HTTP only, like the transcription
+/// service; serve media to import over TLS through a proxy on its own host.
+/// Loopback and private addresses are refused so an import can't probe the
+/// server's own network; hosts given by name are checked once resolved, by
+/// `PublicResolver`.
+fn import_uri(url: &str) -> Result<Uri, String> {
+    let uri: Uri = url.parse().map_err(|_| format!("Not a URL: {}", url))?;
+    if uri.scheme_str() != Some("http") {
+        return Err(format!("Only http:// URLs can be imported: {}", url));
+    }
+    let host = uri
+        .host()
+        .ok_or_else(|| format!("URL has no host: {}", url))?;
+    let local = host.eq_ignore_ascii_case("localhost")
+        || host
+            .trim_matches(|c| c == '[' || c == ']')
+            .parse::<IpAddr>()
+            .is_ok_and(is_local);
+    if local {
+        return Err(format!("Refusing to import from a local address: {}", url));
+    }
+    Ok(uri)
+}
+
+/// Resolves the hosts imports connect to, failing when any address a name
+/// resolves to is local, so a public name can't point an import at the
+/// server's own network. The connector only connects to addresses checked
+/// here, so the name can't resolve differently in between.
+#[derive(Clone)]
+struct PublicResolver;
+
+impl Service<Name> for PublicResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = BoxFuture<'static, io::Result<Self::Response>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            if let Some(addr) = addrs.iter().find(|addr| is_local(addr.ip())) {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("{} resolves to local address {}", name, addr.ip()),
+                ));
+            }
+            Ok(addrs.into_iter())
+        })
+    }
+}
+
+/// Asset name for media downloaded from `uri`: the last segment of its path,
+/// or the host when the path has none.
+fn asset_name(uri: &Uri) -> String {
+    uri.path()
+        .rsplit('/')
+        .find(|segment| !segment.is_empty())
+        .or(uri.host())
+        .unwrap_or("import")
+        .to_string()
+}
+
+/// The `Duration:` ffmpeg logs for its input, absent for still images.
+fn logged_duration(log: &str) -> Option<Duration> {
+    let (_, rest) = log.split_once("Duration: ")?;
+    let mut parts = rest.split(',').next()?.trim().split(':');
+    let hours: f64 = parts.next()?.parse().ok()?;
+    let minutes: f64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    let secs = hours * 3600.0 + minutes * 60.0 + seconds;
+    (secs.is_finite() && secs > 0.0).then(|| Duration::from_secs_f64(secs))
+}
+
+/// Fetches `uri`, following redirects as long as they stay importable.
+async fn fetch(mut uri: Uri) -> Result<Response<Body>, String> {
+    let client =
+        Client::builder().build::<_, Body>(HttpConnector::new_with_resolver(PublicResolver));
+    for _ in 0..=MAX_REDIRECTS {
+        let response = client
+            .get(uri.clone())
+            .await
+            .map_err(|e| format!("Could not fetch: {}", e))?;
+        let status = response.status();
+        if status.is_redirection() {
+            let location = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .ok_or_else(|| format!("Redirect {} without a location", status))?;
+            uri = if location.starts_with('/') {
+                let authority = uri.authority().map(|a| a.as_str()).unwrap_or_default();
+                import_uri(&format!("http://{}{}", authority, location))?
+            } else {
+                import_uri(location)?
+            };
+            continue;
+        }
+        if !status.is_success() {
+            return Err(format!("Remote server answered {}", status));
+        }
+        return Ok(response);
+    }
+    Err(format!("More than {} redirects", MAX_REDIRECTS))
+}
+
+/// One bulk import running in the background, downloading its URLs one at
+/// a time into the media store.
+struct Import {
+    id: String,
+    session_id: String,
+    session: Arc<RwLock<VideoSession>>,
+    manager: Arc<RwLock<SessionManager>>,
+    jobs: JobPool,
+    ids: IdGenerator,
+    store: MediaStore,
+    ffmpeg: PathBuf,
+    namespace: Option<String>,
+    max_bytes: u64,
+}
+
+impl Import {
+    async fn run(self, urls: Vec<String>) {
+        for url in urls {
+            let status = match self.import(&url).await {
+                Ok(asset_id) => ImportStatus::Done { asset_id },
+                Err(message) => {
+                    self.session.write().await.job_failed(
+                        &self.id,
+                        JobKind::Import,
+                        &format!("{}: {}", url, message),
+                    );
+                    ImportStatus::Failed { message }
+                }
+            };
+            self.report(&url, status).await;
+        }
+        println!("Import {} finished", self.id);
+    }
+
+    async fn report(&self, url: &str, status: ImportStatus) {
+        self.session
+            .read()
+            .await
+            .broadcast_message(&ServerMessage::ImportProgress {
+                import_id: self.id.clone(),
+                url: url.to_string(),
+                status,
+            });
+    }
+
+    /// Downloads `url`, checks ffmpeg can read it and adds it to the
+    /// session, returning the new asset's id.
+    async fn import(&self, url: &str) -> Result<String, String> {
+        let uri = import_uri(url)?;
+        let workspace = self
+            .session
+            .read()
+            .await
+            .metadata()
+            .workspace()
+            .map(str::to_string);
+        check_storage(&self.manager, workspace.as_deref(), None).await?;
+        self.report(
+            url,
+            ImportStatus::Downloading {
+                received: 0,
+                total: None,
+            },
+        )
+        .await;
+        let response = fetch(uri.clone()).await?;
+        let total: Option<u64> = response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok()?.parse().ok());
+        if total.is_some_and(|total| total > self.max_bytes) {
+            return Err(format!(
+                "Larger than the {} byte upload limit",
+                self.max_bytes
+            ));
+        }
+
+        let mut received = 0;
+        let mut reported = 0;
+        let body = response.into_body().then(|chunk| {
+            let chunk = chunk.map_err(io::Error::other).and_then(|bytes| {
+                received += bytes.len() as u64;
+                if received > self.max_bytes {
+                    return Err(io::Error::other(format!(
+                        "Larger than the {} byte upload limit",
+                        self.max_bytes
+                    )));
+                }
+                Ok(bytes)
+            });
+            let progress = (received >= reported + PROGRESS_STEP).then(|| {
+                reported = received;
+                ImportStatus::Downloading { received, total }
+            });
+            async move {
+                if let Some(status) = progress {
+                    self.report(url, status).await;
+                }
+                chunk
+            }
+        });
+        let name = asset_name(&uri);
+        let extension = upload_extension(&name);
+        let (key, reused) = self
+            .store
+            .store(
+                Box::pin(body),
+                self.namespace.as_deref(),
+                extension.as_deref(),
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let path = self
+            .store
+            .path(&key)
+            .ok_or_else(|| format!("Invalid media key {}", key))?;
+        let probe = match self
+            .jobs
+            .acquire(JobClass::Analysis, &self.session_id)
+            .await
+        {
+            Ok(_permit) => ffmpeg_log(&self.ffmpeg, &path, &["-t", "0.1"])
+                .await
+                .map_err(|e| format!("Not playable media: {}", e)),
+            Err(message) => Err(message),
+        };
+        let probe = match probe {
+            Ok(log) => check_storage(&self.manager, workspace.as_deref(), Some(&key))
+                .await
+                .map(|()| log),
+            Err(message) => Err(message),
+        };
+        let log = match probe {
+            Ok(log) => log,
+            Err(message) => {
+                if !reused {
+                    self.store.delete(&key).await.ok();
+                }
+                return Err(message);
+            }
+        };
+
+        let asset = Asset {
+            id: self.ids.prefixed("asset"),
+            name,
+            uri: format!("media:{}", key),
+            duration: logged_duration(&log),
+            color_space: None,
+            hdr: None,
+            public_url: None,
+        };
+        self.session
+            .write()
+            .await
+            .apply_server_operation(EditOperation::AddAsset(asset.clone()))?;
+        Ok(asset.id)
+    }
+}
+
+/// `POST /sessions/:id/assets/import` with `{"urls": [...]}` downloads
+/// media that already lives on another server and registers each file as
+/// an asset, like an upload. Answers 202 with the `import_id` at once; the
+/// session's clients then get `ImportProgress` messages as each URL
+/// downloads and succeeds or fails. Unavailable without a media store.
+pub fn import_route(
+    manager: Arc<RwLock<SessionManager>>,
+    store: Option<MediaStore>,
+    ffmpeg: PathBuf,
+    scope: DedupScope,
+    max_upload_bytes: u64,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("sessions" / String / "assets" / "import"))
+        .and(warp::body::content_length_limit(MAX_JSON_BODY_BYTES))
+        .and(warp::body::json())
+        .and(auth::request_role(manager.clone()))
+        .and_then(
+            move |session_id: String, request: ImportRequest, role: Role| {
+                let manager = manager.clone();
+                let store = store.clone();
+                let ffmpeg = ffmpeg.clone();
+                async move {
+                    let store = store.ok_or_else(warp::reject::not_found)?;
+                    if let Err(message) = check_adds_assets(role) {
+                        return Ok(roles::forbidden(message));
+                    }
+                    let (session, jobs, ids) = {
+                        let manager = manager.read().await;
+                        (
+                            manager.get_session(&session_id),
+                            manager.jobs(),
+                            manager.config.ids.clone(),
+                        )
+                    };
+                    let session = session.ok_or_else(warp::reject::not_found)?;
+                    let add = EditOperation::AddAsset(Asset::default());
+                    if let Err(message) = session.read().await.check_frozen(&add) {
+                        return Ok(freeze::frozen(message));
+                    }
+                    if request.urls.is_empty() || request.urls.len() > MAX_IMPORT_URLS {
+                        return Ok(error_reply(
+                            format!("List between 1 and {} URLs", MAX_IMPORT_URLS),
+                            StatusCode::BAD_REQUEST,
+                        ));
+                    }
+                    if let Some(e) = request.urls.iter().find_map(|url| import_uri(url).err()) {
+                        return Ok(error_reply(e, StatusCode::BAD_REQUEST));
+                    }
+
+                    let import = Import {
+                        id: ids.uuid().to_string(),
+                        session_id: session_id.clone(),
+                        session,
+                        manager: manager.clone(),
+                        jobs,
+                        namespace: dedup_namespace(scope, &session_id, &ids),
+                        ids,
+                        store,
+                        ffmpeg,
+                        max_bytes: max_upload_bytes,
+                    };
+                    println!(
+                        "Importing {} URLs into {} as {}",
+                        request.urls.len(),
+                        session_id,
+                        import.id
+                    );
+                    let reply = warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({ "import_id": import.id })),
+                        StatusCode::ACCEPTED,
+                    );
+                    tokio::spawn(import.run(request.urls));
+                    Ok::<_, warp::Rejection>(reply)
+                }
+            },
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imports_from_local_addresses_are_refused() {
+        for (url, allowed) in [
+            ("http://localhost/clip.mp4", false),
+            ("http://LOCALHOST:8080/clip.mp4", false),
+            ("http://127.0.0.1/clip.mp4", false),
+            ("http://0.1.2.3/clip.mp4", false),
+            ("http://10.1.2.3/clip.mp4", false),
+            ("http://100.64.0.1/clip.mp4", false),
+            ("http://169.254.169.254/clip.mp4", false),
+            ("http://192.168.1.1/clip.mp4", false),
+            ("http://198.19.0.1/clip.mp4", false),
+            ("http://[::1]/clip.mp4", false),
+            ("http://[::]/clip.mp4", false),
+            ("http://[::ffff:127.0.0.1]/clip.mp4", false),
+            ("http://[::10.0.0.1]/clip.mp4", false),
+            ("http://[64:ff9b::192.168.0.1]/clip.mp4", false),
+            ("http://[fc00::1]/clip.mp4", false),
+            ("http://[fe80::1]/clip.mp4", false),
+            ("http://93.184.216.34/clip.mp4", true),
+            ("http://100.128.0.1/clip.mp4", true),
+            ("http://198.20.0.1/clip.mp4", true),
+            ("http://[2606:4700::1111]/clip.mp4", true),
+            ("http://[64:ff9b::93.184.216.34]/clip.mp4", true),
+            ("http://example.com/clip.mp4", true),
+        ] {
+            assert_eq!(import_uri(url).is_ok(), allowed, "{}", url);
+        }
+        assert!(import_uri("https://example.com/clip.mp4").is_err());
+    }
+}
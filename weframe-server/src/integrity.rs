@@ -0,0 +1,50 @@
+// weframe-server/src/integrity.rs
+use crate::{SessionManager, VideoSession};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use warp::Filter;
+use weframe_shared::{IntegrityIssue, OTOperation};
+
+impl VideoSession {
+    /// Checks the project after `operation` was applied and logs any issue
+    /// it introduced. Only runs when `check_integrity` is configured, as it
+    /// walks the whole project.
+    pub(crate) fn check_integrity(&mut self, operation: &OTOperation) {
+        if !self.config.check_integrity {
+            return;
+        }
+        let issues = self.project.validate();
+        for issue in issues.iter().filter(|i| !self.integrity_issues.contains(i)) {
+            eprintln!(
+                "Operation {} from {} at version {} left project {} inconsistent: {:?}",
+                operation.operation.kind(),
+                operation.client_id,
+                operation.server_version,
+                self.project.id,
+                issue
+            );
+        }
+        self.integrity_issues = issues;
+    }
+}
+
+/// `GET /sessions/:id/integrity` lists what is wrong with a session's
+/// project, checked on request; an empty list means nothing is.
+pub fn integrity_route(
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("sessions" / String / "integrity"))
+        .and_then(move |session_id: String| {
+            let manager = manager.clone();
+            async move {
+                let session = manager
+                    .read()
+                    .await
+                    .get_session(&session_id)
+                    .ok_or_else(warp::reject::not_found)?;
+                let issues: Vec<IntegrityIssue> = session.read().await.project().validate();
+                Ok::<_, warp::Rejection>(warp::reply::json(&issues))
+            }
+        })
+}
@@ -0,0 +1,256 @@
+// weframe-server/src/jobs.rs
+use crate::SessionManager;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{oneshot, RwLock};
+
+/// Kinds of background media work, each with its own workers so a long
+/// export can't hold up quick jobs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JobClass {
+    /// Timeline exports from the render queue.
+    Render,
+    /// Single frames rendered for someone waiting on them.
+    Preview,
+    /// ffmpeg passes over stored media: scene, silence and beat detection,
+    /// audio sync, transcription and checking imports.
+    Analysis,
+}
+
+impl JobClass {
+    fn name(self) -> &'static str {
+        match self {
+            JobClass::Render => "render",
+            JobClass::Preview => "preview",
+            JobClass::Analysis => "analysis",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct JobLimits {
+    /// Jobs of the class that may run at once.
+    pub workers: usize,
+    /// Jobs that may wait for a worker; more are refused.
+    pub max_queued: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct JobPoolConfig {
+    pub render: JobLimits,
+    pub preview: JobLimits,
+    pub analysis: JobLimits,
+}
+
+impl Default for JobPoolConfig {
+    fn default() -> Self {
+        JobPoolConfig {
+            render: JobLimits {
+                workers: 1,
+                max_queued: 100,
+            },
+            preview: JobLimits {
+                workers: 4,
+                max_queued: 64,
+            },
+            analysis: JobLimits {
+                workers: 2,
+                max_queued: 32,
+            },
+        }
+    }
+}
+
+impl JobPoolConfig {
+    fn limits(&self, class: JobClass) -> JobLimits {
+        match class {
+            JobClass::Render => self.render,
+            JobClass::Preview => self.preview,
+            JobClass::Analysis => self.analysis,
+        }
+    }
+}
+
+struct ClassQueue {
+    limits: JobLimits,
+    running: usize,
+    /// Sessions with jobs waiting, in the order they get the next free
+    /// worker, each with its waiting jobs oldest first.
+    waiting: VecDeque<(String, VecDeque<oneshot::Sender<()>>)>,
+}
+
+impl ClassQueue {
+    /// Forgets jobs whose callers stopped waiting and counts the rest.
+    fn queued(&mut self) -> usize {
+        for (_, senders) in &mut self.waiting {
+            senders.retain(|sender| !sender.is_closed());
+        }
+        self.waiting.retain(|(_, senders)| !senders.is_empty());
+        self.waiting.iter().map(|(_, senders)| senders.len()).sum()
+    }
+
+    /// Hands free workers out to the waiting sessions in turn, one job each,
+    /// so a session with many jobs queued doesn't starve the others.
+    fn dispatch(&mut self) {
+        while self.running < self.limits.workers.max(1) {
+            let Some((session_id, mut senders)) = self.waiting.pop_front() else {
+                return;
+            };
+            let Some(sender) = senders.pop_front() else {
+                continue;
+            };
+            if !senders.is_empty() {
+                self.waiting.push_back((session_id, senders));
+            }
+            if sender.send(()).is_ok() {
+                self.running += 1;
+            }
+        }
+    }
+}
+
+/// Bounded workers for background media jobs, with separate limits per
+/// job class and jobs of different sessions taking turns.
+#[derive(Clone)]
+pub struct JobPool {
+    classes: Arc<Mutex<HashMap<JobClass, ClassQueue>>>,
+}
+
+impl JobPool {
+    pub fn new(config: JobPoolConfig) -> Self {
+        let classes = [JobClass::Render, JobClass::Preview, JobClass::Analysis]
+            .into_iter()
+            .map(|class| {
+                let queue = ClassQueue {
+                    limits: config.limits(class),
+                    running: 0,
+                    waiting: VecDeque::new(),
+                };
+                (class, queue)
+            })
+            .collect();
+        JobPool {
+            classes: Arc::new(Mutex::new(classes)),
+        }
+    }
+
+    /// Takes a place in line for a worker of `class`, failing at once when
+    /// too many jobs of the class are already waiting.
+    pub fn enqueue(&self, class: JobClass, session_id: &str) -> Result<JobTicket, String> {
+        let mut classes = self.classes.lock().unwrap();
+        let queue = classes
+            .get_mut(&class)
+            .expect("every job class has a queue");
+        let queued = queue.queued();
+        if queued == 0 && queue.running < queue.limits.workers.max(1) {
+            queue.running += 1;
+            return Ok(JobTicket {
+                pool: self.clone(),
+                class,
+                waiting: None,
+                started: false,
+            });
+        }
+        if queued >= queue.limits.max_queued {
+            return Err(format!(
+                "Too many {} jobs queued, try again later",
+                class.name()
+            ));
+        }
+        let (sender, receiver) = oneshot::channel();
+        match queue.waiting.iter_mut().find(|(id, _)| id == session_id) {
+            Some((_, senders)) => senders.push_back(sender),
+            None => queue
+                .waiting
+                .push_back((session_id.to_string(), VecDeque::from([sender]))),
+        }
+        Ok(JobTicket {
+            pool: self.clone(),
+            class,
+            waiting: Some(receiver),
+            started: false,
+        })
+    }
+
+    /// Waits for a worker of `class`; see `enqueue`.
+    pub async fn acquire(&self, class: JobClass, session_id: &str) -> Result<JobPermit, String> {
+        Ok(self.enqueue(class, session_id)?.start().await)
+    }
+
+    fn release(&self, class: JobClass) {
+        let mut classes = self.classes.lock().unwrap();
+        if let Some(queue) = classes.get_mut(&class) {
+            queue.running = queue.running.saturating_sub(1);
+            queue.dispatch();
+        }
+    }
+}
+
+/// A job's place in line for a worker. Dropping it gives the place up.
+pub struct JobTicket {
+    pool: JobPool,
+    class: JobClass,
+    /// Resolves once a worker is handed over; `None` once it has been, or
+    /// when one was free right away.
+    waiting: Option<oneshot::Receiver<()>>,
+    /// Set once the worker belongs to a permit.
+    started: bool,
+}
+
+impl JobTicket {
+    /// Waits until a worker is free for the job.
+    pub async fn start(mut self) -> JobPermit {
+        if let Some(receiver) = self.waiting.as_mut() {
+            // The pool lives as long as its tickets, so it always answers
+            let _ = receiver.await;
+            self.waiting = None;
+        }
+        self.started = true;
+        JobPermit {
+            pool: self.pool.clone(),
+            class: self.class,
+        }
+    }
+}
+
+impl Drop for JobTicket {
+    fn drop(&mut self) {
+        if self.started {
+            return;
+        }
+        let granted = match self.waiting.as_mut() {
+            Some(receiver) => {
+                // A worker may have been handed over just as the job gave up
+                receiver.close();
+                receiver.try_recv().is_ok()
+            }
+            None => true,
+        };
+        if granted {
+            self.pool.release(self.class);
+        }
+    }
+}
+
+/// A worker held by a running job, freed when dropped.
+pub struct JobPermit {
+    pool: JobPool,
+    class: JobClass,
+}
+
+impl Drop for JobPermit {
+    fn drop(&mut self) {
+        self.pool.release(self.class);
+    }
+}
+
+/// Waits for a worker of `class` to run a job for `session_id`, without
+/// holding on to the session manager meanwhile.
+pub(crate) async fn acquire(
+    manager: &RwLock<SessionManager>,
+    class: JobClass,
+    session_id: &str,
+) -> Result<JobPermit, String> {
+    let pool = manager.read().await.jobs();
+    pool.acquire(class, session_id).await
+}
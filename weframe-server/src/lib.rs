@@ -1,28 +1,204 @@
 // weframe-server/src/lib.rs
+use futures::stream::SplitSink;
 use futures::{SinkExt, StreamExt};
-use rand::random;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::sync::Arc;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::sync::{broadcast, mpsc, RwLock};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, RwLock};
 use warp::ws::{Message, WebSocket};
 use warp::Filter;
-use weframe_shared::{Collaborator, CursorPosition, OTOperation, VideoProject};
+use weframe_shared::{
+    validate_avatar_url, validate_label, Adjustment, Capabilities, Collaborator, CursorPosition,
+    EditOperation, IdGenerator, IntegrityIssue, OTOperation, RejectionCode, TrafficStats,
+    VideoProject, ViewState, WaveformRef,
+};
+
+pub use weframe_shared::ServerMessage;
+
+pub mod activity;
+pub mod admin;
+pub mod analysis;
+pub mod audio_sync;
+pub mod auth;
+pub mod automation;
+pub mod beats;
+pub mod chat;
+pub mod checksum;
+pub mod clock;
+pub mod connections;
+pub mod dashboard;
+#[cfg(feature = "database")]
+pub mod database;
+pub mod dry_run;
+pub mod effect_chain;
+pub mod flags;
+pub mod freeze;
+pub mod guests;
+pub mod hibernation;
+pub mod history;
+pub mod import;
+pub mod integrity;
+pub mod jobs;
+pub mod media;
+pub mod media_access;
+pub mod memory;
+pub mod metrics;
+pub mod outbox;
+pub mod previews;
+#[cfg(feature = "profiling")]
+pub mod profiling;
+pub mod recycle;
+pub mod relink;
+pub mod render;
+pub mod replies;
+pub mod resume;
+pub mod roles;
+pub mod scenes;
+pub mod scopes;
+pub mod silence;
+pub mod sse;
+pub mod store;
+pub mod transcription;
+pub mod trash;
+pub mod view_state;
+pub mod workspaces;
+
+use auth::{Authenticator, TokenQuery};
+use clock::{Clock, SystemClock};
+use hibernation::HibernationStore;
+use jobs::{JobPool, JobPoolConfig};
+use media::{AssetGcPolicy, DedupScope, MediaStore};
+use media_access::{MediaAccess, MediaUrls};
+use memory::MemoryUsage;
+use metrics::Metrics;
+use outbox::{Outbox, OutboxSender, Priority};
+#[cfg(feature = "profiling")]
+use profiling::{websocket_connection, write_session};
+use store::ProjectStore;
+use transcription::TranscriptionConfig;
+use workspaces::{WorkspaceQuota, Workspaces};
 
 pub struct SessionManager {
     sessions: HashMap<String, Arc<RwLock<VideoSession>>>,
+    config: Arc<ServerConfig>,
+    metrics: Arc<Metrics>,
+    hibernation: Option<HibernationStore>,
+    recycle_bin: Option<HibernationStore>,
+    projects: Option<Arc<dyn ProjectStore>>,
+    auth: Option<Arc<Authenticator>>,
+    jobs: JobPool,
+    workspaces: Workspaces,
 }
 
 pub struct VideoSession {
     metadata: Metadata,
     project: VideoProject,
-    clients: HashMap<String, mpsc::UnboundedSender<Message>>,
+    clients: HashMap<String, ClientHandle>,
     server_version: usize,
     last_activity: SystemTime,
     broadcast: broadcast::Sender<OTOperation>,
-    update_tx: mpsc::Sender<ServerMessage>,
-    update_rx: mpsc::Receiver<ServerMessage>,
+    config: Arc<ServerConfig>,
+    metrics: Arc<Metrics>,
+    asset_unused_since: HashMap<String, SystemTime>,
+    clip_trashed_since: HashMap<String, SystemTime>,
+    /// Applied operations; entry `n` was applied at server version
+    /// `op_log_start + n`. Older entries are dropped under memory pressure.
+    op_log: VecDeque<OTOperation>,
+    op_log_start: usize,
+    memory: MemoryUsage,
+    /// Private view state per user, kept out of the project.
+    view_states: HashMap<String, ViewState>,
+    activity: dashboard::EditActivity,
+    /// Server version of the last snapshot written to the project store.
+    persisted_version: Option<usize>,
+    /// Recent chat, replayed to clients as they join.
+    chat_history: VecDeque<chat::ChatEntry>,
+    /// What the last integrity check found, so only new issues are logged.
+    integrity_issues: Vec<IntegrityIssue>,
+    /// Server version the last `StateChecksum` was sent at.
+    checksummed_version: Option<usize>,
+    /// Intent label of the latest labeled operation: the editing phase the
+    /// project is in.
+    label: Option<String>,
+    /// Recent happenings besides edits and chat, e.g. failed jobs.
+    activity_feed: VecDeque<activity::ActivityEntry>,
+    /// Why the session is frozen for maintenance, while it is.
+    frozen: Option<String>,
+    /// Source each clip's previews were last tried from, so failures aren't
+    /// retried every sweep.
+    preview_attempts: HashMap<String, String>,
+}
+
+/// Serialized `ProjectUpdate`s at least this large are compressed for clients
+/// that support it; smaller ones aren't worth the CPU.
+const COMPRESSION_THRESHOLD: usize = 64 * 1024;
+
+/// Clips (or assets) per message in a chunked initial sync.
+const SYNC_PAGE_SIZE: usize = 500;
+
+/// How long a connection that negotiated `batched_frames` waits for more
+/// messages to the same client before sending what it has as one frame.
+const BATCH_WINDOW: Duration = Duration::from_millis(16);
+
+/// Most messages put in one batched frame.
+const MAX_BATCH: usize = 256;
+
+/// A connected client's outbound queue and what it negotiated in the
+/// handshake. Clients that never send `Hello` speak protocol 1.
+struct ClientHandle {
+    sender: OutboxSender,
+    protocol_version: u32,
+    features: Vec<String>,
+    connected_at: SystemTime,
+    /// Updated by the connection's task as frames cross the socket.
+    traffic: Arc<Mutex<TrafficStats>>,
+    /// Who the client is across connections, from its guest token or the
+    /// subject of its auth token.
+    user_key: Option<String>,
+    /// Whether the client's identity came from a verified token, in which
+    /// case its `Hello` can't change it.
+    signed_in: bool,
+    /// Secret an event-stream client POSTs its messages under; `None` for
+    /// WebSocket clients.
+    post_token: Option<String>,
+    /// Set once the client negotiates `batched_frames`, telling its
+    /// connection's task to coalesce what it sends.
+    batching: Arc<AtomicBool>,
+}
+
+impl ClientHandle {
+    /// Adapts `message` to what the client understands, or `None` if it
+    /// should not be sent at all.
+    fn downgrade(&self, message: &ServerMessage) -> Option<ServerMessage> {
+        match message {
+            ServerMessage::ProjectAdjusted { .. }
+                if !self.features.iter().any(|f| f == "project_adjustments") =>
+            {
+                None
+            }
+            ServerMessage::OperationRejected { message, .. } if self.protocol_version < 2 => {
+                Some(ServerMessage::Error {
+                    client_id: String::new(),
+                    message: message.clone(),
+                    code: None,
+                })
+            }
+            ServerMessage::Frozen {
+                reason: Some(reason),
+            } if self.protocol_version < 2 => Some(ServerMessage::Error {
+                client_id: String::new(),
+                message: format!("Session is frozen for maintenance: {}", reason),
+                code: None,
+            }),
+            message if message.protocol_version() > self.protocol_version => None,
+            message => Some(message.clone()),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -30,89 +206,846 @@ pub struct Metadata {
     name: String,
     created_at: SystemTime,
     max_duration: Duration,
+    /// Enabled entries of `SESSION_FLAGS`.
+    flags: BTreeSet<String>,
+    /// Workspace the session belongs to; `None` until a client with a
+    /// workspace in its token opens it.
+    workspace: Option<String>,
+    /// User key of the session's owner: the first client to open it with a
+    /// guest token or signed in. Only that user gets ownership back.
+    owner: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct ServerConfig {
+    pub bind_addr: SocketAddr,
+    /// Directory holding the built frontend (wasm client + JS shell). When
+    /// set, it is served from `/` alongside the WebSocket endpoint.
+    pub static_dir: Option<PathBuf>,
+    /// Root directory for media files stored by the server.
+    pub media_dir: Option<PathBuf>,
+    /// Base URL clients fetch stored media from, e.g. a CDN in front of the
+    /// media directory. Defaults to the server's own `/media` route, relative
+    /// so it works whatever host and scheme clients reached the server on. A
+    /// CDN must pass the `access` query parameter through to this server.
+    pub public_media_url: Option<String>,
+    /// Secret media URLs are signed with. Random by default, in which case
+    /// URLs stop working when the server restarts; set it when several
+    /// servers share one media directory. Derived from `jwt_secret` when
+    /// only that is set; never the same secret.
+    pub media_secret: String,
+    /// How long a signed media URL stays valid, at least.
+    pub media_url_ttl: Duration,
+    /// Latest time a clip may end at, on top of the project's own duration.
+    pub max_timeline_duration: Option<Duration>,
+    pub asset_gc: AssetGcPolicy,
+    pub dedup_scope: DedupScope,
+    pub max_upload_bytes: u64,
+    /// Approximate memory a single session may hold before history is shed
+    /// and content-adding edits are refused.
+    pub max_session_bytes: Option<usize>,
+    /// How long removed clips stay restorable before they are purged.
+    pub trash_retention: Duration,
+    /// Where deleted and expired sessions are kept so an admin can restore
+    /// them. Without it they are discarded.
+    pub recycle_dir: Option<PathBuf>,
+    /// How long sessions stay in the recycle bin.
+    pub recycle_retention: Duration,
+    /// How long a session with no clients stays in memory.
+    pub session_idle_timeout: Duration,
+    /// Where idle sessions are written so they can be rehydrated on the next
+    /// join. Without it they are discarded.
+    pub hibernate_dir: Option<PathBuf>,
+    /// Where session projects are snapshotted so they survive a restart.
+    /// Without it sessions live only in memory.
+    pub project_dir: Option<PathBuf>,
+    /// Database to keep session projects and their operation log in instead
+    /// of `project_dir`, e.g. `sqlite://weframe.db` or `postgres://...`.
+    #[cfg(feature = "database")]
+    pub project_database_url: Option<String>,
+    /// How often changed sessions are snapshotted to the project store.
+    pub project_snapshot_interval: Duration,
+    /// Secret client tokens (HS256 JWTs) are signed with. When set, every
+    /// connection must present a valid token; without it anyone may join.
+    pub jwt_secret: Option<String>,
+    /// Flags new sessions start with, from `SESSION_FLAGS`.
+    pub session_flags: BTreeSet<String>,
+    /// How often sessions that changed send their clients a checksum of
+    /// the project, so a client that diverged can resync.
+    pub checksum_interval: Duration,
+    /// Whether to check each project for integrity issues after every
+    /// operation and log what breaks it. On in debug builds.
+    pub check_integrity: bool,
+    /// ffmpeg binary used by media analysis jobs.
+    pub ffmpeg: PathBuf,
+    /// Speech recognizer for subtitle transcription, if any.
+    pub transcription: Option<TranscriptionConfig>,
+    /// Where finished renders are written. Defaults to a directory under
+    /// the system temp dir.
+    pub render_dir: Option<PathBuf>,
+    /// How long finished renders are kept before they are deleted.
+    pub render_retention: Duration,
+    /// Workers and queue lengths for background media jobs, per class.
+    pub jobs: JobPoolConfig,
+    /// Makes ids for sessions' projects, clients, assets, markers and jobs.
+    /// Random unless `WEFRAME_ID_SEED` asks for a repeatable sequence, for
+    /// tests and replays.
+    pub ids: IdGenerator,
+    /// Time source for sessions' expiry, sweeps and timestamps.
+    pub clock: Arc<dyn Clock>,
+    /// Quota of each workspace that hasn't been given its own.
+    pub workspace_quota: WorkspaceQuota,
+    /// Bearer token every `/admin` request must carry. Without it the admin
+    /// routes aren't served at all.
+    pub admin_token: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_addr: ([127, 0, 0, 1], 3030).into(),
+            static_dir: None,
+            media_dir: None,
+            public_media_url: None,
+            media_secret: format!(
+                "{}{}",
+                uuid::Uuid::new_v4().simple(),
+                uuid::Uuid::new_v4().simple()
+            ),
+            media_url_ttl: Duration::from_secs(12 * 60 * 60),
+            max_timeline_duration: None,
+            asset_gc: AssetGcPolicy::default(),
+            dedup_scope: DedupScope::Session,
+            max_upload_bytes: 2 * 1024 * 1024 * 1024,
+            max_session_bytes: None,
+            trash_retention: Duration::from_secs(30 * 24 * 60 * 60),
+            recycle_dir: None,
+            recycle_retention: Duration::from_secs(30 * 24 * 60 * 60),
+            session_idle_timeout: Duration::from_secs(24 * 60 * 60),
+            hibernate_dir: None,
+            project_dir: None,
+            #[cfg(feature = "database")]
+            project_database_url: None,
+            project_snapshot_interval: Duration::from_secs(60),
+            jwt_secret: None,
+            session_flags: ["magnetic_timeline", "frame_quantization", "chat"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            checksum_interval: Duration::from_secs(30),
+            check_integrity: cfg!(debug_assertions),
+            ffmpeg: PathBuf::from("ffmpeg"),
+            transcription: None,
+            render_dir: None,
+            render_retention: Duration::from_secs(7 * 24 * 60 * 60),
+            jobs: JobPoolConfig::default(),
+            ids: IdGenerator::Random,
+            clock: Arc::new(SystemClock),
+            workspace_quota: WorkspaceQuota::default(),
+            admin_token: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn from_env() -> Self {
+        let mut config = ServerConfig::default();
+        if let Some(addr) = std::env::var("WEFRAME_BIND_ADDR")
+            .ok()
+            .and_then(|addr| addr.parse().ok())
+        {
+            config.bind_addr = addr;
+        }
+        config.static_dir = std::env::var_os("WEFRAME_STATIC_DIR").map(PathBuf::from);
+        config.media_dir = std::env::var_os("WEFRAME_MEDIA_DIR").map(PathBuf::from);
+        config.public_media_url = std::env::var("WEFRAME_PUBLIC_MEDIA_URL").ok();
+        config.max_timeline_duration = env_secs("WEFRAME_MAX_TIMELINE_SECS");
+        if let Some(grace) = env_secs("WEFRAME_ASSET_GC_GRACE_SECS") {
+            config.asset_gc.grace = grace;
+        }
+        if let Some(scope) = std::env::var("WEFRAME_DEDUP_SCOPE")
+            .ok()
+            .and_then(|scope| scope.parse().ok())
+        {
+            config.dedup_scope = scope;
+        }
+        if let Some(limit) = std::env::var("WEFRAME_MAX_UPLOAD_BYTES")
+            .ok()
+            .and_then(|limit| limit.parse().ok())
+        {
+            config.max_upload_bytes = limit;
+        }
+        if let Some(retention) = env_secs("WEFRAME_TRASH_RETENTION_SECS") {
+            config.trash_retention = retention;
+        }
+        config.recycle_dir = std::env::var_os("WEFRAME_RECYCLE_DIR").map(PathBuf::from);
+        if let Some(retention) = env_secs("WEFRAME_RECYCLE_RETENTION_SECS") {
+            config.recycle_retention = retention;
+        }
+        if let Some(timeout) = env_secs("WEFRAME_SESSION_IDLE_SECS") {
+            config.session_idle_timeout = timeout;
+        }
+        config.hibernate_dir = std::env::var_os("WEFRAME_HIBERNATE_DIR").map(PathBuf::from);
+        config.project_dir = std::env::var_os("WEFRAME_PROJECT_DIR").map(PathBuf::from);
+        #[cfg(feature = "database")]
+        {
+            config.project_database_url = std::env::var("WEFRAME_DATABASE_URL").ok();
+        }
+        if let Some(interval) = env_secs("WEFRAME_PROJECT_SNAPSHOT_SECS") {
+            config.project_snapshot_interval = interval;
+        }
+        config.jwt_secret = std::env::var("WEFRAME_JWT_SECRET")
+            .ok()
+            .filter(|s| !s.is_empty());
+        if let Some(secret) = std::env::var("WEFRAME_MEDIA_SECRET")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .or_else(|| {
+                config
+                    .jwt_secret
+                    .as_deref()
+                    .map(media_access::derive_secret)
+            })
+        {
+            config.media_secret = secret;
+        }
+        if let Some(ttl) = env_secs("WEFRAME_MEDIA_URL_TTL_SECS") {
+            config.media_url_ttl = ttl;
+        }
+        if let Ok(flags) = std::env::var("WEFRAME_SESSION_FLAGS") {
+            let names: Vec<&str> = flags.split(',').filter(|f| !f.trim().is_empty()).collect();
+            match flags::parse_flags(&names) {
+                Ok(flags) => config.session_flags = flags,
+                Err(e) => eprintln!("Ignoring WEFRAME_SESSION_FLAGS: {}", e),
+            }
+        }
+        if let Some(interval) = env_secs("WEFRAME_CHECKSUM_SECS") {
+            config.checksum_interval = interval;
+        }
+        if let Ok(check) = std::env::var("WEFRAME_CHECK_INTEGRITY") {
+            config.check_integrity = check == "1";
+        }
+        if let Some(ffmpeg) = std::env::var_os("WEFRAME_FFMPEG") {
+            config.ffmpeg = PathBuf::from(ffmpeg);
+        }
+        if let Ok(url) = std::env::var("WEFRAME_TRANSCRIPTION_URL") {
+            config.transcription = Some(TranscriptionConfig::Http { url });
+        } else if let Some(model) = std::env::var_os("WEFRAME_WHISPER_MODEL") {
+            config.transcription = Some(TranscriptionConfig::Whisper {
+                binary: std::env::var_os("WEFRAME_WHISPER")
+                    .map_or_else(|| PathBuf::from("whisper-cli"), PathBuf::from),
+                model: PathBuf::from(model),
+            });
+        }
+        config.render_dir = std::env::var_os("WEFRAME_RENDER_DIR").map(PathBuf::from);
+        if let Some(retention) = env_secs("WEFRAME_RENDER_RETENTION_SECS") {
+            config.render_retention = retention;
+        }
+        for (class, limits) in [
+            ("RENDER", &mut config.jobs.render),
+            ("PREVIEW", &mut config.jobs.preview),
+            ("ANALYSIS", &mut config.jobs.analysis),
+        ] {
+            if let Some(workers) = env_count(&format!("WEFRAME_{}_WORKERS", class)) {
+                limits.workers = workers;
+            }
+            if let Some(max_queued) = env_count(&format!("WEFRAME_{}_QUEUE_LEN", class)) {
+                limits.max_queued = max_queued;
+            }
+        }
+        config.max_session_bytes = std::env::var("WEFRAME_MAX_SESSION_BYTES")
+            .ok()
+            .and_then(|limit| limit.parse().ok());
+        config.asset_gc.delete = std::env::var("WEFRAME_ASSET_GC_DELETE").is_ok_and(|v| v == "1");
+        if let Some(seed) = std::env::var("WEFRAME_ID_SEED")
+            .ok()
+            .and_then(|seed| seed.parse().ok())
+        {
+            config.ids = IdGenerator::sequential(seed);
+        }
+        config.workspace_quota = WorkspaceQuota {
+            max_sessions: env_count("WEFRAME_WORKSPACE_MAX_SESSIONS"),
+            max_storage_bytes: std::env::var("WEFRAME_WORKSPACE_MAX_STORAGE_BYTES")
+                .ok()
+                .and_then(|limit| limit.parse().ok()),
+            max_render_minutes: std::env::var("WEFRAME_WORKSPACE_RENDER_MINUTES")
+                .ok()
+                .and_then(|minutes| minutes.parse().ok()),
+        };
+        config.admin_token = std::env::var("WEFRAME_ADMIN_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty());
+        config
+    }
+
+    pub fn media_base_url(&self) -> String {
+        self.public_media_url
+            .clone()
+            .unwrap_or_else(|| "/media".to_string())
+    }
+
+    /// Signer for the media URLs handed to session members.
+    pub fn media_access(&self) -> MediaAccess {
+        MediaAccess::new(
+            self.media_secret.as_bytes(),
+            self.media_url_ttl,
+            self.clock.clone(),
+        )
+    }
+
+    /// Services this deployment offers, as advertised to clients.
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            binary_protocol: true,
+            render_service: self.media_dir.is_some(),
+            chat: self.session_flags.contains("chat"),
+            asset_uploads: self.media_dir.is_some(),
+            transcription: self.media_dir.is_some() && self.transcription.is_some(),
+        }
+    }
+}
+
+fn env_secs(name: &str) -> Option<Duration> {
+    std::env::var(name)
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map(Duration::from_secs)
 }
 
-#[derive(Serialize, Deserialize)]
-pub enum ServerMessage {
-    ClientOperation(OTOperation),
-    NewClient { client_id: String, name: String },
-    ClientDisconnected(String),
-    ProjectUpdate(VideoProject),
-    ChatMessage { client_id: String, message: String },
-    Error { client_id: String, message: String },
-    Ping(u64),
-    Pong(u64),
+fn env_count(name: &str) -> Option<usize> {
+    std::env::var(name)
+        .ok()
+        .and_then(|count| count.parse().ok())
+}
+
+impl Metadata {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn created_at(&self) -> SystemTime {
+        self.created_at
+    }
+
+    pub fn max_duration(&self) -> Duration {
+        self.max_duration
+    }
+
+    pub fn flags(&self) -> &BTreeSet<String> {
+        &self.flags
+    }
+
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.contains(flag)
+    }
+
+    pub fn workspace(&self) -> Option<&str> {
+        self.workspace.as_deref()
+    }
+}
+
+impl Default for SessionManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SessionManager {
     pub fn new() -> Self {
+        Self::with_config(Arc::new(ServerConfig::default()))
+    }
+
+    pub fn with_config(config: Arc<ServerConfig>) -> Self {
         SessionManager {
             sessions: HashMap::new(),
+            hibernation: config
+                .hibernate_dir
+                .clone()
+                .map(|dir| HibernationStore::new(dir, config.clock.clone())),
+            recycle_bin: config
+                .recycle_dir
+                .clone()
+                .map(|dir| HibernationStore::new(dir, config.clock.clone())),
+            projects: store::from_config(&config),
+            auth: config
+                .jwt_secret
+                .as_ref()
+                .map(|secret| Arc::new(Authenticator::new(secret.as_bytes()))),
+            jobs: JobPool::new(config.jobs),
+            workspaces: Workspaces::default(),
+            config,
+            metrics: Arc::new(Metrics::default()),
         }
     }
 
-    pub async fn get_or_create_session(&mut self, id: &str) -> Arc<RwLock<VideoSession>> {
-        self.sessions
-            .entry(id.to_string())
-            .or_insert_with(|| {
-                Arc::new(RwLock::new(VideoSession::new(Metadata {
-                    name: id.to_string(),
-                    created_at: SystemTime::now(),
-                    max_duration: Duration::from_secs(3600), // 1 hour max session duration
-                })))
-            })
-            .clone()
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
     }
 
-    pub async fn cleanup_inactive_sessions(&mut self) {
-        let now = SystemTime::now();
-        self.sessions.retain(|_, session| {
-            let last_activity = session.blocking_read().last_activity;
-            now.duration_since(last_activity)
-                .unwrap_or(Duration::from_secs(0))
-                < Duration::from_secs(24 * 60 * 60)
-        });
+    /// Verifier for client tokens, when the server requires them.
+    pub fn authenticator(&self) -> Option<Arc<Authenticator>> {
+        self.auth.clone()
+    }
+
+    /// Workers background media jobs wait their turn for.
+    pub fn jobs(&self) -> JobPool {
+        self.jobs.clone()
+    }
+
+    fn new_session(&self, id: &str) -> VideoSession {
+        VideoSession::new(
+            Metadata {
+                name: id.to_string(),
+                created_at: self.config.clock.now(),
+                max_duration: Duration::from_secs(3600), // 1 hour max session duration
+                flags: self.config.session_flags.clone(),
+                workspace: None,
+                owner: None,
+            },
+            self.config.clone(),
+            self.metrics.clone(),
+        )
+    }
+
+    pub fn get_session(&self, id: &str) -> Option<Arc<RwLock<VideoSession>>> {
+        self.sessions.get(id).cloned()
+    }
+
+    pub fn sessions(&self) -> impl Iterator<Item = (&String, &Arc<RwLock<VideoSession>>)> {
+        self.sessions.iter()
     }
 }
 
+/// Returns the live session, rehydrating it if it was hibernated or
+/// loading it from the project store if it was persisted. Stores are read
+/// without holding the manager's lock, so a cold load doesn't hold up other
+/// sessions; if another request loaded the session meanwhile, its copy is
+/// the one returned.
+pub async fn get_or_create_session(
+    manager: &RwLock<SessionManager>,
+    id: &str,
+) -> Arc<RwLock<VideoSession>> {
+    let (mut session, hibernation, projects) = {
+        let manager = manager.read().await;
+        if let Some(session) = manager.sessions.get(id) {
+            return session.clone();
+        }
+        (
+            manager.new_session(id),
+            manager.hibernation.clone(),
+            manager.projects.clone(),
+        )
+    };
+
+    let mut rehydrated = false;
+    if let Some(store) = &hibernation {
+        // The snapshot stays until the session is persisted or
+        // hibernated again, so a crash meanwhile doesn't lose it
+        match store.load(id).await {
+            Ok(Some(snapshot)) => {
+                println!("Rehydrated session {}", id);
+                session.restore(snapshot);
+                rehydrated = true;
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("Failed to rehydrate session {}: {}", id, e),
+        }
+    }
+    // A hibernated snapshot is at least as recent as the persisted one,
+    // since sessions are persisted before they are hibernated.
+    if let Some(store) = projects.as_ref().filter(|_| !rehydrated) {
+        match store.load(id).await {
+            Ok(Some(snapshot)) => {
+                println!("Loaded session {} from the project store", id);
+                let version = snapshot.server_version;
+                session.restore(snapshot);
+                session.persisted_version = Some(version);
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("Failed to load session {}: {}", id, e),
+        }
+    }
+
+    let mut manager = manager.write().await;
+    if let Some(loaded) = manager.sessions.get(id) {
+        return loaded.clone();
+    }
+    let session = Arc::new(RwLock::new(session));
+    manager.sessions.insert(id.to_string(), session.clone());
+    manager.workspaces.forget_stored(id);
+    session
+}
+
+/// Unloads sessions with no clients that have been idle for the configured
+/// timeout. They are hibernated when a store is set up, and otherwise go to
+/// the recycle bin if there is one. Sessions are written without holding
+/// the manager's lock, and only unloaded if nothing touched them meanwhile.
+pub async fn cleanup_inactive_sessions(manager: &RwLock<SessionManager>) {
+    let (idle, projects, hibernation, recycle_bin) = {
+        let manager = manager.read().await;
+        let now = manager.config.clock.now();
+        let mut idle = Vec::new();
+        for (id, session) in &manager.sessions {
+            let guard = session.read().await;
+            let idle_for = now
+                .duration_since(guard.last_activity)
+                .unwrap_or(Duration::from_secs(0));
+            if guard.clients.is_empty() && idle_for >= manager.config.session_idle_timeout {
+                idle.push((id.clone(), session.clone()));
+            }
+        }
+        (
+            idle,
+            manager.projects.clone(),
+            manager.hibernation.clone(),
+            manager.recycle_bin.clone(),
+        )
+    };
+
+    for (id, session) in idle {
+        let (snapshot, unsaved, version) = {
+            let session = session.read().await;
+            (
+                session.snapshot(),
+                session.unsaved(),
+                session.server_version,
+            )
+        };
+        if let (Some(store), Some(unsaved)) = (&projects, unsaved) {
+            if let Err(e) = unsaved.write(store.as_ref(), &id).await {
+                eprintln!("Failed to persist idle session {}: {}", id, e);
+                continue;
+            }
+            session.write().await.persisted_version = Some(unsaved.server_version());
+        }
+        if let Some(store) = hibernation.as_ref().or(recycle_bin.as_ref()) {
+            if let Err(e) = store.write(&id, &snapshot).await {
+                eprintln!("Failed to save idle session {}: {}", id, e);
+                continue;
+            }
+        }
+
+        let mut manager = manager.write().await;
+        let unchanged = {
+            let guard = session.read().await;
+            guard.clients.is_empty()
+                && guard.server_version == version
+                && manager
+                    .sessions
+                    .get(&id)
+                    .is_some_and(|loaded| Arc::ptr_eq(loaded, &session))
+        };
+        if !unchanged {
+            // Rejoined while it was written; a hibernated copy is simply
+            // overwritten next time, but it mustn't linger as deleted
+            if let (None, Some(bin)) = (&hibernation, &recycle_bin) {
+                drop(manager);
+                if let Err(e) = bin.remove(&id).await {
+                    eprintln!(
+                        "Failed to take session {} out of the recycle bin: {}",
+                        id, e
+                    );
+                }
+            }
+            continue;
+        }
+        if hibernation.is_some() || projects.is_some() {
+            manager.workspaces.track_stored(&id, &*session.read().await);
+        }
+        manager.sessions.remove(&id);
+        manager.metrics.remove_session(&id);
+    }
+    recycle::purge_recycle_bin(manager).await;
+}
+
 impl VideoSession {
-    pub fn new(metadata: Metadata) -> Self {
+    pub fn new(metadata: Metadata, config: Arc<ServerConfig>, metrics: Arc<Metrics>) -> Self {
         let (broadcast_tx, _) = broadcast::channel(100);
-        let (update_tx, update_rx) = mpsc::channel(100);
+        let project = VideoProject::new(
+            config.ids.uuid().to_string(),
+            metadata.name.clone(),
+            "server".to_string(),
+            "Server".to_string(),
+        );
         VideoSession {
-            metadata: metadata.clone(),
-            project: VideoProject::new(
-                uuid::Uuid::new_v4().to_string(),
-                metadata.name,
-                "server".to_string(),
-                "Server".to_string(),
-            ),
+            metadata,
+            memory: MemoryUsage {
+                project: memory::approx_size(&project),
+                history: 0,
+            },
+            project,
             clients: HashMap::new(),
             server_version: 0,
-            last_activity: SystemTime::now(),
+            last_activity: config.clock.now(),
             broadcast: broadcast_tx,
-            update_tx,
-            update_rx,
+            config,
+            metrics,
+            asset_unused_since: HashMap::new(),
+            clip_trashed_since: HashMap::new(),
+            op_log: VecDeque::new(),
+            op_log_start: 0,
+            view_states: HashMap::new(),
+            activity: dashboard::EditActivity::default(),
+            persisted_version: None,
+            chat_history: VecDeque::new(),
+            integrity_issues: Vec::new(),
+            checksummed_version: None,
+            label: None,
+            activity_feed: VecDeque::new(),
+            frozen: None,
+            preview_attempts: HashMap::new(),
         }
     }
 
-    pub fn apply_operation(&mut self, operation: &OTOperation) {
-        self.project.apply_operation(&operation.operation);
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Stream of applied operations for in-process observers. Clients get
+    /// operations through `broadcast_message` instead.
+    pub fn subscribe(&self) -> broadcast::Receiver<OTOperation> {
+        self.broadcast.subscribe()
+    }
+
+    pub fn project(&self) -> &VideoProject {
+        &self.project
+    }
+
+    pub fn apply_operation(&mut self, operation: &OTOperation) -> Vec<Adjustment> {
+        let started = Instant::now();
+        let adjustments = self.project.apply_operation(&operation.operation);
+        self.metrics
+            .observe_operation("apply", operation.operation.kind(), started.elapsed());
         self.server_version += 1;
+        self.op_log.push_back(operation.clone());
+        self.account_operation(operation);
+        self.record_edit(operation);
         self.broadcast.send(operation.clone()).ok();
+        adjustments
     }
 
-    pub fn add_client(&mut self, client_id: String, client_sender: mpsc::UnboundedSender<Message>) {
-        self.clients.insert(client_id.clone(), client_sender);
+    /// Applies an already transformed operation and tells every client about
+    /// it, followed by any adjustments it caused.
+    pub fn commit_operation(&mut self, operation: OTOperation) {
+        let server_version = operation.server_version;
+        let adjustments = self.apply_operation(&operation);
+        self.check_integrity(&operation);
+        if operation.label.is_some() {
+            self.label = operation.label.clone();
+        }
+        self.broadcast_message(&ServerMessage::ClientOperation(operation));
+        if !adjustments.is_empty() {
+            self.broadcast_message(&ServerMessage::ProjectAdjusted {
+                server_version,
+                adjustments,
+            });
+        }
+    }
+
+    /// Latest time a clip may end at in this session.
+    fn timeline_limit(&self) -> Duration {
+        self.config
+            .max_timeline_duration
+            .map_or(self.project.duration, |max| max.min(self.project.duration))
+    }
+
+    /// Quantizes and clamps an operation from a client, then checks it
+    /// against the current project.
+    fn prepare_operation(
+        &self,
+        client_id: &str,
+        client_op: &mut OTOperation,
+    ) -> Result<(), String> {
+        self.check_flags(&client_op.operation)?;
+        if let Some(label) = &client_op.label {
+            validate_label(label)?;
+        }
+        if self.metadata.has_flag("frame_quantization") {
+            self.project.quantize_operation(&mut client_op.operation);
+        }
+        if self
+            .project
+            .clamp_operation(&mut client_op.operation, self.timeline_limit())
+        {
+            println!(
+                "Clamped operation from {}: {:?}",
+                client_id, client_op.operation
+            );
+        }
+        self.project.validate_operation(&client_op.operation)
+    }
+
+    /// Transforms a client's operation past everything other clients
+    /// committed since the version it was made at, as the client does with
+    /// its pending operations. Fails if one of those superseded it, or if
+    /// the log no longer holds them all.
+    fn rebase(&self, mut client_op: OTOperation) -> Result<OTOperation, String> {
+        if client_op.server_version < self.op_log_start {
+            return Err(format!(
+                "Made at version {}, older than the server still holds ({}); resync and retry",
+                client_op.server_version, self.op_log_start
+            ));
+        }
+        let unseen = client_op.server_version - self.op_log_start;
+        for applied in self.op_log.iter().skip(unseen) {
+            if applied.client_id != client_op.client_id {
+                client_op.operation = client_op
+                    .operation
+                    .transform(&applied.operation)
+                    .ok_or_else(|| {
+                        format!(
+                            "Superseded by a concurrent {} from {}",
+                            applied.operation.kind(),
+                            applied.client_id
+                        )
+                    })?;
+            }
+        }
+        Ok(client_op)
+    }
+
+    /// Whether other clients committed anything since the version
+    /// `client_op` was made at.
+    fn is_concurrent(&self, client_op: &OTOperation) -> bool {
+        let unseen = client_op.server_version.saturating_sub(self.op_log_start);
+        self.op_log
+            .iter()
+            .skip(unseen)
+            .any(|applied| applied.client_id != client_op.client_id)
+    }
+
+    /// Rebases, clamps, validates and transforms an operation received from
+    /// a client, then commits it or sends the client a rejection. Operations
+    /// that can't be rebased are rejected as conflicts.
+    pub fn handle_client_operation(&mut self, client_id: &str, client_op: OTOperation) {
+        self.last_activity = self.config.clock.now();
+
+        if let Err(message) = self.authorize(client_id, &client_op.operation) {
+            let version = client_op.client_version;
+            self.refuse(client_id, version, RejectionCode::Forbidden, message);
+            return;
+        }
+        if let Err(message) = self.check_frozen(&client_op.operation) {
+            let version = client_op.client_version;
+            self.refuse(client_id, version, RejectionCode::Frozen, message);
+            return;
+        }
+        // Failing validation after others' edits means they got in the way
+        let invalid = if self.is_concurrent(&client_op) {
+            RejectionCode::Conflict
+        } else {
+            RejectionCode::Invalid
+        };
+        let client_version = client_op.client_version;
+        let kind = client_op.operation.kind();
+        let started = Instant::now();
+        let rebased = self.rebase(client_op);
+        self.metrics
+            .observe_operation("transform", kind, started.elapsed());
+        let prepared = rebased
+            .map_err(|message| (RejectionCode::Conflict, message))
+            .and_then(|mut client_op| {
+                self.prepare_operation(client_id, &mut client_op)
+                    .map_err(|message| (invalid, message))?;
+                self.reserve_memory(&client_op)
+                    .map_err(|message| (RejectionCode::OverLimit, message))?;
+                Ok(client_op)
+            });
+        let mut client_op = match prepared {
+            Ok(client_op) => client_op,
+            Err((code, message)) => {
+                self.send_to(
+                    client_id,
+                    &ServerMessage::OperationRejected {
+                        client_version,
+                        message,
+                        code,
+                    },
+                );
+                return;
+            }
+        };
+
+        client_op.server_version = self.server_version;
+        println!("Applied operation: {:?}", client_op);
+        self.commit_operation(client_op);
+    }
+
+    /// Applies an operation originating from the server itself (e.g. a
+    /// finished background job) and broadcasts it like a client edit.
+    pub fn apply_server_operation(&mut self, operation: EditOperation) -> Result<(), String> {
+        self.project.validate_operation(&operation)?;
+        let operation = OTOperation {
+            client_id: "server".to_string(),
+            client_version: self.server_version,
+            server_version: self.server_version,
+            operation,
+            label: None,
+        };
+        self.reserve_memory(&operation)?;
+        self.commit_operation(operation);
+        Ok(())
+    }
+
+    /// Attaches generated preview images to a clip.
+    pub fn set_clip_previews(
+        &mut self,
+        clip_id: &str,
+        thumbnail_url: Option<String>,
+        filmstrip_url: Option<String>,
+    ) -> Result<(), String> {
+        self.apply_server_operation(EditOperation::SetClipPreviews {
+            clip_id: clip_id.to_string(),
+            thumbnail_url,
+            filmstrip_url,
+        })
+    }
+
+    /// Attaches precomputed audio peaks to a clip.
+    pub fn set_clip_waveform(
+        &mut self,
+        clip_id: &str,
+        waveform: WaveformRef,
+    ) -> Result<(), String> {
+        self.apply_server_operation(EditOperation::SetClipWaveform {
+            clip_id: clip_id.to_string(),
+            waveform: Some(waveform),
+        })
+    }
+
+    /// Registers a connection under a generated guest name, which it
+    /// returns. The name may change once the client's `Hello` arrives.
+    pub fn add_client(
+        &mut self,
+        client_id: String,
+        client_sender: OutboxSender,
+        traffic: Arc<Mutex<TrafficStats>>,
+    ) -> String {
+        let role = self.initial_role();
+        self.clients.insert(
+            client_id.clone(),
+            ClientHandle {
+                sender: client_sender,
+                protocol_version: 1,
+                features: Vec::new(),
+                connected_at: self.config.clock.now(),
+                traffic,
+                user_key: None,
+                signed_in: false,
+                post_token: None,
+                batching: Arc::new(AtomicBool::new(false)),
+            },
+        );
+        let name = self.unique_name(&client_id, guests::random_name(&self.config.ids));
         self.project.collaborators.push(Collaborator {
             id: client_id.clone(),
-            name: format!("User {}", client_id),
-            cursor_position: CursorPosition {
-                track: 0,
-                time: Duration::from_secs(0),
-            },
+            name: name.clone(),
+            cursor_position: CursorPosition::default(),
+            avatar_url: None,
+            role,
         });
-        self.last_activity = SystemTime::now();
+        self.last_activity = self.config.clock.now();
+        name
     }
 
     pub fn remove_client(&mut self, client_id: &str) {
@@ -120,15 +1053,232 @@ impl VideoSession {
         self.project.collaborators.retain(|c| c.id != client_id);
     }
 
+    /// Records the outcome of a client's `Hello` and replies with `Welcome`
+    /// and the current project, or with an error if the client is too old.
+    /// A guest token gives the client its stable guest name; an invalid
+    /// avatar URL is dropped. A client resuming from a server version gets
+    /// only the operations it missed, when the op log still holds them.
+    pub fn negotiate(
+        &mut self,
+        client_id: &str,
+        protocol_version: u32,
+        features: &[String],
+        guest_token: Option<&str>,
+        avatar_url: Option<&str>,
+        resume_from: Option<usize>,
+    ) -> Result<(), String> {
+        let (protocol_version, features) =
+            weframe_shared::negotiate_protocol(protocol_version, features)?;
+        let mut signed_in = false;
+        if let Some(client) = self.clients.get_mut(client_id) {
+            client.protocol_version = protocol_version;
+            client.features = features.clone();
+            if !client.signed_in {
+                client.user_key = guest_token.and_then(guests::user_key);
+            }
+            signed_in = client.signed_in;
+            client.batching.store(
+                features.iter().any(|f| f == "batched_frames"),
+                Ordering::Relaxed,
+            );
+        }
+        let avatar_url = avatar_url.filter(|url| match validate_avatar_url(url) {
+            Ok(()) => true,
+            Err(message) => {
+                println!("Ignoring avatar from {}: {}", client_id, message);
+                false
+            }
+        });
+        if !signed_in {
+            self.update_identity(
+                client_id,
+                guest_token.and_then(guests::name_for_token),
+                avatar_url.map(str::to_string),
+            );
+            self.claim_ownership(client_id);
+        }
+        let name = self
+            .project
+            .collaborators
+            .iter()
+            .find(|c| c.id == client_id)
+            .map(|c| c.name.clone())
+            .unwrap_or_default();
+        self.send_to(
+            client_id,
+            &ServerMessage::Welcome {
+                protocol_version,
+                features,
+                client_id: client_id.to_string(),
+                capabilities: self.config.capabilities(),
+                name,
+                session_flags: self.metadata.flags.iter().cloned().collect(),
+                role: self.role_of(client_id),
+                server_version: self.server_version,
+            },
+        );
+        self.announce_freeze(client_id);
+        if resume_from.is_some_and(|from| self.resume(client_id, from)) {
+            self.restore_view_state(client_id);
+            self.replay_chat(client_id);
+            return Ok(());
+        }
+        // Initial sync, now that we know how the client can take it
+        self.send_full_project(client_id);
+        self.restore_view_state(client_id);
+        self.replay_chat(client_id);
+        Ok(())
+    }
+
+    /// Gives a client `name`, made unique in the session, and `avatar_url`,
+    /// where given, and tells everyone if that changed anything.
+    fn update_identity(
+        &mut self,
+        client_id: &str,
+        name: Option<String>,
+        avatar_url: Option<String>,
+    ) {
+        let name = name.map(|name| self.unique_name(client_id, name));
+        let Some(collaborator) = self
+            .project
+            .collaborators
+            .iter_mut()
+            .find(|c| c.id == client_id)
+        else {
+            return;
+        };
+        let before = (collaborator.name.clone(), collaborator.avatar_url.clone());
+        if let Some(name) = name {
+            collaborator.name = name;
+        }
+        if avatar_url.is_some() {
+            collaborator.avatar_url = avatar_url;
+        }
+        if before == (collaborator.name.clone(), collaborator.avatar_url.clone()) {
+            return;
+        }
+        let message = ServerMessage::NewClient {
+            client_id: client_id.to_string(),
+            name: collaborator.name.clone(),
+            avatar_url: collaborator.avatar_url.clone(),
+            role: collaborator.role,
+        };
+        self.broadcast_message(&message);
+    }
+
+    /// Whether a client negotiated taking the project in `SyncBegin`,
+    /// `SyncAssets`, `SyncClips` and `SyncComplete` pages.
+    fn takes_chunked_sync(&self, client_id: &str) -> bool {
+        self.clients
+            .get(client_id)
+            .is_some_and(|c| c.features.iter().any(|f| f == "chunked_sync"))
+    }
+
+    /// Sends a client the whole project: in pages if it takes them and the
+    /// project needs more than one, else as a single `ProjectUpdate`.
+    fn send_full_project(&self, client_id: &str) {
+        if self.takes_chunked_sync(client_id) && self.project.clips.len() > SYNC_PAGE_SIZE {
+            self.send_chunked_sync(client_id);
+        } else {
+            self.send_to(
+                client_id,
+                &ServerMessage::ProjectUpdate(self.project.clone()),
+            );
+        }
+    }
+
+    /// Sends the project as a sequence of bounded messages instead of one
+    /// `ProjectUpdate` that could exceed frame limits.
+    fn send_chunked_sync(&self, client_id: &str) {
+        let mut skeleton = self.project.clone();
+        let assets = std::mem::take(&mut skeleton.assets);
+        let mut clips = std::mem::take(&mut skeleton.clips);
+        clips.sort_by_key(|c| (c.track, c.start_time));
+
+        self.send_to(
+            client_id,
+            &ServerMessage::SyncBegin {
+                server_version: self.server_version,
+                project: skeleton,
+            },
+        );
+        for page in assets.chunks(SYNC_PAGE_SIZE) {
+            self.send_to(client_id, &ServerMessage::SyncAssets(page.to_vec()));
+        }
+        for page in clips.chunks(SYNC_PAGE_SIZE) {
+            self.send_to(client_id, &ServerMessage::SyncClips(page.to_vec()));
+        }
+        self.send_to(
+            client_id,
+            &ServerMessage::SyncComplete {
+                server_version: self.server_version,
+            },
+        );
+    }
+
+    /// Serializes `message` for one client, resolving asset URIs to public
+    /// URLs and downgrading it to the client's protocol. Large project
+    /// snapshots go out as a binary frame of zstd-compressed JSON to clients
+    /// that negotiated `zstd`.
+    fn encode(&self, client: &ClientHandle, message: &ServerMessage) -> Option<Message> {
+        let mut message = client.downgrade(message)?;
+        media::add_public_urls(
+            &mut message,
+            &MediaUrls::new(&self.config, &self.metadata.name),
+        );
+        let json = serde_json::to_string(&message).unwrap();
+        if matches!(message, ServerMessage::ProjectUpdate(_))
+            && json.len() >= COMPRESSION_THRESHOLD
+            && client.features.iter().any(|f| f == "zstd")
+        {
+            match zstd::encode_all(json.as_bytes(), 3) {
+                Ok(compressed) => return Some(Message::binary(compressed)),
+                Err(e) => eprintln!("Failed to compress project update: {}", e),
+            }
+        }
+        Some(Message::text(json))
+    }
+
+    /// Queues `message` for `client`, behind any edits if it is only
+    /// presence.
+    fn deliver(&self, client: &ClientHandle, message: &ServerMessage) {
+        if let Some(msg) = self.encode(client, message) {
+            client.sender.send(msg, Priority::of(message)).ok();
+        }
+    }
+
     pub fn broadcast_message(&self, message: &ServerMessage) {
-        let msg = serde_json::to_string(message).unwrap();
-        for sender in self.clients.values() {
-            sender.send(Message::text(msg.clone())).ok();
+        for client in self.clients.values() {
+            self.deliver(client, message);
+        }
+    }
+
+    /// Passes a client's preview solo on to everyone else in the session,
+    /// keeping only clips that exist.
+    pub fn relay_preview_solo(&self, client_id: &str, mut clip_ids: Vec<String>) {
+        clip_ids.retain(|id| self.project.clips.iter().any(|c| c.id == *id));
+        let message = ServerMessage::PreviewSolo {
+            client_id: client_id.to_string(),
+            clip_ids,
+        };
+        for (id, client) in &self.clients {
+            if id != client_id {
+                self.deliver(client, &message);
+            }
+        }
+    }
+
+    pub fn send_to(&self, client_id: &str, message: &ServerMessage) {
+        if let Some(client) = self.clients.get(client_id) {
+            self.deliver(client, message);
         }
     }
 
     pub fn send_ping(&self) -> ServerMessage {
-        let now = SystemTime::now()
+        let now = self
+            .config
+            .clock
+            .now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_millis() as u64;
@@ -136,44 +1286,113 @@ impl VideoSession {
     }
 
     pub fn send_pong(&self, received_time: u64) -> ServerMessage {
-        let now = SystemTime::now()
+        let now = self
+            .config
+            .clock
+            .now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_millis() as u64;
-        let duration = now - received_time;
+        let duration = now.saturating_sub(received_time);
         ServerMessage::Pong(duration)
     }
 }
 
+#[cfg(not(feature = "profiling"))]
+fn websocket_connection(
+    socket: WebSocket,
+    session_id: String,
+    token: Option<String>,
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl std::future::Future<Output = ()> {
+    handle_websocket(socket, session_id, token, manager)
+}
+
+#[cfg(not(feature = "profiling"))]
+async fn write_session(
+    session: &RwLock<VideoSession>,
+) -> tokio::sync::RwLockWriteGuard<'_, VideoSession> {
+    session.write().await
+}
+
+/// Runs one WebSocket client. When the server requires tokens, `token`
+/// (from the URL) or else the client's first message must hold a valid one;
+/// otherwise the client gets a `ServerMessage::Error` and is disconnected
+/// before joining the session.
 pub async fn handle_websocket(
     ws: WebSocket,
     session_id: String,
+    token: Option<String>,
     manager: Arc<RwLock<SessionManager>>,
 ) {
     let (mut ws_sender, mut ws_receiver) = ws.split();
-    let (client_sender, mut client_receiver) = mpsc::unbounded_channel();
+    let (client_sender, mut client_receiver) = outbox::outbox();
 
-    let client_id = format!("user-{}", random::<u32>());
+    let (authenticator, client_id) = {
+        let manager = manager.read().await;
+        let client_id = format!("user-{}", manager.config.ids.number() as u32);
+        (manager.authenticator(), client_id)
+    };
+    let traffic = Arc::new(Mutex::new(TrafficStats::default()));
 
-    let session = {
-        let mut manager = manager.write().await;
-        manager.get_or_create_session(&session_id).await
+    let identity = match authenticator {
+        Some(authenticator) => {
+            let token = match token {
+                Some(token) => Some(token),
+                None => {
+                    let (size, token) = auth::read_token(&mut ws_receiver).await;
+                    traffic.lock().unwrap().record_received(size);
+                    token
+                }
+            };
+            match authenticator.verify(token.as_deref(), &session_id) {
+                Ok(identity) => Some(identity),
+                Err(error) => {
+                    let error = serde_json::to_string(&error.into_message()).unwrap();
+                    let error = Message::text(error);
+                    send_counted(&mut ws_sender, &traffic, error).await.ok();
+                    send_counted(&mut ws_sender, &traffic, Message::close())
+                        .await
+                        .ok();
+                    return;
+                }
+            }
+        }
+        None => None,
     };
 
-    {
-        let mut session = session.write().await;
+    let workspace = identity.as_ref().and_then(|i| i.workspace.clone());
+    let session = workspaces::join_session(&manager, &session_id, workspace.as_deref()).await;
+    let session = match session {
+        Ok(session) => session,
+        Err(error) => {
+            let error = serde_json::to_string(&error.into_message()).unwrap();
+            send_counted(&mut ws_sender, &traffic, Message::text(error))
+                .await
+                .ok();
+            send_counted(&mut ws_sender, &traffic, Message::close())
+                .await
+                .ok();
+            return;
+        }
+    };
+
+    let batching = {
+        let mut session = write_session(&session).await;
         if !session.clients.contains_key(&client_id) {
-            session.add_client(client_id.clone(), client_sender);
+            let name = session.add_client(client_id.clone(), client_sender, traffic.clone());
+            let role = session.role_of(&client_id);
             session.broadcast_message(&ServerMessage::NewClient {
                 client_id: client_id.clone(),
-                name: format!("User {}", client_id),
+                name,
+                avatar_url: None,
+                role,
             });
         }
-    }
-
-    let mut broadcast_rx = {
-        let session = session.read().await;
-        session.broadcast.subscribe()
+        if let Some(identity) = identity {
+            session.sign_in(&client_id, identity);
+        }
+        session.clients[&client_id].batching.clone()
     };
 
     loop {
@@ -181,77 +1400,682 @@ pub async fn handle_websocket(
             Some(result) = ws_receiver.next() => {
                 match result {
                     Ok(msg) => {
-                        if let Ok(client_op) = serde_json::from_str::<OTOperation>(&msg.to_str().unwrap_or_default()) {
-                            let mut session = session.write().await;
-                            session.last_activity = SystemTime::now();
-
-                            let transformed_op = session.project.transform_operation(&client_op, session.server_version);
-                            session.apply_operation(&transformed_op);
-                            println!("Applied operation: {:?}", transformed_op);
-                            let server_message = ServerMessage::ClientOperation(transformed_op);
-                            let msg = serde_json::to_string(&server_message).unwrap();
-                            for (_, sender) in &session.clients {
-                                let _ = sender.send(Message::text(msg.clone()));
+                        traffic.lock().unwrap().record_received(msg.as_bytes().len());
+                        let text = msg.to_str().unwrap_or_default();
+                        match handle_client_message(&session, &client_id, text).await {
+                            Inbound::Handled => {}
+                            Inbound::Reply(reply) => {
+                                send_counted(&mut ws_sender, &traffic, Message::text(serde_json::to_string(&reply).unwrap())).await.ok();
+                            }
+                            Inbound::Close(reply) => {
+                                send_counted(&mut ws_sender, &traffic, Message::text(serde_json::to_string(&reply).unwrap())).await.ok();
+                                send_counted(&mut ws_sender, &traffic, Message::close()).await.ok();
+                                break;
                             }
-                        } else if let Ok(ServerMessage::Ping(timestamp)) = serde_json::from_str(&msg.to_str().unwrap_or_default()) {
-                            let pong = session.read().await.send_pong(timestamp);
-                            ws_sender.send(Message::text(serde_json::to_string(&pong).unwrap())).await.ok();
                         }
                     }
                     Err(_) => break,
                 }
             }
-            Ok(operation) = broadcast_rx.recv() => {
-                let msg = serde_json::to_string(&operation).unwrap();
-                if ws_sender.send(Message::text(msg)).await.is_err() {
-                    break;
-                }
-            }
             Some(msg) = client_receiver.recv() => {
-                if ws_sender.send(msg).await.is_err() {
+                let (frame, rest) = match msg.to_str() {
+                    Ok(text) if batching.load(Ordering::Relaxed) => {
+                        collect_batch(text.to_string(), &mut client_receiver).await
+                    }
+                    _ => (msg, None),
+                };
+                if send_counted(&mut ws_sender, &traffic, frame).await.is_err() {
                     break;
                 }
+                if let Some(rest) = rest {
+                    if send_counted(&mut ws_sender, &traffic, rest).await.is_err() {
+                        break;
+                    }
+                }
             }
             else => break,
         }
     }
 
-    let mut session = session.write().await;
+    let mut session = write_session(&session).await;
     session.remove_client(&client_id);
     session.broadcast_message(&ServerMessage::ClientDisconnected(client_id));
 }
 
+/// Gathers the text messages queued for a client within `BATCH_WINDOW` of
+/// `first` into one frame holding them as a JSON array; a lone message goes
+/// out as it is. A binary or close message ends the batch and is returned
+/// to be sent after it.
+async fn collect_batch(first: String, receiver: &mut Outbox) -> (Message, Option<Message>) {
+    let mut batch = vec![first];
+    let mut rest = None;
+    let deadline = tokio::time::sleep(BATCH_WINDOW);
+    tokio::pin!(deadline);
+    while batch.len() < MAX_BATCH {
+        tokio::select! {
+            _ = &mut deadline => break,
+            next = receiver.recv() => match next {
+                Some(msg) if msg.is_text() => batch.push(msg.to_str().unwrap().to_string()),
+                other => {
+                    rest = other;
+                    break;
+                }
+            },
+        }
+    }
+    let frame = match batch.len() {
+        1 => batch.pop().unwrap(),
+        _ => format!("[{}]", batch.join(",")),
+    };
+    (Message::text(frame), rest)
+}
+
+/// What a connection does after handling a message from its client.
+pub(crate) enum Inbound {
+    Handled,
+    /// Answer the client directly.
+    Reply(ServerMessage),
+    /// Answer the client, then close the connection.
+    Close(ServerMessage),
+}
+
+/// Handles one text message from a client, whichever transport it came over.
+pub(crate) async fn handle_client_message(
+    session: &RwLock<VideoSession>,
+    client_id: &str,
+    text: &str,
+) -> Inbound {
+    if let Ok(client_op) = serde_json::from_str::<OTOperation>(text) {
+        write_session(session)
+            .await
+            .handle_client_operation(client_id, client_op);
+        return Inbound::Handled;
+    }
+    match serde_json::from_str::<ServerMessage>(text) {
+        Ok(ServerMessage::Ping(timestamp)) => {
+            return Inbound::Reply(session.read().await.send_pong(timestamp));
+        }
+        Ok(ServerMessage::Hello {
+            protocol_version,
+            features,
+            guest_token,
+            avatar_url,
+            resume_from,
+        }) => {
+            let negotiated = write_session(session).await.negotiate(
+                client_id,
+                protocol_version,
+                &features,
+                guest_token.as_deref(),
+                avatar_url.as_deref(),
+                resume_from,
+            );
+            if let Err(message) = negotiated {
+                return Inbound::Close(ServerMessage::Error {
+                    client_id: client_id.to_string(),
+                    message,
+                    code: None,
+                });
+            }
+        }
+        Ok(ServerMessage::SaveViewState(state)) => {
+            let mut session = write_session(session).await;
+            if let Err(message) = session.save_view_state(client_id, state) {
+                session.send_to(
+                    client_id,
+                    &ServerMessage::Error {
+                        client_id: client_id.to_string(),
+                        message,
+                        code: None,
+                    },
+                );
+            }
+        }
+        Ok(ServerMessage::Frozen { reason }) => {
+            let mut session = write_session(session).await;
+            if let Err(message) = session.request_freeze(client_id, reason) {
+                session.send_to(
+                    client_id,
+                    &ServerMessage::Error {
+                        client_id: client_id.to_string(),
+                        message,
+                        code: None,
+                    },
+                );
+            }
+        }
+        Ok(ServerMessage::PreviewSolo { clip_ids, .. }) => {
+            session.read().await.relay_preview_solo(client_id, clip_ids);
+        }
+        Ok(ServerMessage::Resync {
+            server_version,
+            checksum,
+        }) => {
+            session
+                .read()
+                .await
+                .resync(client_id, server_version, checksum);
+        }
+        Ok(ServerMessage::ChatMessage { message, .. }) => {
+            let mut session = write_session(session).await;
+            if let Err(message) = session.post_chat(client_id, message) {
+                session.send_to(
+                    client_id,
+                    &ServerMessage::Error {
+                        client_id: client_id.to_string(),
+                        message,
+                        code: None,
+                    },
+                );
+            }
+        }
+        _ => {}
+    }
+    Inbound::Handled
+}
+
+/// Sends `msg` down the socket, counting it in the connection's traffic.
+async fn send_counted(
+    sink: &mut SplitSink<WebSocket, Message>,
+    traffic: &Mutex<TrafficStats>,
+    msg: Message,
+) -> Result<(), warp::Error> {
+    traffic.lock().unwrap().record_sent(msg.as_bytes().len());
+    sink.send(msg).await
+}
+
+fn cache_control(path: &std::path::Path) -> &'static str {
+    // The HTML shell references hashed bundles, so it must always be
+    // revalidated; everything else can be cached aggressively.
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html") | None => "no-cache",
+        _ => "public, max-age=31536000, immutable",
+    }
+}
+
+pub fn static_files(
+    dir: PathBuf,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let index = dir.join("index.html");
+    warp::get()
+        .and(warp::fs::dir(dir).or(warp::fs::file(index)).unify())
+        .map(|file: warp::fs::File| {
+            let cache_control = cache_control(file.path());
+            warp::reply::with_header(file, "cache-control", cache_control)
+        })
+}
+
 pub async fn run_server() {
-    let session_manager = Arc::new(RwLock::new(SessionManager::new()));
+    run_server_with_config(ServerConfig::from_env()).await;
+}
+
+pub async fn run_server_with_config(config: ServerConfig) {
+    #[cfg(feature = "profiling")]
+    profiling::init();
+    let config = Arc::new(config);
+    let session_manager = Arc::new(RwLock::new(SessionManager::with_config(config.clone())));
+    session_manager.write().await.index_stored_sessions().await;
+    let media_store = config.media_dir.clone().map(MediaStore::new);
+    let metrics = session_manager.read().await.metrics();
+    let jobs = session_manager.read().await.jobs();
+    let render_queue = media_store.as_ref().map(|_| {
+        let dir = config
+            .render_dir
+            .clone()
+            .unwrap_or_else(|| std::env::temp_dir().join("weframe-renders"));
+        render::RenderQueue::start(
+            config.ffmpeg.clone(),
+            dir,
+            session_manager.clone(),
+            jobs,
+            config.clock.clone(),
+        )
+    });
 
-    // cleanup inactive sessions
+    // cleanup inactive sessions, at least once an hour
     let cleanup_manager = session_manager.clone();
+    let cleanup_interval = config
+        .session_idle_timeout
+        .clamp(Duration::from_secs(1), Duration::from_secs(3600));
     tokio::spawn(async move {
         loop {
-            tokio::time::sleep(Duration::from_secs(3600)).await; // once an hour
-            cleanup_manager
-                .write()
-                .await
-                .cleanup_inactive_sessions()
-                .await;
+            tokio::time::sleep(cleanup_interval).await;
+            cleanup_inactive_sessions(&cleanup_manager).await;
         }
     });
 
+    let gc_manager = session_manager.clone();
+    let gc_store = media_store.clone();
+    let gc_policy = config.asset_gc;
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(3600)).await;
+            media::collect_unused_assets(&gc_manager, gc_store.as_ref(), None, gc_policy).await;
+        }
+    });
+
+    let trash_manager = session_manager.clone();
+    let trash_retention = config.trash_retention;
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(3600)).await;
+            trash::purge_expired_trash(&trash_manager, trash_retention).await;
+        }
+    });
+
+    if let Some(queue) = render_queue.clone() {
+        let render_retention = config.render_retention;
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(3600)).await;
+                queue.expire(render_retention).await;
+            }
+        });
+    }
+
+    if session_manager.read().await.projects.is_some() {
+        let persist_manager = session_manager.clone();
+        let snapshot_interval = config.project_snapshot_interval;
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(snapshot_interval).await;
+                store::persist_sessions(&persist_manager).await;
+            }
+        });
+    }
+
+    if let Some(store) = media_store.clone() {
+        let preview_manager = session_manager.clone();
+        let ffmpeg = config.ffmpeg.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(15)).await;
+                previews::generate_missing_previews(&preview_manager, &store, &ffmpeg).await;
+            }
+        });
+    }
+
+    let checksum_manager = session_manager.clone();
+    let checksum_interval = config.checksum_interval;
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(checksum_interval).await;
+            checksum::broadcast_checksums(&checksum_manager).await;
+        }
+    });
+
+    let dashboard_topic = dashboard::start(session_manager.clone());
+
     let cors = warp::cors()
         .allow_any_origin()
-        .allow_methods(vec!["GET", "POST", "OPTIONS"])
-        .allow_headers(vec!["Content-Type"]);
+        .allow_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"])
+        .allow_headers(vec!["Content-Type", "Authorization"]);
 
-    let routes = warp::path("ws")
+    let ws_manager = session_manager.clone();
+    let ws_route = warp::path("ws")
         .and(warp::ws())
         .and(warp::path::param())
-        .and(warp::any().map(move || session_manager.clone()))
+        .and(warp::query::<TokenQuery>())
+        .and(warp::any().map(move || ws_manager.clone()))
         .map(
-            |ws: warp::ws::Ws, session_id: String, manager: Arc<RwLock<SessionManager>>| {
-                ws.on_upgrade(move |socket| handle_websocket(socket, session_id, manager))
+            |ws: warp::ws::Ws,
+             session_id: String,
+             query: TokenQuery,
+             manager: Arc<RwLock<SessionManager>>| {
+                ws.on_upgrade(move |socket| {
+                    websocket_connection(socket, session_id, query.token, manager)
+                })
             },
+        );
+
+    let api = ws_route
+        .or(media::media_route(
+            media_store.clone(),
+            config.media_access(),
+        ))
+        .or(media_access::media_urls_route(session_manager.clone()))
+        .or(media::upload_route(
+            session_manager.clone(),
+            media_store.clone(),
+            config.dedup_scope,
+            config.max_upload_bytes,
+        ))
+        .or(import::import_route(
+            session_manager.clone(),
+            media_store.clone(),
+            config.ffmpeg.clone(),
+            config.dedup_scope,
+            config.max_upload_bytes,
+        ))
+        .or(history::history_route(session_manager.clone()))
+        .or(activity::activity_route(session_manager.clone()))
+        .or(history::state_route(session_manager.clone()))
+        .or(integrity::integrity_route(session_manager.clone()))
+        .or(dry_run::dry_run_route(session_manager.clone()))
+        .or(hibernation::prewarm_route(session_manager.clone()))
+        .or(relink::relink_route(session_manager.clone()))
+        .or(effect_chain::effect_chain_routes(session_manager.clone()))
+        .or(flags::flag_route(session_manager.clone()))
+        .or(audio_sync::audio_sync_route(
+            session_manager.clone(),
+            media_store.clone(),
+        ))
+        .or(scenes::scene_routes(
+            session_manager.clone(),
+            media_store.clone(),
+            config.ffmpeg.clone(),
+        ))
+        .or(silence::silence_routes(
+            session_manager.clone(),
+            media_store.clone(),
+            config.ffmpeg.clone(),
+        ))
+        .or(beats::beat_routes(
+            session_manager.clone(),
+            media_store.clone(),
+            config.ffmpeg.clone(),
+        ))
+        .or(transcription::transcription_route(
+            session_manager.clone(),
+            media_store.clone(),
+            config.ffmpeg.clone(),
+            config.transcription.as_ref().map(|t| t.backend()),
+        ))
+        .or(render::render_routes(
+            session_manager.clone(),
+            media_store.clone(),
+            render_queue,
+        ))
+        .or(render::export_frame_route(
+            session_manager.clone(),
+            media_store.clone(),
+            config.ffmpeg.clone(),
+        ))
+        .or(scopes::scopes_route(
+            session_manager.clone(),
+            media_store.clone(),
+            config.ffmpeg.clone(),
+        ))
+        .or(sse::sse_routes(session_manager.clone()))
+        .or(metrics::metrics_route(metrics));
+
+    let admin_api = recycle::admin_routes(session_manager.clone())
+        .or(flags::set_flags_route(session_manager.clone()))
+        .or(automation::automation_route(session_manager.clone()))
+        .or(connections::connections_route(session_manager.clone()))
+        .or(dashboard::dashboard_routes(
+            session_manager.clone(),
+            dashboard_topic,
+        ))
+        .or(workspaces::workspace_routes(session_manager.clone()))
+        .or(freeze::freeze_routes(session_manager.clone()))
+        .or(media::gc_route(
+            session_manager.clone(),
+            media_store,
+            config.asset_gc,
+        ));
+    #[cfg(feature = "profiling")]
+    let admin_api = admin_api.or(profiling::admin_route());
+    let api = auth::session_guard(session_manager.clone())
+        .and(api)
+        .recover(auth::reject_denied)
+        .map(|reply| Box::new(reply) as Box<dyn warp::Reply>)
+        .boxed();
+    let api = match config.admin_token.as_deref() {
+        Some(token) => {
+            let admin_api = admin::admin_guard(token.into())
+                .and(admin_api)
+                .recover(admin::reject_unauthorized)
+                .map(|reply| Box::new(reply) as Box<dyn warp::Reply>)
+                .boxed();
+            api.or(admin_api).unify().boxed()
+        }
+        None => {
+            println!("WEFRAME_ADMIN_TOKEN is not set; admin routes are disabled");
+            api
+        }
+    };
+
+    let server: Pin<Box<dyn Future<Output = ()> + Send>> = match config.static_dir.clone() {
+        Some(dir) => {
+            println!("Serving static files from {}", dir.display());
+            let routes = api.or(static_files(dir)).with(cors);
+            Box::pin(warp::serve(routes).run(config.bind_addr))
+        }
+        None => Box::pin(warp::serve(api.with(cors)).run(config.bind_addr)),
+    };
+    tokio::select! {
+        _ = server => {}
+        _ = shutdown_signal() => println!("Shutting down"),
+    }
+    store::persist_sessions(&session_manager).await;
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                .expect("failed to install SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::http::StatusCode;
+    use weframe_shared::{CursorPosition, Role, VideoClip};
+
+    /// A manager whose server requires tokens, signed as `token` signs them.
+    pub(crate) fn with_tokens() -> Arc<RwLock<SessionManager>> {
+        let config = ServerConfig {
+            jwt_secret: Some("secret".to_string()),
+            ..ServerConfig::default()
+        };
+        Arc::new(RwLock::new(SessionManager::with_config(Arc::new(config))))
+    }
+
+    /// A token `with_tokens` servers accept, claiming `role`.
+    pub(crate) fn token(role: &str) -> String {
+        let claims = serde_json::json!({ "sub": "someone", "exp": 4_000_000_000u64, "role": role });
+        jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_secret(b"secret"),
         )
-        .with(cors);
+        .unwrap()
+    }
+
+    fn remove(client_id: &str, server_version: usize, clip_id: &str) -> OTOperation {
+        OTOperation {
+            client_id: client_id.to_string(),
+            client_version: 0,
+            server_version,
+            operation: EditOperation::RemoveClip(clip_id.to_string()),
+            label: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn rebase_rejects_operations_past_a_shed_log() {
+        let session = get_or_create_session(&RwLock::new(SessionManager::new()), "log").await;
+        let mut session = session.write().await;
+        // Versions 0-4 were shed; the log holds 5 and 6
+        session.op_log_start = 5;
+        session.op_log = VecDeque::from([remove("other", 5, "a"), remove("other", 6, "b")]);
+        session.server_version = 7;
+
+        // Made after seeing version 5, so only 6 is concurrent
+        let rebased = session.rebase(remove("me", 6, "a"));
+        assert!(matches!(
+            rebased.map(|op| op.operation),
+            Ok(EditOperation::RemoveClip(id)) if id == "a"
+        ));
+        assert!(session.rebase(remove("me", 6, "b")).is_err());
+        // Made before the log starts: what it missed is gone
+        assert!(session.rebase(remove("me", 2, "c")).is_err());
+        // The client's own operations are never transformed past
+        assert!(session.rebase(remove("other", 6, "b")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn superseded_operations_are_rejected_as_conflicts() {
+        let session =
+            get_or_create_session(&RwLock::new(SessionManager::new()), "superseded").await;
+        let mut session = session.write().await;
+        let (sender, mut outbox) = outbox::outbox();
+        session.add_client("me".to_string(), sender, Arc::default());
+        session.clients.get_mut("me").unwrap().protocol_version = 2;
+        session.op_log = VecDeque::from([remove("other", 0, "a")]);
+        session.server_version = 1;
 
-    warp::serve(routes).run(([127, 0, 0, 1], 3030)).await;
+        session.handle_client_operation("me", remove("me", 0, "a"));
+        assert_eq!(session.server_version, 1);
+        let message = outbox.recv().await.unwrap();
+        let message: ServerMessage = serde_json::from_str(message.to_str().unwrap()).unwrap();
+        assert!(matches!(
+            message,
+            ServerMessage::OperationRejected {
+                code: RejectionCode::Conflict,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn clients_cannot_attach_previews_whatever_their_role() {
+        let session = get_or_create_session(&RwLock::new(SessionManager::new()), "previews").await;
+        let mut session = session.write().await;
+        session.project.clips.push(VideoClip {
+            id: "clip".to_string(),
+            ..VideoClip::default()
+        });
+        let (sender, _outbox) = outbox::outbox();
+        let owner = "owner".to_string();
+        session.add_client(owner.clone(), sender, Arc::default());
+        session.set_role(&owner, Role::Owner);
+        assert_eq!(session.role_of(&owner), Role::Owner);
+        let previews = EditOperation::SetClipPreviews {
+            clip_id: "clip".to_string(),
+            thumbnail_url: Some("https://example.com/poster.jpg".to_string()),
+            filmstrip_url: None,
+        };
+        assert!(session.authorize(&owner, &previews).is_err());
+        let batch = EditOperation::Batch(vec![EditOperation::SetSnapToFrames(true), previews]);
+        assert!(session.authorize(&owner, &batch).is_err());
+
+        session.handle_client_operation(
+            &owner,
+            OTOperation {
+                client_id: owner.clone(),
+                client_version: 0,
+                server_version: 0,
+                operation: batch,
+                label: None,
+            },
+        );
+        assert_eq!(session.server_version, 0);
+        assert!(session.project.clips[0].thumbnail_url.is_none());
+
+        session
+            .set_clip_previews("clip", Some("media:preview-a.jpg".to_string()), None)
+            .unwrap();
+        assert_eq!(
+            session.project.clips[0].thumbnail_url.as_deref(),
+            Some("media:preview-a.jpg")
+        );
+    }
+
+    #[tokio::test]
+    async fn clients_move_only_their_own_cursor() {
+        let session = get_or_create_session(&RwLock::new(SessionManager::new()), "cursors").await;
+        let mut session = session.write().await;
+        for client_id in ["me", "other"] {
+            let (sender, _outbox) = outbox::outbox();
+            session.add_client(client_id.to_string(), sender, Arc::default());
+            session.set_role(client_id, Role::Viewer);
+        }
+        let cursor = |collaborator_id: &str| EditOperation::UpdateCollaboratorCursor {
+            collaborator_id: collaborator_id.to_string(),
+            new_position: CursorPosition {
+                track: 1,
+                ..CursorPosition::default()
+            },
+            velocity: None,
+        };
+        assert!(session.authorize("me", &cursor("me")).is_ok());
+        assert!(session.authorize("me", &cursor("other")).is_err());
+        let batch = EditOperation::Batch(vec![cursor("me"), cursor("other")]);
+        assert!(session.authorize("me", &batch).is_err());
+
+        session.handle_client_operation(
+            "me",
+            OTOperation {
+                client_id: "me".to_string(),
+                client_version: 0,
+                server_version: 0,
+                operation: cursor("other"),
+                label: None,
+            },
+        );
+        assert_eq!(session.server_version, 0);
+        let other = session
+            .project
+            .collaborators
+            .iter()
+            .find(|c| c.id == "other");
+        assert_eq!(other.unwrap().cursor_position.track, 0);
+    }
+
+    #[tokio::test]
+    async fn viewers_cannot_edit_over_http() {
+        let manager = with_tokens();
+        get_or_create_session(&manager, "s").await;
+        let routes = auth::session_guard(manager.clone())
+            .and(relink::relink_route(manager.clone()).or(dry_run::dry_run_route(manager)))
+            .recover(auth::reject_denied);
+        let relink = |role| {
+            warp::test::request()
+                .method("POST")
+                .path(&format!("/sessions/s/relink?token={}", token(role)))
+                .json(&serde_json::json!({ "from": { "asset_id": "a" }, "new_asset_id": "b" }))
+        };
+        let refused = relink("viewer").reply(&routes).await;
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+        // Editors get as far as finding no clip to relink
+        let allowed = relink("editor").reply(&routes).await;
+        assert_eq!(allowed.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let dry_run = warp::test::request()
+            .method("POST")
+            .path(&format!("/sessions/s/dry-run?token={}", token("viewer")))
+            .json(&EditOperation::SetSnapToFrames(true))
+            .reply(&routes)
+            .await;
+        let outcome: serde_json::Value = serde_json::from_slice(dry_run.body()).unwrap();
+        assert_eq!(outcome["result"], "rejected");
+        assert_eq!(outcome["code"], "forbidden");
+    }
+
+    #[tokio::test]
+    async fn event_stream_clients_get_the_role_their_token_claims() {
+        let manager = with_tokens();
+        let routes = sse::sse_routes(manager.clone());
+        let _stream = warp::test::request()
+            .path(&format!("/sse/s?token={}", token("viewer")))
+            .filter(&routes)
+            .await
+            .unwrap();
+        let session = manager.read().await.get_session("s").unwrap();
+        let session = session.read().await;
+        let client_id = session.clients.keys().next().unwrap();
+        assert_eq!(session.role_of(client_id), Role::Viewer);
+    }
 }
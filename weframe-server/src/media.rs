@@ -0,0 +1,636 @@
+// weframe-server/src/media.rs
+use crate::hibernation::Snapshot;
+use crate::media_access::{MediaAccess, MediaUrls};
+use crate::workspaces::check_storage;
+use crate::{auth, freeze, roles, SessionManager, VideoSession};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::RwLock;
+use warp::filters::path::Peek;
+use warp::filters::BoxedFilter;
+use warp::Buf;
+use warp::Filter;
+use weframe_shared::{
+    Asset, EditOperation, IdGenerator, Role, ServerMessage, VideoClip, VideoProject,
+};
+
+/// Media files held by the server, stored under one root directory and
+/// addressed by key. Assets point at them with `media:<key>` URIs.
+#[derive(Clone)]
+pub struct MediaStore {
+    root: PathBuf,
+}
+
+impl MediaStore {
+    pub fn new(root: PathBuf) -> Self {
+        MediaStore { root }
+    }
+
+    /// Path of the stored file for `key`, or `None` if the key could escape
+    /// the store root.
+    pub fn path(&self, key: &str) -> Option<PathBuf> {
+        let valid = !key.is_empty()
+            && key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+            && !key.starts_with('.');
+        valid.then(|| self.root.join(key))
+    }
+
+    /// Streams an upload into the store, keyed by the SHA-256 of its contents
+    /// (prefixed with `namespace` when dedup is scoped). Returns the key and
+    /// whether an identical file was already stored under it.
+    pub async fn store<S, B, E>(
+        &self,
+        mut body: S,
+        namespace: Option<&str>,
+        extension: Option<&str>,
+    ) -> io::Result<(String, bool)>
+    where
+        S: Stream<Item = Result<B, E>> + Unpin,
+        B: Buf,
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        tokio::fs::create_dir_all(&self.root).await?;
+        let temp_path = self.root.join(format!(".upload-{}", uuid::Uuid::new_v4()));
+        let result = async {
+            let mut file = tokio::fs::File::create(&temp_path).await?;
+            let mut hasher = Sha256::new();
+            while let Some(chunk) = body.next().await {
+                let mut chunk = chunk.map_err(io::Error::other)?;
+                while chunk.has_remaining() {
+                    let bytes = chunk.chunk();
+                    hasher.update(bytes);
+                    file.write_all(bytes).await?;
+                    let len = bytes.len();
+                    chunk.advance(len);
+                }
+            }
+            file.flush().await?;
+            Ok::<_, io::Error>(hex(&hasher.finalize()))
+        }
+        .await;
+
+        let hash = match result {
+            Ok(hash) => hash,
+            Err(e) => {
+                tokio::fs::remove_file(&temp_path).await.ok();
+                return Err(e);
+            }
+        };
+        let mut key = match namespace {
+            Some(namespace) => format!("{}-{}", namespace, hash),
+            None => hash,
+        };
+        if let Some(extension) = extension {
+            key = format!("{}.{}", key, extension);
+        }
+
+        let path = self.root.join(&key);
+        if tokio::fs::try_exists(&path).await? {
+            tokio::fs::remove_file(&temp_path).await?;
+            return Ok((key, true));
+        }
+        tokio::fs::rename(&temp_path, &path).await?;
+        Ok((key, false))
+    }
+
+    pub async fn delete(&self, key: &str) -> io::Result<()> {
+        let path = self
+            .path(key)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid media key"))?;
+        match tokio::fs::remove_file(path).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Fills in the client-facing URL of every asset carried by `message`,
+/// leaving the canonical `uri` untouched, and signs the clip previews it
+/// carries.
+pub fn add_public_urls(message: &mut ServerMessage, urls: &MediaUrls) {
+    match message {
+        ServerMessage::ClientOperation(operation) => {
+            let operations = match &mut operation.operation {
+                EditOperation::Batch(operations) => operations.as_mut_slice(),
+                operation => std::slice::from_mut(operation),
+            };
+            for operation in operations {
+                match operation {
+                    EditOperation::AddAsset(asset) => set_public_url(asset, urls),
+                    EditOperation::SetClipPreviews {
+                        thumbnail_url,
+                        filmstrip_url,
+                        ..
+                    } => {
+                        sign_preview(thumbnail_url, urls);
+                        sign_preview(filmstrip_url, urls);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        ServerMessage::ProjectUpdate(project) => add_project_public_urls(project, urls),
+        ServerMessage::SyncBegin { project, .. } => {
+            for clip in &mut project.trash {
+                sign_previews(clip, urls);
+            }
+        }
+        ServerMessage::SyncAssets(assets) => {
+            for asset in assets {
+                set_public_url(asset, urls);
+            }
+        }
+        ServerMessage::SyncClips(clips) => {
+            for clip in clips {
+                sign_previews(clip, urls);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Resolves the URIs of every asset in `project` to public URLs, and signs
+/// its clip previews.
+pub fn add_project_public_urls(project: &mut VideoProject, urls: &MediaUrls) {
+    for asset in &mut project.assets {
+        set_public_url(asset, urls);
+    }
+    for clip in project.clips.iter_mut().chain(&mut project.trash) {
+        sign_previews(clip, urls);
+    }
+}
+
+fn sign_previews(clip: &mut VideoClip, urls: &MediaUrls) {
+    sign_preview(&mut clip.thumbnail_url, urls);
+    sign_preview(&mut clip.filmstrip_url, urls);
+}
+
+/// Previews the server generated are stored media, `media:<key>` in the
+/// project like asset URIs; clients get signed URLs for them instead.
+fn sign_preview(url: &mut Option<String>, urls: &MediaUrls) {
+    if let Some(key) = url.as_deref().and_then(|url| url.strip_prefix("media:")) {
+        *url = Some(urls.url(key));
+    }
+}
+
+fn set_public_url(asset: &mut Asset, urls: &MediaUrls) {
+    asset.public_url = Some(match asset.storage_key() {
+        Some(key) => urls.url(key),
+        None => asset.uri.clone(),
+    });
+}
+
+#[derive(Deserialize)]
+struct AccessQuery {
+    access: Option<String>,
+}
+
+/// `GET /media/:key?access=` serves stored files directly when no CDN sits
+/// in front, to whoever holds a valid signed URL for the file. Anything
+/// else is answered as if the file didn't exist, so keys can't be probed.
+/// Responses may only be cached privately, and no longer than the URL
+/// stays valid.
+pub fn media_route(
+    store: Option<MediaStore>,
+    access: MediaAccess,
+) -> BoxedFilter<(Box<dyn warp::Reply>,)> {
+    match store {
+        Some(store) => warp::get()
+            .and(warp::path("media"))
+            .and(warp::path::peek())
+            .and(warp::query::<AccessQuery>())
+            .and_then(move |key: Peek, query: AccessQuery| {
+                let remaining = access.verify(query.access.as_deref(), key.as_str());
+                async move { remaining.ok_or_else(warp::reject::not_found) }
+            })
+            .and(warp::fs::dir(store.root))
+            .map(|remaining: Duration, file: warp::fs::File| {
+                Box::new(warp::reply::with_header(
+                    file,
+                    "cache-control",
+                    format!("private, max-age={}", remaining.as_secs()),
+                )) as Box<dyn warp::Reply>
+            })
+            .boxed(),
+        None => warp::path("media")
+            .and_then(|| async { Err::<Box<dyn warp::Reply>, _>(warp::reject::not_found()) })
+            .boxed(),
+    }
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Which uploads may share a stored file when their contents match.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DedupScope {
+    /// Every upload gets its own file.
+    Off,
+    /// Identical uploads within one session share a file.
+    Session,
+    /// Identical uploads anywhere on the server share a file.
+    Global,
+}
+
+impl std::str::FromStr for DedupScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(DedupScope::Off),
+            "session" => Ok(DedupScope::Session),
+            "global" => Ok(DedupScope::Global),
+            _ => Err(format!("Unknown dedup scope: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct AssetGcPolicy {
+    /// How long an asset must stay unreferenced before it is removed.
+    pub grace: Duration,
+    /// When false, unused assets are only reported.
+    pub delete: bool,
+}
+
+impl Default for AssetGcPolicy {
+    fn default() -> Self {
+        AssetGcPolicy {
+            grace: Duration::from_secs(7 * 24 * 60 * 60),
+            delete: false,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct UnusedAsset {
+    pub asset: Asset,
+    pub unused_secs: u64,
+}
+
+#[derive(Default, Serialize)]
+pub struct AssetGcReport {
+    pub unused: Vec<UnusedAsset>,
+    /// Ids of assets removed from the project in this pass.
+    pub removed: Vec<String>,
+}
+
+impl VideoSession {
+    /// Finds assets no clip refers to, remembering when each was first seen
+    /// unused. With `policy.delete`, assets unused for longer than the grace
+    /// period are removed from the project.
+    pub fn collect_unused_assets(&mut self, policy: AssetGcPolicy) -> AssetGcReport {
+        let now = self.config.clock.now();
+        let unused: Vec<Asset> = self.project.unreferenced_assets().cloned().collect();
+        self.asset_unused_since
+            .retain(|id, _| unused.iter().any(|a| a.id == *id));
+
+        let mut report = AssetGcReport::default();
+        for asset in unused {
+            let since = *self
+                .asset_unused_since
+                .entry(asset.id.clone())
+                .or_insert(now);
+            let unused_for = now.duration_since(since).unwrap_or_default();
+            if policy.delete && unused_for >= policy.grace {
+                let removed =
+                    self.apply_server_operation(EditOperation::RemoveAsset(asset.id.clone()));
+                if removed.is_ok() {
+                    self.asset_unused_since.remove(&asset.id);
+                    report.removed.push(asset.id.clone());
+                }
+            }
+            report.unused.push(UnusedAsset {
+                asset,
+                unused_secs: unused_for.as_secs(),
+            });
+        }
+        report
+    }
+}
+
+/// Storage keys of assets in sessions kept outside memory: hibernated, in
+/// the recycle bin or in the project store. A session may be both loaded
+/// and stored; its stored copy counts all the same.
+async fn stored_storage_keys(manager: &RwLock<SessionManager>) -> io::Result<HashSet<String>> {
+    let (hibernation, recycle_bin, projects) = {
+        let manager = manager.read().await;
+        (
+            manager.hibernation.clone(),
+            manager.recycle_bin.clone(),
+            manager.projects.clone(),
+        )
+    };
+    let mut keys = HashSet::new();
+    let mut add = |snapshot: Snapshot| {
+        keys.extend(
+            snapshot
+                .project
+                .assets
+                .iter()
+                .filter_map(|asset| Some(asset.storage_key()?.to_string())),
+        );
+    };
+    for store in [hibernation, recycle_bin].into_iter().flatten() {
+        for info in store.list().await? {
+            if let Some(snapshot) = store.load(&info.session_id).await? {
+                add(snapshot);
+            }
+        }
+    }
+    if let Some(store) = projects {
+        for session_id in store.session_ids().await? {
+            if let Some(snapshot) = store.load(&session_id).await? {
+                add(snapshot);
+            }
+        }
+    }
+    Ok(keys)
+}
+
+/// Runs asset garbage collection over one session, or every session when
+/// `session_id` is `None`, then deletes stored files no remaining asset in any
+/// session points at, loaded or stored. Nothing is deleted if the stored
+/// sessions can't all be read.
+pub async fn collect_unused_assets(
+    manager: &RwLock<SessionManager>,
+    store: Option<&MediaStore>,
+    session_id: Option<&str>,
+    policy: AssetGcPolicy,
+) -> HashMap<String, AssetGcReport> {
+    let sessions: Vec<(String, Arc<RwLock<VideoSession>>)> = manager
+        .read()
+        .await
+        .sessions()
+        .map(|(id, session)| (id.clone(), session.clone()))
+        .collect();
+
+    let mut reports = HashMap::new();
+    let mut orphaned_keys = HashSet::new();
+    for (id, session) in &sessions {
+        if session_id.is_some_and(|target| target != id) {
+            continue;
+        }
+        let mut session = session.write().await;
+        let report = session.collect_unused_assets(policy);
+        for removed in &report.removed {
+            if let Some(unused) = report.unused.iter().find(|u| u.asset.id == *removed) {
+                if let Some(key) = unused.asset.storage_key() {
+                    orphaned_keys.insert(key.to_string());
+                }
+            }
+        }
+        reports.insert(id.clone(), report);
+    }
+
+    if let Some(store) = store.filter(|_| !orphaned_keys.is_empty()) {
+        // The same stored file may back assets in several sessions
+        for (_, session) in &sessions {
+            let session = session.read().await;
+            for asset in &session.project().assets {
+                if let Some(key) = asset.storage_key() {
+                    orphaned_keys.remove(key);
+                }
+            }
+        }
+        // Read after the loaded sessions, so one unloaded meanwhile is
+        // already stored
+        match stored_storage_keys(manager).await {
+            Ok(stored) => orphaned_keys.retain(|key| !stored.contains(key)),
+            Err(e) => {
+                eprintln!("Kept unused media, stored sessions couldn't be read: {}", e);
+                orphaned_keys.clear();
+            }
+        }
+        for key in orphaned_keys {
+            if let Err(e) = store.delete(&key).await {
+                eprintln!("Failed to delete media {}: {}", key, e);
+            }
+        }
+    }
+
+    reports
+}
+
+#[derive(Deserialize)]
+struct GcQuery {
+    #[serde(default)]
+    delete: bool,
+}
+
+/// `POST /admin/sessions/:id/assets/gc?delete=true` reports the session's
+/// unused assets and, with `delete`, removes those past the grace period.
+pub fn gc_route(
+    manager: Arc<RwLock<SessionManager>>,
+    store: Option<MediaStore>,
+    policy: AssetGcPolicy,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("admin" / "sessions" / String / "assets" / "gc"))
+        .and(warp::query::<GcQuery>())
+        .and_then(move |session_id: String, query: GcQuery| {
+            let manager = manager.clone();
+            let store = store.clone();
+            async move {
+                if manager.read().await.get_session(&session_id).is_none() {
+                    return Err(warp::reject::not_found());
+                }
+                let policy = AssetGcPolicy {
+                    delete: query.delete,
+                    ..policy
+                };
+                let mut reports =
+                    collect_unused_assets(&manager, store.as_ref(), Some(&session_id), policy)
+                        .await;
+                let report = reports.remove(&session_id).unwrap_or_default();
+                Ok::<_, warp::Rejection>(warp::reply::json(&report))
+            }
+        })
+}
+
+#[derive(Deserialize)]
+struct UploadQuery {
+    name: String,
+}
+
+/// File extension to keep on stored media so it is served with the right
+/// content type.
+pub(crate) fn upload_extension(name: &str) -> Option<String> {
+    let (_, extension) = name.rsplit_once('.')?;
+    let valid = !extension.is_empty()
+        && extension.len() <= 8
+        && extension.chars().all(|c| c.is_ascii_alphanumeric());
+    valid.then(|| extension.to_ascii_lowercase())
+}
+
+/// Whether `role` may add media to a session, asked before any is stored:
+/// what the asset turns out to be doesn't change the role adding it needs.
+pub(crate) fn check_adds_assets(role: Role) -> Result<(), String> {
+    roles::permits(role, &EditOperation::AddAsset(Asset::default()))
+}
+
+/// Key prefix that keeps a session's uploads from sharing stored files
+/// beyond what `scope` allows.
+pub(crate) fn dedup_namespace(
+    scope: DedupScope,
+    session_id: &str,
+    ids: &IdGenerator,
+) -> Option<String> {
+    match scope {
+        DedupScope::Off => Some(ids.uuid().to_string()),
+        DedupScope::Session => Some(hex(&Sha256::digest(session_id.as_bytes()))[..16].to_string()),
+        DedupScope::Global => None,
+    }
+}
+
+/// `POST /sessions/:id/assets?name=clip.mp4` stores the request body and
+/// registers it as a new asset in the session, reusing an identical stored
+/// file when `scope` allows. Uploads are unavailable without a media store.
+pub fn upload_route(
+    manager: Arc<RwLock<SessionManager>>,
+    store: Option<MediaStore>,
+    scope: DedupScope,
+    max_upload_bytes: u64,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("sessions" / String / "assets"))
+        .and(warp::query::<UploadQuery>())
+        .and(auth::request_role(manager.clone()))
+        .and(warp::body::content_length_limit(max_upload_bytes))
+        .and(warp::body::stream())
+        .and_then(
+            move |session_id: String, query: UploadQuery, role: Role, body| {
+                let manager = manager.clone();
+                let store = store.clone();
+                async move {
+                    let store = store.ok_or_else(warp::reject::not_found)?;
+                    if let Err(message) = check_adds_assets(role) {
+                        return Ok(roles::forbidden(message));
+                    }
+                    let (session, config) = {
+                        let manager = manager.read().await;
+                        (manager.get_session(&session_id), manager.config.clone())
+                    };
+                    let session = session.ok_or_else(warp::reject::not_found)?;
+                    let workspace = {
+                        let session = session.read().await;
+                        let add = EditOperation::AddAsset(Asset::default());
+                        if let Err(message) = session.check_frozen(&add) {
+                            return Ok(freeze::frozen(message));
+                        }
+                        session.metadata().workspace().map(str::to_string)
+                    };
+                    if let Err(e) = check_storage(&manager, workspace.as_deref(), None).await {
+                        return Ok(warp::reply::with_status(
+                            warp::reply::json(&e),
+                            warp::http::StatusCode::FORBIDDEN,
+                        ));
+                    }
+
+                    let namespace = dedup_namespace(scope, &session_id, &config.ids);
+                    let extension = upload_extension(&query.name);
+                    let (key, reused) = match store
+                        .store(Box::pin(body), namespace.as_deref(), extension.as_deref())
+                        .await
+                    {
+                        Ok(stored) => stored,
+                        Err(e) => {
+                            eprintln!("Failed to store upload for {}: {}", session_id, e);
+                            return Ok(warp::reply::with_status(
+                                warp::reply::json(&e.to_string()),
+                                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                            ));
+                        }
+                    };
+                    if reused {
+                        println!("Reusing stored media {} for {}", key, query.name);
+                    }
+                    if let Err(e) = check_storage(&manager, workspace.as_deref(), Some(&key)).await
+                    {
+                        if !reused {
+                            store.delete(&key).await.ok();
+                        }
+                        return Ok(warp::reply::with_status(
+                            warp::reply::json(&e),
+                            warp::http::StatusCode::FORBIDDEN,
+                        ));
+                    }
+
+                    let mut asset = Asset {
+                        id: config.ids.prefixed("asset"),
+                        name: query.name,
+                        uri: format!("media:{}", key),
+                        duration: None,
+                        color_space: None,
+                        hdr: None,
+                        public_url: None,
+                    };
+                    let mut session = session.write().await;
+                    if let Err(e) =
+                        session.apply_server_operation(EditOperation::AddAsset(asset.clone()))
+                    {
+                        return Ok(warp::reply::with_status(
+                            warp::reply::json(&e),
+                            warp::http::StatusCode::BAD_REQUEST,
+                        ));
+                    }
+                    set_public_url(&mut asset, &MediaUrls::new(&config, &session_id));
+                    Ok::<_, warp::Rejection>(warp::reply::with_status(
+                        warp::reply::json(&asset),
+                        warp::http::StatusCode::CREATED,
+                    ))
+                }
+            },
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use std::time::UNIX_EPOCH;
+
+    #[tokio::test]
+    async fn media_is_served_only_to_unexpired_grants_for_its_key() {
+        let dir = std::env::temp_dir().join(format!("weframe-test-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        tokio::fs::write(dir.join("clip.mp4"), b"frames")
+            .await
+            .unwrap();
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(3600 * 1000));
+        let ttl = Duration::from_secs(3600);
+        let access = MediaAccess::new(b"secret", ttl, Arc::new(clock.clone()));
+        let route = media_route(Some(MediaStore::new(dir.clone())), access.clone());
+        let get = |key: &str, token: &str| {
+            warp::test::request()
+                .path(&format!("/media/{}?access={}", key, token))
+                .reply(&route)
+        };
+
+        let token = access.sign("clip.mp4", "s");
+        let served = get("clip.mp4", &token).await;
+        assert_eq!(served.status(), 200);
+        assert_eq!(served.headers()["cache-control"], "private, max-age=3600");
+
+        let other = MediaAccess::new(b"other", ttl, Arc::new(clock.clone()));
+        let forged = get("clip.mp4", &other.sign("clip.mp4", "s")).await;
+        assert_eq!(forged.status(), 404);
+        let other_key = get("clip.mp4", &access.sign("other.mp4", "s")).await;
+        assert_eq!(other_key.status(), 404);
+
+        clock.advance(ttl);
+        let expired = get("clip.mp4", &token).await;
+        assert_eq!(expired.status(), 404);
+        tokio::fs::remove_dir_all(&dir).await.ok();
+    }
+}
@@ -0,0 +1,148 @@
+// weframe-server/src/media_access.rs
+use crate::clock::Clock;
+use crate::media::hex;
+use crate::{ServerConfig, SessionManager};
+use hmac::{Hmac, Mac};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::sync::RwLock;
+use warp::Filter;
+
+/// Claims of a media access token: which stored file, for members of which
+/// session, until when.
+#[derive(Serialize, Deserialize)]
+struct MediaClaims {
+    sub: String,
+    session: String,
+    exp: u64,
+}
+
+/// Signs and checks the `?access=` tokens stored media is served with:
+/// HS256 JWTs naming one media key, handed only to a session's members and
+/// valid for a limited time.
+#[derive(Clone)]
+pub struct MediaAccess {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    validation: Validation,
+    ttl: Duration,
+    /// Expiry is checked against this rather than by the JWT library, so
+    /// it follows the server's clock.
+    clock: Arc<dyn Clock>,
+}
+
+impl MediaAccess {
+    pub fn new(secret: &[u8], ttl: Duration, clock: Arc<dyn Clock>) -> Self {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        validation.validate_exp = false;
+        MediaAccess {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            validation,
+            ttl,
+            clock,
+        }
+    }
+
+    fn now(&self) -> Duration {
+        self.clock
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+
+    /// A token letting members of `session_id` fetch the stored file `key`
+    /// for at least the configured time. Expiry is rounded up to the hour,
+    /// so URLs signed close together are identical and stay cacheable.
+    pub fn sign(&self, key: &str, session_id: &str) -> String {
+        let now = self.now();
+        let claims = MediaClaims {
+            sub: key.to_string(),
+            session: session_id.to_string(),
+            exp: (now + self.ttl).as_secs().div_ceil(3600) * 3600,
+        };
+        jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &self.encoding)
+            .unwrap_or_default()
+    }
+
+    /// How long `token` has left as a grant for the stored file `key`, or
+    /// `None` if it isn't an unexpired one.
+    pub fn verify(&self, token: Option<&str>, key: &str) -> Option<Duration> {
+        let data =
+            jsonwebtoken::decode::<MediaClaims>(token?, &self.decoding, &self.validation).ok()?;
+        let now = self.now().as_secs();
+        (data.claims.sub == key && data.claims.exp > now)
+            .then(|| Duration::from_secs(data.claims.exp - now))
+    }
+}
+
+/// Media secret for a server that only has a client token secret: an
+/// HMAC-SHA256 of a fixed label under it, so media tokens and client
+/// tokens never verify as each other.
+pub fn derive_secret(jwt_secret: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(jwt_secret.as_bytes())
+        .expect("HMAC takes keys of any length");
+    mac.update(b"weframe media urls");
+    hex(&mac.finalize().into_bytes())
+}
+
+/// Builds the URLs one session's members fetch stored media from.
+pub struct MediaUrls {
+    base_url: String,
+    access: MediaAccess,
+    session_id: String,
+}
+
+impl MediaUrls {
+    pub fn new(config: &ServerConfig, session_id: &str) -> Self {
+        MediaUrls {
+            base_url: config.media_base_url(),
+            access: config.media_access(),
+            session_id: session_id.to_string(),
+        }
+    }
+
+    pub fn url(&self, key: &str) -> String {
+        format!(
+            "{}/{}?access={}",
+            self.base_url.trim_end_matches('/'),
+            key,
+            self.access.sign(key, &self.session_id)
+        )
+    }
+}
+
+/// `GET /sessions/:id/media-urls`, with `?token=` when the server requires
+/// one, gives a session member fresh URLs for the session's stored assets,
+/// keyed by asset id, for when the ones it was sent are about to expire.
+pub fn media_urls_route(
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("sessions" / String / "media-urls"))
+        .and_then(move |session_id: String| {
+            let manager = manager.clone();
+            async move {
+                let (session, config) = {
+                    let manager = manager.read().await;
+                    (manager.get_session(&session_id), manager.config.clone())
+                };
+                let session = session.ok_or_else(warp::reject::not_found)?;
+                let urls = MediaUrls::new(&config, &session_id);
+                let assets: HashMap<String, String> = session
+                    .read()
+                    .await
+                    .project
+                    .assets
+                    .iter()
+                    .filter_map(|asset| Some((asset.id.clone(), urls.url(asset.storage_key()?))))
+                    .collect();
+                Ok::<_, warp::Rejection>(warp::reply::json(&assets))
+            }
+        })
+}
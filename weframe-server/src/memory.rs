@@ -0,0 +1,129 @@
+// weframe-server/src/memory.rs
+use crate::VideoSession;
+use serde::Serialize;
+use weframe_shared::{EditOperation, OTOperation};
+
+/// Approximate bytes held by a session, measured as serialized JSON size.
+/// Good enough to spot a runaway session, not an exact heap figure.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct MemoryUsage {
+    pub project: usize,
+    pub history: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.project + self.history
+    }
+}
+
+pub(crate) fn approx_size<T: Serialize>(value: &T) -> usize {
+    serde_json::to_vec(value).map_or(0, |bytes| bytes.len())
+}
+
+/// Operations that make the project bigger. Once a session is at its cap
+/// these are refused; edits and removals still go through.
+pub(crate) fn adds_content(operation: &EditOperation) -> bool {
+    if let EditOperation::Batch(operations) = operation {
+        return operations.iter().any(adds_content);
+    }
+    matches!(
+        operation,
+        EditOperation::AddClip(_)
+            | EditOperation::AddTextClip(_)
+            | EditOperation::RestoreClip(_)
+            | EditOperation::AddEffect { .. }
+            | EditOperation::AddTrackEffect { .. }
+            | EditOperation::SetClipEffects { .. }
+            | EditOperation::AddTransition { .. }
+            | EditOperation::AddCollaborator(_)
+            | EditOperation::AddAsset(_)
+            | EditOperation::AddMulticamGroup(_)
+            | EditOperation::SwitchAngle { .. }
+            | EditOperation::SplitClip { .. }
+            | EditOperation::AddSubtitleTrack(_)
+            | EditOperation::AddSubtitleCues { .. }
+            | EditOperation::AddMarkers(_)
+            | EditOperation::SetClipPreviews { .. }
+            | EditOperation::SetClipWaveform { .. }
+            | EditOperation::RenameProject(_)
+    )
+}
+
+impl VideoSession {
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.memory
+    }
+
+    /// Updates the running totals after `operation` was applied and logged.
+    /// Project growth is estimated from the operation itself; `reserve_memory`
+    /// re-measures exactly before acting on the estimate.
+    pub(crate) fn account_operation(&mut self, operation: &OTOperation) {
+        let size = approx_size(operation);
+        self.memory.history += size;
+        if adds_content(&operation.operation) {
+            self.memory.project += size;
+        }
+        self.report_memory();
+    }
+
+    /// Makes room for `operation` under the configured cap, dropping the
+    /// oldest history first and refusing operations that add content if
+    /// that isn't enough.
+    pub(crate) fn reserve_memory(&mut self, operation: &OTOperation) -> Result<(), String> {
+        self.reserve_bytes(approx_size(operation), adds_content(&operation.operation))
+    }
+
+    /// Whether `reserve_memory` would accept `operation`, without dropping
+    /// any history to make room.
+    pub(crate) fn check_memory(&self, operation: &OTOperation) -> Result<(), String> {
+        let Some(limit) = self.config.max_session_bytes else {
+            return Ok(());
+        };
+        let needed = approx_size(operation);
+        if self.memory.total() + needed <= limit || !adds_content(&operation.operation) {
+            return Ok(());
+        }
+        let project = approx_size(&self.project);
+        if project + needed > limit {
+            return Err(format!(
+                "Session has reached its memory limit ({} of {} bytes); remove content or empty the trash before adding more",
+                project, limit
+            ));
+        }
+        Ok(())
+    }
+
+    /// Like `reserve_memory`, for `needed` bytes of operations at once.
+    pub(crate) fn reserve_bytes(&mut self, needed: usize, adds: bool) -> Result<(), String> {
+        let Some(limit) = self.config.max_session_bytes else {
+            return Ok(());
+        };
+        if self.memory.total() + needed <= limit {
+            return Ok(());
+        }
+
+        self.memory.project = approx_size(&self.project);
+        while self.memory.total() + needed > limit {
+            let Some(oldest) = self.op_log.pop_front() else {
+                break;
+            };
+            self.op_log_start += 1;
+            self.memory.history = self.memory.history.saturating_sub(approx_size(&oldest));
+        }
+        self.report_memory();
+
+        if self.memory.project + needed > limit && adds {
+            return Err(format!(
+                "Session has reached its memory limit ({} of {} bytes); remove content or empty the trash before adding more",
+                self.memory.project, limit
+            ));
+        }
+        Ok(())
+    }
+
+    fn report_memory(&self) {
+        self.metrics
+            .set_session_bytes(&self.metadata.name, self.memory.total());
+    }
+}
@@ -0,0 +1,143 @@
+// weframe-server/src/metrics.rs
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use warp::Filter;
+
+/// Upper bounds, in seconds, of the latency histogram buckets.
+const BUCKETS: [f64; 8] = [0.00001, 0.00005, 0.0001, 0.0005, 0.001, 0.005, 0.01, 0.1];
+
+#[derive(Default)]
+struct Histogram {
+    /// Cumulative counts per bucket, as Prometheus expects.
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+/// Server-wide operation timings, keyed by pipeline stage and operation kind.
+#[derive(Default)]
+pub struct Metrics {
+    operations: Mutex<BTreeMap<(&'static str, &'static str), Histogram>>,
+    session_bytes: Mutex<BTreeMap<String, usize>>,
+    divergences: AtomicU64,
+}
+
+impl Metrics {
+    /// Records how long `stage` (e.g. "apply") took for an operation of
+    /// `kind`.
+    pub fn observe_operation(&self, stage: &'static str, kind: &'static str, elapsed: Duration) {
+        self.operations
+            .lock()
+            .unwrap()
+            .entry((stage, kind))
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn set_session_bytes(&self, session_id: &str, bytes: usize) {
+        self.session_bytes
+            .lock()
+            .unwrap()
+            .insert(session_id.to_string(), bytes);
+    }
+
+    /// Counts a client whose project no longer matched the server's.
+    pub fn record_divergence(&self) {
+        self.divergences.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn remove_session(&self, session_id: &str) {
+        self.session_bytes.lock().unwrap().remove(session_id);
+    }
+
+    /// Prometheus text exposition of every metric.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP weframe_operation_seconds Time spent per operation, by pipeline stage and operation kind.\n");
+        out.push_str("# TYPE weframe_operation_seconds histogram\n");
+        for ((stage, kind), histogram) in self.operations.lock().unwrap().iter() {
+            let labels = format!("stage=\"{}\",operation=\"{}\"", stage, kind);
+            for (count, bound) in histogram.buckets.iter().zip(BUCKETS) {
+                writeln!(
+                    out,
+                    "weframe_operation_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, count
+                )
+                .unwrap();
+            }
+            writeln!(
+                out,
+                "weframe_operation_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, histogram.count
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "weframe_operation_seconds_sum{{{}}} {}",
+                labels, histogram.sum
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "weframe_operation_seconds_count{{{}}} {}",
+                labels, histogram.count
+            )
+            .unwrap();
+        }
+
+        out.push_str(
+            "# HELP weframe_session_memory_bytes Approximate memory held by each session.\n",
+        );
+        out.push_str("# TYPE weframe_session_memory_bytes gauge\n");
+        for (session_id, bytes) in self.session_bytes.lock().unwrap().iter() {
+            let session_id = session_id.replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(
+                out,
+                "weframe_session_memory_bytes{{session=\"{}\"}} {}",
+                session_id, bytes
+            )
+            .unwrap();
+        }
+
+        out.push_str("# HELP weframe_divergences_total Clients found out of sync with the server by state checksum.\n");
+        out.push_str("# TYPE weframe_divergences_total counter\n");
+        writeln!(
+            out,
+            "weframe_divergences_total {}",
+            self.divergences.load(Ordering::Relaxed)
+        )
+        .unwrap();
+        out
+    }
+}
+
+/// `GET /metrics` in Prometheus text format.
+pub fn metrics_route(
+    metrics: Arc<Metrics>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path("metrics"))
+        .and(warp::path::end())
+        .map(move || {
+            warp::reply::with_header(
+                metrics.render(),
+                "content-type",
+                "text/plain; version=0.0.4",
+            )
+        })
+}
@@ -0,0 +1,84 @@
+// weframe-server/src/outbox.rs
+use tokio::sync::mpsc;
+use warp::ws::Message;
+use weframe_shared::{EditOperation, ServerMessage};
+
+/// Where a message waits in a client's outbound queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Edits and anything else that changes what the client shows.
+    Normal,
+    /// Cursor moves, chat and other awareness traffic, sent only when
+    /// nothing more important is waiting.
+    Presence,
+}
+
+impl Priority {
+    pub fn of(message: &ServerMessage) -> Priority {
+        match message {
+            ServerMessage::ClientOperation(op)
+                if matches!(op.operation, EditOperation::UpdateCollaboratorCursor { .. }) =>
+            {
+                Priority::Presence
+            }
+            ServerMessage::ChatMessage { .. } => Priority::Presence,
+            _ => Priority::Normal,
+        }
+    }
+}
+
+/// Queues messages for one client.
+#[derive(Clone)]
+pub struct OutboxSender {
+    normal: mpsc::UnboundedSender<Message>,
+    presence: mpsc::UnboundedSender<Message>,
+}
+
+impl OutboxSender {
+    pub fn send(
+        &self,
+        message: Message,
+        priority: Priority,
+    ) -> Result<(), mpsc::error::SendError<Message>> {
+        match priority {
+            Priority::Normal => self.normal.send(message),
+            Priority::Presence => self.presence.send(message),
+        }
+    }
+}
+
+/// A client's outbound queue, drained by its connection's task. Messages of
+/// the same priority keep their order; a flood of presence updates waits
+/// behind any edit queued after it.
+pub struct Outbox {
+    normal: mpsc::UnboundedReceiver<Message>,
+    presence: mpsc::UnboundedReceiver<Message>,
+}
+
+impl Outbox {
+    /// The next message to send, or `None` once the session has dropped the
+    /// client.
+    pub async fn recv(&mut self) -> Option<Message> {
+        tokio::select! {
+            biased;
+            Some(message) = self.normal.recv() => Some(message),
+            Some(message) = self.presence.recv() => Some(message),
+            else => None,
+        }
+    }
+}
+
+pub fn outbox() -> (OutboxSender, Outbox) {
+    let (normal_tx, normal_rx) = mpsc::unbounded_channel();
+    let (presence_tx, presence_rx) = mpsc::unbounded_channel();
+    (
+        OutboxSender {
+            normal: normal_tx,
+            presence: presence_tx,
+        },
+        Outbox {
+            normal: normal_rx,
+            presence: presence_rx,
+        },
+    )
+}
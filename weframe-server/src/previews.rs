@@ -0,0 +1,258 @@
+// weframe-server/src/previews.rs
+use crate::analysis::{clip_media_path, scratch_path};
+use crate::jobs::{self, JobClass};
+use crate::media::MediaStore;
+use crate::{SessionManager, VideoSession};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use weframe_shared::{ClipKind, SourceKind};
+
+/// Frames across a clip's filmstrip.
+const FILMSTRIP_FRAMES: u32 = 10;
+
+/// Height of thumbnails and filmstrip frames in pixels; widths keep the
+/// media's aspect ratio.
+const PREVIEW_HEIGHT: u32 = 90;
+
+/// A clip the server still owes previews, with what ffmpeg needs to make
+/// them.
+struct PreviewJob {
+    clip_id: String,
+    source_file: String,
+    media: PathBuf,
+    /// Seconds into the media the clip starts playing.
+    start: f64,
+    /// Seconds of media the clip plays.
+    length: f64,
+    /// Stills get a thumbnail only; a filmstrip of one frame shows nothing.
+    still: bool,
+}
+
+impl VideoSession {
+    /// Video clips without previews whose media this server holds. Each
+    /// source is tried once per clip, so media ffmpeg can't read isn't
+    /// retried every sweep; changing the clip's source tries again.
+    fn take_preview_jobs(&mut self, store: &MediaStore) -> Vec<PreviewJob> {
+        let project = &self.project;
+        self.preview_attempts.retain(|clip_id, source| {
+            project
+                .clips
+                .iter()
+                .any(|c| c.id == *clip_id && c.source_file == *source)
+        });
+
+        let mut jobs = Vec::new();
+        for clip in &project.clips {
+            if clip.kind != ClipKind::Video
+                || clip.thumbnail_url.is_some()
+                || self.preview_attempts.contains_key(&clip.id)
+            {
+                continue;
+            }
+            let Some(media) = clip_media_path(project, store, clip) else {
+                continue;
+            };
+            self.preview_attempts
+                .insert(clip.id.clone(), clip.source_file.clone());
+            jobs.push(PreviewJob {
+                clip_id: clip.id.clone(),
+                source_file: clip.source_file.clone(),
+                media,
+                start: clip.source_start.as_secs_f64(),
+                length: clip.end_time.saturating_sub(clip.start_time).as_secs_f64(),
+                still: clip.source_kind == SourceKind::Image,
+            });
+        }
+        jobs
+    }
+}
+
+/// Renders one frame of `filter` output from `media` to a JPEG, reading
+/// `length` seconds from `start` (or to the end).
+async fn render_frame(
+    ffmpeg: &Path,
+    media: &Path,
+    start: f64,
+    length: Option<f64>,
+    filter: &str,
+) -> Result<Vec<u8>, String> {
+    let output = scratch_path("jpg");
+    let mut command = tokio::process::Command::new(ffmpeg);
+    command
+        .args(["-hide_banner", "-nostats", "-y"])
+        .args(["-ss", &format!("{:.3}", start)]);
+    if let Some(length) = length {
+        command.args(["-t", &format!("{:.3}", length)]);
+    }
+    let result = command
+        .arg("-i")
+        .arg(media)
+        .args(["-vf", filter, "-frames:v", "1", "-q:v", "4"])
+        .arg(&output)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| format!("Could not run {}: {}", ffmpeg.display(), e));
+    let image = match result {
+        Ok(result) if result.status.success() => {
+            tokio::fs::read(&output).await.map_err(|e| e.to_string())
+        }
+        Ok(result) => {
+            let log = String::from_utf8_lossy(&result.stderr);
+            let reason = log.lines().last().unwrap_or("no output");
+            Err(format!("ffmpeg failed: {}", reason))
+        }
+        Err(message) => Err(message),
+    };
+    tokio::fs::remove_file(&output).await.ok();
+    image
+}
+
+/// Stores a rendered preview and returns the URI the clip keeps for it;
+/// clients are handed signed URLs instead (see `media::add_public_urls`).
+async fn store_preview(store: &MediaStore, image: Vec<u8>) -> Result<String, String> {
+    let body = futures::stream::iter([Ok::<_, io::Error>(warp::hyper::body::Bytes::from(image))]);
+    let (key, _) = store
+        .store(body, Some("preview"), Some("jpg"))
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(format!("media:{}", key))
+}
+
+/// A poster frame from the middle of the clip and, for moving media, a
+/// filmstrip of frames spread across it.
+async fn render_previews(
+    ffmpeg: &Path,
+    store: &MediaStore,
+    job: &PreviewJob,
+) -> Result<(String, Option<String>), String> {
+    let scale = format!("scale=-2:{}", PREVIEW_HEIGHT);
+    let middle = if job.still {
+        0.0
+    } else {
+        job.start + job.length / 2.0
+    };
+    let thumbnail = render_frame(ffmpeg, &job.media, middle, None, &scale).await?;
+    let thumbnail = store_preview(store, thumbnail).await?;
+    if job.still || job.length <= 0.0 {
+        return Ok((thumbnail, None));
+    }
+
+    let filter = format!(
+        "fps={:.6},{},tile={}x1",
+        FILMSTRIP_FRAMES as f64 / job.length,
+        scale,
+        FILMSTRIP_FRAMES
+    );
+    let filmstrip = render_frame(ffmpeg, &job.media, job.start, Some(job.length), &filter).await?;
+    let filmstrip = store_preview(store, filmstrip).await?;
+    Ok((thumbnail, Some(filmstrip)))
+}
+
+/// Generates thumbnails and filmstrips for the video clips of every live
+/// session that don't have them yet, and attaches them to the clips.
+pub async fn generate_missing_previews(
+    manager: &RwLock<SessionManager>,
+    store: &MediaStore,
+    ffmpeg: &Path,
+) {
+    let sessions: Vec<(String, Arc<RwLock<VideoSession>>)> = manager
+        .read()
+        .await
+        .sessions()
+        .map(|(id, session)| (id.clone(), session.clone()))
+        .collect();
+    for (session_id, session) in sessions {
+        let pending = session.write().await.take_preview_jobs(store);
+        for job in pending {
+            let previews = match jobs::acquire(manager, JobClass::Preview, &session_id).await {
+                Ok(_permit) => render_previews(ffmpeg, store, &job).await,
+                Err(_) => {
+                    // Workers are busy; the next sweep tries again
+                    session.write().await.preview_attempts.remove(&job.clip_id);
+                    continue;
+                }
+            };
+            let attached = match previews {
+                Ok((thumbnail, filmstrip)) => {
+                    // The clip may have been removed or given other media
+                    // while ffmpeg ran
+                    let mut session = session.write().await;
+                    let current = session
+                        .project
+                        .clips
+                        .iter()
+                        .any(|c| c.id == job.clip_id && c.source_file == job.source_file);
+                    if current {
+                        session.set_clip_previews(&job.clip_id, Some(thumbnail), filmstrip)
+                    } else {
+                        Ok(())
+                    }
+                }
+                Err(message) => Err(message),
+            };
+            if let Err(message) = attached {
+                eprintln!(
+                    "Failed to generate previews for clip {} in session {}: {}",
+                    job.clip_id, session_id, message
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_or_create_session;
+    use weframe_shared::{Asset, VideoClip};
+
+    #[tokio::test]
+    async fn each_clip_source_is_tried_once() {
+        let store = MediaStore::new(
+            std::env::temp_dir().join(format!("weframe-test-{}", uuid::Uuid::new_v4())),
+        );
+        let session = get_or_create_session(&RwLock::new(SessionManager::new()), "previews").await;
+        let mut session = session.write().await;
+        for (id, key) in [("stored", "interview.mp4"), ("elsewhere", "")] {
+            session.project.assets.push(Asset {
+                id: id.to_string(),
+                name: id.to_string(),
+                uri: if key.is_empty() {
+                    "https://example.com/b-roll.mp4".to_string()
+                } else {
+                    format!("media:{}", key)
+                },
+                duration: None,
+                color_space: None,
+                hdr: None,
+                public_url: None,
+            });
+        }
+        let clip = |id: &str, asset: &str, kind: ClipKind| VideoClip {
+            id: id.to_string(),
+            asset_id: Some(asset.to_string()),
+            kind,
+            ..VideoClip::default()
+        };
+        session.project.clips = vec![
+            clip("video", "stored", ClipKind::Video),
+            clip("audio", "stored", ClipKind::Audio),
+            clip("remote", "elsewhere", ClipKind::Video),
+        ];
+
+        let jobs = session.take_preview_jobs(&store);
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].clip_id, "video");
+        assert_eq!(jobs[0].media, store.path("interview.mp4").unwrap());
+        // Failed or not, the clip isn't tried again from the same source
+        assert!(session.take_preview_jobs(&store).is_empty());
+
+        session.project.clips[0].source_file = "interview-v2.mp4".to_string();
+        assert_eq!(session.take_preview_jobs(&store).len(), 1);
+    }
+}
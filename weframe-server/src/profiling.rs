@@ -0,0 +1,123 @@
+// weframe-server/src/profiling.rs
+use crate::{handle_websocket, SessionManager, VideoSession};
+use serde::Deserialize;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{RwLock, RwLockWriteGuard};
+use tracing::{Instrument, Span};
+use tracing_subscriber::fmt::format::FmtSpan;
+use warp::ws::WebSocket;
+use warp::Filter;
+
+/// Longest flamegraph capture a single request may ask for.
+const MAX_CAPTURE: Duration = Duration::from_secs(60);
+
+/// Spans and lock timings are only recorded while this is set, so a
+/// profiling build costs next to nothing until an operator turns it on.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Installs the subscriber that logs span durations when they close.
+pub fn init() {
+    tracing_subscriber::fmt()
+        .with_span_events(FmtSpan::CLOSE)
+        .init();
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Runs a WebSocket connection inside a span covering its lifetime.
+pub fn websocket_connection(
+    socket: WebSocket,
+    session_id: String,
+    token: Option<String>,
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl Future<Output = ()> {
+    let span = if enabled() {
+        tracing::info_span!("websocket", session_id = session_id.as_str())
+    } else {
+        Span::none()
+    };
+    handle_websocket(socket, session_id, token, manager).instrument(span)
+}
+
+/// Write-locks `session`, logging how long the wait took.
+pub async fn write_session(session: &RwLock<VideoSession>) -> RwLockWriteGuard<'_, VideoSession> {
+    if !enabled() {
+        return session.write().await;
+    }
+    let started = Instant::now();
+    let guard = session.write().await;
+    tracing::info!(
+        wait_us = started.elapsed().as_micros() as u64,
+        "session write lock"
+    );
+    guard
+}
+
+/// Samples every thread for `duration` and renders the result as an SVG
+/// flamegraph.
+fn capture_flamegraph(duration: Duration) -> Result<Vec<u8>, String> {
+    let guard = pprof::ProfilerGuardBuilder::default()
+        .frequency(99)
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()
+        .map_err(|e| e.to_string())?;
+    std::thread::sleep(duration);
+    let report = guard.report().build().map_err(|e| e.to_string())?;
+    let mut svg = Vec::new();
+    report.flamegraph(&mut svg).map_err(|e| e.to_string())?;
+    Ok(svg)
+}
+
+#[derive(Deserialize)]
+struct ToggleQuery {
+    enabled: bool,
+}
+
+#[derive(Deserialize)]
+struct CaptureQuery {
+    seconds: Option<u64>,
+}
+
+/// `POST /admin/profiling?enabled=` switches span recording on or off, and
+/// `GET /admin/profiling/flamegraph?seconds=` captures a CPU flamegraph.
+/// Neither is authenticated, so profiling builds should not expose them
+/// publicly.
+pub fn admin_route() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone
+{
+    let toggle = warp::post()
+        .and(warp::path!("admin" / "profiling"))
+        .and(warp::query::<ToggleQuery>())
+        .map(|query: ToggleQuery| {
+            ENABLED.store(query.enabled, Ordering::Relaxed);
+            warp::reply::json(&serde_json::json!({ "enabled": query.enabled }))
+        });
+
+    let flamegraph = warp::get()
+        .and(warp::path!("admin" / "profiling" / "flamegraph"))
+        .and(warp::query::<CaptureQuery>())
+        .and_then(|query: CaptureQuery| async move {
+            let duration = Duration::from_secs(query.seconds.unwrap_or(10)).min(MAX_CAPTURE);
+            let result = tokio::task::spawn_blocking(move || capture_flamegraph(duration))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()));
+            let reply: Box<dyn warp::Reply> = match result {
+                Ok(svg) => Box::new(warp::reply::with_header(
+                    svg,
+                    "content-type",
+                    "image/svg+xml",
+                )),
+                Err(message) => Box::new(warp::reply::with_status(
+                    message,
+                    warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                )),
+            };
+            Ok::<_, warp::Rejection>(reply)
+        });
+
+    toggle.or(flamegraph)
+}
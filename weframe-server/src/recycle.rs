@@ -0,0 +1,231 @@
+// weframe-server/src/recycle.rs
+use crate::outbox::Priority;
+use crate::replies::error_reply;
+use crate::{SessionManager, VideoSession};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tokio::sync::RwLock;
+use warp::http::StatusCode;
+use warp::ws::Message;
+use warp::Filter;
+
+impl VideoSession {
+    /// Closes every client connection, e.g. because the session was deleted.
+    pub(crate) fn disconnect_all(&mut self) {
+        for (_, client) in self.clients.drain() {
+            client.sender.send(Message::close(), Priority::Normal).ok();
+        }
+    }
+}
+
+impl SessionManager {
+    /// Removes a session, live, hibernated or persisted, moving its snapshot to the
+    /// recycle bin when one is configured. Returns whether it existed.
+    pub async fn delete_session(&mut self, id: &str) -> Result<bool, String> {
+        let session = match self.sessions.get(id) {
+            Some(session) => session.clone(),
+            None => {
+                let mut stored = match &self.hibernation {
+                    Some(store) => store.take(id).await.map_err(|e| e.to_string())?,
+                    None => None,
+                };
+                if let (None, Some(store)) = (&stored, &self.projects) {
+                    stored = store.load(id).await.map_err(|e| e.to_string())?;
+                }
+                let Some(snapshot) = stored else {
+                    return Ok(false);
+                };
+                let mut session = self.new_session(id);
+                session.restore(snapshot);
+                Arc::new(RwLock::new(session))
+            }
+        };
+
+        let mut session = session.write().await;
+        if let Some(bin) = &self.recycle_bin {
+            bin.save(id, &session).await.map_err(|e| e.to_string())?;
+        }
+        if let Some(store) = &self.projects {
+            store.remove(id).await.map_err(|e| e.to_string())?;
+        }
+        // A loaded session may still have the snapshot it was rehydrated from
+        if let Some(store) = &self.hibernation {
+            store.remove(id).await.map_err(|e| e.to_string())?;
+        }
+        session.disconnect_all();
+        self.sessions.remove(id);
+        self.workspaces.forget_stored(id);
+        self.metrics.remove_session(id);
+        Ok(true)
+    }
+
+    /// Brings a session back from the recycle bin. Fails if a session with
+    /// the same id has been created since.
+    pub async fn restore_deleted_session(&mut self, id: &str) -> Result<bool, String> {
+        let Some(bin) = &self.recycle_bin else {
+            return Ok(false);
+        };
+        let hibernated = match &self.hibernation {
+            Some(store) => store.contains(id).await,
+            None => false,
+        };
+        let persisted = match &self.projects {
+            Some(store) => matches!(store.load(id).await, Ok(Some(_))),
+            None => false,
+        };
+        if self.sessions.contains_key(id) || hibernated || persisted {
+            return Err(format!("Session {} already exists", id));
+        }
+        let Some(snapshot) = bin.take(id).await.map_err(|e| e.to_string())? else {
+            return Ok(false);
+        };
+        let mut session = self.new_session(id);
+        session.restore(snapshot);
+        self.sessions
+            .insert(id.to_string(), Arc::new(RwLock::new(session)));
+        Ok(true)
+    }
+}
+
+/// Permanently deletes recycle bin entries older than the retention period,
+/// without holding the manager's lock while it does.
+pub async fn purge_recycle_bin(manager: &RwLock<SessionManager>) {
+    let (bin, config) = {
+        let manager = manager.read().await;
+        (manager.recycle_bin.clone(), manager.config.clone())
+    };
+    let Some(bin) = bin else {
+        return;
+    };
+    let now = config
+        .clock
+        .now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let retention = config.recycle_retention.as_secs();
+    let snapshots = match bin.list().await {
+        Ok(snapshots) => snapshots,
+        Err(e) => {
+            eprintln!("Failed to list recycle bin: {}", e);
+            return;
+        }
+    };
+    for snapshot in snapshots {
+        if now.saturating_sub(snapshot.saved_at) >= retention {
+            if let Err(e) = bin.remove(&snapshot.session_id).await {
+                eprintln!(
+                    "Failed to purge session {} from recycle bin: {}",
+                    snapshot.session_id, e
+                );
+            }
+        }
+    }
+}
+
+/// Admin endpoints for deleting sessions and managing the recycle bin:
+/// `DELETE /admin/sessions/:id`, `GET /admin/recycle-bin` and
+/// `POST /admin/recycle-bin/:id/restore`.
+pub fn admin_routes(
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl Filter<Extract = (Box<dyn warp::Reply>,), Error = warp::Rejection> + Clone {
+    let delete_manager = manager.clone();
+    let delete = warp::delete()
+        .and(warp::path!("admin" / "sessions" / String))
+        .and_then(move |session_id: String| {
+            let manager = delete_manager.clone();
+            async move {
+                let reply = match manager.write().await.delete_session(&session_id).await {
+                    Ok(true) => Box::new(StatusCode::NO_CONTENT) as Box<dyn warp::Reply>,
+                    Ok(false) => return Err(warp::reject::not_found()),
+                    Err(message) => {
+                        Box::new(error_reply(message, StatusCode::INTERNAL_SERVER_ERROR))
+                    }
+                };
+                Ok::<_, warp::Rejection>(reply)
+            }
+        });
+
+    let list_manager = manager.clone();
+    let list = warp::get()
+        .and(warp::path!("admin" / "recycle-bin"))
+        .and_then(move || {
+            let manager = list_manager.clone();
+            async move {
+                let manager = manager.read().await;
+                let snapshots = match &manager.recycle_bin {
+                    Some(bin) => bin.list().await,
+                    None => Ok(Vec::new()),
+                };
+                let reply = match snapshots {
+                    Ok(snapshots) => {
+                        Box::new(warp::reply::json(&snapshots)) as Box<dyn warp::Reply>
+                    }
+                    Err(e) => Box::new(error_reply(
+                        e.to_string(),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    )),
+                };
+                Ok::<_, warp::Rejection>(reply)
+            }
+        });
+
+    let restore = warp::post()
+        .and(warp::path!("admin" / "recycle-bin" / String / "restore"))
+        .and_then(move |session_id: String| {
+            let manager = manager.clone();
+            async move {
+                let restored = manager
+                    .write()
+                    .await
+                    .restore_deleted_session(&session_id)
+                    .await;
+                let reply = match restored {
+                    Ok(true) => Box::new(StatusCode::NO_CONTENT) as Box<dyn warp::Reply>,
+                    Ok(false) => return Err(warp::reject::not_found()),
+                    Err(message) => Box::new(error_reply(message, StatusCode::CONFLICT)),
+                };
+                Ok::<_, warp::Rejection>(reply)
+            }
+        });
+
+    delete.or(list).unify().or(restore).unify()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::{get_or_create_session, ServerConfig};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn deleted_sessions_leave_the_recycle_bin_after_retention() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_000_000));
+        let dir = std::env::temp_dir().join(format!("weframe-test-{}", uuid::Uuid::new_v4()));
+        let config = ServerConfig {
+            clock: Arc::new(clock.clone()),
+            recycle_dir: Some(dir.clone()),
+            recycle_retention: Duration::from_secs(3600),
+            ..ServerConfig::default()
+        };
+        let manager = RwLock::new(SessionManager::with_config(Arc::new(config)));
+        get_or_create_session(&manager, "deleted").await;
+        assert!(manager
+            .write()
+            .await
+            .delete_session("deleted")
+            .await
+            .unwrap());
+        let bin = manager.read().await.recycle_bin.clone().unwrap();
+
+        clock.advance(Duration::from_secs(3599));
+        purge_recycle_bin(&manager).await;
+        assert!(bin.contains("deleted").await);
+
+        clock.advance(Duration::from_secs(1));
+        purge_recycle_bin(&manager).await;
+        assert!(!bin.contains("deleted").await);
+        tokio::fs::remove_dir_all(&dir).await.ok();
+    }
+}
@@ -0,0 +1,87 @@
+// weframe-server/src/relink.rs
+use crate::replies::{error_reply, MAX_JSON_BODY_BYTES};
+use crate::{auth, freeze, roles, SessionManager, VideoSession};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use warp::http::StatusCode;
+use warp::Filter;
+use weframe_shared::{EditOperation, MediaReference, Role};
+
+#[derive(Debug, Deserialize)]
+pub struct RelinkRequest {
+    pub from: MediaReference,
+    pub new_asset_id: String,
+}
+
+impl RelinkRequest {
+    pub fn operation(&self) -> EditOperation {
+        EditOperation::RelinkAsset {
+            from: self.from.clone(),
+            new_asset_id: self.new_asset_id.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelinkReport {
+    /// Clips now playing the new asset, trashed clips included.
+    pub relinked: usize,
+    pub server_version: usize,
+}
+
+impl VideoSession {
+    /// Relinks every clip playing `request.from` as a single server operation.
+    pub fn relink_asset(&mut self, request: RelinkRequest) -> Result<RelinkReport, String> {
+        let relinked = self
+            .project
+            .clips
+            .iter()
+            .chain(&self.project.trash)
+            .filter(|c| request.from.matches(c))
+            .count();
+        self.apply_server_operation(request.operation())?;
+        Ok(RelinkReport {
+            relinked,
+            server_version: self.server_version,
+        })
+    }
+}
+
+/// `POST /sessions/:id/relink` with `{"from": {"uri": ...} | {"asset_id": ...},
+/// "new_asset_id": ...}` moves all clips off a missing or moved source at once.
+pub fn relink_route(
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("sessions" / String / "relink"))
+        .and(warp::body::content_length_limit(MAX_JSON_BODY_BYTES))
+        .and(warp::body::json())
+        .and(auth::request_role(manager.clone()))
+        .and_then(
+            move |session_id: String, request: RelinkRequest, role: Role| {
+                let manager = manager.clone();
+                async move {
+                    let session = manager
+                        .read()
+                        .await
+                        .get_session(&session_id)
+                        .ok_or_else(warp::reject::not_found)?;
+                    if let Err(message) = roles::permits(role, &request.operation()) {
+                        return Ok(roles::forbidden(message));
+                    }
+                    let mut session = session.write().await;
+                    if let Err(message) = session.check_frozen(&request.operation()) {
+                        return Ok(freeze::frozen(message));
+                    }
+                    let result = session.relink_asset(request);
+                    Ok::<_, warp::Rejection>(match result {
+                        Ok(report) => {
+                            warp::reply::with_status(warp::reply::json(&report), StatusCode::OK)
+                        }
+                        Err(message) => error_reply(message, StatusCode::UNPROCESSABLE_ENTITY),
+                    })
+                }
+            },
+        )
+}
@@ -0,0 +1,1325 @@
+// weframe-server/src/render.rs
+use crate::activity::report_job_failure;
+use crate::analysis::{clip_media_path, scratch_path};
+use crate::auth::TokenQuery;
+use crate::automation::TimeRange;
+use crate::clock::Clock;
+use crate::jobs::{JobClass, JobPool, JobTicket};
+use crate::media::MediaStore;
+use crate::replies::{error_reply, MAX_JSON_BODY_BYTES};
+use crate::SessionManager;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::sync::RwLock;
+use warp::http::StatusCode;
+use warp::{Filter, Reply};
+use weframe_shared::{ClipKind, EffectType, JobKind, SourceKind, VideoClip, VideoProject};
+
+const DEFAULT_GIF_FPS: u32 = 12;
+const MAX_GIF_FPS: u32 = 30;
+const DEFAULT_GIF_WIDTH: u32 = 480;
+const MAX_GIF_WIDTH: u32 = 1280;
+/// GIFs grow quickly with length; longer ranges should go out as video.
+const MAX_GIF_DURATION: Duration = Duration::from_secs(60);
+const MIX_SAMPLE_RATE: u32 = 48_000;
+const FULL_SIZE_SHORT_SIDE: u32 = 1080;
+
+/// What a render job produces.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "format", rename_all = "snake_case")]
+pub enum RenderTarget {
+    /// Looping animated GIF with a palette generated for the range.
+    Gif {
+        /// Part of the timeline to render; the whole timeline by default.
+        #[serde(default)]
+        range: Option<TimeRange>,
+        #[serde(default)]
+        fps: Option<u32>,
+        /// Output width in pixels; height follows the project's aspect ratio.
+        #[serde(default)]
+        width: Option<u32>,
+    },
+    /// The mixed sound of the range, e.g. the work area, with clip gains
+    /// and fades applied and muted tracks left out.
+    Audio {
+        #[serde(default)]
+        range: Option<TimeRange>,
+        #[serde(default)]
+        codec: AudioCodec,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioCodec {
+    #[default]
+    Wav,
+    Mp3,
+    /// AAC in an `.m4a` file.
+    Aac,
+}
+
+impl AudioCodec {
+    fn args(self) -> &'static [&'static str] {
+        match self {
+            AudioCodec::Wav => &["-c:a", "pcm_s16le", "-f", "wav"],
+            AudioCodec::Mp3 => &["-c:a", "libmp3lame", "-b:a", "192k", "-f", "mp3"],
+            AudioCodec::Aac => &[
+                "-c:a",
+                "aac",
+                "-b:a",
+                "192k",
+                "-movflags",
+                "+faststart",
+                "-f",
+                "ipod",
+            ],
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            AudioCodec::Wav => "wav",
+            AudioCodec::Mp3 => "mp3",
+            AudioCodec::Aac => "m4a",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            AudioCodec::Wav => "audio/wav",
+            AudioCodec::Mp3 => "audio/mpeg",
+            AudioCodec::Aac => "audio/mp4",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenderJob {
+    pub id: String,
+    pub session_id: String,
+    pub target: RenderTarget,
+    /// Part of the timeline rendered, with the target's default resolved.
+    pub range: TimeRange,
+    /// Token subject of whoever asked for the render, if they gave one.
+    #[serde(default)]
+    pub requested_by: Option<String>,
+    /// Workspace of the session, whose render minutes the job counts against.
+    #[serde(default)]
+    pub workspace: Option<String>,
+    pub status: JobStatus,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    pub finished_at: Option<u64>,
+    pub error: Option<String>,
+    /// Where the result can be downloaded once the job has completed.
+    pub output_url: Option<String>,
+    /// Size of the finished file in bytes.
+    #[serde(default)]
+    pub size: Option<u64>,
+    #[serde(skip)]
+    output: Option<PathBuf>,
+    #[serde(skip)]
+    content_type: String,
+}
+
+/// Written next to a finished render so it is listed again after a
+/// restart, until it is deleted or expires.
+#[derive(Serialize, Deserialize)]
+struct RenderRecord {
+    job: RenderJob,
+    /// Name of the output file in the render directory.
+    file: String,
+    content_type: String,
+}
+
+/// The ffmpeg run that renders a job. It is worked out from the project as
+/// it is when the job is submitted, so edits made while the job waits in the
+/// queue don't change what it renders.
+pub struct RenderPlan {
+    args: Vec<OsString>,
+    range: TimeRange,
+    extension: &'static str,
+    content_type: &'static str,
+}
+
+type Jobs = Arc<RwLock<HashMap<String, RenderJob>>>;
+
+/// Render jobs of every session, run as the job pool's render workers come
+/// free. Finished renders stay in `dir` until deleted or expired.
+#[derive(Clone)]
+pub struct RenderQueue {
+    jobs: Jobs,
+    pool: JobPool,
+    ffmpeg: PathBuf,
+    dir: PathBuf,
+    manager: Arc<RwLock<SessionManager>>,
+    /// Stamps jobs with when they were created and finished, and decides
+    /// when finished ones expire.
+    clock: Arc<dyn Clock>,
+}
+
+impl RenderQueue {
+    /// Lists the renders already in `dir` in the background. Finished
+    /// renders are written there, and sessions told about failed ones.
+    pub fn start(
+        ffmpeg: PathBuf,
+        dir: PathBuf,
+        manager: Arc<RwLock<SessionManager>>,
+        pool: JobPool,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let jobs = Arc::new(RwLock::new(HashMap::new()));
+        tokio::spawn({
+            let (jobs, dir) = (jobs.clone(), dir.clone());
+            async move { load_records(&jobs, &dir).await }
+        });
+        RenderQueue {
+            jobs,
+            pool,
+            ffmpeg,
+            dir,
+            manager,
+            clock,
+        }
+    }
+
+    fn now_secs(&self) -> u64 {
+        self.clock
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+
+    /// Queues a render, failing when too many are already waiting.
+    pub async fn submit(
+        &self,
+        session_id: String,
+        target: RenderTarget,
+        plan: RenderPlan,
+        requested_by: Option<String>,
+        workspace: Option<String>,
+    ) -> Result<RenderJob, String> {
+        let ticket = self.pool.enqueue(JobClass::Render, &session_id)?;
+        let id = self.manager.read().await.config.ids.uuid().to_string();
+        if let Some(workspace) = &workspace {
+            let length = plan.range.end - plan.range.start;
+            self.manager
+                .write()
+                .await
+                .reserve_render(workspace, length)?;
+        }
+        let job = RenderJob {
+            id,
+            session_id,
+            target,
+            range: plan.range,
+            requested_by,
+            workspace,
+            status: JobStatus::Queued,
+            created_at: self.now_secs(),
+            finished_at: None,
+            error: None,
+            output_url: None,
+            size: None,
+            output: None,
+            content_type: plan.content_type.to_string(),
+        };
+        self.jobs.write().await.insert(job.id.clone(), job.clone());
+        tokio::spawn(self.clone().run(job.id.clone(), plan, ticket));
+        Ok(job)
+    }
+
+    async fn run(self, job_id: String, plan: RenderPlan, ticket: JobTicket) {
+        let _permit = ticket.start().await;
+        let (session_id, workspace) = match self.jobs.write().await.get_mut(&job_id) {
+            Some(job) => {
+                job.status = JobStatus::Running;
+                (job.session_id.clone(), job.workspace.clone())
+            }
+            None => return,
+        };
+        let output = self.dir.join(format!("{}.{}", job_id, plan.extension));
+        let result = render(&self.ffmpeg, &plan, &output).await.map(|()| output);
+        if let Err(message) = &result {
+            report_job_failure(
+                &self.manager,
+                &session_id,
+                &job_id,
+                JobKind::Render,
+                message,
+            )
+            .await;
+            if let Some(workspace) = &workspace {
+                let length = plan.range.end - plan.range.start;
+                self.manager.write().await.release_render(workspace, length);
+            }
+        }
+        finish(&self.jobs, &job_id, result, self.now_secs()).await;
+    }
+
+    pub async fn job(&self, job_id: &str) -> Option<RenderJob> {
+        self.jobs.read().await.get(job_id).cloned()
+    }
+
+    pub async fn session_jobs(&self, session_id: &str) -> Vec<RenderJob> {
+        let mut jobs: Vec<RenderJob> = self
+            .jobs
+            .read()
+            .await
+            .values()
+            .filter(|job| job.session_id == session_id)
+            .cloned()
+            .collect();
+        jobs.sort_by_key(|job| job.created_at);
+        jobs
+    }
+
+    /// Forgets a finished job and deletes its output. Jobs still queued or
+    /// running are left alone and give `None`, like unknown ones.
+    pub async fn remove(&self, job_id: &str) -> Option<RenderJob> {
+        let job = {
+            let mut jobs = self.jobs.write().await;
+            if !matches!(
+                jobs.get(job_id)?.status,
+                JobStatus::Completed | JobStatus::Failed
+            ) {
+                return None;
+            }
+            jobs.remove(job_id)?
+        };
+        if let Some(output) = &job.output {
+            for path in [output.clone(), output.with_extension("json")] {
+                if let Err(e) = tokio::fs::remove_file(&path).await {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        eprintln!("Failed to delete {}: {}", path.display(), e);
+                    }
+                }
+            }
+        }
+        Some(job)
+    }
+
+    /// Deletes renders that finished more than `retention` ago.
+    pub async fn expire(&self, retention: Duration) {
+        let cutoff = self.now_secs().saturating_sub(retention.as_secs());
+        let expired: Vec<String> = self
+            .jobs
+            .read()
+            .await
+            .values()
+            .filter(|job| job.finished_at.is_some_and(|finished| finished < cutoff))
+            .map(|job| job.id.clone())
+            .collect();
+        for job_id in &expired {
+            self.remove(job_id).await;
+        }
+        if !expired.is_empty() {
+            println!("Deleted {} expired renders", expired.len());
+        }
+    }
+}
+
+async fn finish(jobs: &Jobs, job_id: &str, result: Result<PathBuf, String>, now: u64) {
+    let size = match &result {
+        Ok(output) => tokio::fs::metadata(output).await.ok().map(|m| m.len()),
+        Err(_) => None,
+    };
+    let completed = {
+        let mut jobs = jobs.write().await;
+        let Some(job) = jobs.get_mut(job_id) else {
+            return;
+        };
+        job.finished_at = Some(now);
+        match result {
+            Ok(output) => {
+                job.status = JobStatus::Completed;
+                job.output_url = Some(format!(
+                    "/sessions/{}/renders/{}/output",
+                    job.session_id, job_id
+                ));
+                job.size = size;
+                job.output = Some(output);
+                Some(job.clone())
+            }
+            Err(error) => {
+                job.status = JobStatus::Failed;
+                job.error = Some(error);
+                None
+            }
+        }
+    };
+    if let Some(job) = completed {
+        save_record(&job).await;
+    }
+}
+
+async fn save_record(job: &RenderJob) {
+    let Some(output) = &job.output else {
+        return;
+    };
+    let record = RenderRecord {
+        job: job.clone(),
+        file: output
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+        content_type: job.content_type.clone(),
+    };
+    let path = output.with_extension("json");
+    let result = match serde_json::to_vec(&record) {
+        Ok(json) => tokio::fs::write(&path, json)
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to record render {}: {}", job.id, e);
+    }
+}
+
+/// Lists the finished renders recorded in `dir` by a previous run.
+async fn load_records(jobs: &Jobs, dir: &Path) {
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return;
+    };
+    let mut loaded = 0;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let record = match tokio::fs::read(&path)
+            .await
+            .map(|json| serde_json::from_slice::<RenderRecord>(&json))
+        {
+            Ok(Ok(record)) => record,
+            _ => {
+                eprintln!("Ignoring unreadable render record {}", path.display());
+                continue;
+            }
+        };
+        let output = dir.join(&record.file);
+        if !tokio::fs::try_exists(&output).await.unwrap_or(false) {
+            tokio::fs::remove_file(&path).await.ok();
+            continue;
+        }
+        let mut job = record.job;
+        job.output = Some(output);
+        job.content_type = record.content_type;
+        jobs.write().await.insert(job.id.clone(), job);
+        loaded += 1;
+    }
+    if loaded > 0 {
+        println!("Loaded {} finished renders from {}", loaded, dir.display());
+    }
+}
+
+async fn render(ffmpeg: &Path, plan: &RenderPlan, output: &Path) -> Result<(), String> {
+    if let Some(dir) = output.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|e| format!("Could not create render directory: {}", e))?;
+    }
+    let result = tokio::process::Command::new(ffmpeg)
+        .args(["-hide_banner", "-nostats", "-y"])
+        .args(&plan.args)
+        .arg(output)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| format!("Could not run {}: {}", ffmpeg.display(), e))?;
+    if !result.status.success() {
+        let _ = tokio::fs::remove_file(output).await;
+        let log = String::from_utf8_lossy(&result.stderr);
+        let reason = log.lines().last().unwrap_or("no output");
+        return Err(format!("ffmpeg failed: {}", reason));
+    }
+    Ok(())
+}
+
+/// Frame size and rate the timeline is composited at.
+#[derive(Debug, Clone, Copy)]
+struct Canvas {
+    width: u32,
+    height: u32,
+    fps: u32,
+}
+
+impl Canvas {
+    /// A canvas `width` wide in the project's aspect ratio. Dimensions are
+    /// kept even, which most encoders require.
+    fn for_project(project: &VideoProject, width: u32, fps: u32) -> Canvas {
+        let even = |n: f64| ((n / 2.0).round() as u32).max(1) * 2;
+        let aspect = project.settings.aspect_ratio.as_f64();
+        Canvas {
+            width: even(width as f64),
+            height: even(width as f64 / aspect),
+            fps,
+        }
+    }
+
+    /// The project's full frame size: 1080 pixels on the short side.
+    /// Projects don't choose a resolution of their own yet.
+    fn full_size(project: &VideoProject, fps: u32) -> Canvas {
+        let aspect = project.settings.aspect_ratio.as_f64();
+        let width = if aspect >= 1.0 {
+            FULL_SIZE_SHORT_SIDE as f64 * aspect
+        } else {
+            FULL_SIZE_SHORT_SIDE as f64
+        };
+        Canvas::for_project(project, width.round() as u32, fps)
+    }
+}
+
+fn secs(time: Duration) -> String {
+    format!("{:.6}", time.as_secs_f64())
+}
+
+/// Where ffmpeg can read a clip's media: the stored file, or the clip's
+/// own URL for media hosted elsewhere.
+fn clip_input(project: &VideoProject, store: &MediaStore, clip: &VideoClip) -> Option<OsString> {
+    if let Some(path) = clip_media_path(project, store, clip) {
+        return Some(path.into_os_string());
+    }
+    let remote =
+        clip.source_file.starts_with("http://") || clip.source_file.starts_with("https://");
+    remote.then(|| OsString::from(&clip.source_file))
+}
+
+/// The visible part of `clip` within `start..end`, cut at its speed
+/// keyframes. Between keyframes a ramp is rendered at its average speed.
+fn constant_speed_pieces(clip: &VideoClip, start: Duration, end: Duration) -> Vec<VideoClip> {
+    let start = start.max(clip.start_time);
+    let end = end.min(clip.end_time);
+    let mut cuts = vec![start];
+    cuts.extend(
+        clip.speed
+            .iter()
+            .map(|k| clip.start_time + k.offset)
+            .filter(|t| start < *t && *t < end),
+    );
+    cuts.push(end);
+    cuts.windows(2)
+        .filter(|w| w[0] < w[1])
+        .map(|w| clip.slice(w[0], w[1]))
+        .collect()
+}
+
+/// A constant-speed stretch of a clip within the range being rendered.
+struct Piece<'a> {
+    clip: &'a VideoClip,
+    part: VideoClip,
+    /// Source seconds played per timeline second.
+    speed: f64,
+}
+
+/// Adds an ffmpeg input reading just the media each piece plays, for the
+/// clips of `flat` in `range` that `include` accepts. Input `i` is the
+/// `i`th piece returned.
+fn add_inputs<'a>(
+    flat: &'a VideoProject,
+    store: &MediaStore,
+    range: TimeRange,
+    args: &mut Vec<OsString>,
+    include: impl Fn(&VideoClip) -> bool,
+) -> Vec<Piece<'a>> {
+    let mut pieces = Vec::new();
+    for clip in &flat.clips {
+        if clip.end_time <= range.start || clip.start_time >= range.end || !include(clip) {
+            continue;
+        }
+        let Some(media) = clip_input(flat, store, clip) else {
+            continue;
+        };
+        if clip.source_kind == SourceKind::Image {
+            // Looped for as long as the clip shows in the range
+            let part = clip.slice(
+                range.start.max(clip.start_time),
+                range.end.min(clip.end_time),
+            );
+            args.extend(["-loop".into(), "1".into()]);
+            args.extend(["-t".into(), secs(part.end_time - part.start_time).into()]);
+            args.extend(["-i".into(), media]);
+            pieces.push(Piece {
+                clip,
+                part,
+                speed: 1.0,
+            });
+            continue;
+        }
+        for part in constant_speed_pieces(clip, range.start, range.end) {
+            let (source_start, source_end) = part.source_range();
+            let source_length = source_end.saturating_sub(source_start);
+            if source_length.is_zero() {
+                continue;
+            }
+            args.extend(["-ss".into(), secs(source_start).into()]);
+            args.extend(["-t".into(), secs(source_length).into()]);
+            args.extend(["-i".into(), media.clone()]);
+            let length = part.end_time - part.start_time;
+            pieces.push(Piece {
+                clip,
+                speed: source_length.as_secs_f64() / length.as_secs_f64(),
+                part,
+            });
+        }
+    }
+    pieces
+}
+
+/// ffmpeg filters for a clip's effects, matching the CSS filters and
+/// transforms the preview uses where CSS has one. Like the preview, effects
+/// cover the whole clip. Moves are left to the overlay; see `clip_offset`.
+fn effect_filters(clip: &VideoClip) -> String {
+    let mut filters = String::new();
+    for effect in &clip.effects {
+        let value = effect.parameters.get("value").copied();
+        let param =
+            |name: &str, default: f64| effect.parameters.get(name).copied().unwrap_or(default);
+        let _ = match effect.effect_type {
+            EffectType::Brightness => {
+                let v = value.unwrap_or(1.0);
+                write!(filters, ",colorchannelmixer=rr={v}:gg={v}:bb={v}")
+            }
+            EffectType::Contrast => write!(filters, ",eq=contrast={}", value.unwrap_or(1.0)),
+            EffectType::Saturation => {
+                write!(filters, ",eq=saturation={}", value.unwrap_or(1.0).min(3.0))
+            }
+            EffectType::Hue => write!(filters, ",hue=h={}", value.unwrap_or(0.0)),
+            EffectType::Grayscale => write!(filters, ",hue=s={}", 1.0 - value.unwrap_or(1.0)),
+            EffectType::Blur => write!(filters, ",gblur=sigma={}", value.unwrap_or(4.0)),
+            EffectType::Sharpen => write!(filters, ",unsharp=5:5:{}", value.unwrap_or(1.0)),
+            EffectType::Vignette => write!(
+                filters,
+                ",vignette=angle={}",
+                value.unwrap_or(0.5) * std::f64::consts::FRAC_PI_2
+            ),
+            EffectType::Sepia => {
+                // The CSS sepia() matrix, blended with the original by `value`
+                let v = value.unwrap_or(1.0);
+                let mix = |sepia: f64, same: bool| v * sepia + if same { 1.0 - v } else { 0.0 };
+                write!(
+                    filters,
+                    ",colorchannelmixer={}:{}:{}:0:{}:{}:{}:0:{}:{}:{}",
+                    mix(0.393, true),
+                    mix(0.769, false),
+                    mix(0.189, false),
+                    mix(0.349, false),
+                    mix(0.686, true),
+                    mix(0.168, false),
+                    mix(0.272, false),
+                    mix(0.534, false),
+                    mix(0.131, true),
+                )
+            }
+            EffectType::Invert => {
+                let v = value.unwrap_or(1.0);
+                let channel = format!("val*{}+maxval*{}", 1.0 - 2.0 * v, v);
+                write!(filters, ",lutrgb=r={channel}:g={channel}:b={channel}")
+            }
+            EffectType::Noise => write!(filters, ",noise=alls={}:allf=t", value.unwrap_or(20.0)),
+            EffectType::Crop => {
+                // Padded back out with transparency, so what's kept stays
+                // put. Both sizes are kept even, as 4:2:0 needs
+                let (x, y) = (param("x", 0.0), param("y", 0.0));
+                let (w, h) = (param("width", 1.0), param("height", 1.0));
+                write!(
+                    filters,
+                    ",format=yuva420p,crop='max(2,trunc(iw*{w}/2)*2)':'max(2,trunc(ih*{h}/2)*2)':iw*{x}:ih*{y},\
+                     pad='trunc(iw/{w}/2)*2':'trunc(ih/{h}/2)*2':ow*{x}:oh*{y}:color=black@0"
+                )
+            }
+            EffectType::Scale => write!(
+                filters,
+                ",scale='max(2,trunc(iw*{}/2)*2)':'max(2,trunc(ih*{}/2)*2)'",
+                param("x", 1.0),
+                param("y", 1.0)
+            ),
+            EffectType::Rotate => {
+                let angle = param("angle", 0.0).to_radians();
+                write!(
+                    filters,
+                    ",format=yuva420p,rotate={angle}:ow='rotw({angle})':oh='roth({angle})':c=none"
+                )
+            }
+            EffectType::Position => Ok(()),
+        };
+    }
+    filters
+}
+
+/// How far a clip's position effects move its picture from the middle of
+/// the frame, as fractions of the frame.
+fn clip_offset(clip: &VideoClip) -> (f64, f64) {
+    clip.effects
+        .iter()
+        .filter(|effect| effect.effect_type == EffectType::Position)
+        .fold((0.0, 0.0), |(x, y), effect| {
+            let param = |name: &str| effect.parameters.get(name).copied().unwrap_or(0.0);
+            (x + param("x"), y + param("y"))
+        })
+}
+
+/// Inputs and filter graph compositing the picture of `range` onto
+/// `canvas`: black, with each visible video clip scaled to fit and laid
+/// over it, higher tracks on top. Transitions are drawn as fades in. The graph's
+/// output is labelled `[video]`.
+fn composite(
+    project: &VideoProject,
+    store: &MediaStore,
+    range: TimeRange,
+    canvas: Canvas,
+) -> (Vec<OsString>, String) {
+    let flat = project.flatten();
+    let mut args = Vec::new();
+    let pieces = add_inputs(&flat, store, range, &mut args, |clip| {
+        clip.kind == ClipKind::Video
+    });
+    let mut graph = format!(
+        "color=c=black:s={}x{}:r={}:d={}[base]",
+        canvas.width,
+        canvas.height,
+        canvas.fps,
+        secs(range.end - range.start)
+    );
+    let mut below = "base".to_string();
+    for (input, piece) in pieces.iter().enumerate() {
+        let offset = piece.part.start_time - range.start;
+        let _ = write!(
+            graph,
+            ";[{input}:v]setpts=(PTS-STARTPTS)/{speed}+{offset}/TB,fps={fps},\
+             scale={w}:{h}:force_original_aspect_ratio=decrease{effects}",
+            speed = piece.speed,
+            offset = secs(offset),
+            fps = canvas.fps,
+            w = canvas.width,
+            h = canvas.height,
+            effects = effect_filters(piece.clip),
+        );
+        if let Some(transition) = &piece.clip.transition {
+            if piece.part.start_time == piece.clip.start_time {
+                let _ = write!(
+                    graph,
+                    ",format=yuva420p,fade=t=in:st={}:d={}:alpha=1",
+                    secs(offset),
+                    secs(transition.duration)
+                );
+            }
+        }
+        let (x, y) = clip_offset(piece.clip);
+        let _ = write!(
+            graph,
+            "[clip{input}];[{below}][clip{input}]overlay=(W-w)/2+W*({x}):(H-h)/2+H*({y}):eof_action=pass[layer{input}]"
+        );
+        below = format!("layer{}", input);
+    }
+    let _ = write!(graph, ";[{below}]null[video]");
+    (args, graph)
+}
+
+/// `atempo` filters changing tempo by `speed`. Each stage handles 0.5 to
+/// 2, so larger changes are chained.
+fn tempo_filters(mut speed: f64) -> String {
+    let mut filters = String::new();
+    while speed > 2.0 {
+        filters.push_str(",atempo=2");
+        speed /= 2.0;
+    }
+    while speed < 0.5 {
+        filters.push_str(",atempo=0.5");
+        speed /= 0.5;
+    }
+    if (speed - 1.0).abs() > 1e-6 {
+        let _ = write!(filters, ",atempo={}", speed);
+    }
+    filters
+}
+
+/// `volume` filter applying a clip's gain and fades, for audio already
+/// placed at its time in the output. Empty when the clip plays as recorded.
+fn gain_filter(clip: &VideoClip, range: TimeRange) -> String {
+    let start = clip.start_time.as_secs_f64() - range.start.as_secs_f64();
+    let end = clip.end_time.as_secs_f64() - range.start.as_secs_f64();
+    let length = clip.end_time.saturating_sub(clip.start_time);
+    let mut envelope = Vec::new();
+    if clip.audio.gain_db != 0.0 {
+        envelope.push(format!("{}", 10f64.powf(clip.audio.gain_db / 20.0)));
+    }
+    let fade_in = clip.audio.fade_in.min(length).as_secs_f64();
+    if fade_in > 0.0 {
+        envelope.push(format!("clip((t-({start}))/{fade_in},0,1)"));
+    }
+    let fade_out = clip.audio.fade_out.min(length).as_secs_f64();
+    if fade_out > 0.0 {
+        envelope.push(format!("clip(({end}-t)/{fade_out},0,1)"));
+    }
+    if envelope.is_empty() {
+        return String::new();
+    }
+    format!(",volume='{}':eval=frame", envelope.join("*"))
+}
+
+/// Inputs and filter graph mixing the sound of `range`: every video and
+/// audio clip playing media on an unmuted track, at its speed, gain and fades, over
+/// silence so the mix lasts the whole range. The graph's output is
+/// labelled `[audio]`.
+fn mix(project: &VideoProject, store: &MediaStore, range: TimeRange) -> (Vec<OsString>, String) {
+    let flat = project.flatten();
+    let mut args = Vec::new();
+    let pieces = add_inputs(&flat, store, range, &mut args, |clip| {
+        clip.kind != ClipKind::Text
+            && clip.source_kind == SourceKind::Media
+            && !flat.muted_tracks.contains(&clip.track)
+    });
+    let mut graph = format!(
+        "anullsrc=r={}:cl=stereo,atrim=duration={}[silence]",
+        MIX_SAMPLE_RATE,
+        secs(range.end - range.start)
+    );
+    let mut voices = "[silence]".to_string();
+    for (input, piece) in pieces.iter().enumerate() {
+        let delay = (piece.part.start_time - range.start).as_secs_f64() * 1000.0;
+        let _ = write!(
+            graph,
+            ";[{input}:a]asetpts=PTS-STARTPTS,\
+             aformat=sample_rates={rate}:channel_layouts=stereo{tempo},\
+             adelay={delay:.3}:all=1{gain}[voice{input}]",
+            rate = MIX_SAMPLE_RATE,
+            tempo = tempo_filters(piece.speed),
+            gain = gain_filter(piece.clip, range),
+        );
+        let _ = write!(voices, "[voice{}]", input);
+    }
+    let _ = write!(
+        graph,
+        ";{voices}amix=inputs={}:duration=first:normalize=0[audio]",
+        pieces.len() + 1
+    );
+    (args, graph)
+}
+
+/// The range to render: the one asked for, or the whole timeline.
+fn render_range(project: &VideoProject, range: Option<TimeRange>) -> Result<TimeRange, String> {
+    let range = range.unwrap_or(TimeRange {
+        start: Duration::ZERO,
+        end: project
+            .clips
+            .iter()
+            .map(|c| c.end_time)
+            .max()
+            .unwrap_or_default(),
+    });
+    if range.start >= range.end {
+        return Err("Nothing to render in that range".to_string());
+    }
+    Ok(range)
+}
+
+/// Works out how to render `target` from the project as it is now.
+pub fn plan(
+    project: &VideoProject,
+    store: &MediaStore,
+    target: &RenderTarget,
+) -> Result<RenderPlan, String> {
+    match target {
+        RenderTarget::Gif { range, fps, width } => {
+            let range = render_range(project, *range)?;
+            if range.end - range.start > MAX_GIF_DURATION {
+                return Err(format!(
+                    "GIFs can be at most {} seconds long",
+                    MAX_GIF_DURATION.as_secs()
+                ));
+            }
+            let fps = fps.unwrap_or(DEFAULT_GIF_FPS).clamp(1, MAX_GIF_FPS);
+            let width = width.unwrap_or(DEFAULT_GIF_WIDTH).clamp(16, MAX_GIF_WIDTH);
+            let canvas = Canvas::for_project(project, width, fps);
+            let (mut args, mut graph) = composite(project, store, range, canvas);
+            // One palette for the whole range, redrawing only changed areas
+            graph.push_str(
+                ";[video]split[frames][sample];[sample]palettegen=stats_mode=diff[palette];\
+                 [frames][palette]paletteuse=dither=bayer:bayer_scale=5:diff_mode=rectangle[out]",
+            );
+            args.extend(["-filter_complex".into(), graph.into()]);
+            args.extend(["-map".into(), "[out]".into(), "-f".into(), "gif".into()]);
+            Ok(RenderPlan {
+                args,
+                range,
+                extension: "gif",
+                content_type: "image/gif",
+            })
+        }
+        RenderTarget::Audio { range, codec } => {
+            let range = render_range(project, *range)?;
+            let (mut args, graph) = mix(project, store, range);
+            args.extend(["-filter_complex".into(), graph.into()]);
+            args.extend(["-map".into(), "[audio]".into()]);
+            args.extend(codec.args().iter().map(OsString::from));
+            Ok(RenderPlan {
+                args,
+                range,
+                extension: codec.extension(),
+                content_type: codec.content_type(),
+            })
+        }
+    }
+}
+
+/// `POST /sessions/:id/renders` queues a render of the session's timeline
+/// and answers `202` with the job, recording the subject of `?token=` as
+/// its requester when given; `GET /sessions/:id/renders` lists the
+/// session's jobs, `GET /sessions/:id/renders/:job_id` reports one, and
+/// `GET /sessions/:id/renders/:job_id/output` downloads the finished file.
+/// `DELETE /sessions/:id/renders/:job_id` deletes a finished render.
+pub fn render_routes(
+    manager: Arc<RwLock<SessionManager>>,
+    store: Option<MediaStore>,
+    queue: Option<RenderQueue>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let submit_queue = queue.clone();
+    let submit = warp::post()
+        .and(warp::path!("sessions" / String / "renders"))
+        .and(warp::query::<TokenQuery>())
+        .and(warp::body::content_length_limit(MAX_JSON_BODY_BYTES))
+        .and(warp::body::json())
+        .and_then(
+            move |session_id: String, query: TokenQuery, target: RenderTarget| {
+                let manager = manager.clone();
+                let store = store.clone();
+                let queue = submit_queue.clone();
+                async move {
+                    let (session, authenticator) = {
+                        let manager = manager.read().await;
+                        (manager.get_session(&session_id), manager.authenticator())
+                    };
+                    let session = session.ok_or_else(warp::reject::not_found)?;
+                    // The session guard has checked the token; it's read
+                    // again here only for who asked
+                    let requested_by = match authenticator {
+                        Some(authenticator) => {
+                            match authenticator.verify(query.token.as_deref(), &session_id) {
+                                Ok(identity) => Some(identity.user_id),
+                                Err(error) => {
+                                    return Ok(error_reply(error.message, StatusCode::UNAUTHORIZED))
+                                }
+                            }
+                        }
+                        None => None,
+                    };
+                    let workspace = session
+                        .read()
+                        .await
+                        .metadata()
+                        .workspace()
+                        .map(str::to_string);
+                    let (Some(store), Some(queue)) = (store, queue) else {
+                        return Ok(error_reply(
+                            "Rendering needs a media store".to_string(),
+                            StatusCode::SERVICE_UNAVAILABLE,
+                        ));
+                    };
+                    let plan = plan(session.read().await.project(), &store, &target);
+                    let plan = match plan {
+                        Ok(plan) => plan,
+                        Err(message) => {
+                            return Ok(error_reply(message, StatusCode::UNPROCESSABLE_ENTITY))
+                        }
+                    };
+                    Ok::<_, warp::Rejection>(
+                        match queue
+                            .submit(session_id, target, plan, requested_by, workspace)
+                            .await
+                        {
+                            Ok(job) => warp::reply::with_status(
+                                warp::reply::json(&job),
+                                StatusCode::ACCEPTED,
+                            ),
+                            Err(message) => error_reply(message, StatusCode::SERVICE_UNAVAILABLE),
+                        },
+                    )
+                }
+            },
+        );
+
+    let list_queue = queue.clone();
+    let list = warp::get()
+        .and(warp::path!("sessions" / String / "renders"))
+        .and_then(move |session_id: String| {
+            let queue = list_queue.clone();
+            async move {
+                let jobs = match &queue {
+                    Some(queue) => queue.session_jobs(&session_id).await,
+                    None => Vec::new(),
+                };
+                Ok::<_, warp::Rejection>(warp::reply::json(&jobs))
+            }
+        });
+
+    let delete_queue = queue.clone();
+    let delete = warp::delete()
+        .and(warp::path!("sessions" / String / "renders" / String))
+        .and_then(move |session_id: String, job_id: String| {
+            let queue = delete_queue.clone();
+            async move {
+                let Some(queue) = queue else {
+                    return Err(warp::reject::not_found());
+                };
+                let job = queue
+                    .job(&job_id)
+                    .await
+                    .filter(|job| job.session_id == session_id)
+                    .ok_or_else(warp::reject::not_found)?;
+                if queue.remove(&job.id).await.is_none() {
+                    return Ok(error_reply(
+                        "Render has not finished".to_string(),
+                        StatusCode::CONFLICT,
+                    )
+                    .into_response());
+                }
+                println!("Deleted render {} of {}", job.id, session_id);
+                Ok(StatusCode::NO_CONTENT.into_response())
+            }
+        });
+
+    let status_queue = queue.clone();
+    let status = warp::get()
+        .and(warp::path!("sessions" / String / "renders" / String))
+        .and_then(move |session_id: String, job_id: String| {
+            let queue = status_queue.clone();
+            async move {
+                let Some(queue) = queue else {
+                    return Err(warp::reject::not_found());
+                };
+                let job = queue
+                    .job(&job_id)
+                    .await
+                    .filter(|job| job.session_id == session_id)
+                    .ok_or_else(warp::reject::not_found)?;
+                Ok(warp::reply::json(&job))
+            }
+        });
+
+    let output = warp::get()
+        .and(warp::path!(
+            "sessions" / String / "renders" / String / "output"
+        ))
+        .and_then(move |session_id: String, job_id: String| {
+            let queue = queue.clone();
+            async move {
+                let Some(queue) = queue else {
+                    return Err(warp::reject::not_found());
+                };
+                let job = queue
+                    .job(&job_id)
+                    .await
+                    .filter(|job| job.session_id == session_id)
+                    .ok_or_else(warp::reject::not_found)?;
+                let Some(path) = job.output.as_ref() else {
+                    return Ok(error_reply(
+                        "Render has not completed".to_string(),
+                        StatusCode::CONFLICT,
+                    )
+                    .into_response());
+                };
+                let bytes = tokio::fs::read(path)
+                    .await
+                    .map_err(|_| warp::reject::not_found())?;
+                let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+                Ok(warp::http::Response::builder()
+                    .header("content-type", job.content_type.as_str())
+                    .header(
+                        "content-disposition",
+                        format!("attachment; filename=\"{}\"", file_name),
+                    )
+                    .body(bytes.into())
+                    .unwrap())
+            }
+        });
+
+    submit.or(list).or(delete).or(status).or(output)
+}
+
+#[derive(Deserialize)]
+struct FrameRequest {
+    /// Timeline time of the frame.
+    time: Duration,
+}
+
+/// Renders the frame at `time`, `width` pixels wide or at full size, with
+/// `format` as ffmpeg's output options. Gives the canvas it was drawn on
+/// and the file ffmpeg wrote.
+async fn render_still(
+    project: &VideoProject,
+    store: &MediaStore,
+    ffmpeg: &Path,
+    time: Duration,
+    width: Option<u32>,
+    format: &[&str],
+    extension: &'static str,
+) -> Result<(Canvas, Vec<u8>), String> {
+    let rate = project.settings.frame_rate;
+    let range = TimeRange {
+        start: time,
+        end: time + Duration::from_secs_f64(rate.denominator as f64 / rate.numerator as f64),
+    };
+    let fps = rate.numerator.div_ceil(rate.denominator);
+    let canvas = match width {
+        Some(width) => Canvas::for_project(project, width, fps),
+        None => Canvas::full_size(project, fps),
+    };
+    let (mut args, graph) = composite(project, store, range, canvas);
+    args.extend(["-filter_complex".into(), graph.into()]);
+    args.extend(["-map", "[video]", "-frames:v", "1"].map(OsString::from));
+    args.extend(format.iter().map(OsString::from));
+    let plan = RenderPlan {
+        args,
+        range,
+        extension,
+        content_type: "application/octet-stream",
+    };
+    let output = scratch_path(plan.extension);
+    render(ffmpeg, &plan, &output).await?;
+    let bytes = tokio::fs::read(&output)
+        .await
+        .map_err(|e| format!("Could not read rendered frame: {}", e));
+    let _ = tokio::fs::remove_file(&output).await;
+    Ok((canvas, bytes?))
+}
+
+/// Renders the frame at `time` to a PNG.
+async fn export_frame(
+    project: &VideoProject,
+    store: &MediaStore,
+    ffmpeg: &Path,
+    time: Duration,
+) -> Result<Vec<u8>, String> {
+    let format = ["-c:v", "png", "-f", "image2"];
+    let (_, png) = render_still(project, store, ffmpeg, time, None, &format, "png").await?;
+    Ok(png)
+}
+
+/// The frame at `time` as packed 8-bit RGB, `width` pixels wide: the
+/// picture's width, height and pixels.
+pub(crate) async fn frame_pixels(
+    project: &VideoProject,
+    store: &MediaStore,
+    ffmpeg: &Path,
+    time: Duration,
+    width: u32,
+) -> Result<(u32, u32, Vec<u8>), String> {
+    let format = ["-pix_fmt", "rgb24", "-f", "rawvideo"];
+    let (canvas, rgb) =
+        render_still(project, store, ffmpeg, time, Some(width), &format, "rgb").await?;
+    if rgb.len() != canvas.width as usize * canvas.height as usize * 3 {
+        return Err(format!(
+            "Rendered frame is {} bytes, not {}x{} RGB",
+            rgb.len(),
+            canvas.width,
+            canvas.height
+        ));
+    }
+    Ok((canvas.width, canvas.height, rgb))
+}
+
+/// `POST /sessions/:id/export-frame` with `{"time": ...}` renders that
+/// moment of the timeline at full size, effects and all, and answers with
+/// the PNG.
+pub fn export_frame_route(
+    manager: Arc<RwLock<SessionManager>>,
+    store: Option<MediaStore>,
+    ffmpeg: PathBuf,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("sessions" / String / "export-frame"))
+        .and(warp::body::content_length_limit(MAX_JSON_BODY_BYTES))
+        .and(warp::body::json())
+        .and_then(move |session_id: String, request: FrameRequest| {
+            let manager = manager.clone();
+            let store = store.clone();
+            let ffmpeg = ffmpeg.clone();
+            async move {
+                let (session, jobs) = {
+                    let manager = manager.read().await;
+                    (manager.get_session(&session_id), manager.jobs())
+                };
+                let session = session.ok_or_else(warp::reject::not_found)?;
+                let Some(store) = store else {
+                    return Ok(error_reply(
+                        "Rendering needs a media store".to_string(),
+                        StatusCode::SERVICE_UNAVAILABLE,
+                    )
+                    .into_response());
+                };
+                let _permit = match jobs.acquire(JobClass::Preview, &session_id).await {
+                    Ok(permit) => permit,
+                    Err(message) => {
+                        return Ok(
+                            error_reply(message, StatusCode::SERVICE_UNAVAILABLE).into_response()
+                        )
+                    }
+                };
+                // Render from a snapshot so edits aren't held up meanwhile
+                let project = session.read().await.project().clone();
+                Ok::<_, warp::Rejection>(
+                    match export_frame(&project, &store, &ffmpeg, request.time).await {
+                        Ok(png) => warp::http::Response::builder()
+                            .header("content-type", "image/png")
+                            .body(png.into())
+                            .unwrap(),
+                        Err(message) => {
+                            error_reply(message, StatusCode::UNPROCESSABLE_ENTITY).into_response()
+                        }
+                    },
+                )
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{ManualClock, SystemClock};
+    use crate::jobs::JobPoolConfig;
+
+    fn job(id: &str, now: u64) -> RenderJob {
+        RenderJob {
+            id: id.to_string(),
+            session_id: "session".to_string(),
+            target: RenderTarget::Audio {
+                range: None,
+                codec: AudioCodec::Wav,
+            },
+            range: TimeRange {
+                start: Duration::ZERO,
+                end: Duration::from_secs(1),
+            },
+            requested_by: None,
+            workspace: None,
+            status: JobStatus::Queued,
+            created_at: now,
+            finished_at: None,
+            error: None,
+            output_url: None,
+            size: None,
+            output: None,
+            content_type: "audio/wav".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn finished_renders_expire_after_retention_on_the_server_clock() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_000_000));
+        let dir = std::env::temp_dir().join(format!("weframe-test-{}", uuid::Uuid::new_v4()));
+        let queue = RenderQueue::start(
+            PathBuf::from("ffmpeg"),
+            dir,
+            Arc::new(RwLock::new(SessionManager::new())),
+            JobPool::new(JobPoolConfig::default()),
+            Arc::new(clock.clone()),
+        );
+        let now = queue.now_secs();
+        queue
+            .jobs
+            .write()
+            .await
+            .insert("a".to_string(), job("a", now));
+        finish(&queue.jobs, "a", Err("failed".to_string()), now).await;
+        assert_eq!(queue.jobs.read().await["a"].finished_at, Some(1_000_000));
+
+        let retention = Duration::from_secs(3600);
+        clock.advance(Duration::from_secs(3599));
+        queue.expire(retention).await;
+        assert!(queue.jobs.read().await.contains_key("a"));
+
+        clock.advance(Duration::from_secs(2));
+        queue.expire(retention).await;
+        assert!(queue.jobs.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn renders_are_reported_only_under_their_own_session() {
+        let dir = std::env::temp_dir().join(format!("weframe-test-{}", uuid::Uuid::new_v4()));
+        let manager = Arc::new(RwLock::new(SessionManager::new()));
+        let queue = RenderQueue::start(
+            PathBuf::from("ffmpeg"),
+            dir,
+            manager.clone(),
+            JobPool::new(JobPoolConfig::default()),
+            Arc::new(SystemClock),
+        );
+        let now = queue.now_secs();
+        queue
+            .jobs
+            .write()
+            .await
+            .insert("a".to_string(), job("a", now));
+        let routes = render_routes(manager, None, Some(queue));
+
+        let own = warp::test::request()
+            .path("/sessions/session/renders/a")
+            .reply(&routes)
+            .await;
+        assert_eq!(own.status(), StatusCode::OK);
+        for path in [
+            "/sessions/other/renders/a",
+            "/sessions/other/renders/a/output",
+        ] {
+            let other = warp::test::request().path(path).reply(&routes).await;
+            assert!(other.status().is_client_error(), "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn render_output_needs_a_token_when_the_server_requires_one() {
+        let dir = std::env::temp_dir().join(format!("weframe-test-{}", uuid::Uuid::new_v4()));
+        let manager = crate::tests::with_tokens();
+        let queue = RenderQueue::start(
+            PathBuf::from("ffmpeg"),
+            dir,
+            manager.clone(),
+            JobPool::new(JobPoolConfig::default()),
+            Arc::new(SystemClock),
+        );
+        let now = queue.now_secs();
+        queue
+            .jobs
+            .write()
+            .await
+            .insert("a".to_string(), job("a", now));
+        let routes = crate::auth::session_guard(manager.clone())
+            .and(render_routes(manager, None, Some(queue)))
+            .recover(crate::auth::reject_denied);
+
+        let anonymous = warp::test::request()
+            .path("/sessions/session/renders/a/output")
+            .reply(&routes)
+            .await;
+        assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+        let signed_in = warp::test::request()
+            .path(&format!(
+                "/sessions/session/renders/a?token={}",
+                crate::tests::token("viewer")
+            ))
+            .reply(&routes)
+            .await;
+        assert_eq!(signed_in.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,17 @@
+// weframe-server/src/replies.rs
+use warp::http::StatusCode;
+
+/// Largest JSON body an HTTP route reads.
+pub const MAX_JSON_BODY_BYTES: u64 = 64 * 1024;
+
+/// A JSON `{"error": ...}` body with `status`, the shape every HTTP route
+/// reports failures in.
+pub(crate) fn error_reply(
+    message: String,
+    status: StatusCode,
+) -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({ "error": message })),
+        status,
+    )
+}
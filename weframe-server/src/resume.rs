@@ -0,0 +1,33 @@
+// weframe-server/src/resume.rs
+use crate::VideoSession;
+use weframe_shared::ServerMessage;
+
+impl VideoSession {
+    /// Sends a reconnecting client every operation committed since version
+    /// `from`, then `Resumed`. Returns false, sending nothing, when some of
+    /// them were shed from the op log or `from` is ahead of the session, e.g.
+    /// because it was restored from an older snapshot; the client then needs
+    /// the whole project.
+    pub(crate) fn resume(&self, client_id: &str, from: usize) -> bool {
+        if from < self.op_log_start || from > self.server_version {
+            return false;
+        }
+        for operation in self.op_log.iter().skip(from - self.op_log_start) {
+            self.send_to(
+                client_id,
+                &ServerMessage::ClientOperation(operation.clone()),
+            );
+        }
+        self.send_to(
+            client_id,
+            &ServerMessage::Resumed {
+                server_version: self.server_version,
+            },
+        );
+        println!(
+            "Resumed {} from version {} to {}",
+            client_id, from, self.server_version
+        );
+        true
+    }
+}
@@ -0,0 +1,150 @@
+// weframe-server/src/roles.rs
+use crate::replies::error_reply;
+use crate::VideoSession;
+use warp::http::StatusCode;
+use weframe_shared::{EditOperation, RejectionCode, Role, ServerMessage};
+
+/// Whether `role` lets whoever has it apply `op`, over a connection or
+/// through an HTTP route. Operations only the server makes are refused
+/// whatever the role.
+pub(crate) fn permits(role: Role, op: &EditOperation) -> Result<(), String> {
+    if op.server_only() {
+        return Err(format!("{} is made by the server only", op.kind()));
+    }
+    role.check(op)
+}
+
+/// Refuses `op` if it, or an operation in its batch, moves another
+/// collaborator's cursor than `client_id`'s.
+fn check_own_cursor(client_id: &str, op: &EditOperation) -> Result<(), String> {
+    match op {
+        EditOperation::UpdateCollaboratorCursor {
+            collaborator_id, ..
+        } if collaborator_id != client_id => {
+            Err(format!("Only {} can move their cursor", collaborator_id))
+        }
+        EditOperation::Batch(operations) => operations
+            .iter()
+            .try_for_each(|op| check_own_cursor(client_id, op)),
+        _ => Ok(()),
+    }
+}
+
+/// Answers an HTTP request whose caller's role doesn't allow an edit it
+/// asked for: the 403 of `RejectionCode::Forbidden`.
+pub(crate) fn forbidden(message: String) -> warp::reply::WithStatus<warp::reply::Json> {
+    error_reply(message, StatusCode::FORBIDDEN)
+}
+
+impl VideoSession {
+    /// Role of a connected client; anyone not in the session is a viewer.
+    pub fn role_of(&self, client_id: &str) -> Role {
+        self.project
+            .collaborators
+            .iter()
+            .find(|c| c.id == client_id)
+            .map_or(Role::Viewer, |c| c.role)
+    }
+
+    /// Role a joining client starts with, before it says who it is. A
+    /// session whose owner is recorded is given back to that user only, by
+    /// `claim_ownership`; until one is, the first to join a session no
+    /// connected client owns becomes its owner, everyone else an editor.
+    pub(crate) fn initial_role(&self) -> Role {
+        let owned = self.metadata.owner.is_some()
+            || self
+                .project
+                .collaborators
+                .iter()
+                .any(|c| c.role == Role::Owner && self.clients.contains_key(&c.id));
+        if owned {
+            Role::Editor
+        } else {
+            Role::Owner
+        }
+    }
+
+    /// Once a client's user is known, records it as the owner if it holds
+    /// the unclaimed session, or makes it the owner if it is the one
+    /// recorded.
+    pub(crate) fn claim_ownership(&mut self, client_id: &str) {
+        let Some(user_key) = self.clients.get(client_id).and_then(|c| c.user_key.clone()) else {
+            return;
+        };
+        match &self.metadata.owner {
+            None if self.role_of(client_id) == Role::Owner => {
+                self.metadata.owner = Some(user_key);
+                // Have the new owner persisted even if nothing else changes
+                self.persisted_version = None;
+            }
+            Some(owner) if *owner == user_key => self.set_role(client_id, Role::Owner),
+            _ => {}
+        }
+    }
+
+    /// Gives a client `role` and tells everyone.
+    pub fn set_role(&mut self, client_id: &str, role: Role) {
+        let Some(collaborator) = self
+            .project
+            .collaborators
+            .iter_mut()
+            .find(|c| c.id == client_id)
+        else {
+            return;
+        };
+        if collaborator.role == role {
+            return;
+        }
+        collaborator.role = role;
+        let message = ServerMessage::NewClient {
+            client_id: client_id.to_string(),
+            name: collaborator.name.clone(),
+            avatar_url: collaborator.avatar_url.clone(),
+            role,
+        };
+        self.broadcast_message(&message);
+    }
+
+    /// Whether the client's role lets it apply `op`, and `op` moves no
+    /// cursor but its own.
+    pub(crate) fn authorize(&self, client_id: &str, op: &EditOperation) -> Result<(), String> {
+        permits(self.role_of(client_id), op)?;
+        check_own_cursor(client_id, op)
+    }
+
+    /// Tells a client its operation was refused before being looked at, for
+    /// lack of permission or while the session is frozen: the rejection lets
+    /// it roll the operation back, the error says why. Protocol 1 clients get
+    /// the rejection as an error already.
+    pub(crate) fn refuse(
+        &self,
+        client_id: &str,
+        client_version: usize,
+        code: RejectionCode,
+        message: String,
+    ) {
+        println!("Refused operation from {}: {}", client_id, message);
+        self.send_to(
+            client_id,
+            &ServerMessage::OperationRejected {
+                client_version,
+                message: message.clone(),
+                code,
+            },
+        );
+        if self
+            .clients
+            .get(client_id)
+            .is_some_and(|c| c.protocol_version >= 2)
+        {
+            self.send_to(
+                client_id,
+                &ServerMessage::Error {
+                    client_id: client_id.to_string(),
+                    message,
+                    code: None,
+                },
+            );
+        }
+    }
+}
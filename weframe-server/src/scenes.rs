@@ -0,0 +1,214 @@
+// weframe-server/src/scenes.rs
+use crate::analysis::{asset_media_path, ffmpeg_log, log_values};
+use crate::automation::{Script, ScriptStep};
+use crate::jobs::{self, JobClass};
+use crate::media::MediaStore;
+use crate::replies::error_reply;
+use crate::{auth, SessionManager};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use warp::http::StatusCode;
+use warp::Filter;
+use weframe_shared::Role;
+
+/// ffmpeg's scene score runs from 0 to 1; around 0.3 catches hard cuts
+/// without firing on camera moves.
+const DEFAULT_THRESHOLD: f64 = 0.3;
+
+#[derive(Deserialize)]
+struct SceneQuery {
+    threshold: Option<f64>,
+}
+
+impl SceneQuery {
+    fn threshold(&self) -> f64 {
+        self.threshold
+            .filter(|t| t.is_finite())
+            .unwrap_or(DEFAULT_THRESHOLD)
+            .clamp(0.01, 1.0)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SceneReport {
+    pub asset_id: String,
+    pub threshold: f64,
+    /// Times in the asset's media where a new shot starts.
+    pub cut_points: Vec<Duration>,
+}
+
+/// Asks ffmpeg for the frames whose scene-change score exceeds `threshold`.
+pub async fn detect_scenes(
+    ffmpeg: &Path,
+    media: &Path,
+    threshold: f64,
+) -> Result<Vec<Duration>, String> {
+    let filter = format!("select='gt(scene,{})',showinfo", threshold);
+    let log = ffmpeg_log(ffmpeg, media, &["-an", "-vf", &filter]).await?;
+    let mut cuts: Vec<Duration> = log_values(&log, "pts_time:")
+        .into_iter()
+        .filter(|t| t.is_finite() && *t > 0.0)
+        .map(Duration::from_secs_f64)
+        .collect();
+    cuts.sort();
+    cuts.dedup();
+    Ok(cuts)
+}
+
+/// `GET /sessions/:id/assets/:asset_id/scenes?threshold=` lists candidate
+/// cut points in a stored asset, and
+/// `POST /sessions/:id/clips/:clip_id/split-at-scenes?threshold=` splits a
+/// clip at every scene change inside it, as one atomic batch.
+pub fn scene_routes(
+    manager: Arc<RwLock<SessionManager>>,
+    store: Option<MediaStore>,
+    ffmpeg: PathBuf,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let list_manager = manager.clone();
+    let list_store = store.clone();
+    let list_ffmpeg = ffmpeg.clone();
+    let list = warp::get()
+        .and(warp::path!(
+            "sessions" / String / "assets" / String / "scenes"
+        ))
+        .and(warp::query::<SceneQuery>())
+        .and_then(
+            move |session_id: String, asset_id: String, query: SceneQuery| {
+                let manager = list_manager.clone();
+                let store = list_store.clone();
+                let ffmpeg = list_ffmpeg.clone();
+                async move {
+                    let session = manager
+                        .read()
+                        .await
+                        .get_session(&session_id)
+                        .ok_or_else(warp::reject::not_found)?;
+                    let Some(store) = store else {
+                        return Ok(error_reply(
+                            "Scene detection needs a media store".to_string(),
+                            StatusCode::SERVICE_UNAVAILABLE,
+                        ));
+                    };
+                    let _permit =
+                        match jobs::acquire(&manager, JobClass::Analysis, &session_id).await {
+                            Ok(permit) => permit,
+                            Err(message) => {
+                                return Ok(error_reply(message, StatusCode::SERVICE_UNAVAILABLE))
+                            }
+                        };
+                    let media = {
+                        let session = session.read().await;
+                        asset_media_path(session.project(), &store, &asset_id)
+                    };
+                    let threshold = query.threshold();
+                    let result = match media {
+                        Ok(media) => detect_scenes(&ffmpeg, &media, threshold).await,
+                        Err(message) => Err(message),
+                    };
+                    Ok::<_, warp::Rejection>(match result {
+                        Ok(cut_points) => warp::reply::with_status(
+                            warp::reply::json(&SceneReport {
+                                asset_id,
+                                threshold,
+                                cut_points,
+                            }),
+                            StatusCode::OK,
+                        ),
+                        Err(message) => error_reply(message, StatusCode::UNPROCESSABLE_ENTITY),
+                    })
+                }
+            },
+        );
+
+    let split = warp::post()
+        .and(warp::path!(
+            "sessions" / String / "clips" / String / "split-at-scenes"
+        ))
+        .and(warp::query::<SceneQuery>())
+        .and(auth::request_role(manager.clone()))
+        .and_then(
+            move |session_id: String, clip_id: String, query: SceneQuery, role: Role| {
+                let manager = manager.clone();
+                let store = store.clone();
+                let ffmpeg = ffmpeg.clone();
+                async move {
+                    let session = manager
+                        .read()
+                        .await
+                        .get_session(&session_id)
+                        .ok_or_else(warp::reject::not_found)?;
+                    let Some(store) = store else {
+                        return Ok(error_reply(
+                            "Scene detection needs a media store".to_string(),
+                            StatusCode::SERVICE_UNAVAILABLE,
+                        ));
+                    };
+                    let _permit =
+                        match jobs::acquire(&manager, JobClass::Analysis, &session_id).await {
+                            Ok(permit) => permit,
+                            Err(message) => {
+                                return Ok(error_reply(message, StatusCode::SERVICE_UNAVAILABLE))
+                            }
+                        };
+                    let media = {
+                        let session = session.read().await;
+                        let project = session.project();
+                        project
+                            .clips
+                            .iter()
+                            .find(|c| c.id == clip_id)
+                            .ok_or_else(|| format!("Clip {} not found", clip_id))
+                            .and_then(|clip| {
+                                let asset_id = clip.asset_id.as_deref().ok_or_else(|| {
+                                    format!("Clip {} does not play a project asset", clip_id)
+                                })?;
+                                asset_media_path(project, &store, asset_id)
+                            })
+                    };
+                    let cuts = match media {
+                        Ok(media) => detect_scenes(&ffmpeg, &media, query.threshold()).await,
+                        Err(message) => Err(message),
+                    };
+                    let cuts = match cuts {
+                        Ok(cuts) => cuts,
+                        Err(message) => {
+                            return Ok(error_reply(message, StatusCode::UNPROCESSABLE_ENTITY))
+                        }
+                    };
+
+                    // The clip may have moved while ffmpeg ran, so map media
+                    // times onto the timeline only now
+                    let mut session = session.write().await;
+                    let Some(clip) = session.project().clips.iter().find(|c| c.id == clip_id)
+                    else {
+                        return Ok(error_reply(
+                            format!("Clip {} not found", clip_id),
+                            StatusCode::CONFLICT,
+                        ));
+                    };
+                    let at = cuts
+                        .into_iter()
+                        .filter(|cut| *cut >= clip.source_start)
+                        .filter_map(|cut| clip.timeline_time_at(cut))
+                        .collect();
+                    let script = Script {
+                        steps: vec![ScriptStep::SplitClip { clip_id, at }],
+                    };
+                    Ok::<_, warp::Rejection>(match session.run_script(role, &script) {
+                        Ok(report) => {
+                            warp::reply::with_status(warp::reply::json(&report), StatusCode::OK)
+                        }
+                        Err(error) => {
+                            let status = error.status();
+                            warp::reply::with_status(warp::reply::json(&error), status)
+                        }
+                    })
+                }
+            },
+        );
+
+    list.or(split)
+}
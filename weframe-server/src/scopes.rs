@@ -0,0 +1,180 @@
+// weframe-server/src/scopes.rs
+use crate::jobs::{self, JobClass};
+use crate::media::MediaStore;
+use crate::render::frame_pixels;
+use crate::replies::{error_reply, MAX_JSON_BODY_BYTES};
+use crate::SessionManager;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use warp::http::StatusCode;
+use warp::Filter;
+
+/// Width frames are rendered at for scopes. Scopes show distributions, so
+/// a small frame reads the same as a full-size one at a fraction of the
+/// cost.
+const SCOPE_WIDTH: u32 = 256;
+
+/// Brightness levels each waveform column is divided into.
+const WAVEFORM_LEVELS: usize = 128;
+
+/// Cells along each side of the vectorscope grid.
+const VECTORSCOPE_SIZE: usize = 64;
+
+#[derive(Deserialize)]
+struct ScopeRequest {
+    /// Timeline time of the frame.
+    time: Duration,
+}
+
+/// Pixel counts for each 8-bit level, 0 to 255.
+#[derive(Debug, Serialize)]
+pub struct Histogram {
+    pub red: Vec<u32>,
+    pub green: Vec<u32>,
+    pub blue: Vec<u32>,
+    /// Rec. 709 luma.
+    pub luma: Vec<u32>,
+}
+
+/// Scopes of one frame of the composited timeline, for grading UIs.
+#[derive(Debug, Serialize)]
+pub struct Scopes {
+    /// Size of the frame the scopes were read from.
+    pub width: u32,
+    pub height: u32,
+    pub histogram: Histogram,
+    /// One entry per column of the frame, left to right, counting its
+    /// pixels at each of `WAVEFORM_LEVELS` luma levels, darkest first.
+    pub waveform: Vec<Vec<u32>>,
+    /// Pixel counts on a square grid over the Cb/Cr plane, one row per
+    /// band of Cr from most red at the top, and columns running from most
+    /// yellow to most blue. Neutral pixels land in the middle.
+    pub vectorscope: Vec<Vec<u32>>,
+}
+
+impl Scopes {
+    /// Reads the scopes of a `width` by `height` frame of packed 8-bit RGB.
+    pub fn measure(width: u32, height: u32, rgb: &[u8]) -> Scopes {
+        let mut histogram = Histogram {
+            red: vec![0; 256],
+            green: vec![0; 256],
+            blue: vec![0; 256],
+            luma: vec![0; 256],
+        };
+        let mut waveform = vec![vec![0; WAVEFORM_LEVELS]; width as usize];
+        let mut vectorscope = vec![vec![0; VECTORSCOPE_SIZE]; VECTORSCOPE_SIZE];
+        // Position of a chroma value, from -127.5 to 127.5, on the grid
+        let cell = |chroma: f64| {
+            let cell = ((chroma / 255.0 + 0.5) * VECTORSCOPE_SIZE as f64) as usize;
+            cell.min(VECTORSCOPE_SIZE - 1)
+        };
+        for (i, pixel) in rgb.chunks_exact(3).enumerate() {
+            let [r, g, b] = [pixel[0], pixel[1], pixel[2]];
+            histogram.red[r as usize] += 1;
+            histogram.green[g as usize] += 1;
+            histogram.blue[b as usize] += 1;
+            let (r, g, b) = (r as f64, g as f64, b as f64);
+            let luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+            let level = (luma.round() as usize).min(255);
+            histogram.luma[level] += 1;
+            if let Some(column) = waveform.get_mut(i % width.max(1) as usize) {
+                column[level * WAVEFORM_LEVELS / 256] += 1;
+            }
+            let cb = -0.1146 * r - 0.3854 * g + 0.5 * b;
+            let cr = 0.5 * r - 0.4542 * g - 0.0458 * b;
+            vectorscope[VECTORSCOPE_SIZE - 1 - cell(cr)][cell(cb)] += 1;
+        }
+        Scopes {
+            width,
+            height,
+            histogram,
+            waveform,
+            vectorscope,
+        }
+    }
+}
+
+/// `POST /sessions/:id/scopes` with `{"time": ...}` renders that moment of
+/// the timeline as `export-frame` does, only small, and answers with its
+/// histogram, waveform and vectorscope, so grading UIs needn't decode
+/// full-size frames to show them.
+pub fn scopes_route(
+    manager: Arc<RwLock<SessionManager>>,
+    store: Option<MediaStore>,
+    ffmpeg: PathBuf,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("sessions" / String / "scopes"))
+        .and(warp::body::content_length_limit(MAX_JSON_BODY_BYTES))
+        .and(warp::body::json())
+        .and_then(move |session_id: String, request: ScopeRequest| {
+            let manager = manager.clone();
+            let store = store.clone();
+            let ffmpeg = ffmpeg.clone();
+            async move {
+                let session = manager.read().await.get_session(&session_id);
+                let session = session.ok_or_else(warp::reject::not_found)?;
+                let Some(store) = store else {
+                    return Ok(error_reply(
+                        "Scopes need a media store".to_string(),
+                        StatusCode::SERVICE_UNAVAILABLE,
+                    ));
+                };
+                let _permit = match jobs::acquire(&manager, JobClass::Preview, &session_id).await {
+                    Ok(permit) => permit,
+                    Err(message) => {
+                        return Ok(error_reply(message, StatusCode::SERVICE_UNAVAILABLE))
+                    }
+                };
+                // Render from a snapshot so edits aren't held up meanwhile
+                let project = session.read().await.project().clone();
+                let frame =
+                    frame_pixels(&project, &store, &ffmpeg, request.time, SCOPE_WIDTH).await;
+                Ok::<_, warp::Rejection>(match frame {
+                    Ok((width, height, rgb)) => warp::reply::with_status(
+                        warp::reply::json(&Scopes::measure(width, height, &rgb)),
+                        StatusCode::OK,
+                    ),
+                    Err(message) => error_reply(message, StatusCode::UNPROCESSABLE_ENTITY),
+                })
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_a_black_and_white_frame() {
+        // Two columns: black on the left, white on the right
+        let rgb = [0, 0, 0, 255, 255, 255, 0, 0, 0, 255, 255, 255];
+        let scopes = Scopes::measure(2, 2, &rgb);
+        assert_eq!(scopes.histogram.luma[0], 2);
+        assert_eq!(scopes.histogram.luma[255], 2);
+        assert_eq!(scopes.histogram.red[255], 2);
+        assert_eq!(scopes.waveform.len(), 2);
+        assert_eq!(scopes.waveform[0][0], 2);
+        assert_eq!(scopes.waveform[1][WAVEFORM_LEVELS - 1], 2);
+        // Neutral pixels all land in the middle of the vectorscope
+        let middle = VECTORSCOPE_SIZE / 2;
+        assert_eq!(scopes.vectorscope[middle - 1][middle], 4);
+    }
+
+    #[test]
+    fn saturated_colours_land_away_from_the_middle() {
+        let scopes = Scopes::measure(1, 1, &[255, 0, 0]);
+        let (row, column) = scopes
+            .vectorscope
+            .iter()
+            .enumerate()
+            .find_map(|(row, cells)| cells.iter().position(|&n| n == 1).map(|c| (row, c)))
+            .unwrap();
+        // Red is high in Cr, so near the top, and low in Cb, towards yellow
+        assert!(row < VECTORSCOPE_SIZE / 4);
+        assert!(column < VECTORSCOPE_SIZE / 2);
+    }
+}
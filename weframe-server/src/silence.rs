@@ -0,0 +1,233 @@
+// weframe-server/src/silence.rs
+use crate::analysis::{asset_media_path, ffmpeg_log, log_values};
+use crate::automation::{Script, ScriptStep, TimeRange};
+use crate::jobs::{self, JobClass};
+use crate::media::MediaStore;
+use crate::replies::error_reply;
+use crate::{auth, SessionManager, VideoSession};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use warp::http::StatusCode;
+use warp::Filter;
+use weframe_shared::{Role, VideoClip};
+
+const DEFAULT_NOISE_DB: f64 = -35.0;
+const DEFAULT_MIN_SILENCE: f64 = 0.5;
+
+#[derive(Deserialize)]
+struct SilenceQuery {
+    /// Level below which audio counts as silent, in dBFS.
+    noise_db: Option<f64>,
+    /// Shortest pause worth removing, in seconds.
+    min_duration: Option<f64>,
+}
+
+impl SilenceQuery {
+    fn filter(&self) -> String {
+        let noise_db = self
+            .noise_db
+            .filter(|db| db.is_finite())
+            .unwrap_or(DEFAULT_NOISE_DB)
+            .clamp(-90.0, 0.0);
+        let min_duration = self
+            .min_duration
+            .filter(|d| d.is_finite())
+            .unwrap_or(DEFAULT_MIN_SILENCE)
+            .clamp(0.05, 60.0);
+        format!("silencedetect=noise={}dB:d={}", noise_db, min_duration)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SilenceReport {
+    pub clip_id: String,
+    /// Silent stretches of the clip, in timeline time.
+    pub ranges: Vec<TimeRange>,
+}
+
+/// Silent stretches of a file's audio, in media seconds. A silence still
+/// running when the file ends is open-ended.
+pub async fn detect_silence(
+    ffmpeg: &Path,
+    media: &Path,
+    filter: &str,
+) -> Result<Vec<(f64, f64)>, String> {
+    let log = ffmpeg_log(ffmpeg, media, &["-vn", "-af", filter]).await?;
+    let starts = log_values(&log, "silence_start:");
+    let ends = log_values(&log, "silence_end:");
+    Ok(starts
+        .into_iter()
+        .enumerate()
+        .map(|(i, start)| {
+            (
+                start.max(0.0),
+                ends.get(i).copied().unwrap_or(f64::INFINITY),
+            )
+        })
+        .collect())
+}
+
+/// Maps silent media ranges onto the part of the timeline `clip` covers.
+fn timeline_ranges(clip: &VideoClip, silences: &[(f64, f64)]) -> Vec<TimeRange> {
+    let (source_start, source_end) = clip.source_range();
+    silences
+        .iter()
+        .filter_map(|&(start, end)| {
+            let start = Duration::from_secs_f64(start).max(source_start);
+            let end = if end.is_finite() {
+                Duration::from_secs_f64(end).min(source_end)
+            } else {
+                source_end
+            };
+            (start < end).then(|| TimeRange {
+                start: clip.timeline_time_at(start).unwrap_or(clip.start_time),
+                end: clip.timeline_time_at(end).unwrap_or(clip.start_time),
+            })
+        })
+        .collect()
+}
+
+/// Runs silence detection on the media behind a clip.
+async fn clip_silences(
+    session: &RwLock<VideoSession>,
+    store: Option<MediaStore>,
+    ffmpeg: &Path,
+    clip_id: &str,
+    query: &SilenceQuery,
+) -> Result<Vec<(f64, f64)>, String> {
+    let store = store.ok_or("Silence detection needs a media store")?;
+    let media = {
+        let session = session.read().await;
+        let project = session.project();
+        let clip = project
+            .clips
+            .iter()
+            .find(|c| c.id == clip_id)
+            .ok_or_else(|| format!("Clip {} not found", clip_id))?;
+        let asset_id = clip
+            .asset_id
+            .as_deref()
+            .ok_or_else(|| format!("Clip {} does not play a project asset", clip_id))?;
+        asset_media_path(project, &store, asset_id)?
+    };
+    detect_silence(ffmpeg, &media, &query.filter()).await
+}
+
+/// `GET /sessions/:id/clips/:clip_id/silence?noise_db=&min_duration=`
+/// proposes the silent stretches of a clip, and
+/// `POST /sessions/:id/clips/:clip_id/remove-silence` with the same query
+/// cuts them out as one atomic batch.
+pub fn silence_routes(
+    manager: Arc<RwLock<SessionManager>>,
+    store: Option<MediaStore>,
+    ffmpeg: PathBuf,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let propose_manager = manager.clone();
+    let propose_store = store.clone();
+    let propose_ffmpeg = ffmpeg.clone();
+    let propose = warp::get()
+        .and(warp::path!(
+            "sessions" / String / "clips" / String / "silence"
+        ))
+        .and(warp::query::<SilenceQuery>())
+        .and_then(
+            move |session_id: String, clip_id: String, query: SilenceQuery| {
+                let manager = propose_manager.clone();
+                let store = propose_store.clone();
+                let ffmpeg = propose_ffmpeg.clone();
+                async move {
+                    let Some(session) = manager.read().await.get_session(&session_id) else {
+                        return Err(warp::reject::not_found());
+                    };
+                    let _permit =
+                        match jobs::acquire(&manager, JobClass::Analysis, &session_id).await {
+                            Ok(permit) => permit,
+                            Err(message) => {
+                                return Ok(error_reply(message, StatusCode::SERVICE_UNAVAILABLE))
+                            }
+                        };
+                    let silences =
+                        match clip_silences(&session, store, &ffmpeg, &clip_id, &query).await {
+                            Ok(silences) => silences,
+                            Err(message) => {
+                                return Ok(error_reply(message, StatusCode::UNPROCESSABLE_ENTITY))
+                            }
+                        };
+                    let session = session.read().await;
+                    let clip = session.project().clips.iter().find(|c| c.id == clip_id);
+                    let Some(ranges) = clip.map(|clip| timeline_ranges(clip, &silences)) else {
+                        return Ok(error_reply(
+                            format!("Clip {} not found", clip_id),
+                            StatusCode::CONFLICT,
+                        ));
+                    };
+                    Ok(warp::reply::with_status(
+                        warp::reply::json(&SilenceReport { clip_id, ranges }),
+                        StatusCode::OK,
+                    ))
+                }
+            },
+        );
+
+    let remove = warp::post()
+        .and(warp::path!(
+            "sessions" / String / "clips" / String / "remove-silence"
+        ))
+        .and(warp::query::<SilenceQuery>())
+        .and(auth::request_role(manager.clone()))
+        .and_then(
+            move |session_id: String, clip_id: String, query: SilenceQuery, role: Role| {
+                let manager = manager.clone();
+                let store = store.clone();
+                let ffmpeg = ffmpeg.clone();
+                async move {
+                    let Some(session) = manager.read().await.get_session(&session_id) else {
+                        return Err(warp::reject::not_found());
+                    };
+                    let _permit =
+                        match jobs::acquire(&manager, JobClass::Analysis, &session_id).await {
+                            Ok(permit) => permit,
+                            Err(message) => {
+                                return Ok(error_reply(message, StatusCode::SERVICE_UNAVAILABLE))
+                            }
+                        };
+                    let silences =
+                        match clip_silences(&session, store, &ffmpeg, &clip_id, &query).await {
+                            Ok(silences) => silences,
+                            Err(message) => {
+                                return Ok(error_reply(message, StatusCode::UNPROCESSABLE_ENTITY))
+                            }
+                        };
+
+                    // Map onto the timeline only now, in case the clip moved
+                    // while ffmpeg ran
+                    let mut session = session.write().await;
+                    let Some(clip) = session.project().clips.iter().find(|c| c.id == clip_id)
+                    else {
+                        return Ok(error_reply(
+                            format!("Clip {} not found", clip_id),
+                            StatusCode::CONFLICT,
+                        ));
+                    };
+                    let ranges = timeline_ranges(clip, &silences);
+                    let script = Script {
+                        steps: vec![ScriptStep::RemoveRanges { clip_id, ranges }],
+                    };
+                    Ok(match session.run_script(role, &script) {
+                        Ok(report) => {
+                            warp::reply::with_status(warp::reply::json(&report), StatusCode::OK)
+                        }
+                        Err(error) => {
+                            let status = error.status();
+                            warp::reply::with_status(warp::reply::json(&error), status)
+                        }
+                    })
+                }
+            },
+        );
+
+    propose.or(remove)
+}
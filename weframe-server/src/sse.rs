@@ -0,0 +1,236 @@
+// weframe-server/src/sse.rs
+use crate::auth::TokenQuery;
+use crate::outbox::{self, Outbox, Priority};
+use crate::{
+    handle_client_message, workspaces, write_session, Inbound, SessionManager, VideoSession,
+};
+use futures::{stream, Stream, StreamExt};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use tokio::sync::RwLock;
+use warp::http::StatusCode;
+use warp::sse::Event;
+use warp::ws::Message;
+use warp::Filter;
+use weframe_shared::{ServerMessage, TrafficStats};
+
+/// Largest message a client may POST, in line with WebSocket frame limits.
+const MAX_MESSAGE_BYTES: u64 = 16 * 1024 * 1024;
+
+impl VideoSession {
+    /// Id of the event-stream client holding `token`.
+    fn event_stream_client(&self, token: &str) -> Option<String> {
+        self.clients
+            .iter()
+            .find(|(_, client)| client.post_token.as_deref() == Some(token))
+            .map(|(id, _)| id.clone())
+    }
+}
+
+/// Removes the client when its event stream is dropped, which is the only
+/// sign that an event-stream client has gone.
+struct Departure {
+    session: Arc<RwLock<VideoSession>>,
+    client_id: String,
+}
+
+impl Drop for Departure {
+    fn drop(&mut self) {
+        let session = self.session.clone();
+        let client_id = std::mem::take(&mut self.client_id);
+        tokio::spawn(async move {
+            let mut session = write_session(&session).await;
+            session.remove_client(&client_id);
+            session.broadcast_message(&ServerMessage::ClientDisconnected(client_id));
+        });
+    }
+}
+
+/// A `connection` event carrying the token to POST under, then every message
+/// queued for the client as a `message` event, until the connection closes.
+fn event_stream(
+    token: String,
+    receiver: Outbox,
+    traffic: Arc<Mutex<TrafficStats>>,
+    departure: Departure,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    let connected = Event::default().event("connection").data(token);
+    let messages = stream::unfold(
+        (receiver, traffic, departure),
+        |(mut receiver, traffic, departure)| async move {
+            loop {
+                let message = receiver.recv().await?;
+                if message.is_close() {
+                    return None;
+                }
+                // Binary frames can't be sent as events; `Hello`s from
+                // event-stream clients never negotiate them.
+                let Ok(text) = message.to_str() else {
+                    continue;
+                };
+                traffic.lock().unwrap().record_sent(text.len());
+                let event = Event::default().data(text);
+                return Some((Ok(event), (receiver, traffic, departure)));
+            }
+        },
+    );
+    stream::once(async move { Ok(connected) }).chain(messages)
+}
+
+/// Drops features that need binary frames from a `Hello`, leaving any other
+/// message as it is.
+fn text_only(text: &str) -> String {
+    match serde_json::from_str::<ServerMessage>(text) {
+        Ok(ServerMessage::Hello {
+            protocol_version,
+            mut features,
+            guest_token,
+            avatar_url,
+            resume_from,
+        }) => {
+            features.retain(|f| f != "zstd");
+            serde_json::to_string(&ServerMessage::Hello {
+                protocol_version,
+                features,
+                guest_token,
+                avatar_url,
+                resume_from,
+            })
+            .unwrap()
+        }
+        _ => text.to_string(),
+    }
+}
+
+/// Fallback transport for networks that block WebSockets. `GET /sse/:id`,
+/// with `?token=` when the server requires one, joins a session and
+/// streams what a WebSocket would receive as server-sent events, starting
+/// with a `connection` event holding a token; `POST /sse/:id/:token`
+/// submits one message the client would otherwise send over the socket.
+/// Replies arrive on the event stream.
+pub fn sse_routes(
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let connect_manager = manager.clone();
+    let connect = warp::get()
+        .and(warp::path!("sse" / String))
+        .and(warp::query::<TokenQuery>())
+        .and_then(move |session_id: String, query: TokenQuery| {
+            let manager = connect_manager.clone();
+            async move {
+                let (authenticator, client_id) = {
+                    let manager = manager.read().await;
+                    let client_id = format!("user-{}", manager.config.ids.number() as u32);
+                    (manager.authenticator(), client_id)
+                };
+                let identity = match authenticator {
+                    Some(authenticator) => {
+                        match authenticator.verify(query.token.as_deref(), &session_id) {
+                            Ok(identity) => Some(identity),
+                            Err(error) => {
+                                return Ok(Box::new(warp::reply::with_status(
+                                    warp::reply::json(&error.into_message()),
+                                    StatusCode::UNAUTHORIZED,
+                                ))
+                                    as Box<dyn warp::Reply>)
+                            }
+                        }
+                    }
+                    None => None,
+                };
+
+                let (sender, receiver) = outbox::outbox();
+                let token = uuid::Uuid::new_v4().to_string();
+                let traffic = Arc::new(Mutex::new(TrafficStats::default()));
+
+                let workspace = identity.as_ref().and_then(|i| i.workspace.clone());
+                let session =
+                    workspaces::join_session(&manager, &session_id, workspace.as_deref()).await;
+                let session = match session {
+                    Ok(session) => session,
+                    Err(error) => {
+                        return Ok(Box::new(warp::reply::with_status(
+                            warp::reply::json(&error.into_message()),
+                            StatusCode::FORBIDDEN,
+                        )) as Box<dyn warp::Reply>)
+                    }
+                };
+                {
+                    let mut session = write_session(&session).await;
+                    let name = session.add_client(client_id.clone(), sender, traffic.clone());
+                    if let Some(client) = session.clients.get_mut(&client_id) {
+                        client.post_token = Some(token.clone());
+                    }
+                    let role = session.role_of(&client_id);
+                    session.broadcast_message(&ServerMessage::NewClient {
+                        client_id: client_id.clone(),
+                        name,
+                        avatar_url: None,
+                        role,
+                    });
+                    if let Some(identity) = identity {
+                        session.sign_in(&client_id, identity);
+                    }
+                }
+
+                let departure = Departure { session, client_id };
+                let stream = event_stream(token, receiver, traffic, departure);
+                Ok::<_, warp::Rejection>(Box::new(warp::sse::reply(
+                    warp::sse::keep_alive().stream(stream),
+                )) as Box<dyn warp::Reply>)
+            }
+        });
+
+    let post = warp::post()
+        .and(warp::path!("sse" / String / String))
+        .and(warp::body::content_length_limit(MAX_MESSAGE_BYTES))
+        .and(warp::body::bytes())
+        .and_then(
+            move |session_id: String, token: String, body: warp::hyper::body::Bytes| {
+                let manager = manager.clone();
+                async move {
+                    let session = manager
+                        .read()
+                        .await
+                        .get_session(&session_id)
+                        .ok_or_else(warp::reject::not_found)?;
+                    let Ok(text) = std::str::from_utf8(&body) else {
+                        return Ok(StatusCode::BAD_REQUEST);
+                    };
+                    let (client_id, sender, traffic) = {
+                        let session = session.read().await;
+                        let client_id = session
+                            .event_stream_client(&token)
+                            .ok_or_else(warp::reject::not_found)?;
+                        let client = &session.clients[&client_id];
+                        (client_id, client.sender.clone(), client.traffic.clone())
+                    };
+                    traffic.lock().unwrap().record_received(body.len());
+
+                    match handle_client_message(&session, &client_id, &text_only(text)).await {
+                        Inbound::Handled => {}
+                        Inbound::Reply(reply) => {
+                            sender
+                                .send(
+                                    Message::text(serde_json::to_string(&reply).unwrap()),
+                                    Priority::Normal,
+                                )
+                                .ok();
+                        }
+                        Inbound::Close(reply) => {
+                            sender
+                                .send(
+                                    Message::text(serde_json::to_string(&reply).unwrap()),
+                                    Priority::Normal,
+                                )
+                                .ok();
+                            sender.send(Message::close(), Priority::Normal).ok();
+                        }
+                    }
+                    Ok::<_, warp::Rejection>(StatusCode::NO_CONTENT)
+                }
+            },
+        );
+
+    connect.or(post)
+}
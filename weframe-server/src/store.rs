@@ -0,0 +1,190 @@
+// weframe-server/src/store.rs
+use crate::hibernation::{HibernationStore, Snapshot};
+use crate::{ServerConfig, SessionManager, VideoSession};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use weframe_shared::{EditOperation, OTOperation};
+
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send + 'a>>;
+
+/// An operation read back from a store's log.
+#[derive(Debug, Clone)]
+pub struct RecordedOperation {
+    pub server_version: usize,
+    pub operation: EditOperation,
+    pub label: Option<String>,
+}
+
+/// Durable home for session projects, so they outlive the process. Unlike
+/// hibernation, a snapshot stays in the store after it is loaded and is
+/// overwritten as the session changes.
+pub trait ProjectStore: Send + Sync {
+    /// The latest snapshot of a session. `Ok(None)` if there is none.
+    fn load<'a>(&'a self, session_id: &'a str) -> StoreFuture<'a, Option<Snapshot>>;
+    fn save<'a>(&'a self, session_id: &'a str, snapshot: &'a Snapshot) -> StoreFuture<'a, ()>;
+    fn remove<'a>(&'a self, session_id: &'a str) -> StoreFuture<'a, ()>;
+    /// Ids of every session with a snapshot in the store.
+    fn session_ids(&self) -> StoreFuture<'_, Vec<String>>;
+
+    /// Records operations applied since the last snapshot, the first of them
+    /// at server version `first_version`. Operations already recorded are
+    /// skipped. Stores without an operation log ignore them.
+    fn append_operations<'a>(
+        &'a self,
+        _session_id: &'a str,
+        _first_version: usize,
+        _operations: &'a [OTOperation],
+    ) -> StoreFuture<'a, ()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// The newest snapshot taken at or before `server_version`, to replay
+    /// history from. Stores that keep only the latest snapshot have one only
+    /// for versions it doesn't postdate.
+    fn snapshot_at<'a>(
+        &'a self,
+        session_id: &'a str,
+        server_version: usize,
+    ) -> StoreFuture<'a, Option<Snapshot>> {
+        Box::pin(async move {
+            Ok(self
+                .load(session_id)
+                .await?
+                .filter(|snapshot| snapshot.server_version <= server_version))
+        })
+    }
+
+    /// Recorded operations with server versions in `from..to`, oldest first.
+    /// Stores without an operation log have none.
+    fn operations<'a>(
+        &'a self,
+        _session_id: &'a str,
+        _from: usize,
+        _to: usize,
+    ) -> StoreFuture<'a, Vec<RecordedOperation>> {
+        Box::pin(async { Ok(Vec::new()) })
+    }
+}
+
+/// One JSON file per session, in the same format as hibernated sessions.
+impl ProjectStore for HibernationStore {
+    fn load<'a>(&'a self, session_id: &'a str) -> StoreFuture<'a, Option<Snapshot>> {
+        Box::pin(HibernationStore::load(self, session_id))
+    }
+
+    fn save<'a>(&'a self, session_id: &'a str, snapshot: &'a Snapshot) -> StoreFuture<'a, ()> {
+        Box::pin(self.write(session_id, snapshot))
+    }
+
+    fn remove<'a>(&'a self, session_id: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(HibernationStore::remove(self, session_id))
+    }
+
+    fn session_ids(&self) -> StoreFuture<'_, Vec<String>> {
+        Box::pin(async {
+            let snapshots = self.list().await?;
+            Ok(snapshots.into_iter().map(|s| s.session_id).collect())
+        })
+    }
+}
+
+/// The configured project store: a database when the `database` feature is
+/// on and `project_database_url` is set, otherwise files in `project_dir`.
+pub(crate) fn from_config(config: &ServerConfig) -> Option<Arc<dyn ProjectStore>> {
+    #[cfg(feature = "database")]
+    if let Some(url) = &config.project_database_url {
+        match crate::database::DatabaseStore::connect(url, config.clock.clone()) {
+            Ok(store) => return Some(Arc::new(store)),
+            Err(e) => eprintln!("Failed to set up project database: {}", e),
+        }
+    }
+    config.project_dir.clone().map(|dir| {
+        Arc::new(HibernationStore::new(dir, config.clock.clone())) as Arc<dyn ProjectStore>
+    })
+}
+
+/// A session's changes since it was last persisted.
+pub(crate) struct Unsaved {
+    snapshot: Snapshot,
+    first_version: usize,
+    operations: Vec<OTOperation>,
+}
+
+impl VideoSession {
+    /// What changed since the session was last persisted, or `None` if
+    /// nothing did. Operations shed under memory pressure are missing.
+    pub(crate) fn unsaved(&self) -> Option<Unsaved> {
+        if self.persisted_version == Some(self.server_version) {
+            return None;
+        }
+        let from = self
+            .persisted_version
+            .unwrap_or(self.op_log_start)
+            .max(self.op_log_start);
+        Some(Unsaved {
+            snapshot: self.snapshot(),
+            first_version: from,
+            operations: self
+                .op_log
+                .iter()
+                .skip(from - self.op_log_start)
+                .cloned()
+                .collect(),
+        })
+    }
+}
+
+impl Unsaved {
+    pub(crate) fn server_version(&self) -> usize {
+        self.snapshot.server_version
+    }
+
+    /// Writes the operations, then the snapshot they lead up to.
+    pub(crate) async fn write(&self, store: &dyn ProjectStore, session_id: &str) -> io::Result<()> {
+        if !self.operations.is_empty() {
+            store
+                .append_operations(session_id, self.first_version, &self.operations)
+                .await?;
+        }
+        store.save(session_id, &self.snapshot).await
+    }
+}
+
+/// Writes every live session that changed since its last snapshot to the
+/// project store, if one is configured, and drops any hibernated snapshot
+/// it was rehydrated from, which is now older. Sessions are read one at a
+/// time and never locked during I/O.
+pub async fn persist_sessions(manager: &RwLock<SessionManager>) {
+    let (store, hibernation, sessions) = {
+        let manager = manager.read().await;
+        let Some(store) = manager.projects.clone() else {
+            return;
+        };
+        let sessions: Vec<(String, Arc<RwLock<VideoSession>>)> = manager
+            .sessions()
+            .map(|(id, session)| (id.clone(), session.clone()))
+            .collect();
+        (store, manager.hibernation.clone(), sessions)
+    };
+    for (id, session) in sessions {
+        let Some(unsaved) = session.read().await.unsaved() else {
+            continue;
+        };
+        match unsaved.write(store.as_ref(), &id).await {
+            Ok(()) => {
+                let mut session = session.write().await;
+                session.persisted_version = Some(unsaved.server_version());
+                drop(session);
+                if let Some(hibernation) = &hibernation {
+                    if let Err(e) = hibernation.remove(&id).await {
+                        eprintln!("Failed to remove hibernated session {}: {}", id, e);
+                    }
+                }
+            }
+            Err(e) => eprintln!("Failed to persist session {}: {}", id, e),
+        }
+    }
+}
@@ -0,0 +1,453 @@
+// weframe-server/src/transcription.rs
+use crate::analysis::{asset_media_path, extract_audio, scratch_path};
+use crate::jobs::{self, JobClass};
+use crate::media::MediaStore;
+use crate::replies::{error_reply, MAX_JSON_BODY_BYTES};
+use crate::{auth, freeze, roles, SessionManager};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use warp::http::StatusCode;
+use warp::hyper::{self, Body, Client, Request};
+use warp::Filter;
+use weframe_shared::{EditOperation, JobKind, Role, SubtitleCue, SubtitleTrack, SubtitleWord};
+
+/// Sample rate of the audio handed to backends; what Whisper models expect.
+const SPEECH_SAMPLE_RATE: u32 = 16_000;
+/// Cues per `AddSubtitleCues` operation when delivering a transcript.
+const CUE_BATCH: usize = 200;
+
+/// What a backend heard in a recording. Times are seconds into the audio.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Transcript {
+    #[serde(default)]
+    pub language: Option<String>,
+    pub segments: Vec<TranscriptSegment>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscriptSegment {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+    #[serde(default)]
+    pub words: Vec<TranscriptWord>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscriptWord {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+    #[serde(default)]
+    pub confidence: Option<f64>,
+}
+
+pub type TranscriptFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Transcript, String>> + Send + 'a>>;
+
+/// A speech recognizer. It is given 16 kHz mono 16-bit WAV audio and an
+/// optional language hint, and should report word timings where it can.
+pub trait TranscriptionBackend: Send + Sync {
+    fn transcribe<'a>(&'a self, audio: &'a Path, language: Option<&'a str>)
+        -> TranscriptFuture<'a>;
+}
+
+/// Which backend a deployment uses, from `WEFRAME_TRANSCRIPTION_URL` or
+/// `WEFRAME_WHISPER_MODEL`.
+#[derive(Debug, Clone)]
+pub enum TranscriptionConfig {
+    /// A local whisper.cpp build.
+    Whisper { binary: PathBuf, model: PathBuf },
+    /// A service that takes the WAV as a POST body and answers with a
+    /// `Transcript` as JSON.
+    Http { url: String },
+}
+
+impl TranscriptionConfig {
+    pub fn backend(&self) -> Arc<dyn TranscriptionBackend> {
+        match self {
+            TranscriptionConfig::Whisper { binary, model } => Arc::new(WhisperCli {
+                binary: binary.clone(),
+                model: model.clone(),
+            }),
+            TranscriptionConfig::Http { url } => Arc::new(HttpTranscriber { url: url.clone() }),
+        }
+    }
+}
+
+/// Runs whisper.cpp's command line tool and reads its full JSON output,
+/// which carries per-token timings.
+pub struct WhisperCli {
+    pub binary: PathBuf,
+    pub model: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct WhisperOutput {
+    #[serde(default)]
+    result: Option<WhisperResult>,
+    transcription: Vec<WhisperSegment>,
+}
+
+#[derive(Deserialize)]
+struct WhisperResult {
+    language: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct WhisperSegment {
+    offsets: WhisperOffsets,
+    text: String,
+    #[serde(default)]
+    tokens: Vec<WhisperToken>,
+}
+
+/// Milliseconds into the audio.
+#[derive(Deserialize)]
+struct WhisperOffsets {
+    from: u64,
+    to: u64,
+}
+
+#[derive(Deserialize)]
+struct WhisperToken {
+    text: String,
+    offsets: WhisperOffsets,
+    #[serde(default)]
+    p: Option<f64>,
+}
+
+/// Joins Whisper's sub-word tokens into words: a token starting with a space
+/// starts a new word. Special tokens such as `[_BEG_]` are dropped.
+fn whisper_words(tokens: &[WhisperToken]) -> Vec<TranscriptWord> {
+    let mut words: Vec<TranscriptWord> = Vec::new();
+    for token in tokens.iter().filter(|t| !t.text.starts_with("[_")) {
+        let start = token.offsets.from as f64 / 1000.0;
+        let end = token.offsets.to as f64 / 1000.0;
+        match words.last_mut() {
+            Some(word) if !token.text.starts_with(' ') => {
+                word.text.push_str(&token.text);
+                word.end = word.end.max(end);
+                word.confidence = match (word.confidence, token.p) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                };
+            }
+            _ => words.push(TranscriptWord {
+                start,
+                end,
+                text: token.text.trim_start().to_string(),
+                confidence: token.p,
+            }),
+        }
+    }
+    words.retain(|w| !w.text.trim().is_empty());
+    words
+}
+
+impl TranscriptionBackend for WhisperCli {
+    fn transcribe<'a>(
+        &'a self,
+        audio: &'a Path,
+        language: Option<&'a str>,
+    ) -> TranscriptFuture<'a> {
+        Box::pin(async move {
+            let prefix = scratch_path("whisper");
+            let output = tokio::process::Command::new(&self.binary)
+                .arg("-m")
+                .arg(&self.model)
+                .arg("-f")
+                .arg(audio)
+                .args(["-l", language.unwrap_or("auto"), "-np", "-ojf", "-of"])
+                .arg(&prefix)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .output()
+                .await
+                .map_err(|e| format!("Could not run {}: {}", self.binary.display(), e))?;
+            let json_path = prefix.with_extension("whisper.json");
+            let json = tokio::fs::read(&json_path).await;
+            let _ = tokio::fs::remove_file(&json_path).await;
+            if !output.status.success() {
+                let log = String::from_utf8_lossy(&output.stderr);
+                let reason = log.lines().last().unwrap_or("no output");
+                return Err(format!("whisper failed: {}", reason));
+            }
+            let json = json.map_err(|e| format!("whisper wrote no transcript: {}", e))?;
+            let output: WhisperOutput = serde_json::from_slice(&json)
+                .map_err(|e| format!("Unreadable whisper output: {}", e))?;
+            Ok(Transcript {
+                language: output.result.and_then(|r| r.language),
+                segments: output
+                    .transcription
+                    .iter()
+                    .map(|segment| TranscriptSegment {
+                        start: segment.offsets.from as f64 / 1000.0,
+                        end: segment.offsets.to as f64 / 1000.0,
+                        text: segment.text.trim().to_string(),
+                        words: whisper_words(&segment.tokens),
+                    })
+                    .collect(),
+            })
+        })
+    }
+}
+
+/// Posts the audio to a transcription service, with the language hint as a
+/// `language` query parameter. Plain HTTP only; put TLS in front of the
+/// service on its own host.
+pub struct HttpTranscriber {
+    pub url: String,
+}
+
+impl TranscriptionBackend for HttpTranscriber {
+    fn transcribe<'a>(
+        &'a self,
+        audio: &'a Path,
+        language: Option<&'a str>,
+    ) -> TranscriptFuture<'a> {
+        Box::pin(async move {
+            let body = tokio::fs::read(audio).await.map_err(|e| e.to_string())?;
+            let url = match language {
+                Some(language) if self.url.contains('?') => {
+                    format!("{}&language={}", self.url, language)
+                }
+                Some(language) => format!("{}?language={}", self.url, language),
+                None => self.url.clone(),
+            };
+            let request = Request::post(url)
+                .header("Content-Type", "audio/wav")
+                .body(Body::from(body))
+                .map_err(|e| format!("Invalid transcription service URL: {}", e))?;
+            let response = Client::new()
+                .request(request)
+                .await
+                .map_err(|e| format!("Transcription service unreachable: {}", e))?;
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body())
+                .await
+                .map_err(|e| e.to_string())?;
+            if !status.is_success() {
+                return Err(format!("Transcription service answered {}", status));
+            }
+            serde_json::from_slice(&body)
+                .map_err(|e| format!("Unreadable transcription service response: {}", e))
+        })
+    }
+}
+
+fn media_time(secs: f64) -> Option<Duration> {
+    (secs.is_finite() && secs >= 0.0).then(|| Duration::from_secs_f64(secs))
+}
+
+/// Turns a transcript into subtitle cues, one per segment, dropping empty or
+/// badly timed segments and pulling word timings inside their cue.
+fn transcript_cues(transcript: &Transcript) -> Vec<SubtitleCue> {
+    transcript
+        .segments
+        .iter()
+        .filter(|segment| !segment.text.trim().is_empty())
+        .filter_map(|segment| {
+            let start_time = media_time(segment.start)?;
+            let end_time = media_time(segment.end)?;
+            (start_time < end_time).then_some((segment, start_time, end_time))
+        })
+        .enumerate()
+        .map(|(i, (segment, start_time, end_time))| SubtitleCue {
+            id: format!("cue-{}", i),
+            start_time,
+            end_time,
+            text: segment.text.trim().to_string(),
+            words: segment
+                .words
+                .iter()
+                .filter_map(|word| {
+                    let start = media_time(word.start)?.clamp(start_time, end_time);
+                    let end = media_time(word.end)?.clamp(start, end_time);
+                    Some(SubtitleWord {
+                        text: word.text.trim().to_string(),
+                        start_time: start,
+                        end_time: end,
+                        confidence: word.confidence.filter(|c| c.is_finite()),
+                    })
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Language hints are passed on to backends, so only plain tags are taken.
+fn is_language_tag(language: &str) -> bool {
+    !language.is_empty()
+        && language.len() <= 35
+        && language
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct TranscribeRequest {
+    /// Language spoken, e.g. `en`; detected by the backend when absent.
+    pub language: Option<String>,
+    /// Name of the subtitle track; defaults to the asset's name.
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TranscriptionReport {
+    pub track_id: String,
+    pub language: Option<String>,
+    pub cues: usize,
+    pub server_version: usize,
+}
+
+/// `POST /sessions/:id/assets/:asset_id/transcribe` runs speech recognition
+/// over a stored asset's audio and adds the result to the session as a
+/// subtitle track tied to the asset: an `AddSubtitleTrack` followed by
+/// `AddSubtitleCues` batches, which every client receives as usual.
+pub fn transcription_route(
+    manager: Arc<RwLock<SessionManager>>,
+    store: Option<MediaStore>,
+    ffmpeg: PathBuf,
+    backend: Option<Arc<dyn TranscriptionBackend>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!(
+            "sessions" / String / "assets" / String / "transcribe"
+        ))
+        .and(warp::body::content_length_limit(MAX_JSON_BODY_BYTES))
+        .and(warp::body::json())
+        .and(auth::request_role(manager.clone()))
+        .and_then(
+            move |session_id: String, asset_id: String, request: TranscribeRequest, role: Role| {
+                let manager = manager.clone();
+                let store = store.clone();
+                let ffmpeg = ffmpeg.clone();
+                let backend = backend.clone();
+                async move {
+                    let session = manager
+                        .read()
+                        .await
+                        .get_session(&session_id)
+                        .ok_or_else(warp::reject::not_found)?;
+                    let (Some(store), Some(backend)) = (store, backend) else {
+                        return Ok(error_reply(
+                            "Transcription is not configured on this server".to_string(),
+                            StatusCode::SERVICE_UNAVAILABLE,
+                        ));
+                    };
+                    let _permit =
+                        match jobs::acquire(&manager, JobClass::Analysis, &session_id).await {
+                            Ok(permit) => permit,
+                            Err(message) => {
+                                return Ok(error_reply(message, StatusCode::SERVICE_UNAVAILABLE))
+                            }
+                        };
+                    if let Some(language) = request.language.as_deref() {
+                        if !is_language_tag(language) {
+                            return Ok(error_reply(
+                                format!("Invalid language tag {:?}", language),
+                                StatusCode::UNPROCESSABLE_ENTITY,
+                            ));
+                        }
+                    }
+                    let media = {
+                        let session = session.read().await;
+                        asset_media_path(session.project(), &store, &asset_id)
+                    };
+                    let media = match media {
+                        Ok(media) => media,
+                        Err(message) => {
+                            return Ok(error_reply(message, StatusCode::UNPROCESSABLE_ENTITY))
+                        }
+                    };
+
+                    let audio = scratch_path("wav");
+                    let transcript =
+                        match extract_audio(&ffmpeg, &media, &audio, SPEECH_SAMPLE_RATE).await {
+                            Ok(()) => {
+                                backend
+                                    .transcribe(&audio, request.language.as_deref())
+                                    .await
+                            }
+                            Err(message) => Err(message),
+                        };
+                    let _ = tokio::fs::remove_file(&audio).await;
+                    let transcript = match transcript {
+                        Ok(transcript) => transcript,
+                        Err(message) => {
+                            // Long enough that whoever asked may have left
+                            session.write().await.job_failed(
+                                &asset_id,
+                                JobKind::Transcription,
+                                &message,
+                            );
+                            return Ok(error_reply(message, StatusCode::UNPROCESSABLE_ENTITY));
+                        }
+                    };
+
+                    let mut session = session.write().await;
+                    let asset_name = session
+                        .project()
+                        .assets
+                        .iter()
+                        .find(|a| a.id == asset_id)
+                        .map(|a| a.name.clone())
+                        .unwrap_or_default();
+                    let language = transcript.language.clone().or(request.language);
+                    let track = SubtitleTrack {
+                        id: session.config.ids.uuid().to_string(),
+                        name: request.name.unwrap_or(asset_name),
+                        language: language.clone(),
+                        asset_id: Some(asset_id),
+                        cues: Vec::new(),
+                    };
+                    let track_id = track.id.clone();
+                    let cues = transcript_cues(&transcript);
+                    let operations: Vec<EditOperation> =
+                        std::iter::once(EditOperation::AddSubtitleTrack(track))
+                            .chain(cues.chunks(CUE_BATCH).map(|batch| {
+                                EditOperation::AddSubtitleCues {
+                                    track_id: track_id.clone(),
+                                    cues: batch.to_vec(),
+                                }
+                            }))
+                            .collect();
+                    if let Err(message) = operations
+                        .iter()
+                        .try_for_each(|op| roles::permits(role, op))
+                    {
+                        return Ok(roles::forbidden(message));
+                    }
+                    if let Err(message) = operations
+                        .iter()
+                        .try_for_each(|op| session.check_frozen(op))
+                    {
+                        return Ok(freeze::frozen(message));
+                    }
+                    let delivered = operations
+                        .into_iter()
+                        .try_for_each(|op| session.apply_server_operation(op));
+                    if let Err(message) = delivered {
+                        return Ok(error_reply(message, StatusCode::CONFLICT));
+                    }
+                    Ok::<_, warp::Rejection>(warp::reply::with_status(
+                        warp::reply::json(&TranscriptionReport {
+                            track_id,
+                            language,
+                            cues: cues.len(),
+                            server_version: session.server_version,
+                        }),
+                        StatusCode::OK,
+                    ))
+                }
+            },
+        )
+}
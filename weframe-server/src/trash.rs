@@ -0,0 +1,90 @@
+// weframe-server/src/trash.rs
+use crate::{SessionManager, VideoSession};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use weframe_shared::EditOperation;
+
+impl VideoSession {
+    /// Permanently deletes clips that have sat in the trash for longer than
+    /// `retention`, timed from when a sweep first saw them there. Returns the
+    /// ids of the deleted clips.
+    pub fn purge_expired_trash(&mut self, retention: Duration) -> Vec<String> {
+        let now = self.config.clock.now();
+        let trashed: Vec<String> = self.project.trash.iter().map(|c| c.id.clone()).collect();
+        self.clip_trashed_since.retain(|id, _| trashed.contains(id));
+
+        let expired: Vec<String> = trashed
+            .into_iter()
+            .filter(|id| {
+                let since = *self.clip_trashed_since.entry(id.clone()).or_insert(now);
+                now.duration_since(since).unwrap_or_default() >= retention
+            })
+            .collect();
+        if expired.is_empty() {
+            return expired;
+        }
+
+        let purged = self.apply_server_operation(EditOperation::EmptyTrash {
+            clip_ids: Some(expired.clone()),
+        });
+        if purged.is_err() {
+            return Vec::new();
+        }
+        for id in &expired {
+            self.clip_trashed_since.remove(id);
+        }
+        expired
+    }
+}
+
+/// Runs trash retention over every live session.
+pub async fn purge_expired_trash(manager: &RwLock<SessionManager>, retention: Duration) {
+    let sessions: Vec<(String, Arc<RwLock<VideoSession>>)> = manager
+        .read()
+        .await
+        .sessions()
+        .map(|(id, session)| (id.clone(), session.clone()))
+        .collect();
+    for (id, session) in sessions {
+        let purged = session.write().await.purge_expired_trash(retention);
+        if !purged.is_empty() {
+            println!("Purged {} trashed clips from session {}", purged.len(), id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::{get_or_create_session, ServerConfig};
+    use std::time::UNIX_EPOCH;
+    use weframe_shared::VideoClip;
+
+    #[tokio::test]
+    async fn trashed_clips_are_purged_once_retention_passes() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_000_000));
+        let config = ServerConfig {
+            clock: Arc::new(clock.clone()),
+            ..ServerConfig::default()
+        };
+        let manager = RwLock::new(SessionManager::with_config(Arc::new(config)));
+        let session = get_or_create_session(&manager, "trash").await;
+        session.write().await.project.trash.push(VideoClip {
+            id: "clip".to_string(),
+            ..VideoClip::default()
+        });
+
+        let retention = Duration::from_secs(7 * 24 * 3600);
+        // The first sweep starts the clip's retention period
+        purge_expired_trash(&manager, retention).await;
+        clock.advance(retention - Duration::from_secs(1));
+        purge_expired_trash(&manager, retention).await;
+        assert_eq!(session.read().await.project.trash.len(), 1);
+
+        clock.advance(Duration::from_secs(1));
+        purge_expired_trash(&manager, retention).await;
+        assert!(session.read().await.project.trash.is_empty());
+    }
+}
@@ -0,0 +1,43 @@
+// weframe-server/src/view_state.rs
+use crate::VideoSession;
+use weframe_shared::{validate_view_state, ServerMessage, ViewState};
+
+/// Largest view state a user may store, serialized.
+const MAX_VIEW_STATE_BYTES: usize = 64 * 1024;
+
+impl VideoSession {
+    /// Stores a client's view state under its user. Clients that didn't
+    /// present a guest token can't be recognised later, so have nowhere to
+    /// keep it.
+    pub(crate) fn save_view_state(
+        &mut self,
+        client_id: &str,
+        state: ViewState,
+    ) -> Result<(), String> {
+        let Some(user_key) = self.clients.get(client_id).and_then(|c| c.user_key.clone()) else {
+            return Err("View state needs a guest token".to_string());
+        };
+        validate_view_state(&state)?;
+        let size = serde_json::to_vec(&state).map_or(0, |json| json.len());
+        if size > MAX_VIEW_STATE_BYTES {
+            return Err(format!(
+                "View state is {} bytes, over the {} byte limit",
+                size, MAX_VIEW_STATE_BYTES
+            ));
+        }
+        self.view_states.insert(user_key, state);
+        Ok(())
+    }
+
+    /// Sends a client the view state its user saved, if there is one.
+    pub(crate) fn restore_view_state(&self, client_id: &str) {
+        let state = self
+            .clients
+            .get(client_id)
+            .and_then(|c| c.user_key.as_ref())
+            .and_then(|key| self.view_states.get(key));
+        if let Some(state) = state {
+            self.send_to(client_id, &ServerMessage::ViewState(state.clone()));
+        }
+    }
+}
@@ -0,0 +1,488 @@
+// weframe-server/src/workspaces.rs
+use crate::auth::AuthError;
+use crate::media::MediaStore;
+use crate::replies::{error_reply, MAX_JSON_BODY_BYTES};
+use crate::{get_or_create_session, SessionManager, VideoSession};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use warp::http::StatusCode;
+use warp::Filter;
+use weframe_shared::{ErrorCode, VideoProject};
+
+/// Limits on what one workspace may use. `None` is unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorkspaceQuota {
+    /// Sessions of the workspace, loaded or kept in the hibernation or
+    /// project store.
+    pub max_sessions: Option<usize>,
+    /// Bytes of stored media the assets of the workspace's sessions take
+    /// up, loaded or not. Files shared by several of them count once.
+    pub max_storage_bytes: Option<u64>,
+    /// Minutes of timeline rendered since the server started.
+    pub max_render_minutes: Option<f64>,
+}
+
+/// What a workspace uses, next to its quota.
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkspaceUsage {
+    pub workspace: String,
+    pub quota: WorkspaceQuota,
+    pub sessions: usize,
+    pub storage_bytes: u64,
+    pub render_minutes: f64,
+}
+
+/// A workspace's session that isn't loaded but will be again, from the
+/// hibernation or project store.
+struct StoredSession {
+    workspace: String,
+    /// Keys of the stored files its assets use.
+    storage_keys: HashSet<String>,
+}
+
+/// Quotas set for particular workspaces, and the render time each has
+/// used. Workspaces without a quota of their own get the configured one.
+#[derive(Default)]
+pub struct Workspaces {
+    quotas: HashMap<String, WorkspaceQuota>,
+    /// Timeline length of renders queued, running or finished; failed ones
+    /// are given back.
+    rendered: HashMap<String, Duration>,
+    /// Workspace sessions that aren't loaded, by session id. Loaded ones are
+    /// counted from memory instead.
+    stored: HashMap<String, StoredSession>,
+}
+
+impl Workspaces {
+    /// Notes that a session was unloaded to a store it can be loaded back
+    /// from, so it still counts against its workspace's quota.
+    pub(crate) fn track_stored(&mut self, id: &str, session: &VideoSession) {
+        let Some(workspace) = session.metadata.workspace() else {
+            return;
+        };
+        let stored = StoredSession {
+            workspace: workspace.to_string(),
+            storage_keys: storage_keys(session.project()),
+        };
+        self.stored.insert(id.to_string(), stored);
+    }
+
+    /// Stops tracking a session that was loaded again or deleted.
+    pub(crate) fn forget_stored(&mut self, id: &str) {
+        self.stored.remove(id);
+    }
+
+    /// Unloaded sessions belonging to `workspace`.
+    fn stored_sessions<'a>(
+        &'a self,
+        workspace: &'a str,
+    ) -> impl Iterator<Item = &'a StoredSession> {
+        self.stored
+            .values()
+            .filter(move |stored| stored.workspace == workspace)
+    }
+}
+
+fn storage_keys(project: &VideoProject) -> HashSet<String> {
+    project
+        .assets
+        .iter()
+        .filter_map(|asset| Some(asset.storage_key()?.to_string()))
+        .collect()
+}
+
+impl SessionManager {
+    pub fn workspace_quota(&self, workspace: &str) -> WorkspaceQuota {
+        self.workspaces
+            .quotas
+            .get(workspace)
+            .copied()
+            .unwrap_or(self.config.workspace_quota)
+    }
+
+    pub fn set_workspace_quota(&mut self, workspace: &str, quota: WorkspaceQuota) {
+        self.workspaces.quotas.insert(workspace.to_string(), quota);
+    }
+
+    /// Finds the workspace sessions kept in the hibernation and project
+    /// stores, so quotas count them from the start. Run once at startup.
+    pub async fn index_stored_sessions(&mut self) {
+        let mut ids = Vec::new();
+        if let Some(store) = &self.hibernation {
+            match store.list().await {
+                Ok(snapshots) => ids.extend(snapshots.into_iter().map(|s| s.session_id)),
+                Err(e) => eprintln!("Failed to list hibernated sessions: {}", e),
+            }
+        }
+        if let Some(store) = &self.projects {
+            match store.session_ids().await {
+                Ok(stored) => ids.extend(stored),
+                Err(e) => eprintln!("Failed to list persisted sessions: {}", e),
+            }
+        }
+        for id in ids {
+            if self.sessions.contains_key(&id) || self.workspaces.stored.contains_key(&id) {
+                continue;
+            }
+            let mut snapshot = match &self.hibernation {
+                Some(store) => store.load(&id).await.ok().flatten(),
+                None => None,
+            };
+            if let (None, Some(store)) = (&snapshot, &self.projects) {
+                snapshot = store.load(&id).await.ok().flatten();
+            }
+            let Some(snapshot) = snapshot else {
+                continue;
+            };
+            if let Some(workspace) = snapshot.workspace {
+                let stored = StoredSession {
+                    workspace,
+                    storage_keys: storage_keys(&snapshot.project),
+                };
+                self.workspaces.stored.insert(id, stored);
+            }
+        }
+    }
+
+    /// Loaded sessions belonging to `workspace`.
+    async fn workspace_sessions(&self, workspace: &str) -> Vec<Arc<RwLock<VideoSession>>> {
+        let mut sessions = Vec::new();
+        for session in self.sessions.values() {
+            if session.read().await.metadata.workspace() == Some(workspace) {
+                sessions.push(session.clone());
+            }
+        }
+        sessions
+    }
+
+    /// Sessions of `workspace`, loaded or stored.
+    async fn session_count(&self, workspace: &str) -> usize {
+        let loaded = self.workspace_sessions(workspace).await.len();
+        loaded + self.workspaces.stored_sessions(workspace).count()
+    }
+
+    /// Bytes the stored media of `workspace`'s sessions take up, with the
+    /// file under `adding` counted in as if they used it too.
+    async fn storage_bytes(&self, workspace: &str, adding: Option<&str>) -> u64 {
+        let mut keys: HashSet<String> = adding.into_iter().map(str::to_string).collect();
+        for session in self.workspace_sessions(workspace).await {
+            keys.extend(storage_keys(session.read().await.project()));
+        }
+        for stored in self.workspaces.stored_sessions(workspace) {
+            keys.extend(stored.storage_keys.iter().cloned());
+        }
+        let mut storage_bytes = 0;
+        if let Some(store) = self.config.media_dir.clone().map(MediaStore::new) {
+            for path in keys.iter().filter_map(|key| store.path(key)) {
+                if let Ok(metadata) = tokio::fs::metadata(&path).await {
+                    storage_bytes += metadata.len();
+                }
+            }
+        }
+        storage_bytes
+    }
+
+    pub async fn workspace_usage(&self, workspace: &str) -> WorkspaceUsage {
+        let rendered = self.workspaces.rendered.get(workspace).copied();
+        WorkspaceUsage {
+            workspace: workspace.to_string(),
+            quota: self.workspace_quota(workspace),
+            sessions: self.session_count(workspace).await,
+            storage_bytes: self.storage_bytes(workspace, None).await,
+            render_minutes: rendered.unwrap_or_default().as_secs_f64() / 60.0,
+        }
+    }
+
+    /// Fails when `workspace` has as many sessions as it may.
+    async fn check_session_quota(&self, workspace: &str) -> Result<(), AuthError> {
+        let Some(max) = self.workspace_quota(workspace).max_sessions else {
+            return Ok(());
+        };
+        if self.session_count(workspace).await >= max {
+            return Err(AuthError::new(
+                ErrorCode::QuotaExceeded,
+                format!("Workspace {} already has {} sessions", workspace, max),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Counts a render of `length` against `workspace`, or fails if that
+    /// would take it past its quota.
+    pub(crate) fn reserve_render(
+        &mut self,
+        workspace: &str,
+        length: Duration,
+    ) -> Result<(), String> {
+        let rendered = self.workspaces.rendered.get(workspace).copied();
+        let total = rendered.unwrap_or_default() + length;
+        if let Some(max) = self.workspace_quota(workspace).max_render_minutes {
+            if total.as_secs_f64() / 60.0 > max {
+                return Err(format!(
+                    "Workspace {} has used its {} render minutes",
+                    workspace, max
+                ));
+            }
+        }
+        self.workspaces
+            .rendered
+            .insert(workspace.to_string(), total);
+        Ok(())
+    }
+
+    /// Gives back a reserved render that failed.
+    pub(crate) fn release_render(&mut self, workspace: &str, length: Duration) {
+        if let Some(rendered) = self.workspaces.rendered.get_mut(workspace) {
+            *rendered = rendered.saturating_sub(length);
+        }
+    }
+}
+
+/// Refuses a client whose token puts it in `workspace` access to session
+/// `id` if that belongs to a different one, loaded or stored.
+pub(crate) async fn check_member(
+    manager: &SessionManager,
+    id: &str,
+    workspace: Option<&str>,
+) -> Result<(), AuthError> {
+    let owner = match manager.sessions.get(id) {
+        Some(session) => session.read().await.metadata.workspace.clone(),
+        None => manager
+            .workspaces
+            .stored
+            .get(id)
+            .map(|s| s.workspace.clone()),
+    };
+    match owner {
+        Some(owner) if Some(owner.as_str()) != workspace => Err(AuthError::new(
+            ErrorCode::Forbidden,
+            "Session belongs to another workspace",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// The session for a client whose token puts it in `workspace` to join,
+/// loaded or created as by `get_or_create_session`. A session belongs to
+/// the workspace of the first such client to open it; members of other
+/// workspaces are refused, and so is opening more sessions than the
+/// workspace's quota allows.
+pub async fn join_session(
+    manager: &RwLock<SessionManager>,
+    id: &str,
+    workspace: Option<&str>,
+) -> Result<Arc<RwLock<VideoSession>>, AuthError> {
+    if let Some(workspace) = workspace {
+        let manager = manager.read().await;
+        // A stored session of the workspace counts already
+        let counted =
+            manager.sessions.contains_key(id) || manager.workspaces.stored.contains_key(id);
+        if !counted {
+            manager.check_session_quota(workspace).await?;
+        }
+    }
+    let session = get_or_create_session(manager, id).await;
+
+    // Checked again under the lock, as others may have joined sessions
+    // to the workspace while this one loaded
+    let manager = manager.write().await;
+    let owner = session.read().await.metadata.workspace.clone();
+    match (owner.as_deref(), workspace) {
+        (None, Some(workspace)) => {
+            manager.check_session_quota(workspace).await?;
+            session.write().await.metadata.workspace = Some(workspace.to_string());
+            println!("Session {} joined workspace {}", id, workspace);
+        }
+        (owner, workspace) if owner == workspace => {}
+        _ => {
+            return Err(AuthError::new(
+                ErrorCode::Forbidden,
+                "Session belongs to another workspace",
+            ))
+        }
+    }
+    Ok(session)
+}
+
+/// Fails when `workspace` is out of storage: already full, or, given the
+/// key of a newly stored file its sessions are about to use, over quota
+/// with it counted in. A file they already use counts once.
+pub(crate) async fn check_storage(
+    manager: &RwLock<SessionManager>,
+    workspace: Option<&str>,
+    adding: Option<&str>,
+) -> Result<(), String> {
+    let Some(workspace) = workspace else {
+        return Ok(());
+    };
+    let manager = manager.read().await;
+    let Some(max) = manager.workspace_quota(workspace).max_storage_bytes else {
+        return Ok(());
+    };
+    let used = manager.storage_bytes(workspace, adding).await;
+    let full = match adding {
+        Some(_) => used > max,
+        None => used >= max,
+    };
+    if full {
+        return Err(format!(
+            "Workspace {} has used its {} bytes of storage",
+            workspace, max
+        ));
+    }
+    Ok(())
+}
+
+/// `GET /admin/workspaces/:id` reports what a workspace uses against its
+/// quota, and `PUT /admin/workspaces/:id/quota` with a `WorkspaceQuota`
+/// replaces its quota.
+pub fn workspace_routes(
+    manager: Arc<RwLock<SessionManager>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let usage_manager = manager.clone();
+    let usage = warp::get()
+        .and(warp::path!("admin" / "workspaces" / String))
+        .and_then(move |workspace: String| {
+            let manager = usage_manager.clone();
+            async move {
+                let usage = manager.read().await.workspace_usage(&workspace).await;
+                Ok::<_, warp::Rejection>(warp::reply::with_status(
+                    warp::reply::json(&usage),
+                    StatusCode::OK,
+                ))
+            }
+        });
+
+    let set_quota = warp::put()
+        .and(warp::path!("admin" / "workspaces" / String / "quota"))
+        .and(warp::body::content_length_limit(MAX_JSON_BODY_BYTES))
+        .and(warp::body::json())
+        .and_then(move |workspace: String, quota: WorkspaceQuota| {
+            let manager = manager.clone();
+            async move {
+                let invalid = quota
+                    .max_render_minutes
+                    .is_some_and(|minutes| !minutes.is_finite() || minutes < 0.0);
+                if invalid {
+                    return Ok(error_reply(
+                        "max_render_minutes must be a non-negative number".to_string(),
+                        StatusCode::BAD_REQUEST,
+                    ));
+                }
+                let mut manager = manager.write().await;
+                manager.set_workspace_quota(&workspace, quota);
+                println!("Set quota of workspace {}: {:?}", workspace, quota);
+                let usage = manager.workspace_usage(&workspace).await;
+                Ok::<_, warp::Rejection>(warp::reply::with_status(
+                    warp::reply::json(&usage),
+                    StatusCode::OK,
+                ))
+            }
+        });
+
+    usage.or(set_quota)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::{upload_route, DedupScope};
+    use crate::{sse, ServerConfig};
+
+    fn manager_with(config: ServerConfig) -> Arc<RwLock<SessionManager>> {
+        Arc::new(RwLock::new(SessionManager::with_config(Arc::new(config))))
+    }
+
+    #[tokio::test]
+    async fn joins_past_the_session_quota_are_refused() {
+        let manager = manager_with(ServerConfig {
+            jwt_secret: Some("secret".to_string()),
+            workspace_quota: WorkspaceQuota {
+                max_sessions: Some(1),
+                ..WorkspaceQuota::default()
+            },
+            ..ServerConfig::default()
+        });
+        let claims =
+            serde_json::json!({ "sub": "someone", "exp": 4_000_000_000u64, "workspace": "studio" });
+        let token = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        let routes = sse::sse_routes(manager.clone());
+        let join = |session_id: &str| {
+            warp::test::request().path(&format!("/sse/{}?token={}", session_id, token))
+        };
+
+        let _first = join("a").filter(&routes).await.unwrap();
+        let refused = join("b").reply(&routes).await;
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+        let error: serde_json::Value = serde_json::from_slice(refused.body()).unwrap();
+        assert_eq!(error["Error"]["code"], "quota_exceeded");
+        assert!(manager.read().await.get_session("b").is_none());
+        // The session the workspace has can still be joined again
+        let _again = join("a").filter(&routes).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn uploads_past_the_storage_quota_are_refused() {
+        let dir = std::env::temp_dir().join(format!("weframe-test-{}", uuid::Uuid::new_v4()));
+        let manager = manager_with(ServerConfig {
+            media_dir: Some(dir.clone()),
+            workspace_quota: WorkspaceQuota {
+                max_storage_bytes: Some(10),
+                ..WorkspaceQuota::default()
+            },
+            ..ServerConfig::default()
+        });
+        join_session(&manager, "s", Some("studio"))
+            .await
+            .ok()
+            .unwrap();
+        let routes = upload_route(
+            manager.clone(),
+            Some(MediaStore::new(dir.clone())),
+            DedupScope::Off,
+            1024,
+        );
+        let upload = |body: &'static [u8]| {
+            warp::test::request()
+                .method("POST")
+                .path("/sessions/s/assets?name=clip.mp4")
+                .body(body)
+        };
+
+        let stored = upload(b"8 bytes!").reply(&routes).await;
+        assert_eq!(stored.status(), StatusCode::CREATED);
+        let refused = upload(b"8 more!!").reply(&routes).await;
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+        let session = manager.read().await.get_session("s").unwrap();
+        assert_eq!(session.read().await.project().assets.len(), 1);
+        tokio::fs::remove_dir_all(&dir).await.ok();
+    }
+
+    #[tokio::test]
+    async fn renders_past_the_render_quota_are_refused() {
+        let mut manager = SessionManager::with_config(Arc::new(ServerConfig {
+            workspace_quota: WorkspaceQuota {
+                max_render_minutes: Some(1.0),
+                ..WorkspaceQuota::default()
+            },
+            ..ServerConfig::default()
+        }));
+        let clip = Duration::from_secs(45);
+        assert!(manager.reserve_render("studio", clip).is_ok());
+        assert!(manager.reserve_render("studio", clip).is_err());
+        // Other workspaces have quotas of their own
+        assert!(manager.reserve_render("other", clip).is_ok());
+        // A failed render gives its time back
+        manager.release_render("studio", clip);
+        assert!(manager.reserve_render("studio", clip).is_ok());
+    }
+}
@@ -0,0 +1,88 @@
+// weframe-shared/src/checksum.rs
+use crate::VideoProject;
+
+impl VideoProject {
+    /// Hash of the project's content that every replica which applied the
+    /// same operations agrees on, so the server can tell clients what their
+    /// project should be (see `ServerMessage::StateChecksum`). Collaborators
+    /// are left out, as cursor moves travel behind edits, and so are asset
+    /// public URLs and clip previews, which the server signs on the way out.
+    pub fn checksum(&self) -> u64 {
+        let mut project = self.clone();
+        project.collaborators.clear();
+        for asset in &mut project.assets {
+            asset.public_url = None;
+        }
+        for clip in project.clips.iter_mut().chain(&mut project.trash) {
+            clip.thumbnail_url = None;
+            clip.filmstrip_url = None;
+        }
+        // `Value` keeps object keys sorted, so `HashMap` fields come out in
+        // the same order on every replica
+        let canonical = serde_json::to_value(&project)
+            .map(|value| value.to_string())
+            .unwrap_or_default();
+        fnv1a(canonical.as_bytes())
+    }
+}
+
+/// 64-bit FNV-1a. Unlike std's hasher it is the same in every build, which
+/// the server and the browser client are not.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Asset, EditOperation, VideoClip};
+
+    fn project(client_id: &str) -> VideoProject {
+        VideoProject::new(
+            "project".to_string(),
+            "Rough cut".to_string(),
+            client_id.to_string(),
+            client_id.to_string(),
+        )
+    }
+
+    #[test]
+    fn replicas_that_applied_the_same_operations_agree() {
+        let asset = Asset {
+            id: "asset".to_string(),
+            name: "interview.mp4".to_string(),
+            uri: "media:interview".to_string(),
+            duration: None,
+            color_space: None,
+            hdr: None,
+            public_url: None,
+        };
+        // Different collaborators, who are left out
+        let (mut server, mut client) = (project("server"), project("client"));
+        for op in [
+            EditOperation::AddAsset(asset),
+            EditOperation::RenameProject("Final cut".to_string()),
+        ] {
+            server.apply_operation(&op);
+            client.apply_operation(&op);
+        }
+        // Filled in by the server on the way out
+        client.assets[0].public_url = Some("/media/interview?sig=abc".to_string());
+        server.clips.push(VideoClip {
+            id: "clip".to_string(),
+            thumbnail_url: Some("media:preview-poster.jpg".to_string()),
+            ..VideoClip::default()
+        });
+        client.clips.push(VideoClip {
+            id: "clip".to_string(),
+            thumbnail_url: Some("/media/preview-poster.jpg?access=abc".to_string()),
+            ..VideoClip::default()
+        });
+        assert_eq!(server.checksum(), client.checksum());
+
+        client.apply_operation(&EditOperation::SetSnapToFrames(true));
+        assert_ne!(server.checksum(), client.checksum());
+    }
+}
@@ -0,0 +1,57 @@
+// weframe-shared/src/effect_chain.rs
+use crate::{ChainEffect, Effect, EffectChain, IdGenerator, VideoProject, EFFECT_CHAIN_VERSION};
+use std::time::Duration;
+
+impl EffectChain {
+    /// Reads a chain snippet, refusing ones written by a newer format.
+    pub fn from_json(json: &str) -> Result<EffectChain, String> {
+        let chain: EffectChain =
+            serde_json::from_str(json).map_err(|e| format!("Invalid effect chain: {}", e))?;
+        chain.check_version()?;
+        Ok(chain)
+    }
+
+    pub fn check_version(&self) -> Result<(), String> {
+        if self.version > EFFECT_CHAIN_VERSION {
+            return Err(format!(
+                "Effect chain version {} is newer than this build supports ({})",
+                self.version, EFFECT_CHAIN_VERSION
+            ));
+        }
+        Ok(())
+    }
+
+    /// The chain as effects for a clip, each with a fresh id.
+    pub fn to_effects(&self, ids: &IdGenerator) -> Vec<Effect> {
+        self.effects
+            .iter()
+            .map(|e| Effect {
+                id: ids.prefixed("effect"),
+                effect_type: e.effect_type.clone(),
+                start_time: Duration::ZERO,
+                end_time: Duration::ZERO,
+                parameters: e.parameters.clone(),
+            })
+            .collect()
+    }
+}
+
+impl VideoProject {
+    /// A clip's own effects as a chain that can be pasted elsewhere. Track
+    /// effects stay with the track, so pasting onto a clip on the same track
+    /// doesn't apply them twice.
+    pub fn effect_chain(&self, clip_id: &str) -> Result<EffectChain, String> {
+        let clip = self.find_clip(clip_id)?;
+        Ok(EffectChain {
+            version: EFFECT_CHAIN_VERSION,
+            effects: clip
+                .effects
+                .iter()
+                .map(|e| ChainEffect {
+                    effect_type: e.effect_type.clone(),
+                    parameters: e.parameters.clone(),
+                })
+                .collect(),
+        })
+    }
+}
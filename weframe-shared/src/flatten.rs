@@ -0,0 +1,56 @@
+// weframe-shared/src/flatten.rs
+use crate::VideoProject;
+
+impl VideoProject {
+    /// A copy of the project reduced to what a simple exporter such as an
+    /// EDL writer understands: plain clips pointing straight at their media,
+    /// in track and time order, with no editing state around them.
+    ///
+    /// Speed ramps stay on their clips, since a plain clip list can't express
+    /// them otherwise; exporters map times through `VideoClip::source_time_at`.
+    /// Ramps that only ever play at normal speed are dropped, and so are
+    /// disabled clips. Track effects are copied onto each clip on the track,
+    /// after the clip's own, and track gain is added to each clip's gain. The
+    /// model has no nested sequences or adjustment
+    /// layers yet; this is the place to resolve them once they exist.
+    pub fn flatten(&self) -> VideoProject {
+        let mut flat = self.clone();
+        flat.trash.clear();
+        flat.collaborators.clear();
+        flat.settings.ripple_edits = false;
+        flat.settings.magnetic_timeline = false;
+        flat.track_effects.clear();
+        flat.track_gain.clear();
+        flat.presentation = Default::default();
+
+        flat.clips
+            .retain(|c| c.enabled && c.start_time < c.end_time);
+        for clip in &mut flat.clips {
+            if let Some(asset) = clip
+                .asset_id
+                .as_deref()
+                .and_then(|id| self.assets.iter().find(|a| a.id == id))
+            {
+                clip.source_file = asset.uri.clone();
+            }
+            clip.effects = self.effective_effects(clip);
+            clip.audio.gain_db += self.track_gain.get(&clip.track).copied().unwrap_or(0.0);
+            clip.thumbnail_url = None;
+            clip.filmstrip_url = None;
+            clip.waveform = None;
+            if clip.speed.iter().all(|k| k.speed == 1.0) {
+                clip.speed.clear();
+            }
+        }
+        // Subtitles transcribed from an asset become plain timeline cues
+        for track in &mut flat.subtitle_tracks {
+            if track.asset_id.is_some() {
+                track.cues = self.timeline_cues(&track.id);
+                track.asset_id = None;
+            }
+        }
+        flat.clips
+            .sort_by(|a, b| (a.track, a.start_time, &a.id).cmp(&(b.track, b.start_time, &b.id)));
+        flat
+    }
+}
@@ -0,0 +1,162 @@
+// weframe-shared/src/integrity.rs
+use crate::{VideoClip, VideoProject, MAX_TRACKS, PRIMARY_TRACK};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Something wrong with a project that operations should never have led to,
+/// found by `VideoProject::validate`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum IntegrityIssue {
+    /// Two clips on the timeline share an id.
+    DuplicateClip {
+        clip_id: String,
+    },
+    /// A clip ends before it starts.
+    NegativeDuration {
+        clip_id: String,
+    },
+    /// An effect on a clip or track ends before it starts.
+    NegativeEffectDuration {
+        effect_id: String,
+    },
+    TrackOutOfRange {
+        clip_id: String,
+        track: usize,
+    },
+    /// Two clips overlap on a track that must not have overlaps: the primary
+    /// storyline of a magnetic timeline.
+    OverlappingClips {
+        track: usize,
+        first: String,
+        second: String,
+    },
+    /// A transition runs longer than the clip it leads into.
+    TransitionTooLong {
+        clip_id: String,
+        transition_id: String,
+        duration: Duration,
+        clip_length: Duration,
+    },
+    /// A clip plays an asset the project doesn't have.
+    MissingAsset {
+        clip_id: String,
+        asset_id: String,
+    },
+    /// A clip is cut from a multicam group or angle that doesn't exist.
+    MissingMulticamAngle {
+        clip_id: String,
+        group_id: String,
+        angle: usize,
+    },
+    /// Track effects on a track outside `0..MAX_TRACKS`.
+    TrackEffectsOutOfRange {
+        track: usize,
+    },
+}
+
+impl VideoProject {
+    /// Every integrity issue in the project, in clip order. Empty for a
+    /// healthy project. Clips in the trash are checked for what they refer
+    /// to but may overlap anything.
+    pub fn validate(&self) -> Vec<IntegrityIssue> {
+        let mut issues = Vec::new();
+        let mut seen = HashSet::new();
+        for clip in &self.clips {
+            if !seen.insert(clip.id.as_str()) {
+                issues.push(IntegrityIssue::DuplicateClip {
+                    clip_id: clip.id.clone(),
+                });
+            }
+            if clip.end_time < clip.start_time {
+                issues.push(IntegrityIssue::NegativeDuration {
+                    clip_id: clip.id.clone(),
+                });
+            }
+            if clip.track >= MAX_TRACKS {
+                issues.push(IntegrityIssue::TrackOutOfRange {
+                    clip_id: clip.id.clone(),
+                    track: clip.track,
+                });
+            }
+            if let Some(transition) = &clip.transition {
+                let clip_length = clip.end_time.saturating_sub(clip.start_time);
+                if transition.duration > clip_length {
+                    issues.push(IntegrityIssue::TransitionTooLong {
+                        clip_id: clip.id.clone(),
+                        transition_id: transition.id.clone(),
+                        duration: transition.duration,
+                        clip_length,
+                    });
+                }
+            }
+        }
+        for clip in self.clips.iter().chain(&self.trash) {
+            self.check_references(clip, &mut issues);
+        }
+        for (track, effects) in &self.track_effects {
+            if *track >= MAX_TRACKS {
+                issues.push(IntegrityIssue::TrackEffectsOutOfRange { track: *track });
+            }
+            for effect in effects {
+                if effect.end_time < effect.start_time {
+                    issues.push(IntegrityIssue::NegativeEffectDuration {
+                        effect_id: effect.id.clone(),
+                    });
+                }
+            }
+        }
+        if self.settings.magnetic_timeline {
+            self.check_overlaps(PRIMARY_TRACK, &mut issues);
+        }
+        issues
+    }
+
+    /// What a clip points at, and its effects' own timing.
+    fn check_references(&self, clip: &VideoClip, issues: &mut Vec<IntegrityIssue>) {
+        for effect in &clip.effects {
+            if effect.end_time < effect.start_time {
+                issues.push(IntegrityIssue::NegativeEffectDuration {
+                    effect_id: effect.id.clone(),
+                });
+            }
+        }
+        if let Some(asset_id) = &clip.asset_id {
+            if !self.assets.iter().any(|a| a.id == *asset_id) {
+                issues.push(IntegrityIssue::MissingAsset {
+                    clip_id: clip.id.clone(),
+                    asset_id: asset_id.clone(),
+                });
+            }
+        }
+        if let Some(multicam) = &clip.multicam {
+            let exists = self
+                .multicam_groups
+                .iter()
+                .find(|g| g.id == multicam.group_id)
+                .is_some_and(|g| multicam.angle < g.angles.len());
+            if !exists {
+                issues.push(IntegrityIssue::MissingMulticamAngle {
+                    clip_id: clip.id.clone(),
+                    group_id: multicam.group_id.clone(),
+                    angle: multicam.angle,
+                });
+            }
+        }
+    }
+
+    fn check_overlaps(&self, track: usize, issues: &mut Vec<IntegrityIssue>) {
+        let mut clips: Vec<&VideoClip> = self.clips.iter().filter(|c| c.track == track).collect();
+        clips.sort_by_key(|c| c.start_time);
+        for pair in clips.windows(2) {
+            if pair[1].start_time < pair[0].end_time {
+                issues.push(IntegrityIssue::OverlappingClips {
+                    track,
+                    first: pair[0].id.clone(),
+                    second: pair[1].id.clone(),
+                });
+            }
+        }
+    }
+}
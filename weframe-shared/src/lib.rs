@@ -1,17 +1,461 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::time::Duration;
-use uuid::Uuid;
+
+mod checksum;
+mod effect_chain;
+mod flatten;
+mod ids;
+mod integrity;
+pub mod migrations;
+mod network;
+mod speed;
+mod sync;
+mod transform;
+mod undo;
+
+pub use ids::IdGenerator;
+pub use integrity::IntegrityIssue;
+pub use migrations::{migrate_project, CURRENT_SCHEMA_VERSION};
+pub use network::{NetworkConditions, NetworkSimulator};
+pub use sync::SyncState;
+
+/// Version of the client/server message protocol spoken by this build.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Oldest protocol version this build still talks to.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Optional protocol features this build understands. Peers use the
+/// intersection of what both sides advertise in `Hello`/`Welcome`.
+pub const SUPPORTED_FEATURES: &[&str] = &[
+    "cursor_context",
+    "cursor_velocity",
+    "project_adjustments",
+    "zstd",
+    "chunked_sync",
+    "batched_frames",
+];
+
+/// Behaviors switched on or off per session, so they can roll out room by
+/// room. Clients learn a session's flags in `Welcome` and `SessionFlags`.
+pub const SESSION_FLAGS: &[&str] = &[
+    "magnetic_timeline",
+    "frame_quantization",
+    "crdt_mode",
+    "chat",
+];
+
+/// Highest number of tracks a project may use; track indices are `0..MAX_TRACKS`.
+pub const MAX_TRACKS: usize = 16;
+
+/// Slowest and fastest a speed keyframe may play a clip.
+pub const MIN_SPEED: f64 = 0.05;
+pub const MAX_SPEED: f64 = 20.0;
+
+/// Quietest and loudest gain a clip's audio may be set to, in dB.
+pub const MIN_GAIN_DB: f64 = -60.0;
+pub const MAX_GAIN_DB: f64 = 24.0;
+
+/// Smallest, default and largest height of a timeline track row, in pixels.
+pub const MIN_TRACK_HEIGHT: u32 = 20;
+pub const DEFAULT_TRACK_HEIGHT: u32 = 50;
+pub const MAX_TRACK_HEIGHT: u32 = 400;
+
+/// Track holding the primary storyline in magnetic timeline mode.
+pub const PRIMARY_TRACK: usize = 0;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VideoClip {
     pub id: String,
     pub source_file: String,
+    /// Project asset the clip plays, when it was created from one.
+    #[serde(default)]
+    pub asset_id: Option<String>,
     pub start_time: Duration,
     pub end_time: Duration,
+    /// Offset into the source media that plays at `start_time`. Trimming the
+    /// head of a clip moves this along with `start_time`.
+    #[serde(default)]
+    pub source_start: Duration,
     pub track: usize,
+    #[serde(default)]
+    pub kind: ClipKind,
+    #[serde(default)]
+    pub source_kind: SourceKind,
     pub effects: Vec<Effect>,
     pub transition: Option<Transition>,
+    /// Poster image for the clip, filled in by the server once generated.
+    #[serde(default)]
+    pub thumbnail_url: Option<String>,
+    /// Horizontal strip of frames across the clip, filled in by the server.
+    #[serde(default)]
+    pub filmstrip_url: Option<String>,
+    #[serde(default)]
+    pub waveform: Option<WaveformRef>,
+    /// Multicam group and angle the clip plays, for clips cut from a group.
+    #[serde(default)]
+    pub multicam: Option<MulticamRef>,
+    /// Time remapping, in offset order. Empty plays at normal speed; see
+    /// `source_time_at` for how the curve maps timeline to source time.
+    #[serde(default)]
+    pub speed: Vec<SpeedKeyframe>,
+    #[serde(default)]
+    pub audio: ClipAudio,
+    /// What a text clip shows; `None` for other kinds.
+    #[serde(default)]
+    pub text: Option<ClipText>,
+    /// Disabled clips stay on the timeline but are left out of preview and
+    /// export.
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+impl Default for VideoClip {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            source_file: String::new(),
+            asset_id: None,
+            start_time: Duration::ZERO,
+            end_time: Duration::ZERO,
+            source_start: Duration::ZERO,
+            track: 0,
+            kind: ClipKind::Video,
+            source_kind: SourceKind::Media,
+            effects: Vec::new(),
+            transition: None,
+            thumbnail_url: None,
+            filmstrip_url: None,
+            waveform: None,
+            multicam: None,
+            speed: Vec::new(),
+            audio: ClipAudio::default(),
+            text: None,
+            enabled: true,
+        }
+    }
+}
+
+/// How a clip's sound is mixed. The default plays it as recorded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClipAudio {
+    /// Level change in dB; 0 leaves the level alone.
+    pub gain_db: f64,
+    /// Timeline time over which the sound rises from silence at the start
+    /// of the clip and falls to silence at its end.
+    pub fade_in: Duration,
+    pub fade_out: Duration,
+}
+
+/// Words a text clip lays over the picture, such as a title or a lower
+/// third.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClipText {
+    pub content: String,
+    /// Font family name, e.g. `Inter`.
+    pub font: String,
+    /// Height of a line as a fraction of the frame's height.
+    pub size: f64,
+    /// `#rrggbb` or `#rrggbbaa`.
+    pub color: String,
+    /// Where the middle of the text sits, as fractions of the frame's width
+    /// and height from its top left corner.
+    pub x: f64,
+    pub y: f64,
+}
+
+/// What a clip brings to the edit. Video clips are seen and heard; audio
+/// clips are only heard and take no effects; text clips show `text` and
+/// play no media. Each track holds clips of one kind, so a project's audio
+/// tracks are those holding audio clips.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipKind {
+    #[default]
+    Video,
+    Audio,
+    Text,
+}
+
+impl ClipKind {
+    fn name(self) -> &'static str {
+        match self {
+            ClipKind::Video => "video",
+            ClipKind::Audio => "audio",
+            ClipKind::Text => "text",
+        }
+    }
+}
+
+/// What a clip's `source_file` holds. An image is shown unchanged for as
+/// long as its clip lasts, so it has no length of its own to run out of;
+/// image clips are video clips and are never heard.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceKind {
+    /// Video or audio that plays through.
+    #[default]
+    Media,
+    Image,
+}
+
+/// How long an image clip added without a length of its own lasts.
+pub const DEFAULT_IMAGE_DURATION: Duration = Duration::from_secs(5);
+
+/// Playback speed at a point in a clip. Speed changes linearly between
+/// keyframes, so ramps ease from one speed to the next.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SpeedKeyframe {
+    /// Timeline offset from the clip's start.
+    pub offset: Duration,
+    /// Source seconds played per timeline second; 1 is normal speed.
+    pub speed: f64,
+}
+
+/// Says that `source_time` in a clip's media and `reference_source_time` in
+/// the reference clip's media capture the same moment.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyncPoint {
+    pub clip_id: String,
+    pub reference_source_time: Duration,
+    pub source_time: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MulticamRef {
+    pub group_id: String,
+    pub angle: usize,
+}
+
+/// Several recordings of the same event, synced to a shared clock. Clips cut
+/// from the group can switch between angles without losing sync.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MulticamGroup {
+    pub id: String,
+    pub name: String,
+    pub angles: Vec<MulticamAngle>,
+    /// Angle new cuts use when none is given.
+    #[serde(default)]
+    pub active_angle: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MulticamAngle {
+    pub asset_id: String,
+    /// Time on the group's clock at which this asset's first frame plays.
+    #[serde(default)]
+    pub offset: Duration,
+}
+
+impl MulticamGroup {
+    /// Id of the clip created when a cut at `at_time` splits a clip.
+    /// Derived from the group and time so every replica creates the same one.
+    pub fn cut_clip_id(&self, at_time: Duration) -> String {
+        format!("{}@{}", self.id, at_time.as_nanos())
+    }
+}
+
+/// Timed captions, e.g. transcribed from an asset's audio. When `asset_id`
+/// is set, cue times are in that asset's media and the cues follow every clip
+/// that plays it; otherwise they are timeline times.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubtitleTrack {
+    pub id: String,
+    pub name: String,
+    /// BCP 47 language tag, e.g. `en` or `pt-BR`.
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub asset_id: Option<String>,
+    #[serde(default)]
+    pub cues: Vec<SubtitleCue>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubtitleCue {
+    pub id: String,
+    pub start_time: Duration,
+    pub end_time: Duration,
+    pub text: String,
+    /// Per-word timing inside the cue, for karaoke-style highlighting and
+    /// cutting on word boundaries. Empty when the source had none.
+    #[serde(default)]
+    pub words: Vec<SubtitleWord>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubtitleWord {
+    pub text: String,
+    pub start_time: Duration,
+    pub end_time: Duration,
+    /// Recognizer confidence from 0 to 1, if it reported one.
+    #[serde(default)]
+    pub confidence: Option<f64>,
+}
+
+/// A labelled point on the timeline, e.g. a beat to cut on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Marker {
+    pub id: String,
+    pub time: Duration,
+    #[serde(default)]
+    pub label: String,
+    /// CSS color, when the marker should stand out from the default.
+    #[serde(default)]
+    pub color: Option<String>,
+}
+
+/// Most markers a user may keep in their private view state.
+pub const MAX_LOCAL_MARKERS: usize = 1000;
+
+/// One user's own view of a session's timeline. The server keeps it per
+/// user and session and hands it back on reconnect, but never shares it or
+/// makes it part of the project.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ViewState {
+    /// Timeline zoom in pixels per second; 0 leaves it to the UI.
+    pub zoom: f64,
+    /// Timeline time at the left edge of the view.
+    pub scroll_time: Duration,
+    /// Topmost track in view.
+    pub scroll_track: usize,
+    /// Markers only this user sees.
+    pub local_markers: Vec<Marker>,
+}
+
+/// Checks a view state before a client stores it.
+pub fn validate_view_state(state: &ViewState) -> Result<(), String> {
+    if !state.zoom.is_finite() || state.zoom < 0.0 {
+        return Err(format!("Invalid zoom {}", state.zoom));
+    }
+    if state.local_markers.len() > MAX_LOCAL_MARKERS {
+        return Err(format!(
+            "At most {} local markers can be kept",
+            MAX_LOCAL_MARKERS
+        ));
+    }
+    Ok(())
+}
+
+/// Precomputed audio peaks for a clip's whole source file. Timelines draw the
+/// slice given by `VideoClip::source_range`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WaveformRef {
+    pub url: String,
+    pub peaks_per_second: u32,
+}
+
+/// A piece of source media registered with the project. Clips refer to
+/// assets by id.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Asset {
+    pub id: String,
+    pub name: String,
+    /// Where the media lives: an absolute URL, or `media:<key>` for files held
+    /// in the server's media store.
+    pub uri: String,
+    #[serde(default)]
+    pub duration: Option<Duration>,
+    /// Color space the media was recorded in, when known. Untagged media is
+    /// assumed to already be in the project's working space.
+    #[serde(default)]
+    pub color_space: Option<ColorSpace>,
+    /// HDR signalling of the media; `None` for SDR sources.
+    #[serde(default)]
+    pub hdr: Option<HdrMetadata>,
+    /// URL clients should load the media from. Filled in by the server when
+    /// sending assets out; never part of the canonical project.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_url: Option<String>,
+}
+
+impl Asset {
+    /// Key of the stored file for media held by the server.
+    pub fn storage_key(&self) -> Option<&str> {
+        self.uri.strip_prefix("media:")
+    }
+}
+
+impl VideoClip {
+    /// Range of the source media covered by the clip.
+    pub fn source_range(&self) -> (Duration, Duration) {
+        (self.source_start, self.source_time_at(self.end_time))
+    }
+
+    /// Points the clip at `asset`, keeping its place on the timeline but
+    /// pulling the source range inside the asset's duration. Returns whether
+    /// the clip had to be shortened to fit.
+    fn set_source(&mut self, asset: &Asset) -> bool {
+        self.source_file = asset.uri.clone();
+        self.asset_id = Some(asset.id.clone());
+        // Previews and peaks were generated from the old media
+        self.thumbnail_url = None;
+        self.filmstrip_url = None;
+        self.waveform = None;
+
+        let Some(asset_duration) = asset
+            .duration
+            .filter(|_| self.source_kind == SourceKind::Media)
+        else {
+            return false;
+        };
+        let (source_start, source_end) = self.source_range();
+        let length = source_end - source_start;
+        if length > asset_duration {
+            self.source_start = Duration::ZERO;
+            self.end_time = self
+                .timeline_time_at(asset_duration)
+                .unwrap_or(self.start_time);
+            return true;
+        }
+        self.source_start = self.source_start.min(asset_duration - length);
+        false
+    }
+
+    /// Ends the clip where its source runs out, if it plays past the end of
+    /// `asset_duration`. Returns whether it was shortened.
+    fn fit_source(&mut self, asset_duration: Duration) -> bool {
+        if self.source_range().1 <= asset_duration {
+            return false;
+        }
+        let end = self
+            .timeline_time_at(asset_duration)
+            .unwrap_or(self.start_time);
+        self.end_time = end.max(self.start_time);
+        true
+    }
+}
+
+/// The media a clip currently plays, by project asset or by raw URI.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaReference {
+    AssetId(String),
+    Uri(String),
+}
+
+impl MediaReference {
+    pub fn matches(&self, clip: &VideoClip) -> bool {
+        match self {
+            MediaReference::AssetId(id) => clip.asset_id.as_deref() == Some(id.as_str()),
+            MediaReference::Uri(uri) => clip.source_file == *uri,
+        }
+    }
+}
+
+impl std::fmt::Display for MediaReference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MediaReference::AssetId(id) => write!(f, "asset {}", id),
+            MediaReference::Uri(uri) => write!(f, "{}", uri),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,19 +468,52 @@ pub struct Effect {
 }
 
 impl Effect {
-    pub fn new(effect_type: EffectType, value: f64) -> Self {
+    /// An effect with its type's default parameters, its first parameter
+    /// set to `value` when given.
+    pub fn new(ids: &IdGenerator, effect_type: EffectType, value: Option<f64>) -> Self {
         let mut parameters = HashMap::new();
-        parameters.insert("value".to_string(), value);
+        if let (Some(value), Some(first)) = (value, effect_type.parameters().first()) {
+            parameters.insert(first.name.to_string(), value);
+        }
+        Self::with_parameters(ids, effect_type, parameters)
+    }
+
+    /// An effect with `parameters`, and its type's defaults for the rest.
+    pub fn with_parameters(
+        ids: &IdGenerator,
+        effect_type: EffectType,
+        parameters: HashMap<String, f64>,
+    ) -> Self {
+        let mut defaults = effect_type.default_parameters();
+        defaults.extend(parameters);
         Self {
-            id: format!("effect-{}", Uuid::new_v4().to_string()),
+            id: ids.prefixed("effect"),
             effect_type,
             start_time: Duration::from_secs(0),
             end_time: Duration::from_secs(0),
-            parameters,
+            parameters: defaults,
         }
     }
 }
 
+/// Newest `EffectChain` format this build reads and writes.
+pub const EFFECT_CHAIN_VERSION: u32 = 1;
+
+/// A clip's effect stack as a portable JSON snippet, so a look can be
+/// pasted onto other clips or shared with other projects.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EffectChain {
+    pub version: u32,
+    pub effects: Vec<ChainEffect>,
+}
+
+/// An effect in a chain, without the id that ties it to one clip.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChainEffect {
+    pub effect_type: EffectType,
+    pub parameters: HashMap<String, f64>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum EffectType {
     Brightness,
@@ -44,18 +521,535 @@ pub enum EffectType {
     Saturation,
     Hue,
     Grayscale,
+    /// Gaussian blur; `value` is the radius in pixels.
+    Blur,
+    /// Unsharp mask; `value` is its strength.
+    Sharpen,
+    /// Darkened corners; `value` runs from none to the strongest.
+    Vignette,
+    Sepia,
+    Invert,
+    /// Film grain; `value` is its strength.
+    Noise,
+    /// Keeps the part of the picture `width` by `height` from `x`, `y`, all
+    /// as fractions of the picture; the rest is transparent.
+    Crop,
+    /// Resizes the picture about its center by `x` across and `y` down.
+    Scale,
+    /// Turns the picture about its center by `angle` degrees clockwise.
+    Rotate,
+    /// Moves the picture by `x` and `y`, as fractions of the frame.
+    Position,
+}
+
+/// A parameter an effect type takes, with its inclusive range of accepted
+/// values and the value a new effect starts with.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct EffectParameter {
+    pub name: &'static str,
+    pub min: f64,
+    pub max: f64,
+    pub default: f64,
+}
+
+const fn scalar(min: f64, max: f64, default: f64) -> [EffectParameter; 1] {
+    [EffectParameter {
+        name: "value",
+        min,
+        max,
+        default,
+    }]
+}
+
+const fn parameter(name: &'static str, min: f64, max: f64, default: f64) -> EffectParameter {
+    EffectParameter {
+        name,
+        min,
+        max,
+        default,
+    }
+}
+
+const GAIN_PARAMETERS: [EffectParameter; 1] = scalar(0.0, 4.0, 1.0);
+const HUE_PARAMETERS: [EffectParameter; 1] = scalar(-360.0, 360.0, 0.0);
+const AMOUNT_PARAMETERS: [EffectParameter; 1] = scalar(0.0, 1.0, 1.0);
+const BLUR_PARAMETERS: [EffectParameter; 1] = scalar(0.0, 50.0, 4.0);
+const SHARPEN_PARAMETERS: [EffectParameter; 1] = scalar(0.0, 5.0, 1.0);
+const VIGNETTE_PARAMETERS: [EffectParameter; 1] = scalar(0.0, 1.0, 0.5);
+const NOISE_PARAMETERS: [EffectParameter; 1] = scalar(0.0, 100.0, 20.0);
+const CROP_PARAMETERS: [EffectParameter; 4] = [
+    parameter("x", 0.0, 1.0, 0.0),
+    parameter("y", 0.0, 1.0, 0.0),
+    parameter("width", 0.01, 1.0, 1.0),
+    parameter("height", 0.01, 1.0, 1.0),
+];
+const SCALE_PARAMETERS: [EffectParameter; 2] = [
+    parameter("x", 0.01, 10.0, 1.0),
+    parameter("y", 0.01, 10.0, 1.0),
+];
+const ROTATE_PARAMETERS: [EffectParameter; 1] = [parameter("angle", -360.0, 360.0, 0.0)];
+const POSITION_PARAMETERS: [EffectParameter; 2] = [
+    parameter("x", -1.0, 1.0, 0.0),
+    parameter("y", -1.0, 1.0, 0.0),
+];
+
+impl EffectType {
+    pub const ALL: [EffectType; 15] = [
+        EffectType::Brightness,
+        EffectType::Contrast,
+        EffectType::Saturation,
+        EffectType::Hue,
+        EffectType::Grayscale,
+        EffectType::Blur,
+        EffectType::Sharpen,
+        EffectType::Vignette,
+        EffectType::Sepia,
+        EffectType::Invert,
+        EffectType::Noise,
+        EffectType::Crop,
+        EffectType::Scale,
+        EffectType::Rotate,
+        EffectType::Position,
+    ];
+
+    /// Name the effect goes by in client APIs, e.g. `"blur"`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            EffectType::Brightness => "brightness",
+            EffectType::Contrast => "contrast",
+            EffectType::Saturation => "saturation",
+            EffectType::Hue => "hue",
+            EffectType::Grayscale => "grayscale",
+            EffectType::Blur => "blur",
+            EffectType::Sharpen => "sharpen",
+            EffectType::Vignette => "vignette",
+            EffectType::Sepia => "sepia",
+            EffectType::Invert => "invert",
+            EffectType::Noise => "noise",
+            EffectType::Crop => "crop",
+            EffectType::Scale => "scale",
+            EffectType::Rotate => "rotate",
+            EffectType::Position => "position",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<EffectType> {
+        EffectType::ALL.into_iter().find(|t| t.name() == name)
+    }
+
+    /// Parameters effects of the type take. Color effects take one, named
+    /// `value`.
+    pub fn parameters(&self) -> &'static [EffectParameter] {
+        match self {
+            EffectType::Brightness | EffectType::Contrast | EffectType::Saturation => {
+                &GAIN_PARAMETERS
+            }
+            EffectType::Hue => &HUE_PARAMETERS,
+            EffectType::Grayscale | EffectType::Sepia | EffectType::Invert => &AMOUNT_PARAMETERS,
+            EffectType::Blur => &BLUR_PARAMETERS,
+            EffectType::Sharpen => &SHARPEN_PARAMETERS,
+            EffectType::Vignette => &VIGNETTE_PARAMETERS,
+            EffectType::Noise => &NOISE_PARAMETERS,
+            EffectType::Crop => &CROP_PARAMETERS,
+            EffectType::Scale => &SCALE_PARAMETERS,
+            EffectType::Rotate => &ROTATE_PARAMETERS,
+            EffectType::Position => &POSITION_PARAMETERS,
+        }
+    }
+
+    /// Whether the effect moves or reshapes the picture rather than
+    /// changing its colors.
+    pub fn is_transform(&self) -> bool {
+        matches!(
+            self,
+            EffectType::Crop | EffectType::Scale | EffectType::Rotate | EffectType::Position
+        )
+    }
+
+    /// Parameters a new effect of the type starts with.
+    pub fn default_parameters(&self) -> HashMap<String, f64> {
+        self.parameters()
+            .iter()
+            .map(|parameter| (parameter.name.to_string(), parameter.default))
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ServerMessage {
     ClientOperation(OTOperation),
-    NewClient { client_id: String, name: String },
+    /// A client joined, or its name or avatar was settled while completing
+    /// its handshake.
+    NewClient {
+        client_id: String,
+        name: String,
+        #[serde(default)]
+        avatar_url: Option<String>,
+        #[serde(default)]
+        role: Role,
+    },
     ClientDisconnected(String),
     ProjectUpdate(VideoProject),
-    ChatMessage { client_id: String, message: String },
-    Error { client_id: String, message: String },
+    /// Client to server to post to the session's chat, and server to client
+    /// for each message posted, including history replayed on joining.
+    /// Clients leave `name` and `sent_at` to the server.
+    ChatMessage {
+        client_id: String,
+        message: String,
+        /// Author's name when the message was posted.
+        #[serde(default)]
+        name: String,
+        /// Milliseconds since the Unix epoch.
+        #[serde(default)]
+        sent_at: u64,
+    },
+    Error {
+        client_id: String,
+        message: String,
+        /// What went wrong, for errors a client can act on.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<ErrorCode>,
+    },
+    /// Automatic fix-ups the model made while applying the operation at
+    /// `server_version`. Every replica computes the same ones; this lets UIs
+    /// tell users what changed.
+    ProjectAdjusted {
+        server_version: usize,
+        adjustments: Vec<Adjustment>,
+    },
+    /// Sent only to the originating client when one of its operations fails
+    /// validation, so it can roll back its optimistic copy.
+    OperationRejected {
+        client_version: usize,
+        message: String,
+        /// Why, for UIs to explain it; older servers leave it out.
+        #[serde(default)]
+        code: RejectionCode,
+    },
     Ping(u64),
     Pong(u64),
+    /// First message from a client: the newest protocol it speaks and the
+    /// optional features it understands.
+    Hello {
+        protocol_version: u32,
+        features: Vec<String>,
+        /// Opaque id the browser keeps between visits, so a guest gets the
+        /// same generated name each time.
+        #[serde(default)]
+        guest_token: Option<String>,
+        #[serde(default)]
+        avatar_url: Option<String>,
+        /// Server version the client's project was at when its previous
+        /// connection dropped. The server then replays what it missed
+        /// instead of sending the whole project, if it still can.
+        #[serde(default)]
+        resume_from: Option<usize>,
+    },
+    /// Client to server, as the first message when the server requires a
+    /// token and it wasn't given in the URL.
+    Authenticate {
+        token: String,
+    },
+    /// Server reply to `Hello` with the negotiated protocol version and
+    /// features, and the id and name the server knows this connection by.
+    Welcome {
+        protocol_version: u32,
+        features: Vec<String>,
+        client_id: String,
+        #[serde(default)]
+        capabilities: Capabilities,
+        #[serde(default)]
+        name: String,
+        /// Flags enabled in the session, from `SESSION_FLAGS`.
+        #[serde(default)]
+        session_flags: Vec<String>,
+        /// What this connection may do; changes arrive as `NewClient`.
+        #[serde(default)]
+        role: Role,
+        /// Version of the project the `ProjectUpdate` that follows holds.
+        #[serde(default)]
+        server_version: usize,
+    },
+    /// Server to client after replaying the operations a resuming client
+    /// missed, in place of a full sync. `server_version` is the version the
+    /// client is now at.
+    Resumed {
+        server_version: usize,
+    },
+    /// Server to client when the session's flags change.
+    SessionFlags(Vec<String>),
+    /// Server to client every so often while a session changes: the
+    /// `VideoProject::checksum` of the project at `server_version`. A client
+    /// whose confirmed project hashes differently has diverged and sends
+    /// `Resync`.
+    StateChecksum {
+        server_version: usize,
+        checksum: u64,
+    },
+    /// Client to server: send the whole project again, as ours no longer
+    /// matches. Carries the version and checksum the client had, for the
+    /// server's log.
+    Resync {
+        server_version: usize,
+        checksum: u64,
+    },
+    /// Start of a chunked initial sync: the project without its assets and
+    /// clips, which follow in `SyncAssets` and `SyncClips` pages.
+    SyncBegin {
+        server_version: usize,
+        project: VideoProject,
+    },
+    SyncAssets(Vec<Asset>),
+    /// Clips ordered by track, then start time.
+    SyncClips(Vec<VideoClip>),
+    SyncComplete {
+        server_version: usize,
+    },
+    /// Asks collaborators' previews to show only `clip_ids`, e.g. while
+    /// reviewing a selection; an empty list ends the solo. Relayed to the
+    /// other clients as is and never applied to the project.
+    PreviewSolo {
+        client_id: String,
+        clip_ids: Vec<String>,
+    },
+    /// Client to server: replaces this user's saved view state.
+    SaveViewState(ViewState),
+    /// Server to client after the initial sync: the view state this user
+    /// saved last time they were in the session.
+    ViewState(ViewState),
+    /// Server to client while a bulk asset import started with
+    /// `import_id` works through `url`.
+    ImportProgress {
+        import_id: String,
+        url: String,
+        status: ImportStatus,
+    },
+    /// Server to client when a job running in the background for the
+    /// session, such as a render, fails after the request that started it
+    /// was answered.
+    JobFailed {
+        /// The render job id, the import id, or the id of the asset being
+        /// transcribed.
+        job_id: String,
+        kind: JobKind,
+        reason: JobFailure,
+        message: String,
+    },
+    /// Server to client when the session is frozen for maintenance, with
+    /// why, to show as a banner; `None` when it thaws. Client to server, from
+    /// the session's owner, to freeze or thaw it.
+    Frozen {
+        reason: Option<String>,
+    },
+}
+
+/// Work the server does in the background for a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Render,
+    /// One URL of a bulk asset import.
+    Import,
+    Transcription,
+}
+
+/// Why a background job failed, as far as the server can tell, so a UI can
+/// say what to do about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobFailure {
+    /// Media is in a codec or format the server can't read or write.
+    BadCodec,
+    /// A file the job needed is gone, e.g. the asset was deleted.
+    MissingFile,
+    /// The server ran out of disk space.
+    OutOfDisk,
+    /// A remote server or service couldn't be reached or refused.
+    Unreachable,
+    Other,
+}
+
+/// Where one URL of a bulk asset import has got to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ImportStatus {
+    /// `received` bytes downloaded so far, of `total` if the remote server
+    /// said how many.
+    Downloading {
+        received: u64,
+        total: Option<u64>,
+    },
+    /// Registered as the asset `asset_id`.
+    Done {
+        asset_id: String,
+    },
+    Failed {
+        message: String,
+    },
+}
+
+/// Machine-readable reason for a `ServerMessage::Error`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// No token was given, or it isn't valid.
+    Unauthorized,
+    TokenExpired,
+    /// The token is valid but not for this session.
+    Forbidden,
+    /// The session's workspace is at one of its quotas.
+    QuotaExceeded,
+}
+
+/// Why the server rejected an operation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectionCode {
+    /// The client's role doesn't allow the operation.
+    Forbidden,
+    /// The operation is malformed, out of range or needs a session flag
+    /// that isn't enabled.
+    #[default]
+    Invalid,
+    /// The operation no longer fits the project after other clients'
+    /// concurrent edits, e.g. its clip was deleted meanwhile.
+    Conflict,
+    /// The session has no room left for the content the operation adds.
+    OverLimit,
+    /// The session is frozen while maintenance runs.
+    Frozen,
+}
+
+/// What a client can do about a rejected operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Recovery {
+    /// Ask the session's owner for a role that allows the edit.
+    RequestAccess,
+    /// Change the edit before trying again; as sent it will always fail.
+    Revise,
+    /// Make the edit again on the project as it is now.
+    Retry,
+    /// Remove content or empty the trash, then try again.
+    FreeSpace,
+    /// Try again once the session thaws.
+    Wait,
+}
+
+impl RejectionCode {
+    /// The usual way out of a rejection with this code.
+    pub fn recovery(self) -> Recovery {
+        match self {
+            RejectionCode::Forbidden => Recovery::RequestAccess,
+            RejectionCode::Invalid => Recovery::Revise,
+            RejectionCode::Conflict => Recovery::Retry,
+            RejectionCode::OverLimit => Recovery::FreeSpace,
+            RejectionCode::Frozen => Recovery::Wait,
+        }
+    }
+}
+
+impl ServerMessage {
+    /// First protocol version that has this message. Older peers must not be
+    /// sent it.
+    pub fn protocol_version(&self) -> u32 {
+        match self {
+            ServerMessage::OperationRejected { .. }
+            | ServerMessage::ProjectAdjusted { .. }
+            | ServerMessage::Hello { .. }
+            | ServerMessage::Authenticate { .. }
+            | ServerMessage::Welcome { .. }
+            | ServerMessage::SessionFlags(_)
+            | ServerMessage::Resumed { .. }
+            | ServerMessage::StateChecksum { .. }
+            | ServerMessage::Resync { .. }
+            | ServerMessage::SyncBegin { .. }
+            | ServerMessage::SyncAssets(_)
+            | ServerMessage::SyncClips(_)
+            | ServerMessage::SyncComplete { .. }
+            | ServerMessage::PreviewSolo { .. }
+            | ServerMessage::SaveViewState(_)
+            | ServerMessage::ViewState(_)
+            | ServerMessage::ImportProgress { .. }
+            | ServerMessage::JobFailed { .. }
+            | ServerMessage::Frozen { .. } => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// WebSocket traffic over one connection, counted in whole messages as
+/// they go over the wire.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrafficStats {
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    pub messages_received: u64,
+    pub bytes_received: u64,
+}
+
+impl TrafficStats {
+    pub fn record_sent(&mut self, bytes: usize) {
+        self.messages_sent += 1;
+        self.bytes_sent += bytes as u64;
+    }
+
+    pub fn record_received(&mut self, bytes: usize) {
+        self.messages_received += 1;
+        self.bytes_received += bytes as u64;
+    }
+}
+
+/// Optional server-side services, advertised in `Welcome` so clients can hide
+/// UI for what this deployment doesn't offer instead of probing for it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Capabilities {
+    /// Large project snapshots can come as zstd-compressed binary frames to
+    /// clients that negotiate `zstd`.
+    pub binary_protocol: bool,
+    pub render_service: bool,
+    /// New sessions start with the `chat` flag on.
+    pub chat: bool,
+    pub asset_uploads: bool,
+    pub transcription: bool,
+}
+
+/// Picks the protocol version and features for a connection, or explains
+/// why the peer is too old to talk to.
+pub fn negotiate_protocol(
+    protocol_version: u32,
+    features: &[String],
+) -> Result<(u32, Vec<String>), String> {
+    if protocol_version < MIN_PROTOCOL_VERSION {
+        return Err(format!(
+            "Protocol version {} is no longer supported (minimum {})",
+            protocol_version, MIN_PROTOCOL_VERSION
+        ));
+    }
+    let features = features
+        .iter()
+        .filter(|f| SUPPORTED_FEATURES.contains(&f.as_str()))
+        .cloned()
+        .collect();
+    Ok((protocol_version.min(PROTOCOL_VERSION), features))
+}
+
+/// A change `apply_operation` made on its own to keep the project renderable.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Adjustment {
+    TransitionClamped {
+        clip_id: String,
+        transition_id: String,
+        duration: Duration,
+    },
+    TransitionRemoved {
+        clip_id: String,
+        transition_id: String,
+    },
+    /// The clip's new source media is shorter than the clip, so its end was
+    /// pulled in to where the media runs out.
+    ClipShortened { clip_id: String, end_time: Duration },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -74,76 +1068,770 @@ pub enum TransitionType {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VideoProject {
+    /// Shape of the persisted project; see `migrations`.
+    #[serde(default)]
+    pub schema_version: u32,
     pub id: String,
     pub name: String,
     pub clips: Vec<VideoClip>,
     pub duration: Duration,
     pub collaborators: Vec<Collaborator>,
+    #[serde(default)]
+    pub assets: Vec<Asset>,
+    #[serde(default)]
+    pub settings: ProjectSettings,
+    /// Removed clips, kept until the trash is emptied so they can be
+    /// restored.
+    #[serde(default)]
+    pub trash: Vec<VideoClip>,
+    #[serde(default)]
+    pub multicam_groups: Vec<MulticamGroup>,
+    #[serde(default)]
+    pub subtitle_tracks: Vec<SubtitleTrack>,
+    #[serde(default)]
+    pub markers: Vec<Marker>,
+    /// Tracks left out of the audio mix, in ascending order.
+    #[serde(default)]
+    pub muted_tracks: Vec<usize>,
+    /// Level change in dB for everything on a track, on top of each clip's
+    /// own gain. Tracks left alone have no entry.
+    #[serde(default)]
+    pub track_gain: BTreeMap<usize, f64>,
+    /// Effects applied to every clip on a track, after the clip's own.
+    #[serde(default)]
+    pub track_effects: BTreeMap<usize, Vec<Effect>>,
+    /// Timeline layout. Presentation only: nothing in here changes what the
+    /// project plays.
+    #[serde(default)]
+    pub presentation: Presentation,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Collaborator {
-    pub id: String,
-    pub name: String,
-    pub cursor_position: CursorPosition,
+/// How the timeline is laid out on screen, kept apart from the edit so a
+/// project can share one layout or leave it to each user (see
+/// `ProjectSettings::private_track_layout`).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Presentation {
+    /// Headers of tracks that differ from the default.
+    #[serde(default)]
+    pub tracks: BTreeMap<usize, TrackHeader>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CursorPosition {
-    pub track: usize,
-    pub time: Duration,
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrackHeader {
+    /// Row height in pixels.
+    pub height: u32,
+    /// Label color as `#rrggbb`, or `None` for the default.
+    pub color: Option<String>,
+    pub collapsed: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum EditOperation {
-    AddClip(VideoClip),
-    RemoveClip(String),
-    MoveClip {
-        id: String,
-        new_start_time: Duration,
-        new_track: usize,
-    },
-    TrimClip {
-        id: String,
-        new_start_time: Duration,
-        new_end_time: Duration,
-    },
-    AddEffect {
-        clip_id: String,
-        effect: Effect,
-    },
-    RemoveEffect {
-        clip_id: String,
-        effect_id: String,
-    },
-    AddTransition {
-        clip_id: String,
-        transition: Transition,
-    },
-    RemoveTransition {
-        clip_id: String,
-    },
-    SetProjectDuration(Duration),
-    UpdateCollaboratorCursor {
-        collaborator_id: String,
-        new_position: CursorPosition,
-    },
-    RenameProject(String),
-    AddCollaborator(Collaborator),
-    RemoveCollaborator(String),
+impl Default for TrackHeader {
+    fn default() -> Self {
+        TrackHeader {
+            height: DEFAULT_TRACK_HEIGHT,
+            color: None,
+            collapsed: false,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct OTOperation {
-    pub client_id: String,
-    pub client_version: usize,
-    pub server_version: usize,
-    pub operation: EditOperation,
-}
+impl Presentation {
+    /// Header of `track`, default if it was never changed.
+    pub fn track(&self, track: usize) -> TrackHeader {
+        self.tracks.get(&track).cloned().unwrap_or_default()
+    }
 
-impl VideoProject {
-    pub fn new(id: String, name: String, client_id: String, client_name: String) -> Self {
-        VideoProject {
+    /// Checks a track header operation; other operations pass.
+    pub fn validate(op: &EditOperation) -> Result<(), String> {
+        match op {
+            EditOperation::SetTrackHeight { track, height } => {
+                validate_track(*track)?;
+                if !(MIN_TRACK_HEIGHT..=MAX_TRACK_HEIGHT).contains(height) {
+                    return Err(format!(
+                        "Track height must be between {} and {} pixels",
+                        MIN_TRACK_HEIGHT, MAX_TRACK_HEIGHT
+                    ));
+                }
+                Ok(())
+            }
+            EditOperation::SetTrackColor { track, color } => {
+                validate_track(*track)?;
+                let valid = |c: &str| {
+                    c.len() == 7
+                        && c.starts_with('#')
+                        && c[1..].bytes().all(|b| b.is_ascii_hexdigit())
+                };
+                match color {
+                    Some(color) if !valid(color) => {
+                        Err(format!("Track color must be #rrggbb, got {:?}", color))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            EditOperation::SetTrackCollapsed { track, .. } => validate_track(*track),
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies a track header operation; other operations are ignored.
+    /// Headers back at the default are dropped.
+    pub fn apply(&mut self, op: &EditOperation) {
+        let track = match op {
+            EditOperation::SetTrackHeight { track, .. }
+            | EditOperation::SetTrackColor { track, .. }
+            | EditOperation::SetTrackCollapsed { track, .. } => *track,
+            _ => return,
+        };
+        let header = self.tracks.entry(track).or_default();
+        match op {
+            EditOperation::SetTrackHeight { height, .. } => header.height = *height,
+            EditOperation::SetTrackColor { color, .. } => header.color = color.clone(),
+            EditOperation::SetTrackCollapsed { collapsed, .. } => header.collapsed = *collapsed,
+            _ => {}
+        }
+        if *header == TrackHeader::default() {
+            self.tracks.remove(&track);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProjectSettings {
+    pub frame_rate: FrameRate,
+    /// When set, the server rounds every incoming time to a frame boundary.
+    pub snap_to_frames: bool,
+    /// When set, adding, moving and trimming clips shifts the clips after
+    /// them on the same track to keep them adjacent.
+    #[serde(default)]
+    pub ripple_edits: bool,
+    /// When set, track 0 is a primary storyline with no gaps, and clips on
+    /// other tracks move with the primary clip they start over.
+    #[serde(default)]
+    pub magnetic_timeline: bool,
+    /// Color space effects are computed in and output is rendered to.
+    #[serde(default)]
+    pub working_color_space: ColorSpace,
+    /// HDR signalling for rendered output; `None` renders SDR.
+    #[serde(default)]
+    pub hdr_output: Option<HdrMetadata>,
+    /// Shape of the frame being delivered.
+    #[serde(default)]
+    pub aspect_ratio: AspectRatio,
+    #[serde(default)]
+    pub safe_areas: SafeAreas,
+    /// When set, each user keeps their own track layout locally and track
+    /// header operations are refused; otherwise `VideoProject::presentation`
+    /// is shared by everyone.
+    #[serde(default)]
+    pub private_track_layout: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AspectRatio {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for AspectRatio {
+    fn default() -> Self {
+        AspectRatio::LANDSCAPE
+    }
+}
+
+impl AspectRatio {
+    pub const LANDSCAPE: AspectRatio = AspectRatio {
+        width: 16,
+        height: 9,
+    };
+    pub const VERTICAL: AspectRatio = AspectRatio {
+        width: 9,
+        height: 16,
+    };
+    pub const SQUARE: AspectRatio = AspectRatio {
+        width: 1,
+        height: 1,
+    };
+
+    pub fn as_f64(&self) -> f64 {
+        self.width as f64 / self.height as f64
+    }
+
+    /// The largest crop of a frame of this shape that fills `target`.
+    /// `focus` is where the crop sits along the axis being cropped, from 0
+    /// (left or top) to 1 (right or bottom); 0.5 centres it.
+    pub fn reframe(&self, target: AspectRatio, focus: f64) -> Reframe {
+        let (source, target) = (self.as_f64(), target.as_f64());
+        let focus = focus.clamp(0.0, 1.0);
+        if target < source {
+            let width = target / source;
+            Reframe {
+                x: (1.0 - width) * focus,
+                y: 0.0,
+                width,
+                height: 1.0,
+            }
+        } else {
+            let height = source / target;
+            Reframe {
+                x: 0.0,
+                y: (1.0 - height) * focus,
+                width: 1.0,
+                height,
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for AspectRatio {
+    type Err = String;
+
+    /// Accepts `landscape`, `vertical`, `square` or `<width>:<height>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "landscape" => return Ok(AspectRatio::LANDSCAPE),
+            "vertical" => return Ok(AspectRatio::VERTICAL),
+            "square" => return Ok(AspectRatio::SQUARE),
+            _ => {}
+        }
+        let (width, height) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Unknown aspect ratio: {}", s))?;
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<u32>()
+                .map_err(|_| format!("Unknown aspect ratio: {}", s))
+        };
+        Ok(AspectRatio {
+            width: parse(width)?,
+            height: parse(height)?,
+        })
+    }
+}
+
+/// Crop rectangle in fractions of the source frame's width and height.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Reframe {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// Title- and action-safe regions as fractions of the frame, centred.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SafeAreas {
+    pub title: f64,
+    pub action: f64,
+}
+
+impl Default for SafeAreas {
+    fn default() -> Self {
+        SafeAreas {
+            title: 0.9,
+            action: 0.93,
+        }
+    }
+}
+
+/// Color spaces media can be tagged with and projects can work in. Preview
+/// and export both convert each clip from `VideoProject::input_color_space`
+/// to the working space, so they agree on how media looks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSpace {
+    #[default]
+    Rec709,
+    Srgb,
+    DisplayP3,
+    Rec2020,
+    AcesCg,
+}
+
+impl std::str::FromStr for ColorSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rec709" => Ok(ColorSpace::Rec709),
+            "srgb" => Ok(ColorSpace::Srgb),
+            "display_p3" => Ok(ColorSpace::DisplayP3),
+            "rec2020" => Ok(ColorSpace::Rec2020),
+            "aces_cg" => Ok(ColorSpace::AcesCg),
+            _ => Err(format!("Unknown color space: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferFunction {
+    /// SMPTE ST 2084 perceptual quantizer, used by HDR10 and Dolby Vision.
+    Pq,
+    /// Hybrid log-gamma, used in broadcast HDR.
+    Hlg,
+}
+
+/// CIE 1931 xy chromaticity coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Chromaticity {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// SMPTE ST 2086 description of the display the content was graded on.
+/// Luminance is in candelas per square metre.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MasteringDisplay {
+    pub red: Chromaticity,
+    pub green: Chromaticity,
+    pub blue: Chromaticity,
+    pub white_point: Chromaticity,
+    pub min_luminance: f64,
+    pub max_luminance: f64,
+}
+
+/// HDR signalling carried by a source or requested for output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HdrMetadata {
+    pub transfer: TransferFunction,
+    #[serde(default)]
+    pub mastering_display: Option<MasteringDisplay>,
+    /// Maximum content light level, in cd/m².
+    #[serde(default)]
+    pub max_cll: Option<u32>,
+    /// Maximum frame-average light level, in cd/m².
+    #[serde(default)]
+    pub max_fall: Option<u32>,
+}
+
+/// Frames per second as a ratio, so rates like 29.97 (30000/1001) are exact.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FrameRate {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+impl Default for FrameRate {
+    fn default() -> Self {
+        FrameRate {
+            numerator: 30,
+            denominator: 1,
+        }
+    }
+}
+
+impl FrameRate {
+    /// Rounds `time` to the nearest frame boundary.
+    pub fn quantize(&self, time: Duration) -> Duration {
+        let num = self.numerator as u128;
+        let den = self.denominator as u128;
+        let nanos_per_frame_den = den * 1_000_000_000;
+        let frames = (time.as_nanos() * num + nanos_per_frame_den / 2) / nanos_per_frame_den;
+        let nanos = frames * nanos_per_frame_den / num;
+        Duration::from_nanos(nanos as u64)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Collaborator {
+    pub id: String,
+    pub name: String,
+    pub cursor_position: CursorPosition,
+    /// Picture to show next to the collaborator's cursor and messages.
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+    #[serde(default)]
+    pub role: Role,
+}
+
+/// What a collaborator may do to the project. Each role may do everything
+/// the ones before it may.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Watches, moves their cursor and chats.
+    Viewer,
+    /// Edits the timeline.
+    #[default]
+    Editor,
+    /// Also renames the project and manages who is in it.
+    Owner,
+}
+
+impl Role {
+    /// The least role allowed to apply `op`.
+    pub fn required_for(op: &EditOperation) -> Role {
+        match op {
+            EditOperation::UpdateCollaboratorCursor { .. } => Role::Viewer,
+            EditOperation::RenameProject(_)
+            | EditOperation::AddCollaborator(_)
+            | EditOperation::RemoveCollaborator(_) => Role::Owner,
+            EditOperation::Batch(operations) => operations
+                .iter()
+                .map(Role::required_for)
+                .max()
+                .unwrap_or_default(),
+            _ => Role::Editor,
+        }
+    }
+
+    /// Whether someone with this role may apply `op`, and if not, why.
+    pub fn check(self, op: &EditOperation) -> Result<(), String> {
+        let required = Role::required_for(op);
+        if self >= required {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} requires the {:?} role, but you are {:?}",
+                op.kind(),
+                required,
+                self
+            ))
+        }
+    }
+}
+
+impl Collaborator {
+    /// Display color derived from the collaborator id, so every client shows
+    /// the same person in the same color without storing it in the project.
+    pub fn color(&self) -> String {
+        let hash = self
+            .id
+            .bytes()
+            .fold(0u32, |hash, b| hash.wrapping_mul(31).wrapping_add(b as u32));
+        format!("hsl({}, 70%, 50%)", hash % 360)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CursorPosition {
+    pub track: usize,
+    pub time: Duration,
+    /// Clip the collaborator currently has selected, if any.
+    #[serde(default)]
+    pub selected_clip: Option<String>,
+    /// Clip under the collaborator's pointer, if any.
+    #[serde(default)]
+    pub hovered_clip: Option<String>,
+    #[serde(default)]
+    pub tool: Option<EditTool>,
+}
+
+/// Timeline tool a collaborator is currently using.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum EditTool {
+    Select,
+    Trim,
+    Razor,
+    Slip,
+    Hand,
+}
+
+/// Rate of cursor movement: timeline seconds and tracks per wall-clock second.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CursorVelocity {
+    pub time: f64,
+    pub track: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum EditOperation {
+    AddClip(VideoClip),
+    /// Adds a clip of kind `Text`, which must have `text`.
+    AddTextClip(VideoClip),
+    /// Replaces what a text clip shows and how.
+    UpdateTextClip {
+        clip_id: String,
+        text: ClipText,
+    },
+    /// Moves a clip to the trash.
+    RemoveClip(String),
+    /// Puts a trashed clip back where it was.
+    RestoreClip(String),
+    /// Permanently deletes the given trashed clips, or all of them.
+    EmptyTrash {
+        #[serde(default)]
+        clip_ids: Option<Vec<String>>,
+    },
+    MoveClip {
+        id: String,
+        new_start_time: Duration,
+        new_track: usize,
+    },
+    TrimClip {
+        id: String,
+        new_start_time: Duration,
+        new_end_time: Duration,
+    },
+    /// Cuts a clip in two at timeline time `split_time`: the clip keeps what
+    /// comes before it and a new clip, `new_clip_id`, plays the rest of the
+    /// same media. The id is picked by whoever splits, so their next
+    /// operations can already refer to the new clip.
+    SplitClip {
+        id: String,
+        split_time: Duration,
+        new_clip_id: String,
+    },
+    /// Merges `joined_id`, which must carry on exactly where clip `id` ends,
+    /// back into it. What undoing a `SplitClip` sends.
+    JoinClips {
+        id: String,
+        joined_id: String,
+    },
+    /// Swaps the media a clip plays for another asset, keeping its place on
+    /// the timeline, effects and transition.
+    ReplaceClipSource {
+        clip_id: String,
+        new_asset_id: String,
+    },
+    /// Points every clip playing `from`, in the trash included, at another
+    /// asset in one step, e.g. after media was moved or went missing.
+    RelinkAsset {
+        from: MediaReference,
+        new_asset_id: String,
+    },
+    AddEffect {
+        clip_id: String,
+        effect: Effect,
+    },
+    RemoveEffect {
+        clip_id: String,
+        effect_id: String,
+    },
+    /// Replaces a clip's whole effect stack, e.g. when pasting an effect
+    /// chain.
+    SetClipEffects {
+        clip_id: String,
+        effects: Vec<Effect>,
+    },
+    AddTransition {
+        clip_id: String,
+        transition: Transition,
+    },
+    RemoveTransition {
+        clip_id: String,
+    },
+    SetProjectDuration(Duration),
+    SetFrameRate(FrameRate),
+    SetSnapToFrames(bool),
+    SetRippleEdits(bool),
+    SetMagneticTimeline(bool),
+    SetWorkingColorSpace(ColorSpace),
+    /// Tags an asset with the color space its media was recorded in, or
+    /// clears the tag with `None`.
+    SetAssetColorSpace {
+        asset_id: String,
+        color_space: Option<ColorSpace>,
+    },
+    /// Records an asset's HDR metadata, or marks it SDR with `None`.
+    SetAssetHdr {
+        asset_id: String,
+        hdr: Option<HdrMetadata>,
+    },
+    SetHdrOutput(Option<HdrMetadata>),
+    SetAspectRatio(AspectRatio),
+    AddMulticamGroup(MulticamGroup),
+    /// Moves each clip so its sync point plays at the same moment as the
+    /// matching point in the reference clip, e.g. to line up external audio
+    /// with camera footage. Clips keep their length and source range.
+    AlignToReference {
+        reference_clip_id: String,
+        sync_points: Vec<SyncPoint>,
+    },
+    /// Cuts to `angle` at `at_time`: the group clip playing then is split
+    /// there and the part after the cut plays the new angle, in sync.
+    SwitchAngle {
+        group_id: String,
+        angle: usize,
+        at_time: Duration,
+    },
+    SetSafeAreas(SafeAreas),
+    /// Replaces a clip's speed curve. The clip keeps its place and length on
+    /// the timeline and plays more or less of its source; it is shortened if
+    /// that runs past the end of its asset.
+    SetClipSpeed {
+        clip_id: String,
+        keyframes: Vec<SpeedKeyframe>,
+    },
+    SetClipAudio {
+        clip_id: String,
+        audio: ClipAudio,
+    },
+    SetClipEnabled {
+        clip_id: String,
+        enabled: bool,
+    },
+    SetTrackMuted {
+        track: usize,
+        muted: bool,
+    },
+    /// Sets the level change in dB for everything on a track; 0 leaves the
+    /// track's clips at their own gain.
+    SetTrackGain {
+        track: usize,
+        gain_db: f64,
+    },
+    /// Adds an effect to every clip on a track, replacing any track effect
+    /// of the same type.
+    AddTrackEffect {
+        track: usize,
+        effect: Effect,
+    },
+    RemoveTrackEffect {
+        track: usize,
+        effect_id: String,
+    },
+    /// Replaces the track effect with the same id as `effect`.
+    UpdateTrackEffect {
+        track: usize,
+        effect: Effect,
+    },
+    /// Presentation: row height of a track in the timeline.
+    SetTrackHeight {
+        track: usize,
+        height: u32,
+    },
+    /// Presentation: label color of a track, `None` for the default.
+    SetTrackColor {
+        track: usize,
+        color: Option<String>,
+    },
+    /// Presentation: whether a track is folded down in the timeline.
+    SetTrackCollapsed {
+        track: usize,
+        collapsed: bool,
+    },
+    SetPrivateTrackLayout(bool),
+    AddSubtitleTrack(SubtitleTrack),
+    /// Appends cues to a track, e.g. as a transcription streams in.
+    AddSubtitleCues {
+        track_id: String,
+        cues: Vec<SubtitleCue>,
+    },
+    RemoveSubtitleTrack(String),
+    AddMarkers(Vec<Marker>),
+    RemoveMarkers(Vec<String>),
+    UpdateCollaboratorCursor {
+        collaborator_id: String,
+        new_position: CursorPosition,
+        /// Motion at the time of the update, so receivers can interpolate
+        /// between throttled cursor messages.
+        #[serde(default)]
+        velocity: Option<CursorVelocity>,
+    },
+    SetClipPreviews {
+        clip_id: String,
+        thumbnail_url: Option<String>,
+        filmstrip_url: Option<String>,
+    },
+    SetClipWaveform {
+        clip_id: String,
+        waveform: Option<WaveformRef>,
+    },
+    RenameProject(String),
+    AddCollaborator(Collaborator),
+    RemoveCollaborator(String),
+    AddAsset(Asset),
+    RemoveAsset(String),
+    /// Several operations applied, broadcast and undone as one, in order,
+    /// e.g. a split and a transition on the new cut. Each is checked
+    /// against the project as the ones before it leave it, and if any is
+    /// invalid none is applied. Batches don't nest.
+    Batch(Vec<EditOperation>),
+}
+
+impl EditOperation {
+    /// Whether only the server may make the operation: it attaches what the
+    /// server's own jobs generate, such as clip previews and peaks.
+    pub fn server_only(&self) -> bool {
+        match self {
+            EditOperation::SetClipPreviews { .. } | EditOperation::SetClipWaveform { .. } => true,
+            EditOperation::Batch(operations) => operations.iter().any(EditOperation::server_only),
+            _ => false,
+        }
+    }
+
+    /// Variant name, for logs and metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EditOperation::AddClip(_) => "AddClip",
+            EditOperation::AddTextClip(_) => "AddTextClip",
+            EditOperation::UpdateTextClip { .. } => "UpdateTextClip",
+            EditOperation::RemoveClip(_) => "RemoveClip",
+            EditOperation::RestoreClip(_) => "RestoreClip",
+            EditOperation::EmptyTrash { .. } => "EmptyTrash",
+            EditOperation::MoveClip { .. } => "MoveClip",
+            EditOperation::TrimClip { .. } => "TrimClip",
+            EditOperation::SplitClip { .. } => "SplitClip",
+            EditOperation::JoinClips { .. } => "JoinClips",
+            EditOperation::ReplaceClipSource { .. } => "ReplaceClipSource",
+            EditOperation::RelinkAsset { .. } => "RelinkAsset",
+            EditOperation::AddEffect { .. } => "AddEffect",
+            EditOperation::RemoveEffect { .. } => "RemoveEffect",
+            EditOperation::SetClipEffects { .. } => "SetClipEffects",
+            EditOperation::AddTransition { .. } => "AddTransition",
+            EditOperation::RemoveTransition { .. } => "RemoveTransition",
+            EditOperation::SetProjectDuration(_) => "SetProjectDuration",
+            EditOperation::SetFrameRate(_) => "SetFrameRate",
+            EditOperation::SetSnapToFrames(_) => "SetSnapToFrames",
+            EditOperation::SetRippleEdits(_) => "SetRippleEdits",
+            EditOperation::SetMagneticTimeline(_) => "SetMagneticTimeline",
+            EditOperation::SetWorkingColorSpace(_) => "SetWorkingColorSpace",
+            EditOperation::SetAssetColorSpace { .. } => "SetAssetColorSpace",
+            EditOperation::SetAssetHdr { .. } => "SetAssetHdr",
+            EditOperation::SetHdrOutput(_) => "SetHdrOutput",
+            EditOperation::SetAspectRatio(_) => "SetAspectRatio",
+            EditOperation::AddMulticamGroup(_) => "AddMulticamGroup",
+            EditOperation::AlignToReference { .. } => "AlignToReference",
+            EditOperation::SwitchAngle { .. } => "SwitchAngle",
+            EditOperation::SetSafeAreas(_) => "SetSafeAreas",
+            EditOperation::SetClipSpeed { .. } => "SetClipSpeed",
+            EditOperation::SetClipAudio { .. } => "SetClipAudio",
+            EditOperation::SetClipEnabled { .. } => "SetClipEnabled",
+            EditOperation::SetTrackMuted { .. } => "SetTrackMuted",
+            EditOperation::SetTrackGain { .. } => "SetTrackGain",
+            EditOperation::AddTrackEffect { .. } => "AddTrackEffect",
+            EditOperation::RemoveTrackEffect { .. } => "RemoveTrackEffect",
+            EditOperation::UpdateTrackEffect { .. } => "UpdateTrackEffect",
+            EditOperation::SetTrackHeight { .. } => "SetTrackHeight",
+            EditOperation::SetTrackColor { .. } => "SetTrackColor",
+            EditOperation::SetTrackCollapsed { .. } => "SetTrackCollapsed",
+            EditOperation::SetPrivateTrackLayout(_) => "SetPrivateTrackLayout",
+            EditOperation::AddSubtitleTrack(_) => "AddSubtitleTrack",
+            EditOperation::AddSubtitleCues { .. } => "AddSubtitleCues",
+            EditOperation::RemoveSubtitleTrack(_) => "RemoveSubtitleTrack",
+            EditOperation::AddMarkers(_) => "AddMarkers",
+            EditOperation::RemoveMarkers(_) => "RemoveMarkers",
+            EditOperation::UpdateCollaboratorCursor { .. } => "UpdateCollaboratorCursor",
+            EditOperation::SetClipPreviews { .. } => "SetClipPreviews",
+            EditOperation::SetClipWaveform { .. } => "SetClipWaveform",
+            EditOperation::RenameProject(_) => "RenameProject",
+            EditOperation::AddCollaborator(_) => "AddCollaborator",
+            EditOperation::RemoveCollaborator(_) => "RemoveCollaborator",
+            EditOperation::AddAsset(_) => "AddAsset",
+            EditOperation::RemoveAsset(_) => "RemoveAsset",
+            EditOperation::Batch(_) => "Batch",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OTOperation {
+    pub client_id: String,
+    pub client_version: usize,
+    pub server_version: usize,
+    pub operation: EditOperation,
+    /// What the user was doing, e.g. "rough cut pass", so history can be
+    /// browsed by editing phase. See `validate_label`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+impl VideoProject {
+    pub fn new(id: String, name: String, client_id: String, client_name: String) -> Self {
+        VideoProject {
+            schema_version: CURRENT_SCHEMA_VERSION,
             id,
             name,
             clips: Vec::new(),
@@ -151,28 +1839,92 @@ impl VideoProject {
             collaborators: vec![Collaborator {
                 id: client_id,
                 name: client_name,
-                cursor_position: CursorPosition {
-                    track: 0,
-                    time: Duration::from_secs(0),
-                },
+                cursor_position: CursorPosition::default(),
+                avatar_url: None,
+                role: Role::Owner,
             }],
+            assets: Vec::new(),
+            settings: ProjectSettings::default(),
+            trash: Vec::new(),
+            multicam_groups: Vec::new(),
+            subtitle_tracks: Vec::new(),
+            markers: Vec::new(),
+            muted_tracks: Vec::new(),
+            track_gain: BTreeMap::new(),
+            track_effects: BTreeMap::new(),
+            presentation: Presentation::default(),
+        }
+    }
+
+    /// Applies `op` and returns any adjustments made to keep the project
+    /// consistent as a result.
+    pub fn apply_operation(&mut self, op: &EditOperation) -> Vec<Adjustment> {
+        if let EditOperation::Batch(operations) = op {
+            return operations
+                .iter()
+                .flat_map(|op| self.apply_operation(op))
+                .collect();
         }
+        let magnetic = self.settings.magnetic_timeline
+            || matches!(op, EditOperation::SetMagneticTimeline(true));
+        let connections = if magnetic {
+            self.connected_clips()
+        } else {
+            Vec::new()
+        };
+        let adjustments = self.apply_edit(op);
+        if self.settings.magnetic_timeline {
+            self.settle_primary_storyline(op, connections);
+        }
+        adjustments
     }
 
-    pub fn apply_operation(&mut self, op: &EditOperation) {
+    fn apply_edit(&mut self, op: &EditOperation) -> Vec<Adjustment> {
         match op {
-            EditOperation::AddClip(clip) => self.clips.push(clip.clone()),
-            EditOperation::RemoveClip(id) => self.clips.retain(|c| c.id != *id),
+            EditOperation::AddClip(clip) | EditOperation::AddTextClip(clip) => {
+                let mut clip = clip.clone();
+                if clip.source_kind == SourceKind::Image && clip.end_time == clip.start_time {
+                    clip.end_time = clip.start_time + DEFAULT_IMAGE_DURATION;
+                }
+                if self.settings.ripple_edits {
+                    let length = clip.end_time.saturating_sub(clip.start_time);
+                    self.ripple(clip.track, clip.start_time, &clip.id, length, true);
+                }
+                self.clips.push(clip);
+            }
+            EditOperation::RemoveClip(id) => {
+                if let Some(index) = self.clips.iter().position(|c| c.id == *id) {
+                    let clip = self.clips.remove(index);
+                    self.trash.push(clip);
+                }
+            }
+            EditOperation::RestoreClip(id) => {
+                if let Some(index) = self.trash.iter().position(|c| c.id == *id) {
+                    let clip = self.trash.remove(index);
+                    self.clips.push(clip);
+                }
+            }
+            EditOperation::EmptyTrash { clip_ids } => match clip_ids {
+                Some(ids) => self.trash.retain(|c| !ids.contains(&c.id)),
+                None => self.trash.clear(),
+            },
             EditOperation::MoveClip {
                 id,
                 new_start_time,
                 new_track,
             } => {
-                if let Some(clip) = self.clips.iter_mut().find(|c| c.id == *id) {
-                    let duration = clip.end_time - clip.start_time;
-                    clip.start_time = *new_start_time;
-                    clip.end_time = *new_start_time + duration;
-                    clip.track = *new_track;
+                let Some(clip) = self.clips.iter_mut().find(|c| c.id == *id) else {
+                    return Vec::new();
+                };
+                let (old_track, old_end) = (clip.track, clip.end_time);
+                let duration = clip.end_time - clip.start_time;
+                clip.start_time = *new_start_time;
+                clip.end_time = *new_start_time + duration;
+                clip.track = *new_track;
+                if self.settings.ripple_edits {
+                    // Close the gap it left, then make room where it landed
+                    self.ripple(old_track, old_end, id, duration, false);
+                    self.ripple(*new_track, *new_start_time, id, duration, true);
                 }
             }
             EditOperation::TrimClip {
@@ -181,9 +1933,73 @@ impl VideoProject {
                 new_end_time,
             } => {
                 if let Some(clip) = self.clips.iter_mut().find(|c| c.id == *id) {
-                    clip.start_time = *new_start_time;
-                    clip.end_time = *new_end_time;
+                    let (track, old_end) = (clip.track, clip.end_time);
+                    let audio = clip.audio;
+                    *clip = clip.slice(*new_start_time, *new_end_time);
+                    // Fades follow the clip's edges, shortened to fit if need be
+                    let length = new_end_time.saturating_sub(*new_start_time);
+                    clip.audio.fade_in = audio.fade_in.min(length);
+                    clip.audio.fade_out = audio.fade_out.min(length - clip.audio.fade_in);
+                    if self.settings.ripple_edits {
+                        if *new_end_time >= old_end {
+                            self.ripple(track, old_end, id, *new_end_time - old_end, true);
+                        } else {
+                            self.ripple(track, old_end, id, old_end - *new_end_time, false);
+                        }
+                    }
+                }
+            }
+            EditOperation::SplitClip {
+                id,
+                split_time,
+                new_clip_id,
+            } => {
+                if let Some(index) = self.clips.iter().position(|c| c.id == *id) {
+                    let clip = &mut self.clips[index];
+                    let mut tail = clip.slice(*split_time, clip.end_time);
+                    tail.id = new_clip_id.clone();
+                    tail.transition = None;
+                    tail.audio.fade_out = tail.audio.fade_out.min(tail.end_time - *split_time);
+                    clip.end_time = *split_time;
+                    clip.audio.fade_in = clip.audio.fade_in.min(*split_time - clip.start_time);
+                    clip.audio.fade_out = Duration::ZERO;
+                    self.clips.insert(index + 1, tail);
+                }
+            }
+            EditOperation::JoinClips { id, joined_id } => {
+                if let Some(index) = self.clips.iter().position(|c| c.id == *joined_id) {
+                    let joined = self.clips.remove(index);
+                    if let Some(clip) = self.clips.iter_mut().find(|c| c.id == *id) {
+                        clip.end_time = joined.end_time;
+                        clip.audio.fade_out = joined.audio.fade_out;
+                    }
+                }
+            }
+            EditOperation::ReplaceClipSource {
+                clip_id,
+                new_asset_id,
+            } => {
+                let Some(asset) = self.assets.iter().find(|a| a.id == *new_asset_id).cloned()
+                else {
+                    return Vec::new();
+                };
+                return self.relink_clips(std::slice::from_ref(clip_id), &asset);
+            }
+            EditOperation::RelinkAsset { from, new_asset_id } => {
+                let Some(asset) = self.assets.iter().find(|a| a.id == *new_asset_id).cloned()
+                else {
+                    return Vec::new();
+                };
+                for clip in self.trash.iter_mut().filter(|c| from.matches(c)) {
+                    clip.set_source(&asset);
                 }
+                let ids: Vec<String> = self
+                    .clips
+                    .iter()
+                    .filter(|c| from.matches(c))
+                    .map(|c| c.id.clone())
+                    .collect();
+                return self.relink_clips(&ids, &asset);
             }
             EditOperation::AddEffect { clip_id, effect } => {
                 if let Some(clip) = self.clips.iter_mut().find(|c| c.id == *clip_id) {
@@ -195,6 +2011,11 @@ impl VideoProject {
                     clip.effects.retain(|e| e.id != *effect_id);
                 }
             }
+            EditOperation::SetClipEffects { clip_id, effects } => {
+                if let Some(clip) = self.clips.iter_mut().find(|c| c.id == *clip_id) {
+                    clip.effects = effects.clone();
+                }
+            }
             EditOperation::AddTransition {
                 clip_id,
                 transition,
@@ -211,41 +2032,1473 @@ impl VideoProject {
             EditOperation::SetProjectDuration(new_duration) => {
                 self.duration = *new_duration;
             }
-            EditOperation::UpdateCollaboratorCursor {
-                collaborator_id,
-                new_position,
+            EditOperation::SetFrameRate(frame_rate) => {
+                self.settings.frame_rate = *frame_rate;
+            }
+            EditOperation::SetSnapToFrames(snap) => {
+                self.settings.snap_to_frames = *snap;
+            }
+            EditOperation::SetRippleEdits(ripple) => {
+                self.settings.ripple_edits = *ripple;
+            }
+            EditOperation::SetMagneticTimeline(magnetic) => {
+                self.settings.magnetic_timeline = *magnetic;
+            }
+            EditOperation::SetWorkingColorSpace(color_space) => {
+                self.settings.working_color_space = *color_space;
+            }
+            EditOperation::SetAssetColorSpace {
+                asset_id,
+                color_space,
             } => {
-                if let Some(collaborator) = self
-                    .collaborators
-                    .iter_mut()
-                    .find(|c| c.id == *collaborator_id)
-                {
-                    collaborator.cursor_position = new_position.clone();
+                if let Some(asset) = self.assets.iter_mut().find(|a| a.id == *asset_id) {
+                    asset.color_space = *color_space;
                 }
             }
-            EditOperation::RenameProject(new_name) => {
-                self.name = new_name.clone();
+            EditOperation::SetAssetHdr { asset_id, hdr } => {
+                if let Some(asset) = self.assets.iter_mut().find(|a| a.id == *asset_id) {
+                    asset.hdr = hdr.clone();
+                }
             }
-            EditOperation::AddCollaborator(collaborator) => {
-                self.collaborators.push(collaborator.clone());
+            EditOperation::SetHdrOutput(hdr) => {
+                self.settings.hdr_output = hdr.clone();
             }
-            EditOperation::RemoveCollaborator(collaborator_id) => {
-                self.collaborators.retain(|c| c.id != *collaborator_id);
+            EditOperation::SetAspectRatio(aspect_ratio) => {
+                self.settings.aspect_ratio = *aspect_ratio;
             }
-        }
-    }
-
-    pub fn transform_operation(
-        &self,
-        client_op: &OTOperation,
-        server_version: usize,
-    ) -> OTOperation {
-        let mut transformed_op = client_op.clone();
-        transformed_op.server_version = server_version;
-
-        // Implement more sophisticated transformation logic here if needed
-        // This is a simplified version that just updates the server version
-
-        transformed_op
+            EditOperation::AddMulticamGroup(group) => self.multicam_groups.push(group.clone()),
+            EditOperation::AlignToReference {
+                reference_clip_id,
+                sync_points,
+            } => {
+                let Some(reference) = self.clips.iter().find(|c| c.id == *reference_clip_id) else {
+                    return Vec::new();
+                };
+                let reference = reference.clone();
+                for point in sync_points {
+                    let Some(clip) = self.clips.iter_mut().find(|c| c.id == point.clip_id) else {
+                        continue;
+                    };
+                    // Timeline time at which the reference plays its sync
+                    // point, then back to where this clip must start to play
+                    // its own there.
+                    let start = reference.timeline_nanos_at(point.reference_source_time)
+                        - clip.timeline_nanos_at(point.source_time)
+                        + clip.start_time.as_nanos() as i128;
+                    let start = Duration::from_nanos(start.max(0) as u64);
+                    let length = clip.end_time.saturating_sub(clip.start_time);
+                    clip.start_time = start;
+                    clip.end_time = start + length;
+                }
+            }
+            EditOperation::SwitchAngle {
+                group_id,
+                angle,
+                at_time,
+            } => self.switch_angle(group_id, *angle, *at_time),
+            EditOperation::SetSafeAreas(safe_areas) => {
+                self.settings.safe_areas = *safe_areas;
+            }
+            EditOperation::SetClipSpeed { clip_id, keyframes } => {
+                let asset_duration = self
+                    .clips
+                    .iter()
+                    .find(|c| c.id == *clip_id)
+                    .and_then(|c| self.asset_duration(c));
+                let Some(clip) = self.clips.iter_mut().find(|c| c.id == *clip_id) else {
+                    return Vec::new();
+                };
+                clip.speed = keyframes.clone();
+                if asset_duration.is_some_and(|duration| clip.fit_source(duration)) {
+                    let end_time = clip.end_time;
+                    let mut adjustments = vec![Adjustment::ClipShortened {
+                        clip_id: clip_id.clone(),
+                        end_time,
+                    }];
+                    adjustments.extend(self.fit_transition(clip_id));
+                    return adjustments;
+                }
+            }
+            EditOperation::SetClipAudio { clip_id, audio } => {
+                if let Some(clip) = self.clips.iter_mut().find(|c| c.id == *clip_id) {
+                    clip.audio = *audio;
+                }
+            }
+            EditOperation::SetClipEnabled { clip_id, enabled } => {
+                if let Some(clip) = self.clips.iter_mut().find(|c| c.id == *clip_id) {
+                    clip.enabled = *enabled;
+                }
+            }
+            EditOperation::UpdateTextClip { clip_id, text } => {
+                if let Some(clip) = self.clips.iter_mut().find(|c| c.id == *clip_id) {
+                    clip.text = Some(text.clone());
+                }
+            }
+            EditOperation::SetTrackMuted { track, muted } => {
+                self.muted_tracks.retain(|t| t != track);
+                if *muted {
+                    self.muted_tracks.push(*track);
+                    self.muted_tracks.sort_unstable();
+                }
+            }
+            EditOperation::SetTrackGain { track, gain_db } => {
+                if *gain_db == 0.0 {
+                    self.track_gain.remove(track);
+                } else {
+                    self.track_gain.insert(*track, *gain_db);
+                }
+            }
+            EditOperation::AddTrackEffect { track, effect } => {
+                let effects = self.track_effects.entry(*track).or_default();
+                effects.retain(|e| e.effect_type != effect.effect_type);
+                effects.push(effect.clone());
+            }
+            EditOperation::RemoveTrackEffect { track, effect_id } => {
+                if let Some(effects) = self.track_effects.get_mut(track) {
+                    effects.retain(|e| e.id != *effect_id);
+                    if effects.is_empty() {
+                        self.track_effects.remove(track);
+                    }
+                }
+            }
+            EditOperation::UpdateTrackEffect { track, effect } => {
+                if let Some(existing) = self
+                    .track_effects
+                    .get_mut(track)
+                    .and_then(|effects| effects.iter_mut().find(|e| e.id == effect.id))
+                {
+                    *existing = effect.clone();
+                }
+            }
+            EditOperation::SetTrackHeight { .. }
+            | EditOperation::SetTrackColor { .. }
+            | EditOperation::SetTrackCollapsed { .. } => self.presentation.apply(op),
+            EditOperation::SetPrivateTrackLayout(private) => {
+                self.settings.private_track_layout = *private;
+            }
+            EditOperation::AddSubtitleTrack(track) => self.subtitle_tracks.push(track.clone()),
+            EditOperation::AddSubtitleCues { track_id, cues } => {
+                if let Some(track) = self.subtitle_tracks.iter_mut().find(|t| t.id == *track_id) {
+                    track.cues.extend(cues.iter().cloned());
+                    track.cues.sort_by_key(|c| c.start_time);
+                }
+            }
+            EditOperation::RemoveSubtitleTrack(track_id) => {
+                self.subtitle_tracks.retain(|t| t.id != *track_id)
+            }
+            EditOperation::AddMarkers(markers) => {
+                self.markers.extend(markers.iter().cloned());
+                self.markers.sort_by_key(|m| m.time);
+            }
+            EditOperation::RemoveMarkers(marker_ids) => {
+                self.markers.retain(|m| !marker_ids.contains(&m.id))
+            }
+            EditOperation::UpdateCollaboratorCursor {
+                collaborator_id,
+                new_position,
+                ..
+            } => {
+                if let Some(collaborator) = self
+                    .collaborators
+                    .iter_mut()
+                    .find(|c| c.id == *collaborator_id)
+                {
+                    collaborator.cursor_position = new_position.clone();
+                }
+            }
+            EditOperation::SetClipPreviews {
+                clip_id,
+                thumbnail_url,
+                filmstrip_url,
+            } => {
+                if let Some(clip) = self.clips.iter_mut().find(|c| c.id == *clip_id) {
+                    clip.thumbnail_url = thumbnail_url.clone();
+                    clip.filmstrip_url = filmstrip_url.clone();
+                }
+            }
+            EditOperation::SetClipWaveform { clip_id, waveform } => {
+                if let Some(clip) = self.clips.iter_mut().find(|c| c.id == *clip_id) {
+                    clip.waveform = waveform.clone();
+                }
+            }
+            EditOperation::RenameProject(new_name) => {
+                self.name = new_name.clone();
+            }
+            EditOperation::AddCollaborator(collaborator) => {
+                self.collaborators.push(collaborator.clone());
+            }
+            EditOperation::RemoveCollaborator(collaborator_id) => {
+                self.collaborators.retain(|c| c.id != *collaborator_id);
+            }
+            EditOperation::AddAsset(asset) => self.assets.push(asset.clone()),
+            EditOperation::RemoveAsset(asset_id) => self.assets.retain(|a| a.id != *asset_id),
+            // Applied one operation at a time by `apply_operation`
+            EditOperation::Batch(_) => {}
+        }
+
+        match op {
+            EditOperation::TrimClip { id, .. } | EditOperation::SplitClip { id, .. } => {
+                self.fit_transition(id).into_iter().collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Pairs each clip off the primary storyline with the track 0 clip it
+    /// starts over, and that clip's current start time.
+    fn connected_clips(&self) -> Vec<(String, String, Duration)> {
+        self.clips
+            .iter()
+            .filter(|c| c.track != PRIMARY_TRACK)
+            .filter_map(|connected| {
+                let anchor = self.clips.iter().find(|p| {
+                    p.track == PRIMARY_TRACK
+                        && p.start_time <= connected.start_time
+                        && connected.start_time < p.end_time
+                })?;
+                Some((connected.id.clone(), anchor.id.clone(), anchor.start_time))
+            })
+            .collect()
+    }
+
+    /// Closes every gap on the primary storyline, keeping clip order, then
+    /// moves connected clips by however far their anchor moved. A connected
+    /// clip that `op` itself placed stays where it was put.
+    fn settle_primary_storyline(
+        &mut self,
+        op: &EditOperation,
+        connections: Vec<(String, String, Duration)>,
+    ) {
+        let mut primary: Vec<&mut VideoClip> = self
+            .clips
+            .iter_mut()
+            .filter(|c| c.track == PRIMARY_TRACK)
+            .collect();
+        primary.sort_by(|a, b| {
+            a.start_time
+                .cmp(&b.start_time)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        let mut cursor = Duration::ZERO;
+        for clip in primary {
+            let length = clip.end_time.saturating_sub(clip.start_time);
+            clip.start_time = cursor;
+            clip.end_time = cursor + length;
+            cursor += length;
+        }
+
+        let placed = match op {
+            EditOperation::AddClip(clip) | EditOperation::AddTextClip(clip) => {
+                Some(clip.id.as_str())
+            }
+            EditOperation::MoveClip { id, .. } | EditOperation::TrimClip { id, .. } => {
+                Some(id.as_str())
+            }
+            _ => None,
+        };
+        for (connected_id, anchor_id, old_start) in connections {
+            if placed == Some(connected_id.as_str()) {
+                continue;
+            }
+            let Some(new_start) = self
+                .clips
+                .iter()
+                .find(|c| c.id == anchor_id && c.track == PRIMARY_TRACK)
+                .map(|c| c.start_time)
+            else {
+                continue;
+            };
+            let Some(clip) = self
+                .clips
+                .iter_mut()
+                .find(|c| c.id == connected_id && c.track != PRIMARY_TRACK)
+            else {
+                continue;
+            };
+            if new_start >= old_start {
+                clip.start_time += new_start - old_start;
+                clip.end_time += new_start - old_start;
+            } else {
+                let shift = (old_start - new_start).min(clip.start_time);
+                clip.start_time -= shift;
+                clip.end_time -= shift;
+            }
+        }
+    }
+
+    /// Shifts every clip on `track` starting at or after `from`, other than
+    /// `except`, later or earlier by `amount`.
+    fn ripple(
+        &mut self,
+        track: usize,
+        from: Duration,
+        except: &str,
+        amount: Duration,
+        later: bool,
+    ) {
+        for clip in &mut self.clips {
+            if clip.track != track || clip.start_time < from || clip.id == except {
+                continue;
+            }
+            if later {
+                clip.start_time += amount;
+                clip.end_time += amount;
+            } else {
+                let amount = amount.min(clip.start_time);
+                clip.start_time -= amount;
+                clip.end_time -= amount;
+            }
+        }
+    }
+
+    fn switch_angle(&mut self, group_id: &str, angle: usize, at_time: Duration) {
+        let Some(group) = self.multicam_groups.iter_mut().find(|g| g.id == group_id) else {
+            return;
+        };
+        group.active_angle = angle;
+        let group = group.clone();
+        let Some(index) = self.clips.iter().position(|c| {
+            c.multicam.as_ref().is_some_and(|m| m.group_id == group_id)
+                && c.start_time <= at_time
+                && at_time < c.end_time
+        }) else {
+            return;
+        };
+
+        let index = if at_time > self.clips[index].start_time {
+            let clip = &mut self.clips[index];
+            let mut tail = clip.slice(at_time, clip.end_time);
+            tail.id = group.cut_clip_id(at_time);
+            tail.transition = None;
+            clip.end_time = at_time;
+            clip.audio.fade_out = Duration::ZERO;
+            self.clips.insert(index + 1, tail);
+            index + 1
+        } else {
+            index
+        };
+
+        let clip = &mut self.clips[index];
+        let Some(current) = clip.multicam.as_ref().map(|m| m.angle) else {
+            return;
+        };
+        let (Some(from), Some(to)) = (group.angles.get(current), group.angles.get(angle)) else {
+            return;
+        };
+        let group_time = clip.source_start + from.offset;
+        let Some(asset) = self.assets.iter().find(|a| a.id == to.asset_id) else {
+            return;
+        };
+        clip.source_file = asset.uri.clone();
+        clip.asset_id = Some(asset.id.clone());
+        clip.source_start = group_time.saturating_sub(to.offset);
+        clip.thumbnail_url = None;
+        clip.filmstrip_url = None;
+        clip.waveform = None;
+        clip.multicam = Some(MulticamRef {
+            group_id: group_id.to_string(),
+            angle,
+        });
+    }
+
+    /// Moves the given timeline clips onto `asset`, reporting any that had to
+    /// be shortened and any transitions that no longer fit.
+    fn relink_clips(&mut self, clip_ids: &[String], asset: &Asset) -> Vec<Adjustment> {
+        let mut adjustments = Vec::new();
+        for clip_id in clip_ids {
+            let Some(clip) = self.clips.iter_mut().find(|c| c.id == *clip_id) else {
+                continue;
+            };
+            if clip.set_source(asset) {
+                adjustments.push(Adjustment::ClipShortened {
+                    clip_id: clip_id.clone(),
+                    end_time: clip.end_time,
+                });
+            }
+            adjustments.extend(self.fit_transition(clip_id));
+        }
+        adjustments
+    }
+
+    /// Shortens a clip's transition to fit the clip, or drops it when the
+    /// clip has no length left.
+    fn fit_transition(&mut self, clip_id: &str) -> Option<Adjustment> {
+        let clip = self.clips.iter_mut().find(|c| c.id == clip_id)?;
+        let length = clip.end_time.saturating_sub(clip.start_time);
+        let transition = clip.transition.as_mut()?;
+        if transition.duration <= length {
+            return None;
+        }
+        if length.is_zero() {
+            let transition_id = transition.id.clone();
+            clip.transition = None;
+            return Some(Adjustment::TransitionRemoved {
+                clip_id: clip_id.to_string(),
+                transition_id,
+            });
+        }
+        transition.duration = length;
+        Some(Adjustment::TransitionClamped {
+            clip_id: clip_id.to_string(),
+            transition_id: transition.id.clone(),
+            duration: length,
+        })
+    }
+
+    /// Whether any clip on the timeline or in the trash plays `asset`, either
+    /// by id or by its URI.
+    pub fn is_asset_referenced(&self, asset: &Asset) -> bool {
+        self.clips
+            .iter()
+            .chain(&self.trash)
+            .any(|c| c.asset_id.as_deref() == Some(asset.id.as_str()) || c.source_file == asset.uri)
+            || self
+                .multicam_groups
+                .iter()
+                .flat_map(|g| &g.angles)
+                .any(|a| a.asset_id == asset.id)
+            || self
+                .subtitle_tracks
+                .iter()
+                .any(|t| t.asset_id.as_deref() == Some(asset.id.as_str()))
+    }
+
+    /// Cues of a subtitle track in timeline time, ordered by start. Cues of
+    /// an asset's track appear once for every clip playing that part of the
+    /// asset, cut to the clip.
+    pub fn timeline_cues(&self, track_id: &str) -> Vec<SubtitleCue> {
+        let Some(track) = self.subtitle_tracks.iter().find(|t| t.id == track_id) else {
+            return Vec::new();
+        };
+        let Some(asset_id) = track.asset_id.as_deref() else {
+            return track.cues.clone();
+        };
+        let mut cues = Vec::new();
+        for clip in &self.clips {
+            if clip.asset_id.as_deref() != Some(asset_id) || clip.start_time >= clip.end_time {
+                continue;
+            }
+            let (source_start, source_end) = clip.source_range();
+            let to_timeline = |t: Duration| {
+                clip.timeline_time_at(t.clamp(source_start, source_end))
+                    .unwrap_or(clip.start_time)
+            };
+            for cue in &track.cues {
+                if cue.end_time <= source_start || cue.start_time >= source_end {
+                    continue;
+                }
+                cues.push(SubtitleCue {
+                    id: format!("{}:{}", clip.id, cue.id),
+                    start_time: to_timeline(cue.start_time),
+                    end_time: to_timeline(cue.end_time),
+                    text: cue.text.clone(),
+                    words: cue
+                        .words
+                        .iter()
+                        .filter(|w| w.end_time > source_start && w.start_time < source_end)
+                        .map(|w| SubtitleWord {
+                            start_time: to_timeline(w.start_time),
+                            end_time: to_timeline(w.end_time),
+                            ..w.clone()
+                        })
+                        .collect(),
+                });
+            }
+        }
+        cues.sort_by_key(|c| c.start_time);
+        cues
+    }
+
+    pub fn unreferenced_assets(&self) -> impl Iterator<Item = &Asset> {
+        self.assets.iter().filter(|a| !self.is_asset_referenced(a))
+    }
+
+    /// Color space `clip`'s media is in: its asset's tag, or the working
+    /// space when the clip has no tagged asset.
+    pub fn input_color_space(&self, clip: &VideoClip) -> ColorSpace {
+        clip.asset_id
+            .as_deref()
+            .and_then(|id| self.assets.iter().find(|a| a.id == id))
+            .and_then(|a| a.color_space)
+            .unwrap_or(self.settings.working_color_space)
+    }
+
+    /// HDR signalling of `clip`'s media, if its asset has any.
+    pub fn input_hdr(&self, clip: &VideoClip) -> Option<&HdrMetadata> {
+        let asset_id = clip.asset_id.as_deref()?;
+        self.assets.iter().find(|a| a.id == asset_id)?.hdr.as_ref()
+    }
+
+    /// Whether rendering `clip` has to convert its transfer function, i.e.
+    /// tone-map HDR to SDR, lift SDR into HDR, or convert between PQ and HLG.
+    pub fn needs_tone_mapping(&self, clip: &VideoClip) -> bool {
+        let input = self.input_hdr(clip).map(|hdr| hdr.transfer);
+        let output = self.settings.hdr_output.as_ref().map(|hdr| hdr.transfer);
+        input != output
+    }
+
+    /// How long the media a clip plays runs, where known. Images never run
+    /// out.
+    fn asset_duration(&self, clip: &VideoClip) -> Option<Duration> {
+        if clip.source_kind == SourceKind::Image {
+            return None;
+        }
+        let asset_id = clip.asset_id.as_deref()?;
+        self.assets.iter().find(|a| a.id == asset_id)?.duration
+    }
+
+    /// Rounds every time carried by `op` to a frame boundary when the project
+    /// snaps to frames. Returns whether the operation changed.
+    pub fn quantize_operation(&self, op: &mut EditOperation) -> bool {
+        if !self.settings.snap_to_frames {
+            return false;
+        }
+        if let EditOperation::Batch(operations) = op {
+            return operations
+                .iter_mut()
+                .fold(false, |changed, op| self.quantize_operation(op) | changed);
+        }
+        let frame_rate = self.settings.frame_rate;
+        let mut changed = false;
+        let mut snap = |time: &mut Duration| {
+            let snapped = frame_rate.quantize(*time);
+            changed |= snapped != *time;
+            *time = snapped;
+        };
+        match op {
+            EditOperation::AddClip(clip) | EditOperation::AddTextClip(clip) => {
+                snap(&mut clip.start_time);
+                snap(&mut clip.end_time);
+                snap(&mut clip.source_start);
+                for keyframe in &mut clip.speed {
+                    snap(&mut keyframe.offset);
+                }
+            }
+            EditOperation::SetClipSpeed { keyframes, .. } => {
+                for keyframe in keyframes {
+                    snap(&mut keyframe.offset);
+                }
+            }
+            EditOperation::SetClipAudio { audio, .. } => {
+                snap(&mut audio.fade_in);
+                snap(&mut audio.fade_out);
+            }
+            EditOperation::MoveClip { new_start_time, .. } => snap(new_start_time),
+            EditOperation::TrimClip {
+                new_start_time,
+                new_end_time,
+                ..
+            } => {
+                snap(new_start_time);
+                snap(new_end_time);
+            }
+            EditOperation::SplitClip { split_time, .. } => snap(split_time),
+            EditOperation::SetClipEffects { effects, .. } => {
+                for effect in effects {
+                    snap(&mut effect.start_time);
+                    snap(&mut effect.end_time);
+                }
+            }
+            EditOperation::AddEffect { effect, .. }
+            | EditOperation::AddTrackEffect { effect, .. }
+            | EditOperation::UpdateTrackEffect { effect, .. } => {
+                snap(&mut effect.start_time);
+                snap(&mut effect.end_time);
+            }
+            EditOperation::AddTransition { transition, .. } => snap(&mut transition.duration),
+            EditOperation::SetProjectDuration(duration) => snap(duration),
+            EditOperation::AddMarkers(markers) => {
+                for marker in markers {
+                    snap(&mut marker.time);
+                }
+            }
+            _ => {}
+        }
+        changed
+    }
+
+    /// Pulls the times in Move/Trim/AddClip operations into `[0, limit]` and
+    /// within the clip's source media, preserving clip length where possible.
+    /// Returns whether the operation changed.
+    pub fn clamp_operation(&self, op: &mut EditOperation, limit: Duration) -> bool {
+        match op {
+            EditOperation::Batch(operations) => operations.iter_mut().fold(false, |changed, op| {
+                self.clamp_operation(op, limit) | changed
+            }),
+            EditOperation::AddClip(clip) | EditOperation::AddTextClip(clip) => {
+                let mut length = clip.end_time.saturating_sub(clip.start_time).min(limit);
+                if let Some(asset_duration) = self.asset_duration(clip) {
+                    let playable = clip
+                        .timeline_time_at(asset_duration)
+                        .map_or(Duration::ZERO, |end| end.saturating_sub(clip.start_time));
+                    length = length.min(playable);
+                }
+                let start = clip.start_time.min(limit - length);
+                let changed = start != clip.start_time || start + length != clip.end_time;
+                clip.start_time = start;
+                clip.end_time = start + length;
+                changed
+            }
+            EditOperation::MoveClip {
+                id, new_start_time, ..
+            } => {
+                let Some(clip) = self.clips.iter().find(|c| c.id == *id) else {
+                    return false;
+                };
+                let length = clip.end_time.saturating_sub(clip.start_time);
+                let start = (*new_start_time).min(limit.saturating_sub(length));
+                let changed = start != *new_start_time;
+                *new_start_time = start;
+                changed
+            }
+            EditOperation::TrimClip {
+                id,
+                new_start_time,
+                new_end_time,
+            } => {
+                let Some(clip) = self.clips.iter().find(|c| c.id == *id) else {
+                    return false;
+                };
+                let mut start = (*new_start_time).min(limit);
+                let mut end = (*new_end_time).min(limit);
+                if let Some(asset_duration) = self.asset_duration(clip) {
+                    // Timeline times where the source's first and last frames play
+                    let source_first = clip.timeline_time_at(Duration::ZERO).unwrap_or_default();
+                    let source_last = clip.timeline_time_at(asset_duration).unwrap_or_default();
+                    start = start.max(source_first);
+                    end = end.min(source_last);
+                }
+                let changed = start != *new_start_time || end != *new_end_time;
+                *new_start_time = start;
+                *new_end_time = end;
+                changed
+            }
+            _ => false,
+        }
+    }
+
+    fn find_multicam_angle(&self, group_id: &str, angle: usize) -> Result<&MulticamGroup, String> {
+        let group = self
+            .multicam_groups
+            .iter()
+            .find(|g| g.id == group_id)
+            .ok_or_else(|| format!("Multicam group {} not found", group_id))?;
+        if angle >= group.angles.len() {
+            return Err(format!(
+                "Angle {} does not exist in multicam group {}",
+                angle, group_id
+            ));
+        }
+        Ok(group)
+    }
+
+    fn find_subtitle_track(&self, id: &str) -> Result<&SubtitleTrack, String> {
+        self.subtitle_tracks
+            .iter()
+            .find(|t| t.id == id)
+            .ok_or_else(|| format!("Subtitle track {} not found", id))
+    }
+
+    fn find_clip(&self, id: &str) -> Result<&VideoClip, String> {
+        self.clips
+            .iter()
+            .find(|c| c.id == id)
+            .ok_or_else(|| format!("Clip {} not found", id))
+    }
+
+    /// Kind of clip `track` holds, not counting clip `ignoring`, or `None`
+    /// while it holds no others.
+    pub fn track_kind(&self, track: usize, ignoring: &str) -> Option<ClipKind> {
+        self.clips
+            .iter()
+            .find(|c| c.track == track && c.id != ignoring)
+            .map(|c| c.kind)
+    }
+
+    /// Checks that `clip` may sit on `track` alongside the clips already
+    /// there.
+    fn validate_track_kind(&self, clip: &VideoClip, track: usize) -> Result<(), String> {
+        match self.track_kind(track, &clip.id) {
+            Some(kind) if kind != clip.kind => Err(format!(
+                "Track {} holds {} clips, not {} clips",
+                track,
+                kind.name(),
+                clip.kind.name()
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that `clip` may take effects.
+    fn validate_effects_allowed(&self, clip_id: &str) -> Result<(), String> {
+        if self.find_clip(clip_id)?.kind == ClipKind::Audio {
+            return Err(format!("Clip {} is audio, which takes no effects", clip_id));
+        }
+        Ok(())
+    }
+
+    fn find_track_effect(&self, track: usize, id: &str) -> Result<&Effect, String> {
+        self.track_effects
+            .get(&track)
+            .and_then(|effects| effects.iter().find(|e| e.id == id))
+            .ok_or_else(|| format!("Effect {} not found on track {}", id, track))
+    }
+
+    /// Effects that apply to `clip`: its own, then those of its track.
+    pub fn effective_effects(&self, clip: &VideoClip) -> Vec<Effect> {
+        clip.effects
+            .iter()
+            .chain(self.track_effects.get(&clip.track).into_iter().flatten())
+            .cloned()
+            .collect()
+    }
+
+    /// Checks a clip about to be added, of any kind.
+    fn validate_new_clip(&self, clip: &VideoClip) -> Result<(), String> {
+        if self.clips.iter().any(|c| c.id == clip.id) {
+            return Err(format!("Clip {} already exists", clip.id));
+        }
+        if self.trash.iter().any(|c| c.id == clip.id) {
+            return Err(format!("Clip {} is in the trash", clip.id));
+        }
+        // An image added without a length gets the default one
+        if clip.source_kind != SourceKind::Image || clip.end_time != clip.start_time {
+            validate_time_range(clip.start_time, clip.end_time)?;
+        }
+        if clip.source_kind == SourceKind::Image && clip.kind != ClipKind::Video {
+            return Err(format!(
+                "Clip {} shows an image, so it must be a video clip",
+                clip.id
+            ));
+        }
+        validate_track(clip.track)?;
+        self.validate_track_kind(clip, clip.track)?;
+        if clip.kind == ClipKind::Audio && !clip.effects.is_empty() {
+            return Err(format!("Clip {} is audio, which takes no effects", clip.id));
+        }
+        if let Some(multicam) = &clip.multicam {
+            self.find_multicam_angle(&multicam.group_id, multicam.angle)?;
+        }
+        validate_speed(&clip.speed)?;
+        validate_audio(clip, &clip.audio)?;
+        clip.effects.iter().try_for_each(validate_effect)
+    }
+
+    /// Checks that `op` can be applied to the current project state. The same
+    /// rules run on the client before sending and on the server before applying.
+    pub fn validate_operation(&self, op: &EditOperation) -> Result<(), String> {
+        match op {
+            EditOperation::AddClip(clip) => {
+                if clip.kind == ClipKind::Text || clip.text.is_some() {
+                    return Err(format!("Clip {} is text; add it with AddTextClip", clip.id));
+                }
+                self.validate_new_clip(clip)
+            }
+            EditOperation::AddTextClip(clip) => {
+                let Some(text) = clip.text.as_ref().filter(|_| clip.kind == ClipKind::Text) else {
+                    return Err(format!("Clip {} is not a text clip", clip.id));
+                };
+                validate_text(text)?;
+                self.validate_new_clip(clip)
+            }
+            EditOperation::UpdateTextClip { clip_id, text } => {
+                if self.find_clip(clip_id)?.kind != ClipKind::Text {
+                    return Err(format!("Clip {} is not a text clip", clip_id));
+                }
+                validate_text(text)
+            }
+            EditOperation::RemoveClip(id) => self.find_clip(id).map(|_| ()),
+            EditOperation::RestoreClip(id) => {
+                let Some(clip) = self.trash.iter().find(|c| c.id == *id) else {
+                    return Err(format!("Clip {} is not in the trash", id));
+                };
+                self.validate_track_kind(clip, clip.track)
+            }
+            EditOperation::EmptyTrash { .. } => Ok(()),
+            EditOperation::MoveClip { id, new_track, .. } => {
+                let clip = self.find_clip(id)?;
+                validate_track(*new_track)?;
+                self.validate_track_kind(clip, *new_track)
+            }
+            EditOperation::TrimClip {
+                id,
+                new_start_time,
+                new_end_time,
+            } => {
+                self.find_clip(id)?;
+                validate_time_range(*new_start_time, *new_end_time)
+            }
+            EditOperation::SplitClip {
+                id,
+                split_time,
+                new_clip_id,
+            } => {
+                let clip = self.find_clip(id)?;
+                if *split_time <= clip.start_time || *split_time >= clip.end_time {
+                    return Err(format!("{:?} is not inside clip {}", split_time, id));
+                }
+                if new_clip_id.is_empty() {
+                    return Err("New clip id is empty".to_string());
+                }
+                if self
+                    .clips
+                    .iter()
+                    .chain(&self.trash)
+                    .any(|c| c.id == *new_clip_id)
+                {
+                    return Err(format!("Clip {} already exists", new_clip_id));
+                }
+                Ok(())
+            }
+            EditOperation::JoinClips { id, joined_id } => {
+                let clip = self.find_clip(id)?;
+                let joined = self.find_clip(joined_id)?;
+                let continues = id != joined_id
+                    && joined.track == clip.track
+                    && joined.start_time == clip.end_time
+                    && joined.source_file == clip.source_file
+                    && joined.asset_id == clip.asset_id
+                    && joined.source_start == clip.source_time_at(clip.end_time);
+                if !continues {
+                    return Err(format!("Clip {} does not continue clip {}", joined_id, id));
+                }
+                Ok(())
+            }
+            EditOperation::ReplaceClipSource {
+                clip_id,
+                new_asset_id,
+            } => {
+                self.find_clip(clip_id)?;
+                if !self.assets.iter().any(|a| a.id == *new_asset_id) {
+                    return Err(format!("Asset {} not found", new_asset_id));
+                }
+                Ok(())
+            }
+            EditOperation::RelinkAsset { from, new_asset_id } => {
+                if !self.assets.iter().any(|a| a.id == *new_asset_id) {
+                    return Err(format!("Asset {} not found", new_asset_id));
+                }
+                if !self
+                    .clips
+                    .iter()
+                    .chain(&self.trash)
+                    .any(|c| from.matches(c))
+                {
+                    return Err(format!("No clips use {}", from));
+                }
+                Ok(())
+            }
+            EditOperation::AddEffect { clip_id, effect } => {
+                self.validate_effects_allowed(clip_id)?;
+                let clip = self.find_clip(clip_id)?;
+                if clip.effects.iter().any(|e| e.id == effect.id) {
+                    return Err(format!(
+                        "Effect {} is already on clip {}",
+                        effect.id, clip_id
+                    ));
+                }
+                validate_effect(effect)
+            }
+            EditOperation::RemoveEffect { clip_id, effect_id } => {
+                let clip = self.find_clip(clip_id)?;
+                if !clip.effects.iter().any(|e| e.id == *effect_id) {
+                    return Err(format!(
+                        "Effect {} not found on clip {}",
+                        effect_id, clip_id
+                    ));
+                }
+                Ok(())
+            }
+            EditOperation::SetClipEffects { clip_id, effects } => {
+                if effects.is_empty() {
+                    return self.find_clip(clip_id).map(|_| ());
+                }
+                self.validate_effects_allowed(clip_id)?;
+                let mut ids = HashSet::new();
+                for effect in effects {
+                    if !ids.insert(&effect.id) {
+                        return Err(format!("Effect {} appears twice", effect.id));
+                    }
+                    validate_effect(effect)?;
+                }
+                Ok(())
+            }
+            EditOperation::AddTransition {
+                clip_id,
+                transition,
+            } => {
+                let clip = self.find_clip(clip_id)?;
+                if transition.duration > clip.end_time.saturating_sub(clip.start_time) {
+                    return Err(format!(
+                        "Transition of {:?} is longer than clip {}",
+                        transition.duration, clip_id
+                    ));
+                }
+                Ok(())
+            }
+            EditOperation::RemoveTransition { clip_id } => self.find_clip(clip_id).map(|_| ()),
+            EditOperation::SetFrameRate(frame_rate) => {
+                if frame_rate.numerator == 0 || frame_rate.denominator == 0 {
+                    return Err("Frame rate must be positive".to_string());
+                }
+                Ok(())
+            }
+            EditOperation::SetSnapToFrames(_)
+            | EditOperation::SetRippleEdits(_)
+            | EditOperation::SetMagneticTimeline(_)
+            | EditOperation::SetWorkingColorSpace(_) => Ok(()),
+            EditOperation::SetAssetColorSpace { asset_id, .. } => {
+                if !self.assets.iter().any(|a| a.id == *asset_id) {
+                    return Err(format!("Asset {} not found", asset_id));
+                }
+                Ok(())
+            }
+            EditOperation::SetAssetHdr { asset_id, hdr } => {
+                if !self.assets.iter().any(|a| a.id == *asset_id) {
+                    return Err(format!("Asset {} not found", asset_id));
+                }
+                hdr.as_ref().map_or(Ok(()), validate_hdr)
+            }
+            EditOperation::SetHdrOutput(hdr) => hdr.as_ref().map_or(Ok(()), validate_hdr),
+            EditOperation::AlignToReference {
+                reference_clip_id,
+                sync_points,
+            } => {
+                self.find_clip(reference_clip_id)?;
+                for point in sync_points {
+                    if point.clip_id == *reference_clip_id {
+                        return Err(format!(
+                            "Clip {} cannot be aligned to itself",
+                            reference_clip_id
+                        ));
+                    }
+                    self.find_clip(&point.clip_id)?;
+                }
+                Ok(())
+            }
+            EditOperation::AddMulticamGroup(group) => {
+                if self.multicam_groups.iter().any(|g| g.id == group.id) {
+                    return Err(format!("Multicam group {} already exists", group.id));
+                }
+                if group.angles.is_empty() {
+                    return Err("Multicam group needs at least one angle".to_string());
+                }
+                if group.active_angle >= group.angles.len() {
+                    return Err(format!("Angle {} does not exist", group.active_angle));
+                }
+                for angle in &group.angles {
+                    if !self.assets.iter().any(|a| a.id == angle.asset_id) {
+                        return Err(format!("Asset {} not found", angle.asset_id));
+                    }
+                }
+                Ok(())
+            }
+            EditOperation::SwitchAngle {
+                group_id,
+                angle,
+                at_time,
+            } => {
+                let group = self.find_multicam_angle(group_id, *angle)?;
+                let splits = self.clips.iter().any(|c| {
+                    c.multicam.as_ref().is_some_and(|m| m.group_id == *group_id)
+                        && c.start_time < *at_time
+                        && *at_time < c.end_time
+                });
+                let cut_id = group.cut_clip_id(*at_time);
+                if splits && self.clips.iter().chain(&self.trash).any(|c| c.id == cut_id) {
+                    return Err(format!("Angle already switched at {:?}", at_time));
+                }
+                Ok(())
+            }
+            EditOperation::SetAspectRatio(aspect_ratio) => {
+                if aspect_ratio.width == 0 || aspect_ratio.height == 0 {
+                    return Err("Aspect ratio must be positive".to_string());
+                }
+                Ok(())
+            }
+            EditOperation::SetSafeAreas(safe_areas) => {
+                let in_frame = |f: f64| f > 0.0 && f <= 1.0;
+                if !in_frame(safe_areas.title) || !in_frame(safe_areas.action) {
+                    return Err("Safe areas must be between 0 and 1 of the frame".to_string());
+                }
+                if safe_areas.title > safe_areas.action {
+                    return Err("Title safe area must fit inside action safe area".to_string());
+                }
+                Ok(())
+            }
+            EditOperation::SetClipSpeed { clip_id, keyframes } => {
+                self.find_clip(clip_id)?;
+                validate_speed(keyframes)
+            }
+            EditOperation::SetClipAudio { clip_id, audio } => {
+                validate_audio(self.find_clip(clip_id)?, audio)
+            }
+            EditOperation::SetClipEnabled { clip_id, .. } => self.find_clip(clip_id).map(|_| ()),
+            EditOperation::SetTrackMuted { track, .. } => validate_track(*track),
+            EditOperation::SetTrackGain { track, gain_db } => {
+                validate_track(*track)?;
+                validate_gain(*gain_db)
+            }
+            EditOperation::AddTrackEffect { track, effect } => {
+                validate_track(*track)?;
+                if self.track_kind(*track, "") == Some(ClipKind::Audio) {
+                    return Err(format!(
+                        "Track {} holds audio clips, which take no effects",
+                        track
+                    ));
+                }
+                validate_effect(effect)
+            }
+            EditOperation::RemoveTrackEffect { track, effect_id } => {
+                self.find_track_effect(*track, effect_id).map(|_| ())
+            }
+            EditOperation::UpdateTrackEffect { track, effect } => {
+                let existing = self.find_track_effect(*track, &effect.id)?;
+                if existing.effect_type != effect.effect_type {
+                    return Err(format!(
+                        "Effect {} is {:?}, not {:?}",
+                        effect.id, existing.effect_type, effect.effect_type
+                    ));
+                }
+                validate_effect(effect)
+            }
+            EditOperation::SetTrackHeight { .. }
+            | EditOperation::SetTrackColor { .. }
+            | EditOperation::SetTrackCollapsed { .. } => {
+                if self.settings.private_track_layout {
+                    return Err("Track layout is kept by each user in this project".to_string());
+                }
+                Presentation::validate(op)
+            }
+            EditOperation::SetPrivateTrackLayout(_) => Ok(()),
+            EditOperation::AddSubtitleTrack(track) => {
+                if self.subtitle_tracks.iter().any(|t| t.id == track.id) {
+                    return Err(format!("Subtitle track {} already exists", track.id));
+                }
+                if let Some(asset_id) = &track.asset_id {
+                    if !self.assets.iter().any(|a| a.id == *asset_id) {
+                        return Err(format!("Asset {} not found", asset_id));
+                    }
+                }
+                validate_cues(&[], &track.cues)
+            }
+            EditOperation::AddSubtitleCues { track_id, cues } => {
+                let track = self.find_subtitle_track(track_id)?;
+                validate_cues(&track.cues, cues)
+            }
+            EditOperation::RemoveSubtitleTrack(track_id) => {
+                self.find_subtitle_track(track_id).map(|_| ())
+            }
+            EditOperation::AddMarkers(markers) => {
+                let mut ids: HashSet<&str> = self.markers.iter().map(|m| m.id.as_str()).collect();
+                for marker in markers {
+                    if !ids.insert(&marker.id) {
+                        return Err(format!("Marker {} already exists", marker.id));
+                    }
+                }
+                Ok(())
+            }
+            EditOperation::RemoveMarkers(marker_ids) => {
+                for id in marker_ids {
+                    if !self.markers.iter().any(|m| m.id == *id) {
+                        return Err(format!("Marker {} not found", id));
+                    }
+                }
+                Ok(())
+            }
+            EditOperation::SetProjectDuration(duration) => {
+                if duration.is_zero() {
+                    return Err("Project duration must be greater than zero".to_string());
+                }
+                Ok(())
+            }
+            EditOperation::UpdateCollaboratorCursor { new_position, .. } => {
+                validate_track(new_position.track)
+            }
+            EditOperation::SetClipPreviews { clip_id, .. }
+            | EditOperation::SetClipWaveform { clip_id, .. } => self.find_clip(clip_id).map(|_| ()),
+            EditOperation::RenameProject(name) => {
+                if name.trim().is_empty() {
+                    return Err("Project name must not be empty".to_string());
+                }
+                Ok(())
+            }
+            EditOperation::AddCollaborator(collaborator) => collaborator
+                .avatar_url
+                .as_deref()
+                .map_or(Ok(()), validate_avatar_url),
+            EditOperation::RemoveCollaborator(_) => Ok(()),
+            EditOperation::AddAsset(asset) => {
+                if self.assets.iter().any(|a| a.id == asset.id) {
+                    return Err(format!("Asset {} already exists", asset.id));
+                }
+                Ok(())
+            }
+            EditOperation::RemoveAsset(asset_id) => {
+                let asset = self
+                    .assets
+                    .iter()
+                    .find(|a| a.id == *asset_id)
+                    .ok_or_else(|| format!("Asset {} not found", asset_id))?;
+                if self.is_asset_referenced(asset) {
+                    return Err(format!("Asset {} is still used by a clip", asset_id));
+                }
+                Ok(())
+            }
+            EditOperation::Batch(operations) => {
+                if operations.is_empty() {
+                    return Err("Batch is empty".to_string());
+                }
+                let mut preview = self.clone();
+                for (index, operation) in operations.iter().enumerate() {
+                    if matches!(
+                        operation,
+                        EditOperation::Batch(_) | EditOperation::UpdateCollaboratorCursor { .. }
+                    ) {
+                        return Err(format!("{} can't be part of a batch", operation.kind()));
+                    }
+                    preview
+                        .validate_operation(operation)
+                        .map_err(|e| format!("Operation {} of the batch: {}", index + 1, e))?;
+                    preview.apply_operation(operation);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn validate_time_range(start: Duration, end: Duration) -> Result<(), String> {
+    if start >= end {
+        return Err(format!(
+            "Start time {:?} must be before end time {:?}",
+            start, end
+        ));
+    }
+    Ok(())
+}
+
+fn validate_track(track: usize) -> Result<(), String> {
+    if track >= MAX_TRACKS {
+        return Err(format!(
+            "Track {} does not exist (max {})",
+            track,
+            MAX_TRACKS - 1
+        ));
+    }
+    Ok(())
+}
+
+/// Longest avatar URL a collaborator may have.
+const MAX_AVATAR_URL_LEN: usize = 2048;
+
+/// Longest chat message, in characters.
+pub const MAX_CHAT_MESSAGE_LEN: usize = 2000;
+
+/// Checks that a chat message has something to say and isn't too long.
+pub fn validate_chat_message(message: &str) -> Result<(), String> {
+    if message.trim().is_empty() {
+        return Err("Chat message must not be empty".to_string());
+    }
+    if message.chars().count() > MAX_CHAT_MESSAGE_LEN {
+        return Err(format!(
+            "Chat message must be at most {} characters",
+            MAX_CHAT_MESSAGE_LEN
+        ));
+    }
+    Ok(())
+}
+
+/// Longest intent label on an operation, in characters.
+pub const MAX_LABEL_LEN: usize = 80;
+
+/// Checks that an operation's intent label is a short line of text.
+pub fn validate_label(label: &str) -> Result<(), String> {
+    if label.trim().is_empty() {
+        return Err("Label must not be empty".to_string());
+    }
+    if label.chars().count() > MAX_LABEL_LEN {
+        return Err(format!(
+            "Label must be at most {} characters",
+            MAX_LABEL_LEN
+        ));
+    }
+    if label.chars().any(char::is_control) {
+        return Err("Label contains invalid characters".to_string());
+    }
+    Ok(())
+}
+
+/// Checks that `url` is something UIs can safely put in an image `src`: an
+/// http(s) URL of reasonable length.
+pub fn validate_avatar_url(url: &str) -> Result<(), String> {
+    if !url.starts_with("https://") && !url.starts_with("http://") {
+        return Err("Avatar URL must be an http or https URL".to_string());
+    }
+    if url.len() > MAX_AVATAR_URL_LEN {
+        return Err(format!(
+            "Avatar URL must be at most {} characters",
+            MAX_AVATAR_URL_LEN
+        ));
+    }
+    if url
+        .chars()
+        .any(|c| c.is_whitespace() || c.is_control() || c == '"')
+    {
+        return Err("Avatar URL contains invalid characters".to_string());
+    }
+    Ok(())
+}
+
+fn validate_hdr(hdr: &HdrMetadata) -> Result<(), String> {
+    let Some(display) = &hdr.mastering_display else {
+        return Ok(());
+    };
+    for (name, point) in [
+        ("red", display.red),
+        ("green", display.green),
+        ("blue", display.blue),
+        ("white point", display.white_point),
+    ] {
+        if !(0.0..=1.0).contains(&point.x) || !(0.0..=1.0).contains(&point.y) {
+            return Err(format!(
+                "Mastering display {} chromaticity must be within 0 to 1",
+                name
+            ));
+        }
+    }
+    if !(display.min_luminance >= 0.0 && display.min_luminance < display.max_luminance) {
+        return Err(format!(
+            "Mastering display luminance range {} to {} is invalid",
+            display.min_luminance, display.max_luminance
+        ));
+    }
+    Ok(())
+}
+
+fn validate_speed(keyframes: &[SpeedKeyframe]) -> Result<(), String> {
+    for keyframe in keyframes {
+        if !(MIN_SPEED..=MAX_SPEED).contains(&keyframe.speed) {
+            return Err(format!(
+                "Speed must be between {} and {}, got {}",
+                MIN_SPEED, MAX_SPEED, keyframe.speed
+            ));
+        }
+    }
+    if keyframes
+        .windows(2)
+        .any(|pair| pair[0].offset >= pair[1].offset)
+    {
+        return Err("Speed keyframes must be in order, one per offset".to_string());
+    }
+    Ok(())
+}
+
+fn validate_gain(gain_db: f64) -> Result<(), String> {
+    if !(MIN_GAIN_DB..=MAX_GAIN_DB).contains(&gain_db) {
+        return Err(format!(
+            "Gain must be between {} and {} dB, got {}",
+            MIN_GAIN_DB, MAX_GAIN_DB, gain_db
+        ));
+    }
+    Ok(())
+}
+
+fn validate_audio(clip: &VideoClip, audio: &ClipAudio) -> Result<(), String> {
+    validate_gain(audio.gain_db)?;
+    if audio.fade_in + audio.fade_out > clip.end_time.saturating_sub(clip.start_time) {
+        return Err("Audio fades must fit within the clip".to_string());
+    }
+    Ok(())
+}
+
+/// Longest text a text clip may show, in characters.
+pub const MAX_TEXT_LEN: usize = 1000;
+
+fn validate_text(text: &ClipText) -> Result<(), String> {
+    if text.content.trim().is_empty() {
+        return Err("Text must not be empty".to_string());
+    }
+    if text.content.chars().count() > MAX_TEXT_LEN {
+        return Err(format!("Text must be at most {} characters", MAX_TEXT_LEN));
+    }
+    if text.font.trim().is_empty() || text.font.chars().any(char::is_control) {
+        return Err(format!("Invalid font {:?}", text.font));
+    }
+    if !(text.size > 0.0 && text.size <= 1.0) {
+        return Err("Text size must be between 0 and 1 of the frame".to_string());
+    }
+    let hex = text.color.strip_prefix('#').unwrap_or_default();
+    if !matches!(hex.len(), 6 | 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid text color {:?}", text.color));
+    }
+    if !(0.0..=1.0).contains(&text.x) || !(0.0..=1.0).contains(&text.y) {
+        return Err("Text position must be within the frame".to_string());
+    }
+    Ok(())
+}
+
+/// Checks `cues` for adding to a track that already holds `existing`.
+fn validate_cues(existing: &[SubtitleCue], cues: &[SubtitleCue]) -> Result<(), String> {
+    let mut ids: HashSet<&str> = existing.iter().map(|c| c.id.as_str()).collect();
+    for cue in cues {
+        if !ids.insert(&cue.id) {
+            return Err(format!("Subtitle cue {} already exists", cue.id));
+        }
+        validate_time_range(cue.start_time, cue.end_time)?;
+        for word in &cue.words {
+            if word.start_time > word.end_time
+                || word.start_time < cue.start_time
+                || word.end_time > cue.end_time
+            {
+                return Err(format!(
+                    "Word \"{}\" is not timed within cue {}",
+                    word.text, cue.id
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn validate_effect(effect: &Effect) -> Result<(), String> {
+    let accepted = effect.effect_type.parameters();
+    for (name, value) in &effect.parameters {
+        let Some(parameter) = accepted.iter().find(|p| p.name == name) else {
+            return Err(format!(
+                "{:?} has no parameter {}",
+                effect.effect_type, name
+            ));
+        };
+        if !value.is_finite() || *value < parameter.min || *value > parameter.max {
+            return Err(format!(
+                "{:?} {} must be between {} and {}, got {}",
+                effect.effect_type, name, parameter.min, parameter.max, value
+            ));
+        }
+    }
+    if effect.effect_type == EffectType::Crop {
+        let get = |name: &str| effect.parameters.get(name).copied();
+        let x = get("x").unwrap_or(0.0);
+        let y = get("y").unwrap_or(0.0);
+        // Leeway for fractions that add up to 1 but not exactly in floats
+        let beyond = |edge: f64| edge > 1.0 + 1e-9;
+        if beyond(x + get("width").unwrap_or(1.0)) || beyond(y + get("height").unwrap_or(1.0)) {
+            return Err("Crop must stay within the picture".to_string());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project() -> VideoProject {
+        VideoProject::new(
+            "project".to_string(),
+            "Rough cut".to_string(),
+            "client".to_string(),
+            "Client".to_string(),
+        )
+    }
+
+    #[test]
+    fn a_batch_applies_every_operation() {
+        let mut project = project();
+        let batch = EditOperation::Batch(vec![
+            EditOperation::RenameProject("Final cut".to_string()),
+            EditOperation::SetSnapToFrames(true),
+        ]);
+        assert!(project.validate_operation(&batch).is_ok());
+        project.apply_operation(&batch);
+        assert_eq!(project.name, "Final cut");
+        assert!(project.settings.snap_to_frames);
+    }
+
+    #[test]
+    fn a_batch_with_an_invalid_operation_applies_none() {
+        let project = project();
+        let batch = EditOperation::Batch(vec![
+            EditOperation::RenameProject("Final cut".to_string()),
+            EditOperation::RemoveClip("missing".to_string()),
+        ]);
+        let error = project.validate_operation(&batch).unwrap_err();
+        assert!(error.starts_with("Operation 2 of the batch"), "{}", error);
+        assert_eq!(project.name, "Rough cut");
+
+        let nested = EditOperation::Batch(vec![EditOperation::Batch(vec![
+            EditOperation::SetSnapToFrames(true),
+        ])]);
+        assert!(project.validate_operation(&nested).is_err());
+        assert!(project
+            .validate_operation(&EditOperation::Batch(Vec::new()))
+            .is_err());
+    }
+
+    fn crop(parameters: &[(&str, f64)]) -> Effect {
+        Effect {
+            id: "crop".to_string(),
+            effect_type: EffectType::Crop,
+            start_time: Duration::ZERO,
+            end_time: Duration::from_secs(5),
+            parameters: parameters
+                .iter()
+                .map(|(name, value)| (name.to_string(), *value))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn crops_must_stay_within_the_picture() {
+        assert!(validate_effect(&crop(&[("x", 0.25), ("width", 0.75)])).is_ok());
+        assert!(validate_effect(&crop(&[
+            ("x", 0.1),
+            ("width", 0.2),
+            ("y", 0.9),
+            ("height", 0.1)
+        ]))
+        .is_ok());
+        // Fractions that add up to 1 only nearly
+        assert!(validate_effect(&crop(&[("x", 0.1), ("width", 0.9)])).is_ok());
+        assert!(validate_effect(&crop(&[("x", 0.5), ("width", 0.6)])).is_err());
+        assert!(validate_effect(&crop(&[("y", 0.5)])).is_err());
+    }
+
+    #[test]
+    fn effects_take_only_their_parameters_in_range() {
+        assert!(validate_effect(&crop(&[("radius", 0.5)])).is_err());
+        assert!(validate_effect(&crop(&[("width", 1.5)])).is_err());
+        assert!(validate_effect(&crop(&[("x", f64::NAN)])).is_err());
+    }
+
+    #[test]
+    fn added_effects_stack_on_a_clip() {
+        let mut project = project();
+        project.apply_operation(&EditOperation::AddClip(VideoClip {
+            id: "a".to_string(),
+            end_time: Duration::from_secs(5),
+            ..VideoClip::default()
+        }));
+        let ids = IdGenerator::sequential(1);
+        for value in [2.0, 4.0] {
+            project.apply_operation(&EditOperation::AddEffect {
+                clip_id: "a".to_string(),
+                effect: Effect::new(&ids, EffectType::Blur, Some(value)),
+            });
+        }
+        let effects = &project.clips[0].effects;
+        assert_eq!(effects.len(), 2);
+        assert_eq!(effects[1].parameters["value"], 4.0);
+        let restack = EditOperation::SetClipEffects {
+            clip_id: "a".to_string(),
+            effects: effects.clone(),
+        };
+        assert!(project.validate_operation(&restack).is_ok());
+        let again = EditOperation::AddEffect {
+            clip_id: "a".to_string(),
+            effect: effects[0].clone(),
+        };
+        assert!(project.validate_operation(&again).is_err());
     }
 }
@@ -0,0 +1,135 @@
+// weframe-shared/src/migrations.rs
+use crate::{EffectType, VideoProject};
+use serde_json::{json, Value};
+
+/// Schema version written by this build. Bump it together with a new entry
+/// in `MIGRATIONS` whenever the persisted shape of `VideoProject` changes.
+pub const CURRENT_SCHEMA_VERSION: u32 = 2;
+
+type Migration = fn(&mut Value) -> Result<(), String>;
+
+/// `MIGRATIONS[n]` upgrades a project from schema version `n` to `n + 1`.
+const MIGRATIONS: &[Migration] = &[v0_to_v1, v1_to_v2];
+
+/// Upgrades a persisted or imported project, one version at a time, and
+/// deserializes it.
+pub fn migrate_project(mut value: Value) -> Result<VideoProject, String> {
+    let version = value
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .unwrap_or(0) as u32;
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(format!(
+            "Project schema version {} is newer than supported version {}",
+            version, CURRENT_SCHEMA_VERSION
+        ));
+    }
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        migration(&mut value)
+            .map_err(|e| format!("Migrating project from schema {}: {}", from, e))?;
+        value["schema_version"] = json!(from + 1);
+    }
+
+    serde_json::from_value(value).map_err(|e| format!("Invalid project: {}", e))
+}
+
+/// Version 0 is the original model: clips without source offsets, and
+/// projects without assets or settings.
+fn v0_to_v1(project: &mut Value) -> Result<(), String> {
+    let project = project.as_object_mut().ok_or("project is not an object")?;
+    project.entry("assets").or_insert_with(|| json!([]));
+    project.entry("settings").or_insert_with(
+        || json!({ "frame_rate": { "numerator": 30, "denominator": 1 }, "snap_to_frames": false }),
+    );
+    if let Some(clips) = project.get_mut("clips").and_then(Value::as_array_mut) {
+        for clip in clips.iter_mut().filter_map(Value::as_object_mut) {
+            clip.entry("source_start")
+                .or_insert_with(|| json!({ "secs": 0, "nanos": 0 }));
+        }
+    }
+    Ok(())
+}
+
+/// Version 1 let effects carry any parameters. Those their type doesn't take
+/// are dropped, and values out of range clamped, so saved effects pass
+/// validation again.
+fn v1_to_v2(project: &mut Value) -> Result<(), String> {
+    let project = project.as_object_mut().ok_or("project is not an object")?;
+    let mut effects: Vec<&mut Value> = Vec::new();
+    for (key, value) in project.iter_mut() {
+        match key.as_str() {
+            "clips" | "trash" => {
+                for clip in value.as_array_mut().into_iter().flatten() {
+                    if let Some(list) = clip.get_mut("effects").and_then(Value::as_array_mut) {
+                        effects.extend(list.iter_mut());
+                    }
+                }
+            }
+            "track_effects" => {
+                for list in value
+                    .as_object_mut()
+                    .into_iter()
+                    .flat_map(|t| t.values_mut())
+                {
+                    effects.extend(list.as_array_mut().into_iter().flatten());
+                }
+            }
+            _ => {}
+        }
+    }
+    for effect in effects {
+        let Some(effect_type) = effect
+            .get("effect_type")
+            .and_then(|t| serde_json::from_value::<EffectType>(t.clone()).ok())
+        else {
+            continue;
+        };
+        let Some(parameters) = effect.get_mut("parameters").and_then(Value::as_object_mut) else {
+            continue;
+        };
+        let accepted = effect_type.parameters();
+        parameters.retain(|name, value| {
+            let Some(parameter) = accepted.iter().find(|p| p.name == name) else {
+                return false;
+            };
+            if let Some(number) = value.as_f64() {
+                *value = json!(number.clamp(parameter.min, parameter.max));
+            }
+            true
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn v1_effects_lose_parameters_their_type_does_not_take() {
+        let effect = json!({
+            "id": "effect",
+            "effect_type": "Brightness",
+            "start_time": { "secs": 0, "nanos": 0 },
+            "end_time": { "secs": 5, "nanos": 0 },
+            "parameters": { "value": 50.0, "radius": 3.0 }
+        });
+        let mut project = serde_json::to_value(VideoProject::new(
+            "project".to_string(),
+            "Rough cut".to_string(),
+            "client".to_string(),
+            "Client".to_string(),
+        ))
+        .unwrap();
+        project["schema_version"] = json!(1);
+        project["track_effects"] = json!({ "0": [effect] });
+
+        let project = migrate_project(project).unwrap();
+        assert_eq!(project.schema_version, CURRENT_SCHEMA_VERSION);
+        let effect = &project.track_effects[&0][0];
+        assert_eq!(effect.parameters.len(), 1);
+        // Clamped to the most brightness takes
+        assert_eq!(effect.parameters["value"], 4.0);
+    }
+}
@@ -0,0 +1,76 @@
+// weframe-shared/src/network.rs
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// A bad network to put between a client and the server, so frontends and
+/// bots can be tried against one without a proxy. Each message is held
+/// back for `latency_ms`, give or take up to `jitter_ms`. It is lost with
+/// probability `drop_rate`. With probability `reorder_rate` it is held back
+/// further, so messages sent after it overtake it. Other messages arrive in
+/// the order they were sent, as they would over a real connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConditions {
+    pub latency_ms: u32,
+    pub jitter_ms: u32,
+    pub drop_rate: f64,
+    pub reorder_rate: f64,
+}
+
+impl NetworkConditions {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, rate) in [
+            ("drop_rate", self.drop_rate),
+            ("reorder_rate", self.reorder_rate),
+        ] {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(format!("{} must be between 0 and 1, got {}", name, rate));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decides what becomes of each message sent one way across a simulated
+/// network.
+#[derive(Debug, Clone)]
+pub struct NetworkSimulator {
+    conditions: NetworkConditions,
+    /// When the last message kept in order arrives; later ones can't
+    /// arrive before it.
+    last_arrival: Duration,
+}
+
+impl NetworkSimulator {
+    pub fn new(conditions: NetworkConditions) -> Self {
+        NetworkSimulator {
+            conditions,
+            last_arrival: Duration::ZERO,
+        }
+    }
+
+    pub fn conditions(&self) -> NetworkConditions {
+        self.conditions
+    }
+
+    /// When a message sent at `now` arrives, or `None` if it is lost. Times
+    /// are measured from any fixed point; `random` returns numbers in
+    /// `[0, 1)`.
+    pub fn arrival(&mut self, now: Duration, mut random: impl FnMut() -> f64) -> Option<Duration> {
+        let conditions = self.conditions;
+        if random() < conditions.drop_rate {
+            return None;
+        }
+        let jitter = (random() * 2.0 - 1.0) * conditions.jitter_ms as f64;
+        let delay = (conditions.latency_ms as f64 + jitter).max(0.0);
+        let arrival = now + Duration::from_secs_f64(delay / 1000.0);
+        if random() < conditions.reorder_rate {
+            // Late enough for whatever is sent next to get there first
+            let slack = (conditions.latency_ms + conditions.jitter_ms).max(50) as f64;
+            return Some(arrival + Duration::from_secs_f64(slack * (1.0 + random()) / 1000.0));
+        }
+        let arrival = arrival.max(self.last_arrival);
+        self.last_arrival = arrival;
+        Some(arrival)
+    }
+}
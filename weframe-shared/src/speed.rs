@@ -0,0 +1,158 @@
+// weframe-shared/src/speed.rs
+use crate::{SpeedKeyframe, VideoClip};
+use std::time::Duration;
+
+/// Speed at `offset` seconds into a clip with these keyframes.
+fn speed_at(keyframes: &[SpeedKeyframe], offset: f64) -> f64 {
+    let Some(first) = keyframes.first() else {
+        return 1.0;
+    };
+    if offset <= first.offset.as_secs_f64() {
+        return first.speed;
+    }
+    for pair in keyframes.windows(2) {
+        let (a, b) = (pair[0].offset.as_secs_f64(), pair[1].offset.as_secs_f64());
+        if offset <= b {
+            let t = if b > a { (offset - a) / (b - a) } else { 1.0 };
+            return pair[0].speed + (pair[1].speed - pair[0].speed) * t;
+        }
+    }
+    keyframes[keyframes.len() - 1].speed
+}
+
+/// Seconds of source played between the clip's start and `offset` seconds
+/// into it; negative for offsets before the start.
+fn source_elapsed(keyframes: &[SpeedKeyframe], offset: f64) -> f64 {
+    if offset <= 0.0 {
+        return offset * speed_at(keyframes, 0.0);
+    }
+    // Speed is linear between keyframes, so each piece is a trapezoid
+    let mut total = 0.0;
+    let mut t = 0.0;
+    let breaks = keyframes
+        .iter()
+        .map(|k| k.offset.as_secs_f64())
+        .filter(|&b| b > 0.0 && b < offset)
+        .chain(std::iter::once(offset));
+    for next in breaks {
+        total += (next - t) * (speed_at(keyframes, t) + speed_at(keyframes, next)) / 2.0;
+        t = next;
+    }
+    total
+}
+
+/// Inverse of `source_elapsed`: how far into the clip `elapsed` seconds of
+/// source have played. Speeds are always positive, so this is unique.
+fn offset_for(keyframes: &[SpeedKeyframe], elapsed: f64) -> f64 {
+    if elapsed <= 0.0 {
+        return elapsed / speed_at(keyframes, 0.0);
+    }
+    let mut remaining = elapsed;
+    let mut t = 0.0;
+    for next in keyframes
+        .iter()
+        .map(|k| k.offset.as_secs_f64())
+        .filter(|&b| b > 0.0)
+    {
+        let (v0, v1) = (speed_at(keyframes, t), speed_at(keyframes, next));
+        let area = (next - t) * (v0 + v1) / 2.0;
+        if remaining <= area {
+            // Solve v0·d + a·d²/2 = remaining, in a form that stays stable
+            // as the acceleration `a` approaches zero
+            let a = (v1 - v0) / (next - t);
+            let root = (v0 * v0 + 2.0 * a * remaining).max(0.0).sqrt();
+            return t + 2.0 * remaining / (v0 + root);
+        }
+        remaining -= area;
+        t = next;
+    }
+    t + remaining / speed_at(keyframes, t)
+}
+
+fn nanos(secs: f64) -> i128 {
+    (secs * 1e9).round() as i128
+}
+
+impl VideoClip {
+    /// Playback speed at `offset` into the clip: 1 without keyframes, linear
+    /// between keyframes and held before the first and after the last.
+    pub fn speed_at(&self, offset: Duration) -> f64 {
+        speed_at(&self.speed, offset.as_secs_f64())
+    }
+
+    /// Source time the clip plays at `timeline_time`. Outside the clip the
+    /// speed at the nearer end carries on, which is what extending the clip
+    /// by a trim would play.
+    pub fn source_time_at(&self, timeline_time: Duration) -> Duration {
+        let offset = timeline_time.as_nanos() as i128 - self.start_time.as_nanos() as i128;
+        let elapsed = if self.speed.is_empty() {
+            offset
+        } else {
+            nanos(source_elapsed(&self.speed, offset as f64 / 1e9))
+        };
+        let source = self.source_start.as_nanos() as i128 + elapsed;
+        Duration::from_nanos(source.max(0) as u64)
+    }
+
+    /// Timeline time at which the clip plays `source_time`, extending past
+    /// either end as `source_time_at` does. `None` if that would be before
+    /// the start of the timeline.
+    pub fn timeline_time_at(&self, source_time: Duration) -> Option<Duration> {
+        let time = self.timeline_nanos_at(source_time);
+        (time >= 0).then(|| Duration::from_nanos(time as u64))
+    }
+
+    /// `timeline_time_at` in signed nanoseconds, for arithmetic that may
+    /// pass through negative times.
+    pub(crate) fn timeline_nanos_at(&self, source_time: Duration) -> i128 {
+        let elapsed = source_time.as_nanos() as i128 - self.source_start.as_nanos() as i128;
+        let offset = if self.speed.is_empty() {
+            elapsed
+        } else {
+            nanos(offset_for(&self.speed, elapsed as f64 / 1e9))
+        };
+        self.start_time.as_nanos() as i128 + offset
+    }
+
+    /// The clip cut down (or extended) to play from `start` to `end` on the
+    /// timeline, showing the same media at the same speeds there. Audio
+    /// fades stay only on ends that are kept.
+    pub fn slice(&self, start: Duration, end: Duration) -> VideoClip {
+        let mut piece = self.clone();
+        piece.source_start = self.source_time_at(start);
+        piece.start_time = start;
+        piece.end_time = end;
+        if start > self.start_time {
+            piece.audio.fade_in = Duration::ZERO;
+        }
+        if end < self.end_time {
+            piece.audio.fade_out = Duration::ZERO;
+        }
+        if !self.speed.is_empty() {
+            if start >= self.start_time {
+                // Keep the ramp from the cut on, pinning the speed there
+                let cut = start - self.start_time;
+                piece.speed = std::iter::once(SpeedKeyframe {
+                    offset: Duration::ZERO,
+                    speed: self.speed_at(cut),
+                })
+                .chain(
+                    self.speed
+                        .iter()
+                        .filter(|k| k.offset > cut)
+                        .map(|k| SpeedKeyframe {
+                            offset: k.offset - cut,
+                            speed: k.speed,
+                        }),
+                )
+                .collect();
+            } else {
+                let added = self.start_time - start;
+                for keyframe in &mut piece.speed {
+                    keyframe.offset += added;
+                }
+            }
+        }
+        piece
+    }
+}
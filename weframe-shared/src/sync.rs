@@ -0,0 +1,100 @@
+// weframe-shared/src/sync.rs
+use crate::{OTOperation, VideoProject};
+
+/// Server-confirmed project state plus the local operations the server has
+/// not echoed back yet. The optimistic project a client shows is always
+/// `confirmed` with `pending` replayed on top. Shared by the browser client
+/// and native clients such as bots.
+pub struct SyncState {
+    pub confirmed: VideoProject,
+    pub pending: Vec<OTOperation>,
+    pub server_version: usize,
+    /// Project being assembled from a chunked initial sync.
+    pub incoming: Option<VideoProject>,
+    /// Set once the server has sent a whole project, after which a dropped
+    /// connection can resume from `server_version`.
+    pub synced: bool,
+}
+
+impl SyncState {
+    pub fn new(project: VideoProject) -> Self {
+        SyncState {
+            confirmed: project,
+            pending: Vec::new(),
+            server_version: 0,
+            incoming: None,
+            synced: false,
+        }
+    }
+
+    /// Takes a whole project from the server, at `server_version`.
+    pub fn load(&mut self, project: VideoProject, server_version: usize) {
+        self.confirmed = project;
+        self.server_version = server_version;
+        self.synced = true;
+    }
+
+    /// Where a new connection should resume from, if anywhere.
+    pub fn resume_from(&self) -> Option<usize> {
+        self.synced.then_some(self.server_version)
+    }
+
+    /// Compares the server's checksum of its project at `server_version`
+    /// with ours, returning ours if they differ, in which case the client
+    /// should ask for a `Resync`. Cursor moves, which the checksum leaves
+    /// out, may still be on their way, so we can be behind the server but
+    /// not ahead of it.
+    pub fn diverged(&self, server_version: usize, checksum: u64) -> Option<u64> {
+        if !self.synced || self.incoming.is_some() || self.server_version > server_version {
+            return None;
+        }
+        let actual = self.confirmed.checksum();
+        (actual != checksum).then_some(actual)
+    }
+
+    /// Applies an operation the server committed. Our own operations stop
+    /// being pending; others' are ordered before everything still pending.
+    pub fn confirm(&mut self, operation: &OTOperation, client_id: &str) {
+        if operation.client_id == client_id {
+            self.pending
+                .retain(|op| op.client_version != operation.client_version);
+        } else {
+            // The server orders this before all our pending operations and
+            // transforms them past it when they arrive; do the same so the
+            // optimistic project matches what it will commit.
+            self.pending = std::mem::take(&mut self.pending)
+                .into_iter()
+                .filter_map(|mut op| {
+                    op.operation = op.operation.transform(&operation.operation)?;
+                    Some(op)
+                })
+                .collect();
+        }
+        self.confirmed.apply_operation(&operation.operation);
+        // Cursor moves can arrive after edits the server committed later
+        self.server_version = self.server_version.max(operation.server_version + 1);
+    }
+
+    /// Drops the pending operation the server refused, returning it.
+    pub fn reject(&mut self, client_version: usize) -> Option<OTOperation> {
+        let index = self
+            .pending
+            .iter()
+            .position(|op| op.client_version == client_version)?;
+        Some(self.pending.remove(index))
+    }
+
+    /// Replays pending operations on top of the confirmed state, dropping any
+    /// that no longer apply.
+    pub fn rebuild(&mut self) -> VideoProject {
+        let mut project = self.confirmed.clone();
+        self.pending.retain(|op| {
+            let valid = project.validate_operation(&op.operation).is_ok();
+            if valid {
+                project.apply_operation(&op.operation);
+            }
+            valid
+        });
+        project
+    }
+}
@@ -0,0 +1,209 @@
+// weframe-shared/src/transform.rs
+use crate::{EditOperation, Effect};
+
+impl EditOperation {
+    /// Clip whose content or placement the operation changes, if any.
+    fn edited_clip(&self) -> Option<&str> {
+        match self {
+            EditOperation::RemoveClip(id)
+            | EditOperation::MoveClip { id, .. }
+            | EditOperation::TrimClip { id, .. }
+            | EditOperation::SplitClip { id, .. }
+            | EditOperation::JoinClips { id, .. } => Some(id),
+            EditOperation::ReplaceClipSource { clip_id, .. }
+            | EditOperation::AddEffect { clip_id, .. }
+            | EditOperation::RemoveEffect { clip_id, .. }
+            | EditOperation::SetClipEffects { clip_id, .. }
+            | EditOperation::AddTransition { clip_id, .. }
+            | EditOperation::RemoveTransition { clip_id }
+            | EditOperation::SetClipSpeed { clip_id, .. }
+            | EditOperation::SetClipAudio { clip_id, .. }
+            | EditOperation::SetClipEnabled { clip_id, .. }
+            | EditOperation::UpdateTextClip { clip_id, .. }
+            | EditOperation::SetClipPreviews { clip_id, .. }
+            | EditOperation::SetClipWaveform { clip_id, .. } => Some(clip_id),
+            _ => None,
+        }
+    }
+
+    /// Rewrites `self`, made without knowing of `concurrent`, to apply after
+    /// it. `None` when `concurrent` already did what `self` meant to or made
+    /// it moot, e.g. both removed the same clip, or `concurrent` removed the
+    /// clip `self` edits.
+    ///
+    /// The server transforms every client operation past those committed
+    /// since the version it was made at, and clients transform their pending
+    /// operations past each remote one the same way, so both agree on what
+    /// becomes of an operation without a round trip. Anything not covered
+    /// here is last-writer-wins in server order.
+    pub fn transform(&self, concurrent: &EditOperation) -> Option<EditOperation> {
+        match (self, concurrent) {
+            // A batch is transformed past as its operations in turn, and
+            // transforms as its operations each. It applies whole or not at
+            // all, so one superseded operation drops the rest with it
+            (op, EditOperation::Batch(concurrent)) => concurrent
+                .iter()
+                .try_fold(op.clone(), |op, concurrent| op.transform(concurrent)),
+            (EditOperation::Batch(operations), concurrent) => operations
+                .iter()
+                .map(|op| op.transform(concurrent))
+                .collect::<Option<Vec<EditOperation>>>()
+                .map(EditOperation::Batch),
+            (op, EditOperation::RemoveClip(removed)) if op.edited_clip() == Some(removed) => None,
+            (EditOperation::JoinClips { joined_id, .. }, EditOperation::RemoveClip(removed))
+                if joined_id == removed =>
+            {
+                None
+            }
+            // Two cuts of the same clip: the same cut happens once, and a
+            // later one now falls in the piece the other split off
+            (
+                EditOperation::SplitClip {
+                    id,
+                    split_time,
+                    new_clip_id,
+                },
+                EditOperation::SplitClip {
+                    id: split_id,
+                    split_time: split_at,
+                    new_clip_id: split_off,
+                },
+            ) if id == split_id => match split_time.cmp(split_at) {
+                std::cmp::Ordering::Equal => None,
+                std::cmp::Ordering::Greater => Some(EditOperation::SplitClip {
+                    id: split_off.clone(),
+                    split_time: *split_time,
+                    new_clip_id: new_clip_id.clone(),
+                }),
+                std::cmp::Ordering::Less => Some(self.clone()),
+            },
+            (EditOperation::RestoreClip(id), EditOperation::RestoreClip(restored))
+                if id == restored =>
+            {
+                None
+            }
+            (EditOperation::RestoreClip(id), EditOperation::EmptyTrash { clip_ids })
+                if clip_ids.as_ref().is_none_or(|ids| ids.contains(id)) =>
+            {
+                None
+            }
+            (
+                EditOperation::RemoveEffect { clip_id, effect_id },
+                EditOperation::RemoveEffect {
+                    clip_id: removed_from,
+                    effect_id: removed,
+                },
+            ) if clip_id == removed_from && effect_id == removed => None,
+            (
+                EditOperation::RemoveEffect { clip_id, effect_id },
+                EditOperation::SetClipEffects {
+                    clip_id: replaced_on,
+                    effects,
+                },
+            ) if clip_id == replaced_on && !effects.iter().any(|e| e.id == *effect_id) => None,
+            (EditOperation::RemoveMarkers(marker_ids), EditOperation::RemoveMarkers(removed)) => {
+                let left: Vec<String> = marker_ids
+                    .iter()
+                    .filter(|id| !removed.contains(id))
+                    .cloned()
+                    .collect();
+                (!left.is_empty()).then_some(EditOperation::RemoveMarkers(left))
+            }
+            (
+                EditOperation::RemoveTrackEffect { track, effect_id }
+                | EditOperation::UpdateTrackEffect {
+                    track,
+                    effect: Effect { id: effect_id, .. },
+                },
+                EditOperation::RemoveTrackEffect {
+                    track: removed_from,
+                    effect_id: removed,
+                },
+            ) if track == removed_from && effect_id == removed => None,
+            (
+                EditOperation::RemoveSubtitleTrack(track_id)
+                | EditOperation::AddSubtitleCues { track_id, .. },
+                EditOperation::RemoveSubtitleTrack(removed),
+            ) if track_id == removed => None,
+            (EditOperation::RemoveAsset(asset_id), EditOperation::RemoveAsset(removed))
+                if asset_id == removed =>
+            {
+                None
+            }
+            _ => Some(self.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn trim(id: &str) -> EditOperation {
+        EditOperation::TrimClip {
+            id: id.to_string(),
+            new_start_time: Duration::from_secs(1),
+            new_end_time: Duration::from_secs(4),
+        }
+    }
+
+    fn split(id: &str, at: u64, new_clip_id: &str) -> EditOperation {
+        EditOperation::SplitClip {
+            id: id.to_string(),
+            split_time: Duration::from_secs(at),
+            new_clip_id: new_clip_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn edits_of_a_removed_clip_are_superseded() {
+        let removed = EditOperation::RemoveClip("a".to_string());
+        assert!(trim("a").transform(&removed).is_none());
+        assert!(removed.transform(&removed).is_none());
+        assert!(matches!(
+            trim("b").transform(&removed),
+            Some(EditOperation::TrimClip { id, .. }) if id == "b"
+        ));
+    }
+
+    #[test]
+    fn concurrent_splits_of_one_clip() {
+        let first = split("a", 5, "b");
+        assert!(split("a", 5, "c").transform(&first).is_none());
+        // A later cut now falls in the piece the first split off
+        assert!(matches!(
+            split("a", 8, "c").transform(&first),
+            Some(EditOperation::SplitClip { id, split_time, new_clip_id })
+                if id == "b" && split_time == Duration::from_secs(8) && new_clip_id == "c"
+        ));
+        assert!(matches!(
+            split("a", 2, "c").transform(&first),
+            Some(EditOperation::SplitClip { id, .. }) if id == "a"
+        ));
+    }
+
+    #[test]
+    fn batches_transform_as_their_operations() {
+        let removal = EditOperation::Batch(vec![
+            EditOperation::RemoveClip("x".to_string()),
+            EditOperation::RemoveClip("a".to_string()),
+        ]);
+        assert!(trim("a").transform(&removal).is_none());
+        assert!(trim("b").transform(&removal).is_some());
+
+        let batch = EditOperation::Batch(vec![trim("b"), trim("c")]);
+        assert!(matches!(
+            batch.transform(&EditOperation::RemoveClip("a".to_string())),
+            Some(EditOperation::Batch(operations)) if operations.len() == 2
+        ));
+    }
+
+    #[test]
+    fn a_batch_is_dropped_whole_when_any_operation_is_superseded() {
+        let batch = EditOperation::Batch(vec![trim("b"), trim("a"), split("c", 2, "d")]);
+        assert!(batch
+            .transform(&EditOperation::RemoveClip("a".to_string()))
+            .is_none());
+    }
+}
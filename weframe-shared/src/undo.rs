@@ -0,0 +1,338 @@
+// weframe-shared/src/undo.rs
+use crate::{EditOperation, MediaReference, VideoProject};
+
+impl EditOperation {
+    /// The operation that reverts `self`, given the project as it was before
+    /// `self` was applied. `None` when there is nothing to revert or no
+    /// single operation can: emptying the trash, edits that cut or move
+    /// several clips at once, presence updates and server-generated previews.
+    ///
+    /// The inverse puts back what `self` changed directly. Clips that ripple
+    /// edits or the magnetic timeline pushed along are settled again when it
+    /// is applied, not restored, and an undone `AddClip` goes to the trash.
+    pub fn invert(&self, project: &VideoProject) -> Option<EditOperation> {
+        let clip = |id: &str| project.clips.iter().find(|c| c.id == id);
+        let asset = |id: &str| project.assets.iter().find(|a| a.id == id);
+        let settings = &project.settings;
+        Some(match self {
+            EditOperation::AddClip(added) | EditOperation::AddTextClip(added) => {
+                EditOperation::RemoveClip(added.id.clone())
+            }
+            EditOperation::UpdateTextClip { clip_id, .. } => EditOperation::UpdateTextClip {
+                clip_id: clip_id.clone(),
+                text: clip(clip_id)?.text.clone()?,
+            },
+            EditOperation::RemoveClip(id) => EditOperation::RestoreClip(clip(id)?.id.clone()),
+            EditOperation::RestoreClip(id) => {
+                let trashed = project.trash.iter().find(|c| c.id == *id)?;
+                EditOperation::RemoveClip(trashed.id.clone())
+            }
+            EditOperation::MoveClip { id, .. } => {
+                let clip = clip(id)?;
+                EditOperation::MoveClip {
+                    id: id.clone(),
+                    new_start_time: clip.start_time,
+                    new_track: clip.track,
+                }
+            }
+            EditOperation::TrimClip { id, .. } => {
+                let clip = clip(id)?;
+                EditOperation::TrimClip {
+                    id: id.clone(),
+                    new_start_time: clip.start_time,
+                    new_end_time: clip.end_time,
+                }
+            }
+            EditOperation::SplitClip {
+                id, new_clip_id, ..
+            } => EditOperation::JoinClips {
+                id: clip(id)?.id.clone(),
+                joined_id: new_clip_id.clone(),
+            },
+            EditOperation::JoinClips { id, joined_id } => EditOperation::SplitClip {
+                id: id.clone(),
+                split_time: clip(id)?.end_time,
+                new_clip_id: joined_id.clone(),
+            },
+            EditOperation::ReplaceClipSource { clip_id, .. } => EditOperation::ReplaceClipSource {
+                clip_id: clip_id.clone(),
+                new_asset_id: clip(clip_id)?.asset_id.clone()?,
+            },
+            // Reversible only when every clip playing `from` played the same
+            // asset and nothing played the new one yet.
+            EditOperation::RelinkAsset {
+                from: MediaReference::AssetId(old_asset_id),
+                new_asset_id,
+            } => {
+                let new_in_use = project
+                    .clips
+                    .iter()
+                    .chain(&project.trash)
+                    .any(|c| c.asset_id.as_deref() == Some(new_asset_id.as_str()));
+                if new_in_use || asset(old_asset_id).is_none() {
+                    return None;
+                }
+                EditOperation::RelinkAsset {
+                    from: MediaReference::AssetId(new_asset_id.clone()),
+                    new_asset_id: old_asset_id.clone(),
+                }
+            }
+            EditOperation::AddEffect { clip_id, .. }
+            | EditOperation::RemoveEffect { clip_id, .. }
+            | EditOperation::SetClipEffects { clip_id, .. } => EditOperation::SetClipEffects {
+                clip_id: clip_id.clone(),
+                effects: clip(clip_id)?.effects.clone(),
+            },
+            EditOperation::AddTransition { clip_id, .. }
+            | EditOperation::RemoveTransition { clip_id } => match &clip(clip_id)?.transition {
+                Some(transition) => EditOperation::AddTransition {
+                    clip_id: clip_id.clone(),
+                    transition: transition.clone(),
+                },
+                None if matches!(self, EditOperation::RemoveTransition { .. }) => return None,
+                None => EditOperation::RemoveTransition {
+                    clip_id: clip_id.clone(),
+                },
+            },
+            EditOperation::SetProjectDuration(_) => {
+                EditOperation::SetProjectDuration(project.duration)
+            }
+            EditOperation::SetFrameRate(_) => EditOperation::SetFrameRate(settings.frame_rate),
+            EditOperation::SetSnapToFrames(_) => {
+                EditOperation::SetSnapToFrames(settings.snap_to_frames)
+            }
+            EditOperation::SetRippleEdits(_) => {
+                EditOperation::SetRippleEdits(settings.ripple_edits)
+            }
+            EditOperation::SetMagneticTimeline(_) => {
+                EditOperation::SetMagneticTimeline(settings.magnetic_timeline)
+            }
+            EditOperation::SetWorkingColorSpace(_) => {
+                EditOperation::SetWorkingColorSpace(settings.working_color_space)
+            }
+            EditOperation::SetAssetColorSpace { asset_id, .. } => {
+                EditOperation::SetAssetColorSpace {
+                    asset_id: asset_id.clone(),
+                    color_space: asset(asset_id)?.color_space,
+                }
+            }
+            EditOperation::SetAssetHdr { asset_id, .. } => EditOperation::SetAssetHdr {
+                asset_id: asset_id.clone(),
+                hdr: asset(asset_id)?.hdr.clone(),
+            },
+            EditOperation::SetHdrOutput(_) => {
+                EditOperation::SetHdrOutput(settings.hdr_output.clone())
+            }
+            EditOperation::SetAspectRatio(_) => {
+                EditOperation::SetAspectRatio(settings.aspect_ratio)
+            }
+            EditOperation::SetSafeAreas(_) => EditOperation::SetSafeAreas(settings.safe_areas),
+            EditOperation::SetClipSpeed { clip_id, .. } => EditOperation::SetClipSpeed {
+                clip_id: clip_id.clone(),
+                keyframes: clip(clip_id)?.speed.clone(),
+            },
+            EditOperation::SetClipAudio { clip_id, .. } => EditOperation::SetClipAudio {
+                clip_id: clip_id.clone(),
+                audio: clip(clip_id)?.audio,
+            },
+            EditOperation::SetClipEnabled { clip_id, .. } => EditOperation::SetClipEnabled {
+                clip_id: clip_id.clone(),
+                enabled: clip(clip_id)?.enabled,
+            },
+            EditOperation::SetTrackMuted { track, .. } => EditOperation::SetTrackMuted {
+                track: *track,
+                muted: project.muted_tracks.contains(track),
+            },
+            EditOperation::SetTrackGain { track, .. } => EditOperation::SetTrackGain {
+                track: *track,
+                gain_db: project.track_gain.get(track).copied().unwrap_or(0.0),
+            },
+            // Re-adding a replaced or removed effect puts it last in the
+            // track's stack, wherever it was before.
+            EditOperation::AddTrackEffect { track, effect } => {
+                let replaced = project.track_effects.get(track).and_then(|effects| {
+                    effects.iter().find(|e| e.effect_type == effect.effect_type)
+                });
+                match replaced {
+                    Some(replaced) => EditOperation::AddTrackEffect {
+                        track: *track,
+                        effect: replaced.clone(),
+                    },
+                    None => EditOperation::RemoveTrackEffect {
+                        track: *track,
+                        effect_id: effect.id.clone(),
+                    },
+                }
+            }
+            EditOperation::RemoveTrackEffect { track, effect_id } => {
+                EditOperation::AddTrackEffect {
+                    track: *track,
+                    effect: project
+                        .track_effects
+                        .get(track)?
+                        .iter()
+                        .find(|e| e.id == *effect_id)?
+                        .clone(),
+                }
+            }
+            EditOperation::UpdateTrackEffect { track, effect } => {
+                EditOperation::UpdateTrackEffect {
+                    track: *track,
+                    effect: project
+                        .track_effects
+                        .get(track)?
+                        .iter()
+                        .find(|e| e.id == effect.id)?
+                        .clone(),
+                }
+            }
+            EditOperation::SetTrackHeight { track, .. } => EditOperation::SetTrackHeight {
+                track: *track,
+                height: project.presentation.track(*track).height,
+            },
+            EditOperation::SetTrackColor { track, .. } => EditOperation::SetTrackColor {
+                track: *track,
+                color: project.presentation.track(*track).color,
+            },
+            EditOperation::SetTrackCollapsed { track, .. } => EditOperation::SetTrackCollapsed {
+                track: *track,
+                collapsed: project.presentation.track(*track).collapsed,
+            },
+            EditOperation::SetPrivateTrackLayout(_) => {
+                EditOperation::SetPrivateTrackLayout(settings.private_track_layout)
+            }
+            EditOperation::AddSubtitleTrack(track) => {
+                EditOperation::RemoveSubtitleTrack(track.id.clone())
+            }
+            EditOperation::RemoveSubtitleTrack(track_id) => EditOperation::AddSubtitleTrack(
+                project
+                    .subtitle_tracks
+                    .iter()
+                    .find(|t| t.id == *track_id)?
+                    .clone(),
+            ),
+            EditOperation::AddMarkers(markers) => {
+                EditOperation::RemoveMarkers(markers.iter().map(|m| m.id.clone()).collect())
+            }
+            EditOperation::RemoveMarkers(marker_ids) => {
+                let removed: Vec<_> = project
+                    .markers
+                    .iter()
+                    .filter(|m| marker_ids.contains(&m.id))
+                    .cloned()
+                    .collect();
+                if removed.is_empty() {
+                    return None;
+                }
+                EditOperation::AddMarkers(removed)
+            }
+            EditOperation::RenameProject(_) => EditOperation::RenameProject(project.name.clone()),
+            EditOperation::AddAsset(added) => EditOperation::RemoveAsset(added.id.clone()),
+            EditOperation::RemoveAsset(asset_id) => {
+                EditOperation::AddAsset(asset(asset_id)?.clone())
+            }
+            // Reversible when every operation in it is, undoing the last first
+            EditOperation::Batch(operations) => {
+                let mut project = project.clone();
+                let mut inverses = Vec::with_capacity(operations.len());
+                for operation in operations {
+                    inverses.push(operation.invert(&project)?);
+                    project.apply_operation(operation);
+                }
+                inverses.reverse();
+                EditOperation::Batch(inverses)
+            }
+            EditOperation::EmptyTrash { .. }
+            | EditOperation::RelinkAsset { .. }
+            | EditOperation::AddMulticamGroup(_)
+            | EditOperation::AlignToReference { .. }
+            | EditOperation::SwitchAngle { .. }
+            | EditOperation::AddSubtitleCues { .. }
+            | EditOperation::UpdateCollaboratorCursor { .. }
+            | EditOperation::SetClipPreviews { .. }
+            | EditOperation::SetClipWaveform { .. }
+            | EditOperation::AddCollaborator(_)
+            | EditOperation::RemoveCollaborator(_) => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VideoClip;
+    use std::time::Duration;
+
+    fn project() -> VideoProject {
+        let mut project = VideoProject::new(
+            "project".to_string(),
+            "Rough cut".to_string(),
+            "client".to_string(),
+            "Client".to_string(),
+        );
+        project.apply_operation(&EditOperation::AddClip(VideoClip {
+            id: "a".to_string(),
+            source_file: "interview.mp4".to_string(),
+            end_time: Duration::from_secs(10),
+            ..VideoClip::default()
+        }));
+        project
+    }
+
+    /// Applies `op` and then its inverse, checking the project is back where
+    /// it started.
+    fn assert_undoes(op: EditOperation) {
+        let mut project = project();
+        let before = project.checksum();
+        let inverse = op.invert(&project).expect("operation has an inverse");
+        project.apply_operation(&op);
+        assert_ne!(project.checksum(), before, "{:?} changed nothing", op);
+        project.apply_operation(&inverse);
+        assert_eq!(project.checksum(), before, "{:?} wasn't undone", op);
+    }
+
+    #[test]
+    fn inverses_restore_the_project() {
+        assert_undoes(EditOperation::MoveClip {
+            id: "a".to_string(),
+            new_start_time: Duration::from_secs(4),
+            new_track: 1,
+        });
+        assert_undoes(EditOperation::TrimClip {
+            id: "a".to_string(),
+            new_start_time: Duration::from_secs(2),
+            new_end_time: Duration::from_secs(6),
+        });
+        assert_undoes(EditOperation::SplitClip {
+            id: "a".to_string(),
+            split_time: Duration::from_secs(5),
+            new_clip_id: "b".to_string(),
+        });
+        assert_undoes(EditOperation::RemoveClip("a".to_string()));
+        assert_undoes(EditOperation::SetSnapToFrames(true));
+        assert_undoes(EditOperation::RenameProject("Final cut".to_string()));
+    }
+
+    #[test]
+    fn undoing_an_added_clip_removes_it() {
+        let clip = VideoClip {
+            id: "b".to_string(),
+            end_time: Duration::from_secs(3),
+            ..VideoClip::default()
+        };
+        let project = project();
+        assert!(matches!(
+            EditOperation::AddClip(clip).invert(&project),
+            Some(EditOperation::RemoveClip(id)) if id == "b"
+        ));
+    }
+
+    #[test]
+    fn some_operations_have_no_inverse() {
+        let project = project();
+        let empty = EditOperation::EmptyTrash { clip_ids: None };
+        assert!(empty.invert(&project).is_none());
+        let missing = EditOperation::RemoveClip("missing".to_string());
+        assert!(missing.invert(&project).is_none());
+    }
+}